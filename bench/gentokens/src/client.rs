@@ -1,67 +1,38 @@
-use reqwest::{Method, Url};
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
-
-#[derive(Debug, serde::Deserialize)]
-pub struct LoginResponse {
-    pub access_token: String,
-}
-
-pub struct Client {
-    url: Url,
-    client: ClientWithMiddleware,
-}
-
-impl Client {
-    pub async fn new(url: Url) -> Self {
-        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-        let client = ClientBuilder::new(reqwest::Client::new())
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build();
-
-        Self { url, client }
+use std::{future::Future, time::Duration};
+
+use todo_client::{Client, ClientError, ErrorCode, LoginToken};
+
+const MAX_RETRIES: u32 = 3;
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Repeats `request` with exponential backoff while it fails with a transient error.
+pub async fn with_retries<T, F, Fut>(mut request: F) -> Result<T, ClientError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ClientError>>,
+{
+    let mut delay = FIRST_RETRY_DELAY;
+    for _ in 0..MAX_RETRIES {
+        match request().await {
+            Err(e) if e.is_transient() => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
     }
+    request().await
+}
 
-    pub async fn register_and_login(&self, email: &str, password: &str) -> LoginResponse {
-        let url = self.url.join("auth/register").unwrap();
-        let request = reqwest::Client::new()
-            .request(Method::POST, url)
-            .json(&serde_json::json!({
-                "email": email,
-                "password": password
-            }))
-            .build()
-            .unwrap();
-
-        let _response = self.client.execute(request).await.unwrap();
-
-        let url = self.url.join("auth/login").unwrap();
-        let request = reqwest::Client::new()
-            .request(Method::POST, url)
-            .json(&serde_json::json!({
-                "email": email,
-                "password": password
-            }))
-            .build()
-            .unwrap();
-
-        let res = self.client.execute(request).await.unwrap();
-        res.json::<LoginResponse>().await.unwrap()
-    }
-
-    pub async fn get_user_by_email(&self, token: &str, email: &str) -> reqwest::Response {
-        let url = self
-            .url
-            .join("/admin/user/email/")
-            .unwrap()
-            .join(email)
-            .unwrap();
-
-        self.client
-            .get(url)
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
-            .await
-            .unwrap()
+/// Registers the user unless it exists already, then logs in.
+pub async fn register_and_login(
+    client: &Client,
+    email: &str,
+    password: &str,
+) -> Result<LoginToken, ClientError> {
+    match client.register(email, password).await {
+        Err(e) if e.code() != Some(ErrorCode::UserAlreadyExists) => return Err(e),
+        _ => {}
     }
+    client.login(email, password).await
 }
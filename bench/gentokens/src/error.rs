@@ -5,8 +5,8 @@ pub enum GenTokensError {
     #[error("Failed to parse url from string")]
     UrlParse(#[from] url::ParseError),
 
-    #[error("Failed to parse url from string")]
-    Reqwest(#[from] reqwest::Error),
+    #[error("Request to the server failed")]
+    Client(#[from] todo_client::ClientError),
 
     #[error("Failed to read env vars")]
     ReadEnvVars(#[from] std::env::VarError),
@@ -1,6 +1,8 @@
 use std::{io::Write, str::FromStr};
 
+use client::{register_and_login, with_retries};
 use error::GenTokensError;
+use todo_client::Client;
 use tokio::time::Instant;
 
 mod client;
@@ -14,11 +16,6 @@ struct Token {
     id: String,
 }
 
-#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
-pub struct User {
-    pub id: String,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), GenTokensError> {
     dotenv::dotenv().ok();
@@ -51,31 +48,28 @@ async fn generate(
     token_file: String,
     token_count: usize,
 ) -> Result<(), GenTokensError> {
-    let client = client::Client::new(url).await;
-
-    let admin_token = client
-        .register_and_login("admin@gmail.com", "admin")
-        .await
-        .access_token;
+    // one connection pool for the sessions of all users
+    let http = reqwest::Client::new();
+    let admin = Client::with_http_client(url.clone(), http.clone());
+    with_retries(|| register_and_login(&admin, "admin@gmail.com", "admin")).await?;
 
     let mut tokens = Vec::with_capacity(token_count);
     for _ in 0..token_count {
         let email = random_string::generate(20, random_string::charsets::ALPHANUMERIC);
         let password = random_string::generate(20, random_string::charsets::ALPHANUMERIC);
 
-        let token = client
-            .register_and_login(&email, &password)
-            .await
+        let client = Client::with_http_client(url.clone(), http.clone());
+        let token = with_retries(|| register_and_login(&client, &email, &password))
+            .await?
             .access_token;
 
-        let response = client.get_user_by_email(&admin_token, &email).await;
-        let user = response.json::<User>().await.unwrap();
+        let user = with_retries(|| admin.user_by_email(&email)).await?;
 
         tokens.push(Token {
             token,
             email,
             password,
-            id: user.id,
+            id: user.id.to_string(),
         });
     }
 
@@ -0,0 +1,13 @@
+// Compiled with protox so that building doesn't need a protoc binary.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let protos = [
+        "proto-contracts/todo/v1/todo.proto",
+        "proto-contracts/todo/v1/auth.proto",
+        "proto-contracts/todo/v1/admin.proto",
+    ];
+    let file_descriptors = protox::compile(protos, ["proto-contracts"])?;
+    tonic_build::configure().compile_fds(file_descriptors)?;
+
+    println!("cargo:rerun-if-changed=proto-contracts");
+    Ok(())
+}
@@ -2,7 +2,8 @@ use std::convert::Infallible;
 
 use crate::config::Settings;
 use crate::docs::openapi::ApiDoc;
-use crate::middleware::rate_limiter::{GlobalRateLimitLayer, PerIpRateLimiter};
+use crate::graphql::build_schema;
+use crate::middleware::rate_limiter::{GlobalRateLimitLayer, PerIpRateLimiter, RateLimiters};
 use crate::service::Service;
 use crate::storage::Role;
 use crate::{
@@ -25,7 +26,7 @@ use utoipa_swagger_ui::SwaggerUi;
 
 use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
 
-fn admin_routs(settings: &Settings) -> OpenApiRouter<Service> {
+fn admin_routs(limiters: &RateLimiters) -> OpenApiRouter<Service> {
     OpenApiRouter::new()
         .route("/users", get(handlers::admin::get_all))
         .route(
@@ -34,34 +35,23 @@ fn admin_routs(settings: &Settings) -> OpenApiRouter<Service> {
         )
         .route("/user/email/{email}", get(handlers::admin::get_by_email))
         .route("/user/{id}/role", patch(handlers::admin::update))
+        .route(
+            "/backups",
+            post(handlers::admin::create_backup).get(handlers::admin::get_backups),
+        )
+        .route("/fsck", get(handlers::admin::fsck))
+        .route("/fsck/repair", post(handlers::admin::repair))
+        .route("/storage/stats", get(handlers::admin::storage_stats))
         .layer(from_fn_with_state(Role::Admin, require_role))
-        .layer(GlobalRateLimitLayer::new(
-            settings.rate_limiter.admin.global.cells_per_second,
-            settings.rate_limiter.admin.global.burst_per_second,
-        ))
-        .layer(PerIpRateLimiter::new(
-            settings.rate_limiter.admin.per_ip.cells_per_second,
-            settings.rate_limiter.admin.per_ip.burst_per_second,
-        ))
+        .layer(limiters.admin.global_layer())
+        .layer(limiters.admin.per_ip_layer())
 }
 
-fn user_routs(settings: &Settings) -> OpenApiRouter<Service> {
-    let global_light_limiter = GlobalRateLimitLayer::new(
-        settings.rate_limiter.crud_light.global.cells_per_second,
-        settings.rate_limiter.crud_light.global.burst_per_second,
-    );
-    let per_ip_light_limiter = PerIpRateLimiter::new(
-        settings.rate_limiter.crud_light.per_ip.cells_per_second,
-        settings.rate_limiter.crud_light.per_ip.burst_per_second,
-    );
-    let global_heavy_limiter = GlobalRateLimitLayer::new(
-        settings.rate_limiter.crud_heavy.global.cells_per_second,
-        settings.rate_limiter.crud_heavy.global.burst_per_second,
-    );
-    let per_ip_heavy_limiter = PerIpRateLimiter::new(
-        settings.rate_limiter.crud_heavy.per_ip.cells_per_second,
-        settings.rate_limiter.crud_heavy.per_ip.burst_per_second,
-    );
+fn user_routs(limiters: &RateLimiters) -> OpenApiRouter<Service> {
+    let global_light_limiter = limiters.crud_light.global_layer();
+    let per_ip_light_limiter = limiters.crud_light.per_ip_layer();
+    let global_heavy_limiter = limiters.crud_heavy.global_layer();
+    let per_ip_heavy_limiter = limiters.crud_heavy.per_ip_layer();
     OpenApiRouter::new()
         .route(
             "/",
@@ -81,6 +71,66 @@ fn user_routs(settings: &Settings) -> OpenApiRouter<Service> {
                 .layer::<_, Infallible>(global_heavy_limiter.clone())
                 .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
         )
+        .route(
+            "/",
+            patch(handlers::todo::update_group)
+                .layer::<_, Infallible>(global_heavy_limiter.clone())
+                .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
+        )
+        .route(
+            "/batch",
+            post(handlers::todo::batch)
+                .layer::<_, Infallible>(global_heavy_limiter.clone())
+                .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
+        )
+        .route(
+            "/export",
+            get(handlers::todo::export)
+                .layer::<_, Infallible>(global_heavy_limiter.clone())
+                .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
+        )
+        .route(
+            "/import",
+            post(handlers::todo::import)
+                .layer::<_, Infallible>(global_heavy_limiter.clone())
+                .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
+        )
+        .route(
+            "/events",
+            get(handlers::events::sse)
+                .layer::<_, Infallible>(global_light_limiter.clone())
+                .layer::<_, Infallible>(per_ip_light_limiter.clone()),
+        )
+        .route(
+            "/ws",
+            get(handlers::events::websocket)
+                .layer::<_, Infallible>(global_light_limiter.clone())
+                .layer::<_, Infallible>(per_ip_light_limiter.clone()),
+        )
+        .route(
+            "/trash",
+            get(handlers::todo::get_trash)
+                .layer::<_, Infallible>(global_light_limiter.clone())
+                .layer::<_, Infallible>(per_ip_light_limiter.clone()),
+        )
+        .route(
+            "/undo",
+            post(handlers::todo::undo)
+                .layer::<_, Infallible>(global_heavy_limiter.clone())
+                .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
+        )
+        .route(
+            "/{id}/restore",
+            post(handlers::todo::restore)
+                .layer::<_, Infallible>(global_light_limiter.clone())
+                .layer::<_, Infallible>(per_ip_light_limiter.clone()),
+        )
+        .route(
+            "/{id}/history",
+            get(handlers::todo::get_history)
+                .layer::<_, Infallible>(global_light_limiter.clone())
+                .layer::<_, Infallible>(per_ip_light_limiter.clone()),
+        )
         .route(
             "/{id}",
             get(handlers::todo::get)
@@ -99,44 +149,102 @@ fn user_routs(settings: &Settings) -> OpenApiRouter<Service> {
                 .layer::<_, Infallible>(global_light_limiter.clone())
                 .layer::<_, Infallible>(per_ip_light_limiter.clone()),
         )
+        .route(
+            "/{id}/blocked_by",
+            post(handlers::todo::add_blocker)
+                .layer::<_, Infallible>(global_heavy_limiter.clone())
+                .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
+        )
+        .route(
+            "/{id}/blocked_by/{blocker_id}",
+            delete(handlers::todo::remove_blocker)
+                .layer::<_, Infallible>(global_heavy_limiter.clone())
+                .layer::<_, Infallible>(per_ip_heavy_limiter.clone()),
+        )
 }
 
 #[instrument(name = "build_app", skip_all)]
-pub fn build_app(service: Service, settings: Settings) -> Router {
+pub fn build_app(service: Service, settings: Settings, limiters: &RateLimiters) -> Router {
     let app_router = OpenApiRouter::new()
-        .nest("/admin", admin_routs(&settings))
-        .nest("/todos", user_routs(&settings))
+        .nest("/admin", admin_routs(limiters))
+        .nest("/todos", user_routs(limiters))
         .route("/auth/logout", post(handlers::auth::logout))
         .route("/auth/refresh", post(handlers::auth::refresh))
-        .layer(from_fn_with_state(service.clone(), auth))
         .route(
-            "/auth/register",
-            post(handlers::auth::register)
+            "/calendar/token",
+            post(handlers::calendar::create_token).delete(handlers::calendar::revoke_token),
+        )
+        .route(
+            "/sync",
+            get(handlers::sync::pull)
+                .post(handlers::sync::push)
                 .layer::<_, Infallible>(GlobalRateLimitLayer::new(
-                    settings.rate_limiter.registration.global.cells_per_second,
-                    settings.rate_limiter.registration.global.burst_per_second,
+                    settings.rate_limiter.crud_heavy.global.cells_per_second,
+                    settings.rate_limiter.crud_heavy.global.burst_per_second,
                 ))
                 .layer::<_, Infallible>(PerIpRateLimiter::new(
-                    settings.rate_limiter.registration.per_ip.cells_per_second,
-                    settings.rate_limiter.registration.per_ip.burst_per_second,
+                    settings.rate_limiter.crud_heavy.per_ip.cells_per_second,
+                    settings.rate_limiter.crud_heavy.per_ip.burst_per_second,
                 )),
         )
         .route(
-            "/auth/login",
-            post(handlers::auth::login)
+            "/webhooks",
+            post(handlers::webhooks::create).get(handlers::webhooks::get_all),
+        )
+        .route("/webhooks/{id}", delete(handlers::webhooks::delete))
+        .route(
+            "/webhooks/dead_letters",
+            get(handlers::webhooks::get_dead_letters),
+        )
+        .route(
+            "/webhooks/dead_letters/{id}/retry",
+            post(handlers::webhooks::retry_dead_letter),
+        )
+        // one request may resolve many todos and users
+        .route(
+            "/graphql",
+            post(handlers::graphql::execute)
+                .layer::<_, Infallible>(GlobalRateLimitLayer::new(
+                    settings.rate_limiter.crud_heavy.global.cells_per_second,
+                    settings.rate_limiter.crud_heavy.global.burst_per_second,
+                ))
+                .layer::<_, Infallible>(PerIpRateLimiter::new(
+                    settings.rate_limiter.crud_heavy.per_ip.cells_per_second,
+                    settings.rate_limiter.crud_heavy.per_ip.burst_per_second,
+                )),
+        )
+        .layer(from_fn_with_state(service.clone(), auth))
+        .route("/graphql", get(handlers::graphql::graphiql))
+        // authenticated by the token in the path, calendar clients can't send a JWT
+        .route(
+            "/calendar/{file}",
+            get(handlers::calendar::feed)
                 .layer::<_, Infallible>(GlobalRateLimitLayer::new(
-                    settings.rate_limiter.login.global.cells_per_second,
-                    settings.rate_limiter.login.global.burst_per_second,
+                    settings.rate_limiter.crud_heavy.global.cells_per_second,
+                    settings.rate_limiter.crud_heavy.global.burst_per_second,
                 ))
                 .layer::<_, Infallible>(PerIpRateLimiter::new(
-                    settings.rate_limiter.login.per_ip.cells_per_second,
-                    settings.rate_limiter.login.per_ip.burst_per_second,
+                    settings.rate_limiter.crud_heavy.per_ip.cells_per_second,
+                    settings.rate_limiter.crud_heavy.per_ip.burst_per_second,
                 )),
         )
+        .route(
+            "/auth/register",
+            post(handlers::auth::register)
+                .layer::<_, Infallible>(limiters.registration.global_layer())
+                .layer::<_, Infallible>(limiters.registration.per_ip_layer()),
+        )
+        .route(
+            "/auth/login",
+            post(handlers::auth::login)
+                .layer::<_, Infallible>(limiters.login.global_layer())
+                .layer::<_, Infallible>(limiters.login.per_ip_layer()),
+        )
         .route("/health", get(handlers::health))
         .layer(from_fn(record_metrics))
         .layer(from_fn(trace_root))
         .layer(TraceLayer::new_for_http())
+        .layer(Extension(build_schema(service.clone())))
         .layer(Extension(settings))
         .with_state(service);
 
@@ -4,7 +4,7 @@ use std::net::SocketAddr;
 
 use config::{Config, Environment, File};
 use serde::Deserialize;
-use types::{AuthSettings, RateLimiterSettings};
+use types::{AuthSettings, LimitsConfig, RateLimiterSettings, WebhookConfig};
 pub(crate) use types::{JwtConfig, ServerConfig, StorageSettings, TelemetryConfig};
 
 use crate::{init::StartupError, trace_err, utils::JWT_SECRET_KEY};
@@ -17,6 +17,8 @@ pub struct Settings {
     pub(crate) server: ServerConfig,
     pub(crate) auth: AuthSettings,
     pub(crate) rate_limiter: RateLimiterSettings,
+    pub(crate) webhooks: WebhookConfig,
+    pub(crate) limits: LimitsConfig,
 }
 
 impl Settings {
@@ -48,6 +50,10 @@ impl Settings {
         self.server.addr
     }
 
+    pub fn grpc_addr(&self) -> SocketAddr {
+        self.server.grpc_addr
+    }
+
     pub fn tracing_enabled(&self) -> bool {
         self.telemetry.tracing
     }
@@ -16,12 +16,107 @@ pub enum StorageKind {
 pub struct StorageSettings {
     pub backend: StorageKind,
     pub sled: Option<SledConfig>,
+    pub trash: TrashConfig,
+    pub backup: BackupConfig,
+    pub stats: StatsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrashConfig {
+    /// How long deleted todos stay restorable.
+    pub retention_sec: i64,
+    pub purge_interval_sec: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackupConfig {
+    /// Where scheduled backups and the ones of `POST /admin/backups` are written.
+    pub dir: PathBuf,
+    pub interval_sec: u64,
+    /// Older archives in `dir` are removed once there are more than this.
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatsConfig {
+    /// How often storage statistics are collected for the gauges and
+    /// `GET /admin/storage/stats`.
+    pub interval_sec: u64,
+    /// Users with the most todos listed in the statistics.
+    pub top_users: usize,
+}
+
+/// Limits on what a single user can store.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LimitsConfig {
+    pub max_todos: usize,
+    /// In characters, as are the other lengths.
+    pub max_text_len: usize,
+    pub max_group_len: usize,
+    /// Distinct non-empty groups.
+    pub max_groups: usize,
+}
+
+impl Default for LimitsConfig {
+    /// Nothing is limited.
+    fn default() -> Self {
+        Self {
+            max_todos: usize::MAX,
+            max_text_len: usize::MAX,
+            max_group_len: usize::MAX,
+            max_groups: usize::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    /// How often the dispatcher looks for new events and due retries.
+    pub poll_interval_ms: u64,
+    pub timeout_ms: u64,
+    /// Failed deliveries move to the dead letters after this many attempts.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every further one.
+    pub backoff_base_ms: i64,
+    pub backoff_max_ms: i64,
+    /// Events and deliveries handled per dispatcher round.
+    pub batch_size: usize,
+    /// Refuses endpoints on loopback, private and link-local addresses.
+    pub block_private_targets: bool,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SledConfig {
     pub path: PathBuf,
     pub delete_batch_size: usize,
+    /// How many recent todo operations each user can undo.
+    pub undo_log_size: usize,
+    /// Todo content is encrypted at rest when set.
+    pub encryption: Option<EncryptionConfig>,
+    /// Large values are compressed when set.
+    pub compression: Option<CompressionConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompressionConfig {
+    /// Encoded size in bytes from which a value is compressed.
+    pub threshold: usize,
+    /// zstd level, from 1 (fastest) to 22 (smallest).
+    pub level: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncryptionConfig {
+    /// File holding the base64 encoded 32 byte master key. The
+    /// `STORAGE_MASTER_KEY` env variable holds it instead when set.
+    pub master_key_file: Option<PathBuf>,
+    /// Master keys the current one replaced. Data keys wrapped with them stay
+    /// readable until `rotate-keys` wraps them with the current one.
+    #[serde(default)]
+    pub previous_master_key_files: Vec<PathBuf>,
+    /// Encrypt user emails too, the email index then holds keyed hashes.
+    #[serde(default)]
+    pub emails: bool,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -44,6 +139,7 @@ pub struct JwtConfig {
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
     pub addr: SocketAddr,
+    pub grpc_addr: SocketAddr,
 }
 
 #[derive(Debug, Deserialize, Copy, Clone, AsRefStr)]
@@ -16,12 +16,41 @@ use crate::handlers::LoginToken;
         crate::handlers::admin::delete,
         crate::handlers::admin::get,
         crate::handlers::admin::get_by_email,
+        crate::handlers::admin::create_backup,
+        crate::handlers::admin::get_backups,
+        crate::handlers::admin::fsck,
+        crate::handlers::admin::repair,
+        crate::handlers::admin::storage_stats,
         crate::handlers::todo::get_all,
         crate::handlers::todo::get,
         crate::handlers::todo::add,
         crate::handlers::todo::update,
         crate::handlers::todo::delete,
         crate::handlers::todo::delete_all,
+        crate::handlers::todo::add_blocker,
+        crate::handlers::todo::remove_blocker,
+        crate::handlers::todo::get_trash,
+        crate::handlers::todo::restore,
+        crate::handlers::todo::get_history,
+        crate::handlers::todo::undo,
+        crate::handlers::todo::batch,
+        crate::handlers::todo::update_group,
+        crate::handlers::todo::export,
+        crate::handlers::todo::import,
+        crate::handlers::events::sse,
+        crate::handlers::events::websocket,
+        crate::handlers::calendar::create_token,
+        crate::handlers::calendar::revoke_token,
+        crate::handlers::calendar::feed,
+        crate::handlers::sync::pull,
+        crate::handlers::sync::push,
+        crate::handlers::webhooks::create,
+        crate::handlers::webhooks::get_all,
+        crate::handlers::webhooks::delete,
+        crate::handlers::webhooks::get_dead_letters,
+        crate::handlers::webhooks::retry_dead_letter,
+        crate::handlers::graphql::execute,
+        crate::handlers::graphql::graphiql,
     ),
     components(
         schemas(RegisterUser, AppError, LoginToken),
@@ -29,6 +58,10 @@ use crate::handlers::LoginToken;
     tags(
         (name = "auth", description = "Authentication endpoints"),
         (name = "todos", description = "Endpoints to create and manage todo items"),
+        (name = "calendar", description = "iCalendar feed of todos for calendar clients"),
+        (name = "sync", description = "Incremental sync of todos for offline-first clients"),
+        (name = "webhooks", description = "Signed HTTP callbacks for todo and user changes"),
+        (name = "graphql", description = "GraphQL queries and mutations over todos and users"),
         (name = "admin", description = "Endpoints to manage users, accessible only with Admin role")
     ),
     info(
@@ -0,0 +1,113 @@
+//! GraphQL schema served at `/graphql`. Resolvers call the same service methods as the REST
+//! handlers, the caller is put into the request data by the handler.
+mod mutation;
+mod query;
+mod types;
+
+use std::{fmt::Display, str::FromStr};
+
+use async_graphql::{
+    connection::{Connection, Edge},
+    extensions::Tracing,
+    Context, EmptySubscription, Error, ErrorExtensions, Guard, OutputType, Schema, ID,
+};
+
+use crate::{
+    handlers::{error::AppError, MAX_PAGE_SIZE},
+    service::Service,
+    storage::{Pagination, Role, User},
+};
+use mutation::MutationRoot;
+use query::QueryRoot;
+
+pub(crate) type TodoSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Page size of a connection queried without `first`.
+const DEFAULT_PAGE_SIZE: usize = 20;
+/// Deep enough for `me { todos { edges { node { ... } } } }`.
+const MAX_DEPTH: usize = 10;
+
+pub(crate) fn build_schema(service: Service) -> TodoSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(service)
+        .extension(Tracing)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// Errors carry the same `code` as the `error` field of the REST body.
+impl From<AppError> for Error {
+    fn from(error: AppError) -> Self {
+        tracing::error!(error = ?error, "AppError");
+
+        let code = error.as_ref().to_owned();
+        Error::new(error.to_string()).extend_with(|_, extensions| extensions.set("code", code))
+    }
+}
+
+/// Field guard, the counterpart of the `require_role` middleware.
+pub(crate) struct RoleGuard(pub Role);
+
+impl Guard for RoleGuard {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        if caller(ctx)?.role == self.0 {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden.into())
+        }
+    }
+}
+
+fn service<'a>(ctx: &Context<'a>) -> &'a Service {
+    ctx.data_unchecked::<Service>()
+}
+
+fn caller<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a User> {
+    ctx.data::<User>()
+}
+
+fn parse_id<Id: FromStr>(id: &ID) -> async_graphql::Result<Id> {
+    id.parse()
+        .map_err(|_| Error::new(format!("Invalid id: {}", id.as_str())))
+}
+
+/// Forward-only pagination, the cursor of an edge is the id of its node like
+/// the `after` of the REST API.
+fn pagination<Id: FromStr>(
+    first: Option<i32>,
+    after: Option<ID>,
+) -> async_graphql::Result<Pagination<Id>> {
+    let limit = match first {
+        None => DEFAULT_PAGE_SIZE,
+        Some(first) if first > 0 && first as usize <= MAX_PAGE_SIZE => first as usize,
+        Some(_) => {
+            return Err(Error::new(format!(
+                "`first` must be from 1 to {MAX_PAGE_SIZE}"
+            )))
+        }
+    };
+    Ok(Pagination {
+        after: after.as_ref().map(parse_id).transpose()?,
+        limit,
+    })
+}
+
+fn connection<T, Id, Node>(
+    items: Vec<T>,
+    cursor: Option<Id>,
+    has_previous_page: bool,
+    id: impl Fn(&T) -> Id,
+    node: impl Fn(T) -> Node,
+) -> Connection<String, Node>
+where
+    Id: Display,
+    Node: OutputType,
+{
+    let mut connection = Connection::new(has_previous_page, cursor.is_some());
+    connection.edges.extend(
+        items
+            .into_iter()
+            .map(|item| Edge::new(id(&item).to_string(), node(item))),
+    );
+    connection
+}
@@ -0,0 +1,55 @@
+use async_graphql::{Context, Object, ID};
+
+use super::{
+    caller, parse_id, service,
+    types::{GqlTodo, UpdateTodoInput},
+};
+use crate::{
+    handlers::{error::AppError, UpdateTodo},
+    storage::TodoId,
+};
+
+pub(crate) struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_todo(
+        &self,
+        ctx: &Context<'_>,
+        text: String,
+        due: Option<i64>,
+    ) -> async_graphql::Result<GqlTodo> {
+        let user = caller(ctx)?;
+        let todo = service(ctx).todo();
+
+        let id = todo.add(user, &text, due).await?;
+        Ok(GqlTodo(todo.get(user, id).await?))
+    }
+
+    /// Returns the todo after the update.
+    async fn update_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        patch: UpdateTodoInput,
+    ) -> async_graphql::Result<GqlTodo> {
+        let id: TodoId = parse_id(&id)?;
+        let user = caller(ctx)?;
+        let todo = service(ctx).todo();
+
+        let patch = UpdateTodo::from(patch);
+        if patch.is_empty() {
+            return Err(AppError::EmptyPatch.into());
+        }
+        todo.update(user, id, &patch).await?;
+        Ok(GqlTodo(todo.get(user, id).await?))
+    }
+
+    /// Moves the todo to the trash and returns its id.
+    async fn delete_todo(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<ID> {
+        let todo_id: TodoId = parse_id(&id)?;
+
+        service(ctx).todo().delete(caller(ctx)?, todo_id).await?;
+        Ok(id)
+    }
+}
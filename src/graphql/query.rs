@@ -0,0 +1,75 @@
+use async_graphql::{connection::Connection, Context, Object, ID};
+
+use super::{
+    caller, connection, pagination, parse_id, service,
+    types::{todos_page, GqlTodo, GqlUser},
+    RoleGuard,
+};
+use crate::{
+    handlers::DisplayUser,
+    storage::{Role, TodoId, UserId},
+};
+
+pub(crate) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The caller.
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<GqlUser> {
+        Ok(DisplayUser::from(caller(ctx)?.clone()).into())
+    }
+
+    async fn todo(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<GqlTodo> {
+        let id: TodoId = parse_id(&id)?;
+        let todo = service(ctx).todo().get(caller(ctx)?, id).await?;
+        Ok(GqlTodo(todo))
+    }
+
+    async fn todos(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<ID>,
+    ) -> async_graphql::Result<Connection<String, GqlTodo>> {
+        todos_page(ctx, first, after).await
+    }
+
+    #[graphql(guard = "RoleGuard(Role::Admin)")]
+    async fn user(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<GqlUser> {
+        let id: UserId = parse_id(&id)?;
+        let user = service(ctx).user().get(id).await?;
+        Ok(DisplayUser::from(user).into())
+    }
+
+    #[graphql(guard = "RoleGuard(Role::Admin)")]
+    async fn user_by_email(
+        &self,
+        ctx: &Context<'_>,
+        email: String,
+    ) -> async_graphql::Result<GqlUser> {
+        let user = service(ctx).user().get_by_email(&email).await?;
+        Ok(DisplayUser::from(user).into())
+    }
+
+    /// All users except the caller.
+    #[graphql(guard = "RoleGuard(Role::Admin)")]
+    async fn users(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<ID>,
+    ) -> async_graphql::Result<Connection<String, GqlUser>> {
+        let has_previous_page = after.is_some();
+        let page = pagination::<UserId>(first, after)?;
+
+        let (items, cursor) = service(ctx).user().get_all(caller(ctx)?, page).await?;
+
+        Ok(connection(
+            items,
+            cursor,
+            has_previous_page,
+            |user| user.id,
+            GqlUser::from,
+        ))
+    }
+}
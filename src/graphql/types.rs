@@ -0,0 +1,168 @@
+use async_graphql::{
+    connection::Connection, ComplexObject, Context, Enum, InputObject, MaybeUndefined, Object,
+    SimpleObject, ID,
+};
+
+use super::{caller, connection, pagination, service};
+use crate::{
+    handlers::{error::AppError, DisplayUser, UpdateTodo},
+    storage::{Role, Todo, TodoId},
+};
+
+pub(crate) struct GqlTodo(pub Todo);
+
+#[Object(name = "Todo")]
+impl GqlTodo {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn text(&self) -> &str {
+        &self.0.text
+    }
+
+    async fn completed(&self) -> bool {
+        self.0.completed
+    }
+
+    async fn group(&self) -> &str {
+        &self.0.group
+    }
+
+    /// Unix timestamp (seconds) the todo is due at.
+    async fn due(&self) -> Option<i64> {
+        self.0.due
+    }
+
+    /// Unfinished todos this one waits for.
+    async fn blocked_by(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<ID>> {
+        let links = service(ctx)
+            .todo()
+            .get_links(caller(ctx)?, self.0.id)
+            .await?;
+        Ok(links
+            .blocked_by
+            .iter()
+            .map(|id| ID(id.to_string()))
+            .collect())
+    }
+
+    async fn blocking(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<ID>> {
+        let links = service(ctx)
+            .todo()
+            .get_links(caller(ctx)?, self.0.id)
+            .await?;
+        Ok(links.blocking.iter().map(|id| ID(id.to_string())).collect())
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[graphql(name = "Role")]
+pub(crate) enum GqlRole {
+    User,
+    Admin,
+}
+
+impl From<Role> for GqlRole {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::User => Self::User,
+            Role::Admin => Self::Admin,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "User", complex)]
+pub(crate) struct GqlUser {
+    id: ID,
+    email: String,
+    role: GqlRole,
+}
+
+impl From<DisplayUser> for GqlUser {
+    fn from(user: DisplayUser) -> Self {
+        Self {
+            id: ID(user.id.to_string()),
+            email: user.email,
+            role: user.role.into(),
+        }
+    }
+}
+
+impl GqlUser {
+    /// Todos are private, even admins only see their own.
+    fn check_owner(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        if caller(ctx)?.id.to_string() == self.id.as_str() {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden.into())
+        }
+    }
+}
+
+#[ComplexObject]
+impl GqlUser {
+    /// Distinct non-empty groups of the user's todos.
+    async fn groups(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        self.check_owner(ctx)?;
+        Ok(service(ctx).todo().get_groups(caller(ctx)?).await?)
+    }
+
+    async fn todos(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<ID>,
+    ) -> async_graphql::Result<Connection<String, GqlTodo>> {
+        self.check_owner(ctx)?;
+        todos_page(ctx, first, after).await
+    }
+}
+
+pub(super) async fn todos_page(
+    ctx: &Context<'_>,
+    first: Option<i32>,
+    after: Option<ID>,
+) -> async_graphql::Result<Connection<String, GqlTodo>> {
+    let has_previous_page = after.is_some();
+    let page = pagination::<TodoId>(first, after)?;
+
+    let (items, cursor) = service(ctx).todo().get_all(caller(ctx)?, page).await?;
+
+    Ok(connection(
+        items,
+        cursor,
+        has_previous_page,
+        |todo| todo.id,
+        GqlTodo,
+    ))
+}
+
+/// Unset fields are left as they are, `due: null` removes the due date.
+#[derive(InputObject)]
+pub(crate) struct UpdateTodoInput {
+    text: Option<String>,
+    completed: Option<bool>,
+    group: Option<String>,
+    due: MaybeUndefined<i64>,
+    /// Allows completing a todo that still has open blockers.
+    #[graphql(default)]
+    ignore_blockers: bool,
+}
+
+impl From<UpdateTodoInput> for UpdateTodo {
+    fn from(input: UpdateTodoInput) -> Self {
+        Self {
+            text: input.text,
+            completed: input.completed,
+            group: input.group,
+            due: match input.due {
+                MaybeUndefined::Undefined => None,
+                MaybeUndefined::Null => Some(None),
+                MaybeUndefined::Value(due) => Some(Some(due)),
+            },
+            ignore_blockers: input.ignore_blockers,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use tonic::{Request, Response, Status};
+
+use super::{
+    caller, limit, pagination, parse_id,
+    proto::{
+        self, admin_service_server::AdminService, DeleteUserRequest, DeleteUserResponse,
+        GetUserByEmailRequest, GetUserRequest, ListUsersRequest, ListUsersResponse,
+        UpdateRoleRequest, UpdateRoleResponse,
+    },
+};
+use crate::{
+    handlers::{error::AppError, DisplayUser},
+    middleware::rate_limiter::{RateLimiters, RequestLimiter},
+    service::Service,
+    storage::{Role, UserId},
+};
+
+pub(crate) struct AdminGrpc {
+    service: Service,
+    limiter: RequestLimiter,
+    x_forwarded_for: bool,
+}
+
+impl AdminGrpc {
+    pub(crate) fn new(service: Service, limiters: &RateLimiters, x_forwarded_for: bool) -> Self {
+        Self {
+            service,
+            limiter: limiters.admin.clone(),
+            x_forwarded_for,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl AdminService for AdminGrpc {
+    #[tracing::instrument(name = "grpc::admin::list_users", skip_all)]
+    async fn list_users(
+        &self,
+        request: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        limit(&self.limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let input = request.into_inner();
+
+        let (items, cursor) = self
+            .service
+            .user()
+            .get_all(&user, pagination(input.after, input.limit)?)
+            .await?;
+
+        Ok(Response::new(ListUsersResponse {
+            items: items.into_iter().map(Into::into).collect(),
+            cursor: cursor.map(|id| id.to_string()),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc::admin::get_user", skip_all)]
+    async fn get_user(
+        &self,
+        request: Request<GetUserRequest>,
+    ) -> Result<Response<proto::User>, Status> {
+        limit(&self.limiter, self.x_forwarded_for, &request)?;
+        let id: UserId = parse_id(&request.get_ref().id)?;
+
+        let user = self.service.user().get(id).await?;
+
+        Ok(Response::new(DisplayUser::from(user).into()))
+    }
+
+    #[tracing::instrument(name = "grpc::admin::get_user_by_email", skip_all)]
+    async fn get_user_by_email(
+        &self,
+        request: Request<GetUserByEmailRequest>,
+    ) -> Result<Response<proto::User>, Status> {
+        limit(&self.limiter, self.x_forwarded_for, &request)?;
+        let user = self
+            .service
+            .user()
+            .get_by_email(&request.get_ref().email)
+            .await?;
+
+        Ok(Response::new(DisplayUser::from(user).into()))
+    }
+
+    #[tracing::instrument(name = "grpc::admin::update_role", skip_all)]
+    async fn update_role(
+        &self,
+        request: Request<UpdateRoleRequest>,
+    ) -> Result<Response<UpdateRoleResponse>, Status> {
+        limit(&self.limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let input = request.into_inner();
+        let id: UserId = parse_id(&input.id)?;
+        let role = match input.role() {
+            proto::Role::User => Role::User,
+            proto::Role::Admin => Role::Admin,
+            proto::Role::Unspecified => {
+                return Err(AppError::InvalidRole(input.role.to_string()).into())
+            }
+        };
+
+        self.service.user().update(&user, id, role).await?;
+
+        Ok(Response::new(UpdateRoleResponse {}))
+    }
+
+    #[tracing::instrument(name = "grpc::admin::delete_user", skip_all)]
+    async fn delete_user(
+        &self,
+        request: Request<DeleteUserRequest>,
+    ) -> Result<Response<DeleteUserResponse>, Status> {
+        limit(&self.limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let id: UserId = parse_id(&request.get_ref().id)?;
+
+        self.service.user().delete(&user, id).await?;
+
+        Ok(Response::new(DeleteUserResponse {}))
+    }
+}
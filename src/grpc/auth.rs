@@ -0,0 +1,99 @@
+use tonic::{Request, Response, Status};
+
+use super::{
+    caller, limit,
+    proto::{
+        auth_service_server::AuthService, LoginRequest, LogoutRequest, LogoutResponse,
+        RefreshRequest, RegisterRequest, RegisterResponse, TokenPair,
+    },
+};
+use crate::{
+    config::Settings,
+    handlers::{auth::check_credentials, LoginUser, RegisterUser},
+    middleware::rate_limiter::{RateLimiters, RequestLimiter},
+    service::Service,
+};
+
+pub(crate) struct AuthGrpc {
+    service: Service,
+    settings: Settings,
+    registration_limiter: RequestLimiter,
+    login_limiter: RequestLimiter,
+}
+
+impl AuthGrpc {
+    pub(crate) fn new(service: Service, settings: Settings, limiters: &RateLimiters) -> Self {
+        Self {
+            registration_limiter: limiters.registration.clone(),
+            login_limiter: limiters.login.clone(),
+            service,
+            settings,
+        }
+    }
+
+    fn limit<T>(&self, limiter: &RequestLimiter, request: &Request<T>) -> Result<(), Status> {
+        limit(limiter, self.settings.rate_limiter.x_forwarded_for, request)
+    }
+}
+
+#[tonic::async_trait]
+impl AuthService for AuthGrpc {
+    #[tracing::instrument(name = "grpc::auth::register", skip_all)]
+    async fn register(
+        &self,
+        request: Request<RegisterRequest>,
+    ) -> Result<Response<RegisterResponse>, Status> {
+        self.limit(&self.registration_limiter, &request)?;
+        let RegisterRequest { email, password } = request.into_inner();
+        check_credentials(&email, &password)?;
+
+        self.service
+            .user()
+            .add(RegisterUser { email, password }, &self.settings)
+            .await?;
+
+        Ok(Response::new(RegisterResponse {}))
+    }
+
+    #[tracing::instrument(name = "grpc::auth::login", skip_all)]
+    async fn login(&self, request: Request<LoginRequest>) -> Result<Response<TokenPair>, Status> {
+        self.limit(&self.login_limiter, &request)?;
+        let LoginRequest { email, password } = request.into_inner();
+        check_credentials(&email, &password)?;
+
+        let tokens = self
+            .service
+            .login_user(LoginUser { email, password }, &self.settings)
+            .await?;
+
+        Ok(Response::new(tokens.into()))
+    }
+
+    #[tracing::instrument(name = "grpc::auth::refresh", skip_all)]
+    async fn refresh(
+        &self,
+        request: Request<RefreshRequest>,
+    ) -> Result<Response<TokenPair>, Status> {
+        let (_, session) = caller(&request)?;
+
+        let tokens = self
+            .service
+            .auth()
+            .refresh_token(&session, &self.settings.jwt)
+            .await?;
+
+        Ok(Response::new(tokens.into()))
+    }
+
+    #[tracing::instrument(name = "grpc::auth::logout", skip_all)]
+    async fn logout(
+        &self,
+        request: Request<LogoutRequest>,
+    ) -> Result<Response<LogoutResponse>, Status> {
+        let (_, session) = caller(&request)?;
+
+        self.service.auth().delete(session.id).await?;
+
+        Ok(Response::new(LogoutResponse {}))
+    }
+}
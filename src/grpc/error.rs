@@ -0,0 +1,51 @@
+use tonic::{Code, Status};
+
+use crate::{handlers::error::AppError, middleware::auth::AuthError};
+
+fn code(error: &AppError) -> Code {
+    match error {
+        AppError::NotFound | AppError::NoContent => Code::NotFound,
+        AppError::UserAlreadyExists => Code::AlreadyExists,
+        AppError::DependencyCycle | AppError::TodoBlocked => Code::FailedPrecondition,
+        AppError::QuotaExceeded { .. } => Code::ResourceExhausted,
+        AppError::UserByEmailNotFound | AppError::PasswordMismatch => Code::Unauthenticated,
+        AppError::Forbidden => Code::PermissionDenied,
+        AppError::InvalidRole { .. }
+        | AppError::MissingPasswordEmail
+        | AppError::EmptyPatch
+        | AppError::InvalidBatchSize(_)
+        | AppError::InvalidLastEventId
+        | AppError::InvalidWebhook(_)
+        | AppError::InvalidImport(_)
+        | AppError::InvalidBackup(_)
+        | AppError::TooLong { .. } => Code::InvalidArgument,
+        AppError::BatchOperation { source, .. } => code(source),
+        AppError::InternalStorage { .. }
+        | AppError::EncodingToken { .. }
+        | AppError::FailedToLoadEnvVar { .. }
+        | AppError::InvalidTtl
+        | AppError::HashingPassword { .. }
+        | AppError::ParsePasswordHash { .. }
+        | AppError::InvalidArgon2Config { .. }
+        | AppError::MissingArgon2Config
+        | AppError::MissingPbkdf2Config
+        | AppError::SerializeExport { .. }
+        | AppError::BackupDir { .. }
+        | AppError::JoinTask { .. } => Code::Internal,
+    }
+}
+
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        tracing::error!(error = ?error, "AppError");
+
+        Status::new(code(&error), error.to_string())
+    }
+}
+
+impl From<AuthError> for Status {
+    fn from(error: AuthError) -> Self {
+        // same message as the `401` body of the REST API
+        Status::unauthenticated(error.to_string())
+    }
+}
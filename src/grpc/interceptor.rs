@@ -0,0 +1,172 @@
+use std::{
+    convert::Infallible,
+    task::{Context, Poll},
+};
+
+use futures_util::future::BoxFuture;
+use tonic::{
+    body::Body,
+    codegen::http::{Request, Response},
+    server::NamedService,
+    Status,
+};
+use tower::Service as TowerService;
+use tracing::{error, info, instrument};
+
+use crate::{
+    middleware::auth::{
+        expect_token_kind, validate_header, validate_refresh_token, validate_session,
+        validate_token, validate_user, AuthError,
+    },
+    service::{jwt::TokenKind, Service},
+    storage::Role,
+    trace_err,
+    utils::JWT_SECRET_KEY,
+};
+
+/// Which methods of a gRPC service need which token.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AuthPolicy {
+    /// Methods callable without a token.
+    pub public: &'static [&'static str],
+    /// Methods taking the refresh token instead of the access token.
+    pub refresh: &'static [&'static str],
+    pub role: Option<Role>,
+}
+
+impl AuthPolicy {
+    pub(crate) const USER: Self = Self {
+        public: &[],
+        refresh: &[],
+        role: None,
+    };
+
+    pub(crate) const ADMIN: Self = Self {
+        role: Some(Role::Admin),
+        ..Self::USER
+    };
+}
+
+/// Async counterpart of the `auth` and `require_role` middlewares for a gRPC service: puts the
+/// caller's `User` and `Session` into the request extensions or answers with
+/// `UNAUTHENTICATED` / `PERMISSION_DENIED` without reaching the service.
+#[derive(Clone)]
+pub(crate) struct AuthInterceptor<S> {
+    inner: S,
+    service: Service,
+    policy: AuthPolicy,
+}
+
+impl<S> AuthInterceptor<S> {
+    pub(crate) fn new(inner: S, service: Service, policy: AuthPolicy) -> Self {
+        Self {
+            inner,
+            service,
+            policy,
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for AuthInterceptor<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> TowerService<Request<Body>> for AuthInterceptor<S>
+where
+    S: TowerService<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        // the clone isn't ready, so the ready one goes into the future
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let service = self.service.clone();
+        let policy = self.policy;
+
+        Box::pin(async move {
+            match authenticate(&service, policy, &mut request).await {
+                Ok(()) => inner.call(request).await,
+                Err(status) => Ok(status.into_http()),
+            }
+        })
+    }
+}
+
+#[instrument(name = "grpc::interceptor::authenticate", skip_all)]
+async fn authenticate(
+    service: &Service,
+    policy: AuthPolicy,
+    request: &mut Request<Body>,
+) -> Result<(), Status> {
+    let path = request.uri().path();
+    let method = path.rsplit('/').next().unwrap_or_default();
+    info!(path, "grpc auth interceptor");
+
+    if policy.public.contains(&method) {
+        return Ok(());
+    }
+
+    let bearer = trace_err!(
+        validate_header(request.headers()),
+        "failed to validate header"
+    )?;
+
+    let jwt_secret = std::env::var(JWT_SECRET_KEY).map_err(|e| {
+        error!(error = ?e, key = JWT_SECRET_KEY, "Failed to load env var");
+        AuthError::FailedToDecodeToken
+    })?;
+    let token_data = trace_err!(
+        validate_token(bearer.token(), &jwt_secret),
+        "failed to validate token"
+    )?;
+
+    let expected = if policy.refresh.contains(&method) {
+        TokenKind::Refresh
+    } else {
+        TokenKind::Access
+    };
+    trace_err!(
+        expect_token_kind(expected, &token_data.claims),
+        "failed to validate token kind"
+    )?;
+
+    let user = trace_err!(
+        validate_user(service, &token_data.claims).await,
+        "failed to validate user"
+    )?;
+    let session = trace_err!(
+        validate_session(service, &token_data.claims).await,
+        "failed to validate session"
+    )?;
+    if token_data.claims.kind == TokenKind::Refresh {
+        trace_err!(
+            validate_refresh_token(&token_data.claims, &session),
+            "failed to validate refresh token"
+        )?;
+    }
+
+    if let Some(required) = policy.role {
+        if user.role != required {
+            error!(required_role = ?required, actual_role = ?user.role, "Role check failed");
+            return Err(Status::permission_denied("Forbidden"));
+        }
+    }
+
+    info!(user_email = %user.email, user_role = ?user.role, session_id = %session.id, "Get user and session from token");
+
+    request.extensions_mut().insert(user);
+    request.extensions_mut().insert(session);
+
+    Ok(())
+}
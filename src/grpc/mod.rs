@@ -0,0 +1,155 @@
+//! gRPC API generated from `proto-contracts/`. It calls the same `Service` methods as the axum
+//! handlers, auth is done by [`interceptor::AuthInterceptor`].
+// `Status` is what every generated method returns, boxing it in the helpers gains nothing
+#![allow(clippy::result_large_err)]
+mod admin;
+mod auth;
+mod error;
+mod interceptor;
+mod todo;
+
+use std::{net::IpAddr, str::FromStr};
+
+use tonic::{service::Routes, Request, Status};
+
+use crate::{
+    config::Settings,
+    handlers::{DisplayUser, LoginToken, MAX_PAGE_SIZE},
+    middleware::rate_limiter::{RateLimiters, RequestLimiter},
+    service::Service,
+    storage::{Pagination, Role, Session, Todo, User},
+};
+use admin::AdminGrpc;
+use auth::AuthGrpc;
+use interceptor::{AuthInterceptor, AuthPolicy};
+use proto::{
+    admin_service_server::AdminServiceServer, auth_service_server::AuthServiceServer,
+    todo_service_server::TodoServiceServer,
+};
+use todo::TodoGrpc;
+
+/// Messages, clients and servers of the `todo.v1` package.
+pub mod proto {
+    tonic::include_proto!("todo.v1");
+}
+
+/// The methods take their cells from the same `limiters` as the REST routes.
+pub fn build_grpc(service: Service, settings: Settings, limiters: &RateLimiters) -> Routes {
+    let x_forwarded_for = settings.rate_limiter.x_forwarded_for;
+    let mut routes = Routes::builder();
+    routes
+        .add_service(AuthInterceptor::new(
+            TodoServiceServer::new(TodoGrpc::new(service.clone(), limiters, x_forwarded_for)),
+            service.clone(),
+            AuthPolicy::USER,
+        ))
+        .add_service(AuthInterceptor::new(
+            AuthServiceServer::new(AuthGrpc::new(service.clone(), settings, limiters)),
+            service.clone(),
+            AuthPolicy {
+                public: &["Register", "Login"],
+                refresh: &["Refresh"],
+                role: None,
+            },
+        ))
+        .add_service(AuthInterceptor::new(
+            AdminServiceServer::new(AdminGrpc::new(service.clone(), limiters, x_forwarded_for)),
+            service,
+            AuthPolicy::ADMIN,
+        ));
+    routes.routes()
+}
+
+/// The caller put into the request by the interceptor.
+fn caller<T>(request: &Request<T>) -> Result<(User, Session), Status> {
+    let extensions = request.extensions();
+    match (extensions.get::<User>(), extensions.get::<Session>()) {
+        (Some(user), Some(session)) => Ok((user.clone(), session.clone())),
+        _ => Err(Status::unauthenticated("Missing auth")),
+    }
+}
+
+/// Takes a cell of `limiter` for the caller, found by the `x-forwarded-for`
+/// header when the server is configured to trust it like the REST one.
+fn limit<T>(
+    limiter: &RequestLimiter,
+    x_forwarded_for: bool,
+    request: &Request<T>,
+) -> Result<(), Status> {
+    let ip: Option<IpAddr> = if x_forwarded_for {
+        request
+            .metadata()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    } else {
+        request.remote_addr().map(|addr| addr.ip())
+    };
+    limiter.check(ip).map_err(|kind| {
+        tracing::warn!(kind, "gRPC request rate limited");
+        Status::resource_exhausted("Too many requests")
+    })
+}
+
+fn parse_id<Id: FromStr>(id: &str) -> Result<Id, Status> {
+    id.parse()
+        .map_err(|_| Status::invalid_argument(format!("Invalid id: {id}")))
+}
+
+/// `limit` is required like in the REST API, proto3 can't tell `0` from unset.
+fn pagination<Id: FromStr>(after: Option<String>, limit: u32) -> Result<Pagination<Id>, Status> {
+    if limit == 0 {
+        return Err(Status::invalid_argument(
+            "Must provide `limit`, and optionally `after`.",
+        ));
+    }
+    if limit as usize > MAX_PAGE_SIZE {
+        return Err(Status::invalid_argument(format!(
+            "`limit` must be at most {MAX_PAGE_SIZE}"
+        )));
+    }
+    Ok(Pagination {
+        after: after.as_deref().map(parse_id).transpose()?,
+        limit: limit as usize,
+    })
+}
+
+impl From<Todo> for proto::Todo {
+    fn from(todo: Todo) -> Self {
+        Self {
+            id: todo.id.to_string(),
+            text: todo.text,
+            completed: todo.completed,
+            group: todo.group,
+            due: todo.due,
+        }
+    }
+}
+
+impl From<Role> for proto::Role {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::User => Self::User,
+            Role::Admin => Self::Admin,
+        }
+    }
+}
+
+impl From<DisplayUser> for proto::User {
+    fn from(user: DisplayUser) -> Self {
+        Self {
+            id: user.id.to_string(),
+            email: user.email,
+            role: proto::Role::from(user.role).into(),
+        }
+    }
+}
+
+impl From<LoginToken> for proto::TokenPair {
+    fn from(tokens: LoginToken) -> Self {
+        Self {
+            access_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use tonic::{Request, Response, Status};
+
+use super::{
+    caller, limit, pagination, parse_id,
+    proto::{
+        todo_service_server::TodoService, update_todo_request::DueChange, CreateTodoRequest,
+        CreateTodoResponse, DeleteTodoRequest, DeleteTodoResponse, GetTodoRequest, GetTodoResponse,
+        ListTodosRequest, ListTodosResponse, UpdateTodoRequest, UpdateTodoResponse,
+    },
+};
+use crate::{
+    handlers::{error::AppError, UpdateTodo},
+    middleware::rate_limiter::{RateLimiters, RequestLimiter},
+    service::Service,
+    storage::TodoId,
+};
+
+pub(crate) struct TodoGrpc {
+    service: Service,
+    // the limits of the `/todos` routes doing the same
+    light_limiter: RequestLimiter,
+    heavy_limiter: RequestLimiter,
+    x_forwarded_for: bool,
+}
+
+impl TodoGrpc {
+    pub(crate) fn new(service: Service, limiters: &RateLimiters, x_forwarded_for: bool) -> Self {
+        Self {
+            service,
+            light_limiter: limiters.crud_light.clone(),
+            heavy_limiter: limiters.crud_heavy.clone(),
+            x_forwarded_for,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl TodoService for TodoGrpc {
+    #[tracing::instrument(name = "grpc::todo::create_todo", skip_all)]
+    async fn create_todo(
+        &self,
+        request: Request<CreateTodoRequest>,
+    ) -> Result<Response<CreateTodoResponse>, Status> {
+        limit(&self.light_limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let input = request.into_inner();
+
+        let id = self
+            .service
+            .todo()
+            .add(&user, &input.text, input.due)
+            .await?;
+
+        Ok(Response::new(CreateTodoResponse { id: id.to_string() }))
+    }
+
+    #[tracing::instrument(name = "grpc::todo::get_todo", skip_all)]
+    async fn get_todo(
+        &self,
+        request: Request<GetTodoRequest>,
+    ) -> Result<Response<GetTodoResponse>, Status> {
+        limit(&self.light_limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let id: TodoId = parse_id(&request.get_ref().id)?;
+
+        let todo = self.service.todo().get(&user, id).await?;
+        let links = self.service.todo().get_links(&user, id).await?;
+
+        Ok(Response::new(GetTodoResponse {
+            todo: Some(todo.into()),
+            blocked_by: links.blocked_by.iter().map(ToString::to_string).collect(),
+            blocking: links.blocking.iter().map(ToString::to_string).collect(),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc::todo::list_todos", skip_all)]
+    async fn list_todos(
+        &self,
+        request: Request<ListTodosRequest>,
+    ) -> Result<Response<ListTodosResponse>, Status> {
+        limit(&self.light_limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let input = request.into_inner();
+
+        let (items, cursor) = self
+            .service
+            .todo()
+            .get_all(&user, pagination(input.after, input.limit)?)
+            .await?;
+
+        Ok(Response::new(ListTodosResponse {
+            items: items.into_iter().map(Into::into).collect(),
+            cursor: cursor.map(|id| id.to_string()),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc::todo::update_todo", skip_all)]
+    async fn update_todo(
+        &self,
+        request: Request<UpdateTodoRequest>,
+    ) -> Result<Response<UpdateTodoResponse>, Status> {
+        limit(&self.heavy_limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let input = request.into_inner();
+        let id: TodoId = parse_id(&input.id)?;
+
+        let patch = UpdateTodo {
+            text: input.text,
+            completed: input.completed,
+            group: input.group,
+            due: match input.due_change {
+                Some(DueChange::Due(due)) => Some(Some(due)),
+                Some(DueChange::ClearDue(true)) => Some(None),
+                Some(DueChange::ClearDue(false)) | None => None,
+            },
+            ignore_blockers: input.ignore_blockers,
+        };
+        if patch.is_empty() {
+            return Err(AppError::EmptyPatch.into());
+        }
+        self.service.todo().update(&user, id, &patch).await?;
+
+        Ok(Response::new(UpdateTodoResponse {}))
+    }
+
+    #[tracing::instrument(name = "grpc::todo::delete_todo", skip_all)]
+    async fn delete_todo(
+        &self,
+        request: Request<DeleteTodoRequest>,
+    ) -> Result<Response<DeleteTodoResponse>, Status> {
+        limit(&self.light_limiter, self.x_forwarded_for, &request)?;
+        let (user, _) = caller(&request)?;
+        let id: TodoId = parse_id(&request.get_ref().id)?;
+
+        self.service.todo().delete(&user, id).await?;
+
+        Ok(Response::new(DeleteTodoResponse {}))
+    }
+}
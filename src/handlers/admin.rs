@@ -1,10 +1,12 @@
 use super::error::AppError;
 use super::types::*;
 use super::Service;
+use crate::config::Settings;
 use crate::storage::{Role, Session, User, UserId};
 use crate::utils::RootSpan;
 use axum::{
     extract::{Path, State},
+    http::StatusCode,
     response::IntoResponse,
     Extension, Json,
 };
@@ -200,3 +202,157 @@ pub(crate) async fn get_by_email(
 
     Ok(Json(user))
 }
+
+#[utoipa::path(
+    post,
+    path = "/admin/backups",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 201, description = "Backup written to the backup directory", body = BackupResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+    tag = "admin"
+)]
+#[tracing::instrument(name = "handlers::admin::create_backup", skip_all)]
+pub(crate) async fn create_backup(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Extension(settings): Extension<Settings>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let (name, manifest) = service
+        .maintenance()
+        .create_backup(&settings.storage.backup)
+        .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(BackupResponse::new(name, manifest)),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/backups",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 200, description = "Backups in the backup directory, newest first", body = [BackupFileResponse]),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+    tag = "admin"
+)]
+#[tracing::instrument(name = "handlers::admin::get_backups", skip_all)]
+pub(crate) async fn get_backups(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Extension(settings): Extension<Settings>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let backups = service
+        .maintenance()
+        .list_backups(&settings.storage.backup)
+        .await?;
+
+    Ok(Json(
+        backups
+            .into_iter()
+            .map(BackupFileResponse::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/fsck",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 200, description = "Records per tree and problems found, nothing is changed", body = FsckResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+    tag = "admin"
+)]
+#[tracing::instrument(name = "handlers::admin::fsck", skip_all)]
+pub(crate) async fn fsck(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let report = service.maintenance().verify(false).await?;
+    Ok(Json(FsckResponse::from(report)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/fsck/repair",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 200, description = "Problems found, with the ones that were repaired", body = FsckResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+    tag = "admin"
+)]
+#[tracing::instrument(name = "handlers::admin::repair", skip_all)]
+pub(crate) async fn repair(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let report = service.maintenance().verify(true).await?;
+    Ok(Json(FsckResponse::from(report)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/storage/stats",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 200, description = "Storage statistics of the last collection", body = StorageStatsResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+    tag = "admin"
+)]
+#[tracing::instrument(name = "handlers::admin::storage_stats", skip_all)]
+pub(crate) async fn storage_stats(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Extension(settings): Extension<Settings>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let stats = service.maintenance().stats(&settings.storage.stats).await?;
+    Ok(Json(StorageStatsResponse::from(stats)))
+}
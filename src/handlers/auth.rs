@@ -8,6 +8,24 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
 
+/// Longest email accepted, the limit RFC 5321 puts on a path.
+pub(crate) const MAX_EMAIL_LEN: usize = 254;
+
+/// Checks credentials of a login or registration before they reach the
+/// service, the email becomes part of a storage key.
+pub(crate) fn check_credentials(email: &str, password: &str) -> Result<(), AppError> {
+    if email.is_empty() || password.is_empty() {
+        return Err(AppError::MissingPasswordEmail);
+    }
+    if email.chars().count() > MAX_EMAIL_LEN {
+        return Err(AppError::TooLong {
+            field: "email",
+            max: MAX_EMAIL_LEN,
+        });
+    }
+    Ok(())
+}
+
 #[utoipa::path(
     post,
     path = "/auth/logout",
@@ -81,7 +99,7 @@ pub(crate) async fn refresh(
         (status = 200, description = "User logged in successfully", body = LoginToken),
         (status = 400, description = "Missing email or password"),
         (status = 401, description = "Wrong password"),
-        (status = 422, description = "Unprocessable Entity"),
+        (status = 422, description = "Email too long or unprocessable entity"),
     ),
     tag = "auth"
 )]
@@ -94,9 +112,7 @@ pub(crate) async fn login(
 ) -> Result<impl IntoResponse, AppError> {
     root_span.record().enduser_email(&input.email);
 
-    if input.email.is_empty() || input.password.is_empty() {
-        return Err(AppError::MissingPasswordEmail);
-    }
+    check_credentials(&input.email, &input.password)?;
 
     let tokens = service.login_user(input, &settings).await?;
 
@@ -115,7 +131,7 @@ pub(crate) async fn login(
         (status = 201, description = "User created successfully"),
         (status = 400, description = "Missing email or password"),
         (status = 409, description = "User with email already registered"),
-        (status = 422, description = "Unprocessable Entity"),
+        (status = 422, description = "Email too long or unprocessable entity"),
     ),
     tag = "auth"
 )]
@@ -128,9 +144,7 @@ pub(crate) async fn register(
 ) -> Result<impl IntoResponse, AppError> {
     root_span.record().enduser_email(&input.email);
 
-    if input.email.is_empty() || input.password.is_empty() {
-        return Err(AppError::MissingPasswordEmail);
-    }
+    check_credentials(&input.email, &input.password)?;
     let result = service.user().add(input, &settings).await;
 
     match result {
@@ -0,0 +1,100 @@
+use super::error::AppError;
+use super::types::*;
+use super::Service;
+use crate::{
+    service::transfer::TransferFormat,
+    storage::{Session, User},
+    utils::RootSpan,
+};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+#[utoipa::path(
+    post,
+    path = "/calendar/token",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 201, description = "New feed token, the previous one is revoked", body = CalendarTokenResponse),
+        (status = 401, description = "Unauthorized"),
+    ),
+    tag = "calendar"
+)]
+#[tracing::instrument(name = "handlers::calendar::create_token", skip_all)]
+pub(crate) async fn create_token(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let token = service.user().create_calendar_token(&user).await?;
+    let feed = format!("/calendar/{token}.ics");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CalendarTokenResponse { token, feed }),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/calendar/token",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 200, description = "Feed token revoked"),
+        (status = 204, description = "User has no feed token"),
+        (status = 401, description = "Unauthorized"),
+    ),
+    tag = "calendar"
+)]
+#[tracing::instrument(name = "handlers::calendar::revoke_token", skip_all)]
+pub(crate) async fn revoke_token(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+) -> Result<(), AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    service.user().revoke_calendar_token(&user).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/calendar/{token}.ics",
+    params(
+        ("token" = String, Path, description = "Feed token")
+    ),
+    responses(
+        (status = 200, description = "Todos of the token owner as VTODO entries", content_type = "text/calendar", body = String),
+        (status = 404, description = "Unknown or revoked token"),
+    ),
+    tag = "calendar"
+)]
+#[tracing::instrument(name = "handlers::calendar::feed", skip_all)]
+pub(crate) async fn feed(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Path(file): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = file.strip_suffix(".ics").ok_or(AppError::NotFound)?;
+    let user = service.user().get_by_calendar_token(token).await?;
+    root_span.record().enduser_id(&user.id);
+
+    let format = TransferFormat::Ical;
+    let body = Body::from_stream(service.todo().export(&user, format));
+
+    Ok(([(header::CONTENT_TYPE, format.content_type())], body))
+}
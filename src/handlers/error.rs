@@ -1,4 +1,4 @@
-use crate::storage::StorageError;
+use crate::{service::transfer::ImportLineError, storage::StorageError};
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
@@ -71,6 +71,48 @@ pub enum AppError {
     #[error("Patch must not be empty")]
     EmptyPatch,
 
+    #[error("Dependency would create a cycle")]
+    DependencyCycle,
+
+    #[error("Todo is blocked by unfinished todos")]
+    TodoBlocked,
+
+    #[error("Operation {index} failed: {source}")]
+    BatchOperation {
+        index: usize,
+        #[schema(value_type = String)]
+        source: Box<AppError>,
+    },
+
+    #[error("The {field} is longer than {max} characters")]
+    TooLong { field: &'static str, max: usize },
+
+    #[error("The limit of {max} {quota} is reached")]
+    QuotaExceeded { quota: &'static str, max: usize },
+
+    #[error("Batch must contain from 1 to {0} operations")]
+    InvalidBatchSize(usize),
+
+    #[error("Last event id must be a number")]
+    InvalidLastEventId,
+
+    #[error("Invalid webhook: {0}")]
+    InvalidWebhook(&'static str),
+
+    #[error("Import has {} invalid lines", .0.len())]
+    InvalidImport(Vec<ImportLineError>),
+
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+
+    #[schema(value_type = String)]
+    #[error("Failed to access backup directory")]
+    BackupDir(#[source] std::io::Error),
+
+    #[schema(value_type = String)]
+    #[error("Failed to serialize export")]
+    SerializeExport(#[from] serde_json::Error),
+
     #[schema(value_type = String)]
     #[error("Failed joining tokio task")]
     JoinTask(#[from] tokio::task::JoinError),
@@ -81,25 +123,42 @@ impl From<StorageError> for AppError {
         match value {
             StorageError::NotFound => Self::NotFound,
             StorageError::NoContent => Self::NoContent,
+            StorageError::DependencyCycle => Self::DependencyCycle,
+            StorageError::TodoBlocked => Self::TodoBlocked,
+            StorageError::QuotaExceeded { quota, max } => Self::QuotaExceeded { quota, max },
+            StorageError::InvalidBackup(reason) => Self::InvalidBackup(reason),
+            StorageError::BatchOperation { index, source } => Self::BatchOperation {
+                index,
+                source: Box::new((*source).into()),
+            },
             _ => Self::InternalStorage(value),
         }
     }
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        tracing::error!(error = ?self, "AppError");
-
-        let status = match &self {
+impl AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
             AppError::NotFound => StatusCode::NOT_FOUND,
-            AppError::NoContent => return StatusCode::NO_CONTENT.into_response(),
-            AppError::UserAlreadyExists => StatusCode::CONFLICT,
+            AppError::NoContent => StatusCode::NO_CONTENT,
+            AppError::UserAlreadyExists
+            | AppError::DependencyCycle
+            | AppError::TodoBlocked
+            | AppError::QuotaExceeded { .. } => StatusCode::CONFLICT,
             AppError::UserByEmailNotFound => StatusCode::UNAUTHORIZED,
             AppError::PasswordMismatch => StatusCode::UNAUTHORIZED,
             AppError::Forbidden => StatusCode::FORBIDDEN,
             AppError::InvalidRole { .. }
             | AppError::MissingPasswordEmail
-            | AppError::EmptyPatch => StatusCode::BAD_REQUEST,
+            | AppError::EmptyPatch
+            | AppError::InvalidBatchSize(_)
+            | AppError::InvalidLastEventId
+            | AppError::InvalidWebhook(_)
+            | AppError::InvalidImport(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidBackup(_) | AppError::TooLong { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            AppError::BatchOperation { source, .. } => source.status_code(),
             AppError::InternalStorage { .. }
             | AppError::EncodingToken { .. }
             | AppError::FailedToLoadEnvVar { .. }
@@ -109,12 +168,46 @@ impl IntoResponse for AppError {
             | AppError::InvalidArgon2Config { .. }
             | AppError::MissingArgon2Config
             | AppError::MissingPbkdf2Config
+            | AppError::SerializeExport { .. }
+            | AppError::BackupDir { .. }
             | AppError::JoinTask { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        tracing::error!(error = ?self, "AppError");
+
+        let status = self.status_code();
+        if status == StatusCode::NO_CONTENT {
+            return status.into_response();
+        }
+
+        let body = match &self {
+            AppError::BatchOperation { index, source } => Json(json!({
+                "error": self.as_ref(),
+                "message": self.to_string(),
+                "index": index,
+                "source": AsRef::<str>::as_ref(source.as_ref()),
+            })),
+            AppError::TooLong { field: limit, max }
+            | AppError::QuotaExceeded { quota: limit, max } => Json(json!({
+                "error": self.as_ref(),
+                "message": self.to_string(),
+                "limit": limit,
+                "max": max,
+            })),
+            AppError::InvalidImport(errors) => Json(json!({
+                "error": self.as_ref(),
+                "message": self.to_string(),
+                "errors": errors,
+            })),
+            _ => Json(json!({
+                "error": self.as_ref(),
+                "message": self.to_string(),
+            })),
         };
-        let body = Json(json!({
-            "error": self.as_ref(),
-            "message": self.to_string(),
-        }));
         (status, body).into_response()
     }
 }
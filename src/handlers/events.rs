@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use super::error::AppError;
+use super::types::*;
+use super::Service;
+use crate::{
+    service::events::TodoEvent,
+    storage::{Session, User},
+    utils::RootSpan,
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use futures_util::{Stream, StreamExt};
+use tracing::{error, info};
+
+const LAST_EVENT_ID: &str = "last-event-id";
+/// Idle connections get a comment this often so proxies keep them open.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The `Last-Event-ID` header sent by reconnecting `EventSource`s wins over the query.
+fn last_event_id(headers: &HeaderMap, query: &EventsQuery) -> Result<Option<u64>, AppError> {
+    match headers.get(LAST_EVENT_ID) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .map(Some)
+            .ok_or(AppError::InvalidLastEventId),
+        None => Ok(query.last_event_id),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/events",
+    params(
+        ("last_event_id" = Option<u64>, Query, description = "Resume after this event, the `Last-Event-ID` header takes precedence"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last event received before reconnecting")
+    ),
+    responses(
+        (status = 200, description = "Stream of `created`, `updated` and `deleted` events of the user's todos, preceded by a `reset` when missed events are no longer buffered", body = TodoEvent, content_type = "text/event-stream"),
+        (status = 400, description = "Invalid last event id"),
+        (status = 401, description = "Unauthorized"),
+    ),
+    security(("BearerAuth" = [])),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::events::sse", skip_all)]
+pub(crate) async fn sse(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let last_event_id = last_event_id(&headers, &query)?;
+    let events = service.todo().subscribe(&user, last_event_id).map(|event| {
+        Event::default()
+            .id(event.id.to_string())
+            .event(event.kind.as_str())
+            .json_data(&event)
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/ws",
+    params(
+        ("last_event_id" = Option<u64>, Query, description = "Resume after this event, the `Last-Event-ID` header takes precedence"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last event received before reconnecting")
+    ),
+    responses(
+        (status = 101, description = "WebSocket sending every event of the user's todos as a JSON text message", body = TodoEvent),
+        (status = 400, description = "Invalid last event id or not a WebSocket upgrade"),
+        (status = 401, description = "Unauthorized"),
+    ),
+    security(("BearerAuth" = [])),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::events::websocket", skip_all)]
+pub(crate) async fn websocket(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let last_event_id = last_event_id(&headers, &query)?;
+    // subscribed before the upgrade so nothing published during the handshake is missed
+    let events = service.todo().subscribe(&user, last_event_id);
+
+    Ok(upgrade.on_upgrade(|socket| forward_events(socket, events)))
+}
+
+/// Sends events until the client leaves or falls behind, in which case the
+/// socket is closed and the client reconnects with its last event id.
+async fn forward_events(
+    mut socket: WebSocket,
+    events: impl Stream<Item = TodoEvent> + Send + 'static,
+) {
+    let mut events = std::pin::pin!(events);
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        error!(error = ?e, "failed to serialize todo event");
+                        break;
+                    }
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                // pings are answered by axum, anything else from the client is ignored
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    info!("closing todo event socket");
+    let _ = socket.send(Message::Close(None)).await;
+}
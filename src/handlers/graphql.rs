@@ -0,0 +1,55 @@
+use crate::{
+    graphql::TodoSchema,
+    storage::{Session, User},
+    utils::RootSpan,
+};
+use async_graphql::http::GraphiQLSource;
+use axum::{
+    response::{Html, IntoResponse},
+    Extension, Json,
+};
+
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    security(("BearerAuth" = [])),
+    request_body(
+        content = Object,
+        description = "GraphQL request: `query`, optional `variables` and `operationName`",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "GraphQL response, failed fields are listed in `errors`", body = Object),
+        (status = 401, description = "Unauthorized"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "graphql"
+)]
+#[tracing::instrument(name = "handlers::graphql::execute", skip_all)]
+pub(crate) async fn execute(
+    Extension(schema): Extension<TodoSchema>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Json(request): Json<async_graphql::Request>,
+) -> impl IntoResponse {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    Json(schema.execute(request.data(user)).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    responses(
+        (status = 200, description = "GraphiQL IDE, set the `Authorization` header in its headers tab", content_type = "text/html"),
+    ),
+    tag = "graphql"
+)]
+#[tracing::instrument(name = "handlers::graphql::graphiql", skip_all)]
+pub(crate) async fn graphiql() -> impl IntoResponse {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
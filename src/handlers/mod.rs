@@ -1,8 +1,13 @@
 pub(crate) mod admin;
 pub(crate) mod auth;
+pub(crate) mod calendar;
 pub(crate) mod error;
+pub(crate) mod events;
+pub(crate) mod graphql;
+pub(crate) mod sync;
 pub(crate) mod todo;
 pub mod types;
+pub(crate) mod webhooks;
 
 pub(crate) use crate::service::Service;
 use axum::{http::StatusCode, response::IntoResponse};
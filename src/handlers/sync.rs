@@ -0,0 +1,106 @@
+use super::error::AppError;
+use super::types::*;
+use super::Service;
+use crate::{
+    storage::{Pagination, Session, User},
+    utils::RootSpan,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use tracing::info;
+
+/// Changes returned by one pull, clients keep pulling while `has_more` is set.
+const SYNC_PAGE_SIZE: usize = 500;
+/// Upper bound of client changes accepted in one push.
+const MAX_SYNC_CHANGES: usize = 100;
+
+#[utoipa::path(
+    get,
+    path = "/sync",
+    params(
+        ("since" = Option<u64>, Query, description = "Token returned by the previous sync")
+    ),
+    responses(
+        (status = 200, description = "Todos changed since the token, deleted ones without `todo`", body = SyncResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    security(("BearerAuth" = [])),
+    tag = "sync"
+)]
+#[tracing::instrument(name = "handlers::sync::pull", skip_all)]
+pub(crate) async fn pull(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<SyncQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let page = Pagination {
+        after: query.since,
+        limit: SYNC_PAGE_SIZE,
+    };
+    let (changes, cursor) = service.todo().get_changes(&user, page).await?;
+    let token = changes
+        .last()
+        .map(|change| change.rev)
+        .or(query.since)
+        .unwrap_or_default();
+
+    info!(count = changes.len(), token = %token, "pulled todo changes");
+
+    Ok(Json(SyncResponse {
+        changes,
+        token,
+        has_more: cursor.is_some(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sync",
+    security(("BearerAuth" = [])),
+    request_body(
+        content = SyncRequest,
+        description = "Changes made on the client, applied in a single transaction",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "Outcome of every change, conflicting ones are not applied", body = SyncPushResponse),
+        (status = 400, description = "Invalid number of changes"),
+        (status = 401, description = "Unauthorized"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "sync"
+)]
+#[tracing::instrument(name = "handlers::sync::push", skip_all)]
+pub(crate) async fn push(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Json(input): Json<SyncRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    if input.changes.is_empty() || input.changes.len() > MAX_SYNC_CHANGES {
+        return Err(AppError::InvalidBatchSize(MAX_SYNC_CHANGES));
+    }
+
+    let results = service.todo().apply_changes(&user, input.changes).await?;
+
+    info!(count = results.len(), "applied client changes");
+
+    Ok(Json(SyncPushResponse { results }))
+}
@@ -2,12 +2,14 @@ use super::error::AppError;
 use super::types::*;
 use crate::{
     handlers::Service,
-    storage::{Session, Todo, TodoId, User},
+    service::transfer::TransferFormat,
+    storage::{HistorySeq, Session, TodoId, UndoResult, User},
     utils::RootSpan,
 };
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::IntoResponse,
     Extension, Json,
 };
@@ -51,6 +53,147 @@ pub(crate) async fn get_all(
     Ok(Json(TodosPageResponse { items, cursor }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/todos/trash",
+    params(
+        ("after" = Option<Uuid>, Query, description = "Cursor ID"),
+        ("limit" = usize, Query, description = "Page size")
+    ),
+    responses(
+        (status = 200, description = "List deleted todos which can still be restored", body = TrashPageResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    security(("BearerAuth" = [])),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::get_trash", skip_all)]
+pub(crate) async fn get_trash(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    params: PaginationParams<TodoId>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    info!(pagination_params = ?params, "get trashed todos");
+
+    let (items, cursor) = service.todo().get_trash(&user, params.into()).await?;
+
+    Ok(Json(TrashPageResponse { items, cursor }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/undo",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 200, description = "Latest todo operation reverted", body = UndoResult),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Nothing to undo"),
+    ),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::undo", skip_all)]
+pub(crate) async fn undo(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let result = service.todo().undo(&user).await?;
+
+    info!(operation = ?result.operation, count = result.count, "undone todo operation");
+
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/history",
+    params(
+        ("id" = String, Path, description = "ToDo ID"),
+        ("after" = Option<u64>, Query, description = "Cursor sequence number"),
+        ("limit" = usize, Query, description = "Page size")
+    ),
+    responses(
+        (status = 200, description = "List recorded changes of the todo, oldest first", body = HistoryPageResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    security(("BearerAuth" = [])),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::get_history", skip_all)]
+pub(crate) async fn get_history(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<TodoId>,
+    params: PaginationParams<HistorySeq>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id)
+        .todo_id(&id);
+
+    info!(pagination_params = ?params, "get todo history");
+
+    let (items, cursor) = service.todo().get_history(&user, id, params.into()).await?;
+
+    Ok(Json(HistoryPageResponse { items, cursor }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/restore",
+    security(("BearerAuth" = [])),
+    params(
+        ("id" = String, Path, description = "ToDo ID")
+    ),
+    responses(
+        (status = 200, description = "ToDo restored from the trash"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "ToDo not found in the trash"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::restore", skip_all)]
+pub(crate) async fn restore(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<TodoId>,
+) -> Result<(), AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id)
+        .todo_id(&id);
+
+    service.todo().restore(&user, id).await?;
+
+    Ok(())
+}
+
 #[utoipa::path(
     get,
     path = "/todos/{id}",
@@ -59,7 +202,7 @@ pub(crate) async fn get_all(
         ("id" = String, Path, description = "ToDo ID")
     ),
     responses(
-        (status = 200, description = "Get ToDo by ID", body = Todo),
+        (status = 200, description = "Get ToDo by ID with its dependencies", body = TodoDetails),
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
         (status = 404, description = "ToDo not found"),
@@ -82,10 +225,11 @@ pub(crate) async fn get(
         .todo_id(&id);
 
     let todo = service.todo().get(&user, id).await?;
+    let links = service.todo().get_links(&user, id).await?;
 
-    tracing::info!(todo = ?todo, "Get ToDo");
+    tracing::info!(todo = ?todo, links = ?links, "Get ToDo");
 
-    Ok(Json(todo))
+    Ok(Json(TodoDetails { todo, links }))
 }
 
 #[utoipa::path(
@@ -119,7 +263,7 @@ pub(crate) async fn add(
         .enduser_id(&user.id)
         .session_id(&session.id);
 
-    match service.todo().add(&user, &input.text).await {
+    match service.todo().add(&user, &input.text, input.due).await {
         Ok(id) => {
             root_span.record().todo_id(&id);
             Ok((StatusCode::CREATED, Json(id)))
@@ -149,6 +293,7 @@ pub(crate) async fn add(
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
         (status = 404, description = "ToDo not found"),
+        (status = 409, description = "ToDo is blocked by unfinished ToDos"),
         (status = 422, description = "Unprocessable Entity"),
     ),
     tag = "todos"
@@ -168,7 +313,7 @@ pub(crate) async fn update(
         .session_id(&session.id)
         .todo_id(&id);
 
-    if input.completed.is_none() && input.text.is_none() && input.group.is_none() {
+    if input.is_empty() {
         return Err(AppError::EmptyPatch);
     }
     service.todo().update(&user, id, &input).await?;
@@ -176,12 +321,200 @@ pub(crate) async fn update(
     Ok(())
 }
 
+/// Upper bound of operations accepted in one batch request.
+const MAX_BATCH_OPERATIONS: usize = 100;
+
+#[utoipa::path(
+    post,
+    path = "/todos/batch",
+    security(("BearerAuth" = [])),
+    request_body(
+        content = BatchRequest,
+        description = "Operations applied in a single transaction, all or none of them",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "All operations applied", body = BatchResponse),
+        (status = 400, description = "Invalid batch size or empty patch"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "ToDo of an operation not found, nothing applied"),
+        (status = 409, description = "ToDo of an operation is blocked, nothing applied"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::batch", skip_all)]
+pub(crate) async fn batch(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Json(input): Json<BatchRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    if input.operations.is_empty() || input.operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(AppError::InvalidBatchSize(MAX_BATCH_OPERATIONS));
+    }
+    for (index, op) in input.operations.iter().enumerate() {
+        if matches!(op, BatchOperation::Update { patch, .. } if patch.is_empty()) {
+            return Err(AppError::BatchOperation {
+                index,
+                source: Box::new(AppError::EmptyPatch),
+            });
+        }
+    }
+
+    let results = service.todo().batch(&user, &input.operations).await?;
+
+    info!(count = results.len(), "applied batch of todo operations");
+
+    Ok(Json(BatchResponse { results }))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/todos",
+    security(("BearerAuth" = [])),
+    params(
+        ("group" = String, Query, description = "Group whose ToDos are updated")
+    ),
+    request_body(
+        content = UpdateTodo,
+        description = "Partial update applied to every ToDo of the group",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "ToDos of the group updated", body = GroupUpdateResponse),
+        (status = 400, description = "Empty patch or missing group"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 409, description = "A ToDo of the group is blocked, nothing updated"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::update_group", skip_all)]
+pub(crate) async fn update_group(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<GroupQuery>,
+    Json(input): Json<UpdateTodo>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    if input.is_empty() {
+        return Err(AppError::EmptyPatch);
+    }
+    let updated = service
+        .todo()
+        .update_group(&user, &query.group, &input)
+        .await?;
+
+    Ok(Json(GroupUpdateResponse { updated }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/export",
+    params(
+        ("format" = TransferFormat, Query, description = "File format of the export")
+    ),
+    responses(
+        (status = 200, description = "All todos of the user as a file", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+            (String = "text/markdown"),
+            (String = "text/plain"),
+        )),
+        (status = 400, description = "Unknown format"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+    security(("BearerAuth" = [])),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::export", skip_all)]
+pub(crate) async fn export(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<TransferQuery>,
+) -> impl IntoResponse {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let body = Body::from_stream(service.todo().export(&user, query.format));
+    let disposition = format!("attachment; filename=\"{}\"", query.format.file_name());
+
+    (
+        [
+            (header::CONTENT_TYPE, query.format.content_type().to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/import",
+    params(
+        ("format" = TransferFormat, Query, description = "File format of the request body")
+    ),
+    request_body(
+        content = String,
+        description = "File in the given format, imported only if every line is valid",
+        content_type = "text/plain"
+    ),
+    responses(
+        (status = 200, description = "All todos of the file added", body = ImportResponse),
+        (status = 400, description = "Unknown format or invalid lines, nothing added"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+    ),
+    security(("BearerAuth" = [])),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::import", skip_all)]
+pub(crate) async fn import(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Query(query): Query<TransferQuery>,
+    input: String,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let imported = service.todo().import(&user, query.format, &input).await?;
+
+    info!(imported, "imported todos");
+
+    Ok(Json(ImportResponse { imported }))
+}
+
 #[utoipa::path(
     delete,
     path = "/todos",
     security(("BearerAuth" = [])),
     responses(
-        (status = 200, description = "All user ToDos deleted"),
+        (status = 200, description = "All user ToDos moved to the trash"),
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
         (status = 422, description = "Unprocessable Entity"),
@@ -214,7 +547,7 @@ pub(crate) async fn delete_all(
         ("id" = String, Path, description = "ToDo ID")
     ),
     responses(
-        (status = 200, description = "ToDo deleted"),
+        (status = 200, description = "ToDo moved to the trash"),
         (status = 204, description = "ToDo not found"),
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
@@ -240,3 +573,84 @@ pub(crate) async fn delete(
 
     Ok(())
 }
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/blocked_by",
+    security(("BearerAuth" = [])),
+    params(
+        ("id" = String, Path, description = "ToDo ID")
+    ),
+    request_body(
+        content = AddBlocker,
+        description = "ToDo that blocks this one",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "Blocker added"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "ToDo or blocker not found"),
+        (status = 409, description = "Link would create a cycle"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::add_blocker", skip_all)]
+pub(crate) async fn add_blocker(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<TodoId>,
+    Json(input): Json<AddBlocker>,
+) -> Result<(), AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id)
+        .todo_id(&id);
+
+    service
+        .todo()
+        .add_blocker(&user, id, input.blocker_id)
+        .await?;
+
+    Ok(())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/blocked_by/{blocker_id}",
+    security(("BearerAuth" = [])),
+    params(
+        ("id" = String, Path, description = "ToDo ID"),
+        ("blocker_id" = String, Path, description = "Blocking ToDo ID")
+    ),
+    responses(
+        (status = 200, description = "Blocker removed"),
+        (status = 204, description = "Link not found"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "todos"
+)]
+#[tracing::instrument(name = "handlers::todo::remove_blocker", skip_all)]
+pub(crate) async fn remove_blocker(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Path((id, blocker_id)): Path<(TodoId, TodoId)>,
+) -> Result<(), AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id)
+        .todo_id(&id);
+
+    service.todo().remove_blocker(&user, id, blocker_id).await?;
+
+    Ok(())
+}
@@ -8,7 +8,12 @@ use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 use utoipa::ToSchema;
 
-use crate::storage::{Role, StorageError, Todo, TodoId, User, UserId};
+use crate::service::{maintenance::BackupFile, transfer::TransferFormat};
+use crate::storage::{
+    BackupManifest, Delivery, DeliveryId, HistoryEntry, HistorySeq, Role, StorageError,
+    StorageReport, StorageStats, SyncChange, SyncResult, SyncSeq, Todo, TodoId, TodoLinks,
+    TodoOpResult, TrashedTodo, User, UserId, Webhook, WebhookEventKind, WebhookId,
+};
 
 #[derive(Debug, Deserialize, ToSchema)]
 pub(crate) struct RegisterUser {
@@ -31,6 +36,18 @@ pub(crate) struct LoginToken {
 #[derive(Debug, Deserialize, ToSchema)]
 pub(crate) struct CreateTodo {
     pub text: String,
+    /// Unix timestamp (seconds) the todo is due at.
+    #[serde(default)]
+    pub due: Option<i64>,
+}
+
+/// Tells an explicit `null` (`Some(None)`) apart from a missing field (`None`).
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 #[derive(Debug, Deserialize, ToSchema)]
@@ -38,6 +55,215 @@ pub(crate) struct UpdateTodo {
     pub text: Option<String>,
     pub completed: Option<bool>,
     pub group: Option<String>,
+    /// Unix timestamp (seconds), `null` removes the due date.
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i64>)]
+    pub due: Option<Option<i64>>,
+    /// Allows completing a todo that still has open blockers.
+    #[serde(default)]
+    pub ignore_blockers: bool,
+}
+
+impl UpdateTodo {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.completed.is_none()
+            && self.text.is_none()
+            && self.group.is_none()
+            && self.due.is_none()
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum BatchOperation {
+    Create {
+        text: String,
+        #[serde(default)]
+        due: Option<i64>,
+    },
+    Update {
+        #[schema(value_type = String)]
+        id: TodoId,
+        #[serde(flatten)]
+        patch: UpdateTodo,
+    },
+    Delete {
+        #[schema(value_type = String)]
+        id: TodoId,
+    },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BatchResponse {
+    pub results: Vec<TodoOpResult>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GroupQuery {
+    pub group: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct GroupUpdateResponse {
+    pub updated: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TransferQuery {
+    pub format: TransferFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ImportResponse {
+    pub imported: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CalendarTokenResponse {
+    /// Shown only once, a lost token has to be replaced by a new one.
+    pub token: String,
+    /// Path of the iCalendar feed to subscribe to.
+    pub feed: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct CreateWebhook {
+    /// Endpoint receiving the events, `http` or `https`.
+    pub url: String,
+    /// `user.*` events are only available to admins.
+    pub events: Vec<WebhookEventKind>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct WebhookResponse {
+    #[schema(value_type = String)]
+    pub id: WebhookId,
+    pub url: String,
+    pub events: Vec<WebhookEventKind>,
+    pub created_at: i64,
+}
+
+impl From<Webhook> for WebhookResponse {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CreatedWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: WebhookResponse,
+    /// Key of the `X-Webhook-Signature` HMAC, shown only once.
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct DeadLetter {
+    #[schema(value_type = u64)]
+    pub id: DeliveryId,
+    #[schema(value_type = String)]
+    pub webhook_id: WebhookId,
+    pub event_id: u64,
+    pub event: WebhookEventKind,
+    pub occurred_at: i64,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+impl From<Delivery> for DeadLetter {
+    fn from(delivery: Delivery) -> Self {
+        Self {
+            id: delivery.id,
+            webhook_id: delivery.webhook_id,
+            event_id: delivery.event.id,
+            event: delivery.event.kind,
+            occurred_at: delivery.event.occurred_at,
+            attempts: delivery.attempts,
+            last_error: delivery.last_error,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct DeadLettersPageResponse {
+    pub items: Vec<DeadLetter>,
+    #[schema(value_type = u64)]
+    pub cursor: Option<DeliveryId>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct EventsQuery {
+    /// For clients which can't set the `Last-Event-ID` header.
+    pub last_event_id: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SyncQuery {
+    /// Token of the previous sync, all todos are returned without it.
+    pub since: Option<SyncSeq>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SyncResponse {
+    pub changes: Vec<SyncChange>,
+    /// Pass as `since` to the next sync.
+    #[schema(value_type = u64)]
+    pub token: SyncSeq,
+    /// More changes are waiting, sync again with the new token.
+    pub has_more: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum SyncOperation {
+    /// Creates the todo when `base_rev` is missing, replaces it otherwise.
+    Upsert {
+        #[serde(flatten)]
+        todo: Todo,
+        #[serde(default)]
+        #[schema(value_type = Option<u64>)]
+        base_rev: Option<SyncSeq>,
+    },
+    Delete {
+        #[schema(value_type = String)]
+        id: TodoId,
+        #[schema(value_type = u64)]
+        base_rev: SyncSeq,
+    },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct SyncRequest {
+    pub changes: Vec<SyncOperation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SyncPushResponse {
+    pub results: Vec<SyncResult>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct AddBlocker {
+    #[schema(value_type = String)]
+    pub blocker_id: TodoId,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TodoDetails {
+    #[serde(flatten)]
+    pub todo: Todo,
+    #[serde(flatten)]
+    pub links: TodoLinks,
 }
 
 #[derive(Debug, Deserialize, ToSchema)]
@@ -59,6 +285,20 @@ pub struct TodosPageResponse {
     pub cursor: Option<TodoId>,
 }
 
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TrashPageResponse {
+    pub items: Vec<TrashedTodo>,
+    #[schema(value_type = String)]
+    pub cursor: Option<TodoId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct HistoryPageResponse {
+    pub items: Vec<HistoryEntry>,
+    #[schema(value_type = u64)]
+    pub cursor: Option<HistorySeq>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
 pub struct DisplayUser {
     #[schema(value_type = String)]
@@ -75,6 +315,171 @@ pub struct UsersPageResponse {
     pub cursor: Option<UserId>,
 }
 
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BackupResponse {
+    /// File name in the backup directory.
+    pub name: String,
+    pub created_at: i64,
+    pub records: u64,
+    pub trees: Vec<BackupTreeResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BackupTreeResponse {
+    pub name: String,
+    pub records: u64,
+    /// CRC32 of the keys and values of the tree.
+    pub checksum: u32,
+}
+
+impl BackupResponse {
+    pub(crate) fn new(name: String, manifest: BackupManifest) -> Self {
+        Self {
+            name,
+            created_at: manifest.created_at,
+            records: manifest.records(),
+            trees: manifest
+                .trees
+                .into_iter()
+                .map(|tree| BackupTreeResponse {
+                    name: tree.name,
+                    records: tree.records,
+                    checksum: tree.checksum,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BackupFileResponse {
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+impl From<BackupFile> for BackupFileResponse {
+    fn from(file: BackupFile) -> Self {
+        Self {
+            name: file.name,
+            size: file.size,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct FsckResponse {
+    pub trees: Vec<FsckTreeResponse>,
+    pub problems: Vec<FsckProblemResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct FsckTreeResponse {
+    pub name: String,
+    pub records: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct FsckProblemResponse {
+    pub tree: String,
+    pub key: String,
+    /// `malformed_key`, `undecodable_value`, `missing_email`, `dangling_email`,
+    /// `taken_email`, `orphan_todo`, `orphan_session`, `orphan_usage` or
+    /// `wrong_usage`.
+    pub kind: String,
+    pub detail: String,
+    pub repaired: bool,
+}
+
+impl From<StorageReport> for FsckResponse {
+    fn from(report: StorageReport) -> Self {
+        Self {
+            trees: report
+                .records
+                .into_iter()
+                .map(|(name, records)| FsckTreeResponse {
+                    name: name.to_string(),
+                    records,
+                })
+                .collect(),
+            problems: report
+                .problems
+                .into_iter()
+                .map(|problem| FsckProblemResponse {
+                    tree: problem.tree.to_string(),
+                    key: problem.key,
+                    kind: problem.kind.as_ref().to_string(),
+                    detail: problem.detail,
+                    repaired: problem.repaired,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct StorageStatsResponse {
+    /// Unix timestamp the stats were collected at.
+    pub collected_at: i64,
+    pub size_on_disk: u64,
+    pub trees: Vec<FsckTreeResponse>,
+    /// Users with the most todos, most first.
+    pub top_users: Vec<TopUserResponse>,
+    /// Users by how many todos they have.
+    pub todos_per_user: Vec<TodoBucketResponse>,
+    pub active_sessions: usize,
+    pub expired_sessions: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TopUserResponse {
+    #[schema(value_type = String)]
+    pub user_id: UserId,
+    pub todos: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TodoBucketResponse {
+    /// Most todos a user of the bucket has, missing for the last bucket.
+    pub up_to: Option<usize>,
+    pub users: usize,
+}
+
+impl From<StorageStats> for StorageStatsResponse {
+    fn from(stats: StorageStats) -> Self {
+        Self {
+            collected_at: stats.collected_at,
+            size_on_disk: stats.size_on_disk,
+            trees: stats
+                .records
+                .into_iter()
+                .map(|(name, records)| FsckTreeResponse {
+                    name: name.to_string(),
+                    records,
+                })
+                .collect(),
+            top_users: stats
+                .top_users
+                .into_iter()
+                .map(|(user_id, todos)| TopUserResponse { user_id, todos })
+                .collect(),
+            todos_per_user: stats
+                .todos_per_user
+                .into_iter()
+                .map(|bucket| TodoBucketResponse {
+                    up_to: bucket.up_to,
+                    users: bucket.users,
+                })
+                .collect(),
+            active_sessions: stats.active_sessions,
+            expired_sessions: stats.expired_sessions,
+        }
+    }
+}
+
+/// Largest page a client can ask for, over REST, gRPC and GraphQL.
+pub const MAX_PAGE_SIZE: usize = 100;
+
 #[derive(Debug, Deserialize)]
 struct RawPagination<Id> {
     after: Option<Id>,
@@ -97,6 +502,14 @@ where
             (axum::http::StatusCode::BAD_REQUEST, "Invalid input")
         })?;
 
+        if raw.limit.is_some_and(|limit| limit > MAX_PAGE_SIZE) {
+            error!(limit = raw.limit, "Page size over the maximum");
+            return Err((
+                axum::http::StatusCode::BAD_REQUEST,
+                "`limit` must be at most 100",
+            ));
+        }
+
         let after_is_some = raw.after.is_some();
         match (raw.after, raw.limit) {
             (Some(after), Some(limit)) => Ok(PaginationParams::NextPage { after, limit }),
@@ -0,0 +1,190 @@
+use super::error::AppError;
+use super::types::*;
+use super::Service;
+use crate::{
+    config::Settings,
+    storage::{DeliveryId, Session, User, WebhookId},
+    utils::RootSpan,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use tracing::info;
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    security(("BearerAuth" = [])),
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, description = "Webhook registered, the secret signs its deliveries", body = CreatedWebhookResponse),
+        (status = 400, description = "Invalid url or events, url on a private address, or too many webhooks"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "User events requested without Admin role"),
+    ),
+    tag = "webhooks"
+)]
+#[tracing::instrument(name = "handlers::webhooks::create", skip_all)]
+pub(crate) async fn create(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Extension(settings): Extension<Settings>,
+    Json(payload): Json<CreateWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let webhook = service
+        .webhook()
+        .register(&user, payload, &settings.webhooks)
+        .await?;
+    let secret = webhook.secret.clone();
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedWebhookResponse {
+            webhook: webhook.into(),
+            secret,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    security(("BearerAuth" = [])),
+    responses(
+        (status = 200, description = "Webhooks of the user", body = Vec<WebhookResponse>),
+        (status = 401, description = "Unauthorized"),
+    ),
+    tag = "webhooks"
+)]
+#[tracing::instrument(name = "handlers::webhooks::get_all", skip_all)]
+pub(crate) async fn get_all(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    let webhooks = service.webhook().get_all(&user).await?;
+
+    Ok(Json(
+        webhooks
+            .into_iter()
+            .map(WebhookResponse::from)
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    security(("BearerAuth" = [])),
+    params(
+        ("id" = String, Path, description = "Webhook ID")
+    ),
+    responses(
+        (status = 200, description = "Webhook removed, its pending deliveries are dropped"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Webhook not found"),
+    ),
+    tag = "webhooks"
+)]
+#[tracing::instrument(name = "handlers::webhooks::delete", skip_all)]
+pub(crate) async fn delete(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<WebhookId>,
+) -> Result<(), AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    service.webhook().delete(&user, id).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/dead_letters",
+    security(("BearerAuth" = [])),
+    params(
+        ("after" = Option<u64>, Query, description = "Cursor dead letter ID"),
+        ("limit" = usize, Query, description = "Page size")
+    ),
+    responses(
+        (status = 200, description = "Deliveries which ran out of attempts", body = DeadLettersPageResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 422, description = "Unprocessable Entity"),
+    ),
+    tag = "webhooks"
+)]
+#[tracing::instrument(name = "handlers::webhooks::get_dead_letters", skip_all)]
+pub(crate) async fn get_dead_letters(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    params: PaginationParams<DeliveryId>,
+) -> Result<impl IntoResponse, AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    info!(pagination_params = ?params, "get dead letters");
+
+    let (items, cursor) = service
+        .webhook()
+        .get_dead_letters(&user, params.into())
+        .await?;
+
+    Ok(Json(DeadLettersPageResponse {
+        items: items.into_iter().map(DeadLetter::from).collect(),
+        cursor,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks/dead_letters/{id}/retry",
+    security(("BearerAuth" = [])),
+    params(
+        ("id" = u64, Path, description = "Dead letter ID")
+    ),
+    responses(
+        (status = 200, description = "Delivery scheduled again with fresh attempts"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Dead letter not found"),
+    ),
+    tag = "webhooks"
+)]
+#[tracing::instrument(name = "handlers::webhooks::retry_dead_letter", skip_all)]
+pub(crate) async fn retry_dead_letter(
+    State(service): State<Service>,
+    Extension(root_span): Extension<RootSpan>,
+    Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<DeliveryId>,
+) -> Result<(), AppError> {
+    root_span
+        .record()
+        .enduser_id(&user.id)
+        .session_id(&session.id);
+
+    service.webhook().retry_dead_letter(&user, id).await
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::{info, info_span, Instrument};
+
+use crate::{
+    config::types::{BackupConfig, StatsConfig, TrashConfig, WebhookConfig},
+    service::{webhook, Service},
+};
+
+/// Periodically removes todos that outlived the trash retention.
+pub fn spawn_trash_purge(service: Service, config: &TrashConfig) -> JoinHandle<()> {
+    let retention_sec = config.retention_sec;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.purge_interval_sec));
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let result = service
+                .todo()
+                .purge_trash(retention_sec)
+                .instrument(info_span!("trash_purge_job"))
+                .await;
+
+            match result {
+                Ok(count) => info!(count, "trash purge finished"),
+                Err(e) => tracing::error!(error = ?e, "trash purge failed"),
+            }
+        }
+    })
+}
+
+/// Periodically writes a backup archive into the backup directory.
+pub fn spawn_scheduled_backup(service: Service, config: &BackupConfig) -> JoinHandle<()> {
+    let config = config.clone();
+    let period = Duration::from_secs(config.interval_sec);
+    // restarts shouldn't each leave a new archive behind
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let result = service
+                .maintenance()
+                .create_backup(&config)
+                .instrument(info_span!("scheduled_backup_job"))
+                .await;
+
+            match result {
+                Ok((name, manifest)) => {
+                    info!(
+                        name,
+                        records = manifest.records(),
+                        "scheduled backup finished"
+                    )
+                }
+                Err(e) => tracing::error!(error = ?e, "scheduled backup failed"),
+            }
+        }
+    })
+}
+
+/// Periodically collects storage statistics for the gauges and the admin api.
+pub fn spawn_storage_stats(service: Service, config: &StatsConfig) -> JoinHandle<()> {
+    let config = config.clone();
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_sec));
+    // every run scans all trees, missed ones aren't worth catching up
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let result = service
+                .maintenance()
+                .collect_stats(&config)
+                .instrument(info_span!("storage_stats_job"))
+                .await;
+
+            match result {
+                Ok(stats) => info!(size_on_disk = stats.size_on_disk, "storage stats collected"),
+                Err(e) => tracing::error!(error = ?e, "storage stats collection failed"),
+            }
+        }
+    })
+}
+
+/// Periodically turns outbox events into deliveries and sends the due ones.
+pub fn spawn_webhook_dispatcher(service: Service, config: &WebhookConfig) -> JoinHandle<()> {
+    let config = config.clone();
+    let client = webhook::client(&config);
+    let mut interval = tokio::time::interval(Duration::from_millis(config.poll_interval_ms));
+    // a slow round shouldn't be followed by a burst of catch up rounds
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let result = service
+                .webhook()
+                .dispatch(&client, &config)
+                .instrument(info_span!("webhook_dispatch_job"))
+                .await;
+
+            match result {
+                Ok(0) => {}
+                Ok(count) => info!(count, "webhook dispatch finished"),
+                Err(e) => tracing::error!(error = ?e, "webhook dispatch failed"),
+            }
+        }
+    })
+}
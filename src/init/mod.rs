@@ -1,9 +1,13 @@
+mod jobs;
 mod observability;
 mod storage;
 
 use crate::{handlers::error::AppError, storage::SledStartupError};
 use thiserror::Error;
 
+pub use jobs::{
+    spawn_scheduled_backup, spawn_storage_stats, spawn_trash_purge, spawn_webhook_dispatcher,
+};
 pub use observability::{init_metrics_provider, init_tracer_provider};
 pub use storage::init_storage;
 
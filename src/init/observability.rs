@@ -17,6 +17,7 @@ use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 use crate::config::Settings;
 use crate::utils::blocking_task_guard::init_blocking_tasks_metric;
 use crate::utils::measure_metrics::init_memory_metrics;
+use crate::utils::storage_metrics::init_storage_metrics;
 use crate::utils::APP_NAME;
 
 use super::StartupError;
@@ -82,6 +83,7 @@ pub fn init_metrics_provider(settings: &Settings) -> Result<SdkMeterProvider, St
 
     init_memory_metrics();
     init_blocking_tasks_metric();
+    init_storage_metrics();
 
     Ok(provider)
 }
@@ -1,7 +1,9 @@
 use crate::{
     config::types::StorageKind,
     service::Service,
-    storage::{FlushStorage, SessionStorage, TodoStorage, UserStorage},
+    storage::{
+        FlushStorage, MaintenanceStorage, SessionStorage, TodoStorage, UserStorage, WebhookStorage,
+    },
     Settings,
 };
 use std::sync::Arc;
@@ -32,8 +34,11 @@ pub async fn init_storage(settings: &Settings) -> Result<Service, StartupError>
                 sled_storage.clone() as Arc<dyn UserStorage>,
                 sled_storage.clone() as Arc<dyn SessionStorage>,
                 sled_storage.clone() as Arc<dyn FlushStorage>,
+                sled_storage.clone() as Arc<dyn WebhookStorage>,
+                sled_storage.clone() as Arc<dyn MaintenanceStorage>,
             )
             .await
+            .with_limits(settings.limits.clone())
         }
         kind => {
             return Err(StartupError::UnsupportedStorage(kind.as_ref().to_string()));
@@ -1,8 +1,11 @@
 mod app;
 mod config;
+mod graphql;
+mod grpc;
 pub(crate) mod handlers;
 mod init;
 pub(crate) mod middleware;
+pub mod ops;
 pub(crate) mod service;
 pub(crate) mod storage;
 pub(crate) mod utils;
@@ -10,33 +13,62 @@ pub(crate) mod utils;
 mod docs;
 
 pub use config::Settings;
+pub use grpc::proto;
 pub use handlers::error::AppError;
 pub use init::StartupError;
 
 use axum::Router;
 use opentelemetry_sdk::{metrics::SdkMeterProvider, trace::SdkTracerProvider};
+use tonic::service::Routes;
 
 #[cfg(feature = "integration_tests")]
 pub use app::build_app;
 
+#[cfg(feature = "integration_tests")]
+pub use grpc::build_grpc;
+
 #[cfg(feature = "integration_tests")]
 pub use init::init_storage;
 
 #[cfg(feature = "integration_tests")]
-pub use storage::{Session, SessionId, Todo, TodoId, User, UserId};
+pub use config::types::{LimitsConfig, WebhookConfig};
+
+#[cfg(feature = "integration_tests")]
+pub use init::spawn_webhook_dispatcher;
+
+#[cfg(feature = "integration_tests")]
+pub use storage::{
+    HistoryAction, Session, SessionId, SyncChange, SyncResult, SyncSeq, Todo, TodoId, TodoOpResult,
+    UndoOperation, UndoResult, User, UserId, WebhookEventKind,
+};
 
 #[cfg(feature = "integration_tests")]
-pub use service::Service;
+pub use service::{
+    events::{TodoEvent, TodoEventKind},
+    transfer::ImportLineError,
+    Service,
+};
 
 #[cfg(feature = "integration_tests")]
 pub use storage::test_util::TestStorageBuilder;
 
 #[cfg(feature = "integration_tests")]
-pub use handlers::types::{TodosPageResponse, UsersPageResponse};
+pub use storage::{Fault, Faults};
+
+#[cfg(feature = "integration_tests")]
+pub use handlers::types::{
+    BackupFileResponse, BackupResponse, BatchResponse, CalendarTokenResponse,
+    CreatedWebhookResponse, DeadLettersPageResponse, FsckResponse, GroupUpdateResponse,
+    HistoryPageResponse, ImportResponse, StorageStatsResponse, SyncPushResponse, SyncResponse,
+    TodoDetails, TodosPageResponse, TrashPageResponse, UsersPageResponse, WebhookResponse,
+};
 
 #[cfg(feature = "integration_tests")]
 pub use middleware::auth::AuthError;
 
+#[cfg(feature = "integration_tests")]
+pub use middleware::rate_limiter::RateLimiters;
+
 #[cfg(feature = "integration_tests")]
 pub use init::init_tracer_provider;
 use tracing::{info, instrument};
@@ -78,10 +110,22 @@ impl Drop for MetricsProviderGuard {
 }
 
 #[instrument(name = "init_app", skip_all)]
-pub async fn init_app(settings: Settings) -> Result<(Router, service::Service), StartupError> {
+pub async fn init_app(
+    settings: Settings,
+) -> Result<(Router, Routes, service::Service), StartupError> {
     info!(settings = ?settings, "init_app with settings");
 
     let service = init::init_storage(&settings).await?;
-
-    Ok((app::build_app(service.clone(), settings), service))
+    init::spawn_trash_purge(service.clone(), &settings.storage.trash);
+    init::spawn_scheduled_backup(service.clone(), &settings.storage.backup);
+    init::spawn_storage_stats(service.clone(), &settings.storage.stats);
+    init::spawn_webhook_dispatcher(service.clone(), &settings.webhooks);
+
+    let limiters = middleware::rate_limiter::RateLimiters::new(&settings);
+    let grpc = grpc::build_grpc(service.clone(), settings.clone(), &limiters);
+    Ok((
+        app::build_app(service.clone(), settings, &limiters),
+        grpc,
+        service,
+    ))
 }
@@ -1,13 +1,18 @@
-use std::net::SocketAddr;
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc};
 
-use todo_app::{MetricsProviderGuard, Settings, StartupError, TracingProviderGuard};
+use clap::Parser;
+use todo_app::{
+    ops::{self, Cli, Command, OpsCommand, OpsError},
+    MetricsProviderGuard, Settings, StartupError, TracingProviderGuard,
+};
 
 use thiserror::Error;
-use tokio::net::TcpListener;
 #[cfg(unix)]
 use tokio::signal;
 #[cfg(unix)]
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::{net::TcpListener, sync::watch};
+use tonic::transport::Server;
 
 #[cfg(feature = "jemalloc")]
 #[global_allocator]
@@ -20,16 +25,33 @@ enum RoutingAppError {
 
     #[error("Io error")]
     Io(#[from] std::io::Error),
+
+    #[error("gRPC server error")]
+    Grpc(#[from] tonic::transport::Error),
+
+    #[error("Operator command failed")]
+    Ops(#[from] OpsError),
 }
 
 fn main() -> Result<(), RoutingAppError> {
+    let cli = Cli::parse();
+
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .worker_threads(num_cpus::get())
         .max_blocking_threads(num_cpus::get() * 2)
         .enable_all()
         .build()?;
 
-    runtime.block_on(async_main())
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => runtime.block_on(async_main()),
+        Command::Ops(command) => runtime.block_on(operate(command)),
+    }
+}
+
+async fn operate(command: OpsCommand) -> Result<(), RoutingAppError> {
+    let settings = Settings::new()?;
+    ops::run(command, &settings, &mut std::io::stdout().lock()).await?;
+    Ok(())
 }
 
 #[cfg(unix)]
@@ -61,23 +83,47 @@ async fn async_main() -> Result<(), RoutingAppError> {
         .transpose()?;
 
     let server_addr = settings.server_addr();
-    let (app, service) = todo_app::init_app(settings).await?;
+    let grpc_addr = settings.grpc_addr();
+    let (app, grpc, service) = todo_app::init_app(settings).await?;
 
     let listener = TcpListener::bind(&server_addr).await?;
 
-    let shutdown_signal = async {
-        #[cfg(unix)]
-        shutdown_signal().await;
+    // both servers stop on the same signal, which a failing server sends too
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let shutdown_tx = Arc::new(shutdown_tx);
+    tokio::spawn({
+        let shutdown_tx = shutdown_tx.clone();
+        async move {
+            #[cfg(unix)]
+            shutdown_signal().await;
+            let _ = shutdown_tx.send(());
+        }
+    });
+    let shutdown = |mut rx: watch::Receiver<()>| async move {
+        let _ = rx.changed().await;
     };
 
-    axum::serve(
+    let rest = axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .with_graceful_shutdown(shutdown_signal)
-    .await?;
+    .with_graceful_shutdown(shutdown(shutdown_rx.clone()));
+    let grpc = Server::builder()
+        .add_routes(grpc)
+        .serve_with_shutdown(grpc_addr, shutdown(shutdown_rx));
+
+    let stop_on_error = |server: &'static str, result: Result<(), RoutingAppError>| {
+        if let Err(e) = &result {
+            tracing::error!(error = %e, server, "server failed, shutting down");
+            let _ = shutdown_tx.send(());
+        }
+        result
+    };
+    let rest = async { stop_on_error("rest", rest.into_future().await.map_err(Into::into)) };
+    let grpc = async { stop_on_error("grpc", grpc.await.map_err(Into::into)) };
+    let result = tokio::try_join!(rest, grpc);
 
     let _ = service.flush_storage().await;
 
-    Ok(())
+    result.map(|_| ())
 }
@@ -124,7 +124,9 @@ pub(crate) async fn auth(
 }
 
 #[instrument(name = "validate_header", skip_all)]
-fn validate_header(headers: &axum::http::HeaderMap) -> Result<Authorization<Bearer>, AuthError> {
+pub(crate) fn validate_header(
+    headers: &axum::http::HeaderMap,
+) -> Result<Authorization<Bearer>, AuthError> {
     headers.typed_get::<Authorization<Bearer>>().ok_or_else(|| {
         error!("Malformed Authorization header");
         AuthError::InvalidHeader
@@ -132,7 +134,10 @@ fn validate_header(headers: &axum::http::HeaderMap) -> Result<Authorization<Bear
 }
 
 #[instrument(name = "validate_token", skip_all)]
-fn validate_token(token: &str, jwt_secret: &str) -> Result<TokenData<Claims>, AuthError> {
+pub(crate) fn validate_token(
+    token: &str,
+    jwt_secret: &str,
+) -> Result<TokenData<Claims>, AuthError> {
     let decoding_key = DecodingKey::from_secret(jwt_secret.as_bytes());
     let mut validation = Validation::default();
     validation.leeway = 0;
@@ -152,9 +157,12 @@ fn validate_token_kind(claims: &Claims, normalized_uri: &str) -> Result<(), Auth
     } else {
         TokenKind::Access
     };
-    let actual = &claims.kind;
 
-    match (expected, actual) {
+    expect_token_kind(expected, claims)
+}
+
+pub(crate) fn expect_token_kind(expected: TokenKind, claims: &Claims) -> Result<(), AuthError> {
+    match (expected, &claims.kind) {
         (TokenKind::Refresh, TokenKind::Access) => Err(AuthError::ExpectedRefreshToken),
         (TokenKind::Access, TokenKind::Refresh) => Err(AuthError::ExpectedAccessToken),
         _ => Ok(()),
@@ -162,7 +170,7 @@ fn validate_token_kind(claims: &Claims, normalized_uri: &str) -> Result<(), Auth
 }
 
 #[instrument(name = "validate_user", skip_all)]
-async fn validate_user(service: &Service, claims: &Claims) -> Result<User, AuthError> {
+pub(crate) async fn validate_user(service: &Service, claims: &Claims) -> Result<User, AuthError> {
     service.user().get(claims.sub).await.map_err(|e| {
         error!(error = ?e, "Failed to get user by id from db");
         AuthError::InvalidUser
@@ -170,7 +178,10 @@ async fn validate_user(service: &Service, claims: &Claims) -> Result<User, AuthE
 }
 
 #[instrument(name = "validate_session", skip_all)]
-async fn validate_session(service: &Service, claims: &Claims) -> Result<Session, AuthError> {
+pub(crate) async fn validate_session(
+    service: &Service,
+    claims: &Claims,
+) -> Result<Session, AuthError> {
     let session = service.auth().get(claims.session_id).await.map_err(|e| {
         error!(error = ?e, "Failed to get session by id from db");
         AuthError::InvalidSession
@@ -185,7 +196,7 @@ async fn validate_session(service: &Service, claims: &Claims) -> Result<Session,
 }
 
 #[instrument(name = "validate_session", skip_all)]
-fn validate_refresh_token(claims: &Claims, session: &Session) -> Result<(), AuthError> {
+pub(crate) fn validate_refresh_token(claims: &Claims, session: &Session) -> Result<(), AuthError> {
     let refresh_jti = claims.refresh_jti.ok_or_else(|| {
         error!("Missing refresh jti in refresh token");
         AuthError::MissingRefreshJti
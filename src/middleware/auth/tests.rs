@@ -140,6 +140,8 @@ async fn test_invalid_user() {
             test_storage.build_user().await,
             test_storage.build_session().await,
             test_storage.build_flush().await,
+            test_storage.build_webhook().await,
+            test_storage.build_maintenance().await,
         )
         .await,
     );
@@ -175,6 +177,8 @@ async fn test_valid_user() {
             user_storage,
             test_storage.build_session().await,
             test_storage.build_flush().await,
+            test_storage.build_webhook().await,
+            test_storage.build_maintenance().await,
         )
         .await,
     );
@@ -199,6 +203,8 @@ async fn test_invalid_session() {
             test_storage.build_user().await,
             test_storage.build_session().await,
             test_storage.build_flush().await,
+            test_storage.build_webhook().await,
+            test_storage.build_maintenance().await,
         )
         .await,
     );
@@ -235,6 +241,8 @@ async fn test_expired_session() {
             test_storage.build_user().await,
             session_storage,
             test_storage.build_flush().await,
+            test_storage.build_webhook().await,
+            test_storage.build_maintenance().await,
         )
         .await,
     );
@@ -271,6 +279,8 @@ async fn test_valid_session() {
             test_storage.build_user().await,
             session_storage,
             test_storage.build_flush().await,
+            test_storage.build_webhook().await,
+            test_storage.build_maintenance().await,
         )
         .await,
     );
@@ -362,6 +372,8 @@ async fn test_valid_access_token_flow() {
             user_storage,
             session_storage,
             test_storage.build_flush().await,
+            test_storage.build_webhook().await,
+            test_storage.build_maintenance().await,
         )
         .await,
     );
@@ -438,6 +450,8 @@ async fn test_valid_refresh_token_flow() {
             user_storage,
             session_storage,
             test_storage.build_flush().await,
+            test_storage.build_webhook().await,
+            test_storage.build_maintenance().await,
         )
         .await,
     );
@@ -15,7 +15,91 @@ use std::{
 };
 use tower::{Layer, Service};
 
-use crate::{utils::metrics, Settings};
+use crate::{config::types::RateLimits, utils::metrics, Settings};
+
+fn quota(cell_per_sec: u32, burst: u32) -> Quota {
+    Quota::per_second(
+        std::num::NonZeroU32::new(cell_per_sec).expect("cells per second must not be zero"),
+    )
+    .allow_burst(std::num::NonZeroU32::new(burst).expect("burst must not be zero"))
+}
+
+/// Global and per IP limits of one kind of request, checked by hand where
+/// requests don't pass the axum layers, like gRPC methods.
+#[derive(Clone)]
+pub(crate) struct RequestLimiter {
+    global: Arc<DefaultDirectRateLimiter>,
+    per_ip: Arc<DefaultKeyedRateLimiter<IpAddr>>,
+}
+
+impl RequestLimiter {
+    pub(crate) fn new(limits: &RateLimits) -> Self {
+        Self {
+            global: Arc::new(RateLimiter::direct(quota(
+                limits.global.cells_per_second,
+                limits.global.burst_per_second,
+            ))),
+            per_ip: Arc::new(RateLimiter::keyed(quota(
+                limits.per_ip.cells_per_second,
+                limits.per_ip.burst_per_second,
+            ))),
+        }
+    }
+
+    /// Takes a cell for a request from `ip`, returns the kind of the limit
+    /// that refused it.
+    pub(crate) fn check(&self, ip: Option<IpAddr>) -> Result<(), &'static str> {
+        if self.global.check().is_err() {
+            metrics::REQUEST_COUNTER_429.add(1.0, &[KeyValue::new("kind", "global")]);
+            return Err("global");
+        }
+        if let Some(ip) = ip {
+            if self.per_ip.check_key(&ip).is_err() {
+                metrics::REQUEST_COUNTER_429.add(1.0, &[KeyValue::new("kind", "per_ip")]);
+                return Err("per_ip");
+            }
+        }
+        Ok(())
+    }
+
+    /// Layer taking its cells from the global limit of this limiter.
+    pub(crate) fn global_layer(&self) -> GlobalRateLimitLayer {
+        GlobalRateLimitLayer {
+            limiter: self.global.clone(),
+        }
+    }
+
+    /// Layer taking its cells from the per IP limit of this limiter.
+    pub(crate) fn per_ip_layer(&self) -> PerIpRateLimiter {
+        PerIpRateLimiter {
+            limiter: self.per_ip.clone(),
+        }
+    }
+}
+
+/// The limiters of each kind of request, built once and shared by the REST
+/// routes and the gRPC services, so switching APIs gains no requests.
+#[derive(Clone)]
+pub struct RateLimiters {
+    pub(crate) registration: RequestLimiter,
+    pub(crate) login: RequestLimiter,
+    pub(crate) admin: RequestLimiter,
+    pub(crate) crud_light: RequestLimiter,
+    pub(crate) crud_heavy: RequestLimiter,
+}
+
+impl RateLimiters {
+    pub fn new(settings: &Settings) -> Self {
+        let settings = &settings.rate_limiter;
+        Self {
+            registration: RequestLimiter::new(&settings.registration),
+            login: RequestLimiter::new(&settings.login),
+            admin: RequestLimiter::new(&settings.admin),
+            crud_light: RequestLimiter::new(&settings.crud_light),
+            crud_heavy: RequestLimiter::new(&settings.crud_heavy),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct GlobalRateLimitLayer {
@@ -24,12 +108,7 @@ pub struct GlobalRateLimitLayer {
 
 impl GlobalRateLimitLayer {
     pub fn new(cell_per_sec: u32, burst: u32) -> Self {
-        let quota = Quota::per_second(
-            std::num::NonZeroU32::new(cell_per_sec).expect("cells per second must not be zero"),
-        )
-        .allow_burst(std::num::NonZeroU32::new(burst).expect("burst must not be zero"));
-
-        let limiter = RateLimiter::direct(quota);
+        let limiter = RateLimiter::direct(quota(cell_per_sec, burst));
         Self {
             limiter: Arc::new(limiter),
         }
@@ -91,12 +170,7 @@ pub struct PerIpRateLimiter {
 
 impl PerIpRateLimiter {
     pub fn new(cell_per_sec: u32, burst: u32) -> Self {
-        let quota = Quota::per_second(
-            std::num::NonZeroU32::new(cell_per_sec).expect("cells per second must not be zero"),
-        )
-        .allow_burst(std::num::NonZeroU32::new(burst).expect("burst must not be zero"));
-
-        let limiter = RateLimiter::keyed(quota);
+        let limiter = RateLimiter::keyed(quota(cell_per_sec, burst));
         Self {
             limiter: Arc::new(limiter),
         }
@@ -0,0 +1,380 @@
+//! Operator subcommands of the server binary. They open the configured storage
+//! directly instead of going through HTTP, so they run while the server is
+//! stopped: sled allows one process per database.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::DateTime;
+use clap::{Parser, Subcommand};
+use thiserror::Error;
+
+use crate::{
+    config::types::{KDFKind, SledConfig, StorageKind},
+    handlers::{auth::check_credentials, error::AppError, RegisterUser},
+    init::{self, StartupError},
+    service::Service,
+    storage::{BackupManifest, MigrationReport, Pagination, Role, SledStorage, UserId},
+    Settings,
+};
+
+/// Users fetched per page by `list-users`.
+const USERS_PAGE_SIZE: usize = 100;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "To-Do service")]
+pub struct Cli {
+    /// Runs the servers when left out.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the REST and gRPC servers.
+    Serve,
+    #[command(flatten)]
+    Ops(OpsCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum OpsCommand {
+    /// Load the configuration and report settings that can't work.
+    CheckConfig,
+    /// Create a user with the admin role.
+    CreateAdmin {
+        email: String,
+        #[arg(long, env = "ADMIN_PASSWORD", hide_env_values = true)]
+        password: String,
+    },
+    /// Change the role of a user.
+    SetRole {
+        /// Email or id of the user.
+        user: String,
+        /// `user` or `admin`.
+        role: String,
+    },
+    /// Print every user with its role.
+    ListUsers,
+    /// Give a user another email without opening the storage, for emails that
+    /// differ only in case and keep the email index migration from running.
+    RenameEmail {
+        /// Id of the user, as the failed migration reports it.
+        user: UserId,
+        email: String,
+    },
+    /// Write every record to a backup file.
+    Backup { path: PathBuf },
+    /// Replace all records with the ones of a backup file. The server must be
+    /// stopped, and a restore that is interrupted has to be run again before
+    /// it starts.
+    Restore {
+        path: PathBuf,
+        /// Only check the archive, leave the storage as it is.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Remove expired sessions and sessions of deleted users.
+    GcSessions,
+    /// Bring the storage to the latest schema version.
+    Migrate {
+        /// List the steps the storage is missing without running them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check records and the links between trees, fails on problems left.
+    #[command(alias = "verify-storage")]
+    Fsck {
+        /// Remove or rebuild the records with problems that can be repaired.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Seal all records with new data keys and wrap the keys with the current
+    /// master key. Needs `[storage.sled.encryption]`.
+    RotateKeys {
+        /// Records read and sealed again at a time.
+        #[arg(long, default_value_t = 100)]
+        batch_size: usize,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum OpsError {
+    #[error("Failed to open storage")]
+    Startup(#[from] StartupError),
+
+    #[error("Command failed")]
+    App(#[from] AppError),
+
+    #[error("Failed to write output")]
+    Output(#[from] std::io::Error),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Storage has {0} problems")]
+    StorageProblems(usize),
+
+    #[error("{0} only runs before the storage is opened")]
+    StorageOpened(&'static str),
+}
+
+/// Runs one command against the storage configured in `settings`.
+pub async fn run(
+    command: OpsCommand,
+    settings: &Settings,
+    out: &mut dyn Write,
+) -> Result<(), OpsError> {
+    match command {
+        OpsCommand::CheckConfig => return check_config(settings, out),
+        // opening the storage would run the migrations already
+        OpsCommand::Migrate { dry_run } => {
+            let report = SledStorage::migrate(sled_config(settings)?, dry_run)
+                .map_err(StartupError::OpenSledStorage)?;
+            return print_migration(&report, dry_run, out);
+        }
+        // opening the storage runs the migration that refuses the email
+        OpsCommand::RenameEmail { user, email } => {
+            let renamed = SledStorage::rename_email(sled_config(settings)?, user, &email)
+                .map_err(StartupError::OpenSledStorage)?;
+            writeln!(out, "renamed {} to {email}", renamed.email)?;
+            return Ok(());
+        }
+        // opening the storage fails while a restore is left interrupted
+        OpsCommand::Restore {
+            path,
+            dry_run: false,
+        } => {
+            let manifest = SledStorage::restore(sled_config(settings)?, &path)
+                .map_err(StartupError::OpenSledStorage)?;
+            return print_restore(&manifest, &path, false, out);
+        }
+        _ => {}
+    }
+
+    let service = init::init_storage(settings).await?;
+    let result = execute(command, &service, settings, out).await;
+    service.flush_storage().await?;
+    result
+}
+
+/// Runs one command against an already opened storage.
+pub async fn execute(
+    command: OpsCommand,
+    service: &Service,
+    settings: &Settings,
+    out: &mut dyn Write,
+) -> Result<(), OpsError> {
+    match command {
+        OpsCommand::CheckConfig => check_config(settings, out)?,
+        OpsCommand::CreateAdmin { email, password } => {
+            check_credentials(&email, &password)?;
+            service
+                .user()
+                .add_admin(
+                    RegisterUser {
+                        email: email.clone(),
+                        password,
+                    },
+                    settings,
+                )
+                .await?;
+            writeln!(out, "created admin {email}")?;
+        }
+        OpsCommand::SetRole { user, role } => {
+            let role = Role::from_str(&role).map_err(|_| AppError::InvalidRole(role))?;
+            let user = match UserId::from_str(&user) {
+                Ok(id) => service.user().get(id).await?,
+                Err(_) => service.user().get_by_email(&user).await?,
+            };
+            service.user().set_role(user.id, role).await?;
+            writeln!(out, "{} is now {}", user.email, role.as_ref())?;
+        }
+        OpsCommand::ListUsers => {
+            let mut after = None;
+            loop {
+                let page = Pagination {
+                    after,
+                    limit: USERS_PAGE_SIZE,
+                };
+                let (users, cursor) = service.user().list(page).await?;
+                for user in users {
+                    writeln!(
+                        out,
+                        "{}  {:<5}  {}",
+                        user.id,
+                        user.role.as_ref(),
+                        user.email
+                    )?;
+                }
+                match cursor {
+                    Some(cursor) => after = Some(cursor),
+                    None => break,
+                }
+            }
+        }
+        OpsCommand::RenameEmail { .. } => return Err(OpsError::StorageOpened("rename-email")),
+        OpsCommand::Backup { path } => {
+            let manifest = service.maintenance().backup(path.clone()).await?;
+            writeln!(
+                out,
+                "backed up {} records to {}",
+                manifest.records(),
+                path.display()
+            )?;
+        }
+        OpsCommand::Restore { path, dry_run } => {
+            let manifest = if dry_run {
+                service.maintenance().inspect(path.clone()).await?
+            } else {
+                service.maintenance().restore(path.clone()).await?
+            };
+            print_restore(&manifest, &path, dry_run, out)?;
+        }
+        OpsCommand::GcSessions => {
+            let removed = service.auth().purge().await?;
+            writeln!(out, "removed {removed} sessions")?;
+        }
+        OpsCommand::Migrate { dry_run } => {
+            let report = service.maintenance().migrate(dry_run).await?;
+            print_migration(&report, dry_run, out)?;
+        }
+        OpsCommand::Fsck { repair } => {
+            let report = service.maintenance().verify(repair).await?;
+            for (tree, records) in &report.records {
+                writeln!(out, "{tree}: {records} records")?;
+            }
+            for problem in &report.problems {
+                let repaired = if problem.repaired { " [repaired]" } else { "" };
+                writeln!(
+                    out,
+                    "{} {}: {}: {}{repaired}",
+                    problem.tree,
+                    problem.key,
+                    problem.kind.as_ref(),
+                    problem.detail
+                )?;
+            }
+            if !report.is_ok() {
+                let left = report.problems.iter().filter(|p| !p.repaired).count();
+                return Err(OpsError::StorageProblems(left));
+            }
+        }
+        OpsCommand::RotateKeys { batch_size } => {
+            let report = service.maintenance().rotate_keys(batch_size).await?;
+            writeln!(out, "rotated data keys of {} users", report.rotated)?;
+            for (tree, records) in &report.records {
+                writeln!(out, "{tree}: {records} records sealed again")?;
+            }
+            writeln!(
+                out,
+                "retired {} data keys, rewrapped {}",
+                report.retired, report.rewrapped
+            )?;
+            if report.skipped > 0 {
+                writeln!(
+                    out,
+                    "{} records could not be opened, old data keys were kept",
+                    report.skipped
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn sled_config(settings: &Settings) -> Result<&SledConfig, StartupError> {
+    settings
+        .storage
+        .sled
+        .as_ref()
+        .ok_or(StartupError::MissingStorageConfig("sled".to_string()))
+}
+
+fn print_restore(
+    manifest: &BackupManifest,
+    path: &Path,
+    dry_run: bool,
+    out: &mut dyn Write,
+) -> Result<(), OpsError> {
+    let taken_at = DateTime::from_timestamp(manifest.created_at, 0).unwrap_or_default();
+    writeln!(
+        out,
+        "{} {} records from {}, taken at {}",
+        if dry_run { "would restore" } else { "restored" },
+        manifest.records(),
+        path.display(),
+        taken_at.to_rfc3339()
+    )?;
+    Ok(())
+}
+
+fn print_migration(
+    report: &MigrationReport,
+    dry_run: bool,
+    out: &mut dyn Write,
+) -> Result<(), OpsError> {
+    if report.steps.is_empty() {
+        writeln!(out, "storage is at schema version {}", report.to)?;
+        return Ok(());
+    }
+    let done = if dry_run { "would migrate" } else { "migrated" };
+    for step in &report.steps {
+        writeln!(
+            out,
+            "{done} to version {}: {} ({} writes)",
+            step.version, step.description, step.writes
+        )?;
+    }
+    Ok(())
+}
+
+fn check_config(settings: &Settings, out: &mut dyn Write) -> Result<(), OpsError> {
+    let storage = &settings.storage;
+    match (storage.backend, &storage.sled) {
+        (StorageKind::Sled, Some(sled)) => {
+            writeln!(out, "storage: sled at {}", sled.path.display())?
+        }
+        (StorageKind::Sled, None) => {
+            return Err(OpsError::InvalidConfig("missing [storage.sled]".into()))
+        }
+        (kind, _) => {
+            return Err(OpsError::InvalidConfig(format!(
+                "unsupported storage backend {}",
+                kind.as_ref()
+            )))
+        }
+    }
+
+    let auth = &settings.auth;
+    let kdf = match (auth.kdf_algo, &auth.argon2, &auth.pbkdf2) {
+        (KDFKind::Argon2, Some(_), _) | (KDFKind::Pbkdf2, _, Some(_)) => auth.kdf_algo.as_ref(),
+        (KDFKind::Argon2, None, _) => return Err(AppError::MissingArgon2Config.into()),
+        (KDFKind::Pbkdf2, _, None) => return Err(AppError::MissingPbkdf2Config.into()),
+    };
+    writeln!(out, "password hashing: {kdf}")?;
+
+    let jwt = &settings.jwt;
+    if [
+        jwt.access_token_ttl_sec,
+        jwt.refresh_token_ttl_sec,
+        jwt.session_ttl_sec,
+    ]
+    .iter()
+    .any(|ttl| *ttl <= 0)
+    {
+        return Err(OpsError::InvalidConfig("jwt ttls must be positive".into()));
+    }
+
+    writeln!(
+        out,
+        "rest: {}, grpc: {}",
+        settings.server.addr, settings.server.grpc_addr
+    )?;
+    writeln!(out, "configured admins: {}", auth.admins.len())?;
+    writeln!(out, "config ok")?;
+    Ok(())
+}
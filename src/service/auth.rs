@@ -51,6 +51,17 @@ impl ServiceAuthRef {
         Ok(())
     }
 
+    /// Removes expired sessions and sessions of deleted users.
+    #[instrument(name = "Service::session::purge", skip_all)]
+    pub(crate) async fn purge(&self) -> Result<usize, AppError> {
+        let now = chrono::Utc::now().timestamp();
+        info!(now, "purge sessions");
+
+        measure_and_record_service("purge_sessions", || async { self.storage.purge(now).await })
+            .await
+            .map_err(Into::into)
+    }
+
     #[instrument(name = "Service::session::refresh_token", skip_all)]
     pub(crate) async fn refresh_token(
         &self,
@@ -0,0 +1,253 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::storage::{Todo, TodoId, UserId};
+
+/// Events buffered per subscriber before it falls behind and gets disconnected.
+const CHANNEL_CAPACITY: usize = 256;
+/// Latest events of all users kept to resume streams from `Last-Event-ID`.
+const REPLAY_SIZE: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoEventKind {
+    Created,
+    Updated,
+    Deleted,
+    /// Events after the requested last event id are no longer buffered, the
+    /// client has to fetch its todos again.
+    Reset,
+}
+
+impl TodoEventKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted",
+            Self::Reset => "reset",
+        }
+    }
+}
+
+/// Change of a todo pushed to the subscribers of its owner, `todo` is `None`
+/// once it was deleted. A `reset` has neither, its id is the last lost event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct TodoEvent {
+    /// Increases with every event, clients resume after it with `Last-Event-ID`.
+    pub id: u64,
+    pub kind: TodoEventKind,
+    #[schema(value_type = Option<String>)]
+    pub todo_id: Option<TodoId>,
+    pub todo: Option<Todo>,
+}
+
+struct Published {
+    user_id: UserId,
+    event: TodoEvent,
+}
+
+struct Backlog {
+    last_id: u64,
+    recent: VecDeque<Arc<Published>>,
+}
+
+/// In-process broadcast of todo changes. Every subscriber receives events of
+/// all users and keeps only the ones of its own user.
+pub struct TodoEvents {
+    sender: broadcast::Sender<Arc<Published>>,
+    backlog: Mutex<Backlog>,
+}
+
+impl TodoEvents {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        // ids start from the current time so they keep growing across restarts
+        let last_id = chrono::Utc::now().timestamp_micros().max(0) as u64;
+        Self {
+            sender,
+            backlog: Mutex::new(Backlog {
+                last_id,
+                recent: VecDeque::with_capacity(REPLAY_SIZE),
+            }),
+        }
+    }
+
+    pub(crate) fn publish(
+        &self,
+        user_id: UserId,
+        kind: TodoEventKind,
+        todo_id: TodoId,
+        todo: Option<Todo>,
+    ) {
+        let mut backlog = self.backlog.lock().unwrap_or_else(|e| e.into_inner());
+        backlog.last_id += 1;
+        let published = Arc::new(Published {
+            user_id,
+            event: TodoEvent {
+                id: backlog.last_id,
+                kind,
+                todo_id: Some(todo_id),
+                todo,
+            },
+        });
+        if backlog.recent.len() == REPLAY_SIZE {
+            backlog.recent.pop_front();
+        }
+        backlog.recent.push_back(published.clone());
+        // sent under the lock so subscribers see events in the order of their ids,
+        // it fails only when nobody is subscribed
+        let _ = self.sender.send(published);
+    }
+
+    /// Streams events of the user, starting with the buffered ones after
+    /// `last_event_id`, which are preceded by a `reset` when some of them are
+    /// gone. The stream ends once the subscriber falls behind, the client is
+    /// expected to reconnect with the id of its last event.
+    pub(crate) fn subscribe(
+        &self,
+        user_id: UserId,
+        last_event_id: Option<u64>,
+    ) -> impl Stream<Item = TodoEvent> + Send + 'static {
+        let backlog = self.backlog.lock().unwrap_or_else(|e| e.into_inner());
+        let receiver = self.sender.subscribe();
+
+        let missed = match last_event_id {
+            Some(last_id) => {
+                let mut missed = Vec::new();
+                // events of an earlier run are never buffered
+                let first_id = backlog
+                    .recent
+                    .front()
+                    .map_or(backlog.last_id.saturating_add(1), |oldest| oldest.event.id);
+                if first_id > last_id.saturating_add(1) {
+                    warn!(
+                        last_id,
+                        "events after the last event id are no longer buffered"
+                    );
+                    missed.push(TodoEvent {
+                        id: first_id - 1,
+                        kind: TodoEventKind::Reset,
+                        todo_id: None,
+                        todo: None,
+                    });
+                }
+                missed.extend(
+                    backlog
+                        .recent
+                        .iter()
+                        .filter(|published| {
+                            published.event.id > last_id && published.user_id == user_id
+                        })
+                        .map(|published| published.event.clone()),
+                );
+                missed
+            }
+            None => Vec::new(),
+        };
+        drop(backlog);
+
+        let live = stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(published) if published.user_id == user_id => {
+                        return Some((published.event.clone(), receiver));
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "todo event subscriber fell behind");
+                        return None;
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        stream::iter(missed).chain(live)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribe_replays_events_after_last_id() {
+        let events = TodoEvents::new();
+        let user_id = UserId::new();
+        let other_id = UserId::new();
+
+        let first = TodoId::new();
+        let second = TodoId::new();
+        events.publish(user_id, TodoEventKind::Created, first, None);
+        events.publish(other_id, TodoEventKind::Created, TodoId::new(), None);
+        events.publish(user_id, TodoEventKind::Deleted, second, None);
+
+        // after the reset for the ids of an earlier run
+        let replayed = events
+            .subscribe(user_id, Some(0))
+            .skip(1)
+            .take(2)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(replayed[0].todo_id, Some(first));
+        assert_eq!(replayed[1].todo_id, Some(second));
+        assert_eq!(replayed[1].kind, TodoEventKind::Deleted);
+
+        let mut resumed = Box::pin(events.subscribe(user_id, Some(replayed[0].id)));
+        assert_eq!(resumed.next().await.unwrap().todo_id, Some(second));
+
+        let third = TodoId::new();
+        events.publish(other_id, TodoEventKind::Updated, TodoId::new(), None);
+        events.publish(user_id, TodoEventKind::Updated, third, None);
+        let live = resumed.next().await.unwrap();
+        assert_eq!(live.todo_id, Some(third));
+        assert!(live.id > replayed[1].id);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_resets_when_events_are_gone() {
+        let events = TodoEvents::new();
+        let user_id = UserId::new();
+
+        // ids of an earlier run
+        let mut resumed = Box::pin(events.subscribe(user_id, Some(0)));
+        let reset = resumed.next().await.unwrap();
+        assert_eq!(reset.kind, TodoEventKind::Reset);
+        assert_eq!(reset.todo_id, None);
+
+        let first = TodoId::new();
+        events.publish(user_id, TodoEventKind::Created, first, None);
+        let created = resumed.next().await.unwrap();
+        assert_eq!(created.todo_id, Some(first));
+        assert_eq!(created.id, reset.id + 1);
+
+        // resuming after the reset replays what is still buffered
+        let mut resumed = Box::pin(events.subscribe(user_id, Some(reset.id)));
+        assert_eq!(resumed.next().await.unwrap(), created);
+
+        for _ in 0..=REPLAY_SIZE {
+            events.publish(user_id, TodoEventKind::Updated, first, None);
+        }
+        let mut resumed = Box::pin(events.subscribe(user_id, Some(created.id)));
+        let reset = resumed.next().await.unwrap();
+        assert_eq!(reset.kind, TodoEventKind::Reset);
+        assert_eq!(reset.id, created.id + 1);
+        let oldest = resumed.next().await.unwrap();
+        assert_eq!(oldest.id, created.id + 2);
+
+        // ids past the latest one never overflow
+        let mut resumed = Box::pin(events.subscribe(user_id, Some(u64::MAX)));
+        let first = TodoId::new();
+        events.publish(user_id, TodoEventKind::Created, first, None);
+        assert_eq!(resumed.next().await.unwrap().todo_id, Some(first));
+    }
+}
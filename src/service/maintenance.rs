@@ -0,0 +1,226 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use chrono::Utc;
+use tracing::{info, instrument};
+
+use super::UserCache;
+use crate::{
+    config::types::{BackupConfig, StatsConfig},
+    handlers::error::AppError,
+    storage::{
+        BackupManifest, MaintenanceStorage, MigrationReport, RotationReport, StorageReport,
+        StorageStats,
+    },
+    utils::{measure_metrics::measure_and_record_service, storage_metrics::record_storage_stats},
+};
+
+/// Extension of the archives kept in the backup directory.
+const BACKUP_EXTENSION: &str = "todo-backup";
+
+/// Archive in the backup directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupFile {
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+pub struct ServiceMaintenanceRef {
+    storage: Arc<dyn MaintenanceStorage>,
+    user_cache: Arc<UserCache>,
+    stats: Arc<RwLock<Option<StorageStats>>>,
+}
+
+impl ServiceMaintenanceRef {
+    pub(crate) fn new(
+        storage: Arc<dyn MaintenanceStorage>,
+        user_cache: Arc<UserCache>,
+        stats: Arc<RwLock<Option<StorageStats>>>,
+    ) -> Self {
+        Self {
+            storage,
+            user_cache,
+            stats,
+        }
+    }
+
+    #[instrument(name = "Service::maintenance::backup", skip_all)]
+    pub(crate) async fn backup(&self, path: PathBuf) -> Result<BackupManifest, AppError> {
+        info!(path = ?path, "backup storage");
+
+        measure_and_record_service("backup_storage", || async {
+            self.storage.backup(path).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::maintenance::inspect", skip_all)]
+    pub(crate) async fn inspect(&self, path: PathBuf) -> Result<BackupManifest, AppError> {
+        info!(path = ?path, "inspect backup");
+
+        measure_and_record_service("inspect_backup", || async {
+            self.storage.inspect(path).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Replaces every record with the ones of a backup. Only runs while the
+    /// server is stopped, see [`MaintenanceStorage::restore`].
+    #[instrument(name = "Service::maintenance::restore", skip_all)]
+    pub(crate) async fn restore(&self, path: PathBuf) -> Result<BackupManifest, AppError> {
+        info!(path = ?path, "restore storage");
+
+        let manifest = measure_and_record_service("restore_storage", || async {
+            self.storage.restore(path).await
+        })
+        .await?;
+
+        // nothing cached from before describes the restored records
+        self.user_cache.clear();
+        *self.stats.write().expect("storage stats lock poisoned") = None;
+        Ok(manifest)
+    }
+
+    /// Writes a new archive into the backup directory, then removes the oldest
+    /// ones beyond `config.keep`.
+    #[instrument(name = "Service::maintenance::create_backup", skip_all)]
+    pub(crate) async fn create_backup(
+        &self,
+        config: &BackupConfig,
+    ) -> Result<(String, BackupManifest), AppError> {
+        tokio::fs::create_dir_all(&config.dir)
+            .await
+            .map_err(AppError::BackupDir)?;
+
+        // names sort in creation order
+        let name = format!(
+            "{}.{BACKUP_EXTENSION}",
+            Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let manifest = self.backup(config.dir.join(&name)).await?;
+
+        let backups = self.list_backups(config).await?;
+        for old in backups.iter().skip(config.keep.max(1)) {
+            info!(name = old.name, "remove old backup");
+            tokio::fs::remove_file(config.dir.join(&old.name))
+                .await
+                .map_err(AppError::BackupDir)?;
+        }
+
+        Ok((name, manifest))
+    }
+
+    /// Archives of the backup directory, newest first.
+    #[instrument(name = "Service::maintenance::list_backups", skip_all)]
+    pub(crate) async fn list_backups(
+        &self,
+        config: &BackupConfig,
+    ) -> Result<Vec<BackupFile>, AppError> {
+        let mut entries = match tokio::fs::read_dir(&config.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(AppError::BackupDir(e)),
+        };
+
+        let mut backups = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(AppError::BackupDir)? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !is_backup_name(&name) {
+                continue;
+            }
+            let metadata = entry.metadata().await.map_err(AppError::BackupDir)?;
+            backups.push(BackupFile {
+                name,
+                size: metadata.len(),
+            });
+        }
+        backups.sort_by(|a, b| b.name.cmp(&a.name));
+        Ok(backups)
+    }
+
+    /// Brings stored records to the current schema, changes nothing on `dry_run`.
+    #[instrument(name = "Service::maintenance::migrate", skip_all, fields(dry_run))]
+    pub(crate) async fn migrate(&self, dry_run: bool) -> Result<MigrationReport, AppError> {
+        measure_and_record_service("migrate_storage", || async {
+            self.storage.migrate(dry_run).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Checks every record and the links between trees, repairs what can be
+    /// repaired when `repair` is set.
+    #[instrument(name = "Service::maintenance::verify", skip_all, fields(repair))]
+    pub(crate) async fn verify(&self, repair: bool) -> Result<StorageReport, AppError> {
+        measure_and_record_service("verify_storage", || async {
+            self.storage.verify(repair).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Seals the stored records with new data keys.
+    #[instrument(
+        name = "Service::maintenance::rotate_keys",
+        skip_all,
+        fields(batch_size)
+    )]
+    pub(crate) async fn rotate_keys(&self, batch_size: usize) -> Result<RotationReport, AppError> {
+        info!(batch_size, "rotating data keys");
+        measure_and_record_service("rotate_keys", || async {
+            self.storage.rotate_keys(batch_size).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Collects storage statistics and keeps them for `stats` and the gauges.
+    #[instrument(name = "Service::maintenance::collect_stats", skip_all)]
+    pub(crate) async fn collect_stats(
+        &self,
+        config: &StatsConfig,
+    ) -> Result<StorageStats, AppError> {
+        let stats = measure_and_record_service("collect_storage_stats", || async {
+            self.storage.stats(config.top_users).await
+        })
+        .await?;
+
+        record_storage_stats(&stats);
+        *self.stats.write().expect("storage stats lock poisoned") = Some(stats.clone());
+        Ok(stats)
+    }
+
+    /// The statistics of the last collection, collected now when the job
+    /// didn't run yet.
+    #[instrument(name = "Service::maintenance::stats", skip_all)]
+    pub(crate) async fn stats(&self, config: &StatsConfig) -> Result<StorageStats, AppError> {
+        let last = self
+            .stats
+            .read()
+            .expect("storage stats lock poisoned")
+            .clone();
+        match last {
+            Some(stats) => Ok(stats),
+            None => self.collect_stats(config).await,
+        }
+    }
+}
+
+fn is_backup_name(name: &str) -> bool {
+    name.strip_suffix(BACKUP_EXTENSION)
+        .and_then(|stem| stem.strip_suffix('.'))
+        .is_some_and(|stem| {
+            !stem.is_empty()
+                && stem
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+                && !stem.starts_with('.')
+        })
+}
@@ -1,24 +1,37 @@
 pub(crate) mod auth;
+pub(crate) mod events;
 pub(crate) mod jwt;
+pub(crate) mod maintenance;
 pub(crate) mod password;
+pub(crate) mod quota;
 pub(crate) mod todo;
+pub(crate) mod transfer;
 pub(crate) mod user;
+pub(crate) mod webhook;
 
 use moka::future::Cache;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use crate::{
+    config::types::LimitsConfig,
     handlers::{LoginToken, LoginUser},
-    storage::{FlushStorage, Jti, Session, SessionStorage, TodoStorage, User, UserId, UserStorage},
+    storage::{
+        FlushStorage, Jti, MaintenanceStorage, Session, SessionStorage, StorageStats, TodoStorage,
+        User, UserId, UserStorage, WebhookStorage,
+    },
     trace_err,
     utils::{measure_metrics::measure_and_record_service, JWT_SECRET_KEY},
     Settings,
 };
 use auth::ServiceAuthRef;
+use events::TodoEvents;
+use maintenance::ServiceMaintenanceRef;
 use password::verify_password;
+use quota::TodoQuota;
 use todo::ServiceTodoRef;
 use tracing::{info, info_span, instrument};
 use user::ServiceUserRef;
+use webhook::ServiceWebhookRef;
 
 use crate::handlers::error::AppError;
 
@@ -28,13 +41,27 @@ pub struct UserCache {
     by_email: Cache<String, User>,
 }
 
+impl UserCache {
+    /// Drops every cached user, for when the stored users were replaced.
+    pub(crate) fn clear(&self) {
+        self.by_id.invalidate_all();
+        self.by_email.invalidate_all();
+    }
+}
+
 #[derive(Clone)]
 pub struct Service {
     todo_storage: Arc<dyn TodoStorage>,
     user_storage: Arc<dyn UserStorage>,
     session_storage: Arc<dyn SessionStorage>,
     flush_storage: Arc<dyn FlushStorage>,
+    webhook_storage: Arc<dyn WebhookStorage>,
+    maintenance_storage: Arc<dyn MaintenanceStorage>,
     user_cache: Arc<UserCache>,
+    todo_events: Arc<TodoEvents>,
+    todo_quota: Arc<TodoQuota>,
+    /// Last statistics collected by the stats job.
+    storage_stats: Arc<RwLock<Option<StorageStats>>>,
 }
 
 impl Service {
@@ -44,21 +71,39 @@ impl Service {
         user_storage: Arc<dyn UserStorage>,
         session_storage: Arc<dyn SessionStorage>,
         flush_storage: Arc<dyn FlushStorage>,
+        webhook_storage: Arc<dyn WebhookStorage>,
+        maintenance_storage: Arc<dyn MaintenanceStorage>,
     ) -> Self {
         Self {
             todo_storage,
             user_storage,
             session_storage,
             flush_storage,
+            webhook_storage,
+            maintenance_storage,
             user_cache: Arc::new(UserCache {
                 by_id: Cache::new(10_000),
                 by_email: Cache::new(10_000),
             }),
+            todo_events: Arc::new(TodoEvents::new()),
+            todo_quota: Arc::new(TodoQuota::new(LimitsConfig::default())),
+            storage_stats: Arc::new(RwLock::new(None)),
         }
     }
 
+    /// Enforces `limits` on the todos of every user, nothing is limited without it.
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.todo_storage.set_limits(limits.clone());
+        self.todo_quota = Arc::new(TodoQuota::new(limits));
+        self
+    }
+
     pub fn todo(&self) -> ServiceTodoRef {
-        ServiceTodoRef::new(self.todo_storage.clone())
+        ServiceTodoRef::new(
+            self.todo_storage.clone(),
+            self.todo_events.clone(),
+            self.todo_quota.clone(),
+        )
     }
 
     pub fn user(&self) -> ServiceUserRef {
@@ -68,6 +113,18 @@ impl Service {
     pub fn auth(&self) -> ServiceAuthRef {
         ServiceAuthRef::new(self.session_storage.clone())
     }
+
+    pub fn webhook(&self) -> ServiceWebhookRef {
+        ServiceWebhookRef::new(self.webhook_storage.clone())
+    }
+
+    pub fn maintenance(&self) -> ServiceMaintenanceRef {
+        ServiceMaintenanceRef::new(
+            self.maintenance_storage.clone(),
+            self.user_cache.clone(),
+            self.storage_stats.clone(),
+        )
+    }
 }
 
 impl Service {
@@ -0,0 +1,31 @@
+use crate::{config::types::LimitsConfig, handlers::error::AppError};
+
+/// Checks the content of todos before they are written. How many todos and
+/// groups a user has is counted and checked by the storage, in the same
+/// transaction as the writes.
+pub struct TodoQuota {
+    limits: LimitsConfig,
+}
+
+impl TodoQuota {
+    pub(crate) fn new(limits: LimitsConfig) -> Self {
+        Self { limits }
+    }
+
+    /// Checks the length of a todo's text and group.
+    pub(crate) fn check_content(
+        &self,
+        text: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<(), AppError> {
+        check_len("text", text, self.limits.max_text_len)?;
+        check_len("group", group, self.limits.max_group_len)
+    }
+}
+
+fn check_len(field: &'static str, value: Option<&str>, max: usize) -> Result<(), AppError> {
+    match value {
+        Some(value) if value.chars().count() > max => Err(AppError::TooLong { field, max }),
+        _ => Ok(()),
+    }
+}
@@ -1,31 +1,94 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
-use tracing::{info, instrument};
+use futures_util::{stream, Stream};
+use tracing::{info, instrument, warn};
 
+use super::events::{TodoEvent, TodoEventKind, TodoEvents};
+use super::quota::TodoQuota;
+use super::transfer::{self, Exporter, TransferFormat};
 use crate::{
-    handlers::{error::AppError, UpdateTodo},
-    storage::{Pagination, Todo, TodoId, TodoStorage, User},
+    handlers::{error::AppError, BatchOperation, SyncOperation, UpdateTodo},
+    storage::{
+        ClientChange, HistoryEntry, HistorySeq, Pagination, StorageError, SyncChange, SyncResult,
+        SyncSeq, Todo, TodoId, TodoLinks, TodoOp, TodoOpResult, TodoStorage, TrashedTodo,
+        UndoResult, User,
+    },
     utils::measure_metrics::measure_and_record_service,
 };
 
+/// Todos read from storage per chunk of an export or groups scan.
+const EXPORT_PAGE_SIZE: usize = 100;
+/// Imported todos written per batch transaction.
+const IMPORT_BATCH_SIZE: usize = 100;
+
 pub struct ServiceTodoRef {
     storage: Arc<dyn TodoStorage>,
+    events: Arc<TodoEvents>,
+    quota: Arc<TodoQuota>,
 }
 
 impl ServiceTodoRef {
-    pub(crate) fn new(storage: Arc<dyn TodoStorage>) -> Self {
-        Self { storage }
+    pub(crate) fn new(
+        storage: Arc<dyn TodoStorage>,
+        events: Arc<TodoEvents>,
+        quota: Arc<TodoQuota>,
+    ) -> Self {
+        Self {
+            storage,
+            events,
+            quota,
+        }
+    }
+
+    /// Publishes changed todos together with their current state, the ones
+    /// removed in the meantime are reported as deleted.
+    async fn notify(&self, user: &User, changed: Vec<(TodoEventKind, TodoId)>) {
+        for (kind, todo_id) in changed {
+            if kind == TodoEventKind::Deleted {
+                self.events.publish(user.id, kind, todo_id, None);
+                continue;
+            }
+            match self.storage.get(user.id, todo_id).await {
+                Ok(todo) => self.events.publish(user.id, kind, todo_id, Some(todo)),
+                Err(StorageError::NotFound) => {
+                    self.events
+                        .publish(user.id, TodoEventKind::Deleted, todo_id, None)
+                }
+                Err(e) => warn!(error = ?e, todo_id = %todo_id, "failed to load changed todo"),
+            }
+        }
+    }
+
+    /// Streams changes of the user's todos, resuming after `last_event_id`.
+    #[instrument(name = "Service::todo::subscribe", skip_all)]
+    pub(crate) fn subscribe(
+        &self,
+        user: &User,
+        last_event_id: Option<u64>,
+    ) -> impl Stream<Item = TodoEvent> + Send + 'static {
+        info!(last_event_id, "subscribe to todo events");
+
+        self.events.subscribe(user.id, last_event_id)
     }
 
     #[instrument(name = "Service::todo::add", skip_all)]
-    pub(crate) async fn add(&self, user: &User, text: &str) -> Result<TodoId, AppError> {
+    pub(crate) async fn add(
+        &self,
+        user: &User,
+        text: &str,
+        due: Option<i64>,
+    ) -> Result<TodoId, AppError> {
+        self.quota.check_content(Some(text), None)?;
+
         let id = TodoId::new();
         measure_and_record_service("add_todo", || async {
-            let todo = Todo::new(id, text);
+            let mut todo = Todo::new(id, text);
+            todo.due = due;
             self.storage.put(user.id, id, todo).await
         })
         .await?;
 
+        self.notify(user, vec![(TodoEventKind::Created, id)]).await;
         Ok(id)
     }
 
@@ -38,6 +101,51 @@ impl ServiceTodoRef {
         .map_err(Into::into)
     }
 
+    #[instrument(name = "Service::todo::get_links", skip_all)]
+    pub(crate) async fn get_links(
+        &self,
+        user: &User,
+        todo_id: TodoId,
+    ) -> Result<TodoLinks, AppError> {
+        measure_and_record_service("get_todo_links", || async {
+            self.storage.get_links(user.id, todo_id).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::todo::add_blocker", skip_all)]
+    pub(crate) async fn add_blocker(
+        &self,
+        user: &User,
+        todo_id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), AppError> {
+        info!(todo_id = %todo_id, blocker_id = %blocker_id, "add blocker");
+
+        measure_and_record_service("add_todo_blocker", || async {
+            self.storage.add_link(user.id, todo_id, blocker_id).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::todo::remove_blocker", skip_all)]
+    pub(crate) async fn remove_blocker(
+        &self,
+        user: &User,
+        todo_id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), AppError> {
+        info!(todo_id = %todo_id, blocker_id = %blocker_id, "remove blocker");
+
+        measure_and_record_service("remove_todo_blocker", || async {
+            self.storage.remove_link(user.id, todo_id, blocker_id).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
     #[instrument(name = "Service::todo::get_all", skip_all, fields(after_is_some = page.after.is_some(),
     limit = page.limit))]
     pub(crate) async fn get_all(
@@ -54,6 +162,34 @@ impl ServiceTodoRef {
         .map_err(Into::into)
     }
 
+    /// Distinct non-empty groups of the user's todos, sorted.
+    #[instrument(name = "Service::todo::get_groups", skip_all)]
+    pub(crate) async fn get_groups(&self, user: &User) -> Result<Vec<String>, AppError> {
+        let mut groups = BTreeSet::new();
+        let mut after = None;
+        loop {
+            let page = Pagination {
+                after,
+                limit: EXPORT_PAGE_SIZE,
+            };
+            let (todos, cursor) = measure_and_record_service("get_todo_groups_page", || async {
+                self.storage.get_all(user.id, page).await
+            })
+            .await?;
+
+            groups.extend(
+                todos
+                    .into_iter()
+                    .map(|todo| todo.group)
+                    .filter(|group| !group.is_empty()),
+            );
+            match cursor {
+                Some(cursor) => after = Some(cursor),
+                None => return Ok(groups.into_iter().collect()),
+            }
+        }
+    }
+
     #[instrument(
         name = "Service::todo::update",
         skip_all,
@@ -69,18 +205,316 @@ impl ServiceTodoRef {
     ) -> Result<(), AppError> {
         info!(todo_id = %id, "update todo");
 
+        self.quota
+            .check_content(patch.text.as_deref(), patch.group.as_deref())?;
+
         measure_and_record_service("update_todo", || async {
             self.storage.update(user.id, id, patch.into()).await
         })
-        .await
-        .map_err(Into::into)
+        .await?;
+
+        self.notify(user, vec![(TodoEventKind::Updated, id)]).await;
+        Ok(())
+    }
+
+    #[instrument(name = "Service::todo::batch", skip_all, fields(count = ops.len()))]
+    pub(crate) async fn batch(
+        &self,
+        user: &User,
+        ops: &[BatchOperation],
+    ) -> Result<Vec<TodoOpResult>, AppError> {
+        for (index, op) in ops.iter().enumerate() {
+            let checked = match op {
+                BatchOperation::Create { text, .. } => self.quota.check_content(Some(text), None),
+                BatchOperation::Update { patch, .. } => self
+                    .quota
+                    .check_content(patch.text.as_deref(), patch.group.as_deref()),
+                BatchOperation::Delete { .. } => Ok(()),
+            };
+            checked.map_err(|source| AppError::BatchOperation {
+                index,
+                source: Box::new(source),
+            })?;
+        }
+
+        let ops = ops
+            .iter()
+            .map(|op| match op {
+                BatchOperation::Create { text, due } => {
+                    let mut todo = Todo::new(TodoId::new(), text);
+                    todo.due = *due;
+                    TodoOp::Create(todo)
+                }
+                BatchOperation::Update { id, patch } => TodoOp::Update {
+                    id: *id,
+                    patch: patch.into(),
+                },
+                BatchOperation::Delete { id } => TodoOp::Delete { id: *id },
+            })
+            .collect();
+
+        let results = measure_and_record_service("batch_todos", || async {
+            self.storage.batch(user.id, ops).await
+        })
+        .await?;
+
+        self.notify(user, results.iter().map(event_of).collect())
+            .await;
+        Ok(results)
+    }
+
+    #[instrument(name = "Service::todo::update_group", skip_all)]
+    pub(crate) async fn update_group(
+        &self,
+        user: &User,
+        group: &str,
+        patch: &UpdateTodo,
+    ) -> Result<usize, AppError> {
+        info!(group, "update todo group");
+
+        self.quota
+            .check_content(patch.text.as_deref(), patch.group.as_deref())?;
+
+        let updated = measure_and_record_service("update_todo_group", || async {
+            self.storage
+                .update_group(user.id, group.to_owned(), patch.into())
+                .await
+        })
+        .await?;
+
+        self.notify(
+            user,
+            updated
+                .iter()
+                .map(|id| (TodoEventKind::Updated, *id))
+                .collect(),
+        )
+        .await;
+        Ok(updated.len())
+    }
+
+    /// Streams all todos of the user rendered in `format`, one storage page per chunk.
+    #[instrument(name = "Service::todo::export", skip_all)]
+    pub(crate) fn export(
+        &self,
+        user: &User,
+        format: TransferFormat,
+    ) -> impl Stream<Item = Result<String, AppError>> + Send + 'static {
+        info!(format = ?format, "export todos");
+
+        let storage = self.storage.clone();
+        let user_id = user.id;
+        let start = Some((Exporter::new(format), None));
+
+        stream::try_unfold(start, move |state| {
+            let storage = storage.clone();
+            async move {
+                let Some((mut exporter, after)) = state else {
+                    return Ok(None);
+                };
+                let page = Pagination {
+                    after,
+                    limit: EXPORT_PAGE_SIZE,
+                };
+                let (todos, cursor) = measure_and_record_service("export_todos_page", || async {
+                    storage.get_all(user_id, page).await
+                })
+                .await?;
+
+                let mut chunk = exporter.render(&todos)?;
+                if cursor.is_none() {
+                    chunk.push_str(exporter.finish());
+                }
+                Ok(Some((chunk, cursor.map(|c| (exporter, Some(c))))))
+            }
+        })
+    }
+
+    /// Validates the whole file before writing anything and returns how many todos were added.
+    #[instrument(name = "Service::todo::import", skip_all, fields(len = input.len()))]
+    pub(crate) async fn import(
+        &self,
+        user: &User,
+        format: TransferFormat,
+        input: &str,
+    ) -> Result<usize, AppError> {
+        let todos = transfer::parse(format, input).map_err(AppError::InvalidImport)?;
+        info!(format = ?format, count = todos.len(), "import todos");
+
+        for todo in &todos {
+            self.quota
+                .check_content(Some(&todo.text), Some(&todo.group))?;
+        }
+
+        let mut todos = todos.into_iter().map(TodoOp::Create).peekable();
+        let mut imported = 0;
+        while todos.peek().is_some() {
+            let ops = todos.by_ref().take(IMPORT_BATCH_SIZE).collect::<Vec<_>>();
+            let results = measure_and_record_service("import_todos_batch", || async {
+                self.storage.batch(user.id, ops).await
+            })
+            .await?;
+            self.notify(user, results.iter().map(event_of).collect())
+                .await;
+            imported += results.len();
+        }
+
+        Ok(imported)
     }
 
     #[instrument(name = "Service::todo::delete_all", skip_all)]
     pub(crate) async fn delete_all(&self, user: &User) -> Result<(), AppError> {
-        measure_and_record_service("delete_all_todos", || async {
+        let deleted = measure_and_record_service("delete_all_todos", || async {
             self.storage.delete_all(user.id).await
         })
+        .await?;
+
+        self.notify(
+            user,
+            deleted
+                .into_iter()
+                .map(|id| (TodoEventKind::Deleted, id))
+                .collect(),
+        )
+        .await;
+        Ok(())
+    }
+
+    #[instrument(name = "Service::todo::get_trash", skip_all, fields(after_is_some = page.after.is_some(),
+    limit = page.limit))]
+    pub(crate) async fn get_trash(
+        &self,
+        user: &User,
+        page: Pagination<TodoId>,
+    ) -> Result<(Vec<TrashedTodo>, Option<TodoId>), AppError> {
+        measure_and_record_service("get_trash", || async {
+            self.storage.get_trash(user.id, page).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::todo::get_history", skip_all, fields(after_is_some = page.after.is_some(),
+    limit = page.limit))]
+    pub(crate) async fn get_history(
+        &self,
+        user: &User,
+        todo_id: TodoId,
+        page: Pagination<HistorySeq>,
+    ) -> Result<(Vec<HistoryEntry>, Option<HistorySeq>), AppError> {
+        measure_and_record_service("get_todo_history", || async {
+            self.storage.get_history(user.id, todo_id, page).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::todo::undo", skip_all)]
+    pub(crate) async fn undo(&self, user: &User) -> Result<UndoResult, AppError> {
+        info!("undo last todo operation");
+
+        let (result, touched) =
+            measure_and_record_service("undo", || async { self.storage.undo(user.id).await })
+                .await?;
+
+        // todos gone after the revert are published as deleted
+        self.notify(
+            user,
+            touched
+                .into_iter()
+                .map(|id| (TodoEventKind::Updated, id))
+                .collect(),
+        )
+        .await;
+        Ok(result)
+    }
+
+    #[instrument(name = "Service::todo::get_changes", skip_all, fields(after_is_some = page.after.is_some(),
+    limit = page.limit))]
+    pub(crate) async fn get_changes(
+        &self,
+        user: &User,
+        page: Pagination<SyncSeq>,
+    ) -> Result<(Vec<SyncChange>, Option<SyncSeq>), AppError> {
+        measure_and_record_service("get_todo_changes", || async {
+            self.storage.get_changes(user.id, page).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::todo::apply_changes", skip_all, fields(count = changes.len()))]
+    pub(crate) async fn apply_changes(
+        &self,
+        user: &User,
+        changes: Vec<SyncOperation>,
+    ) -> Result<Vec<SyncResult>, AppError> {
+        let kinds = changes
+            .iter()
+            .map(|change| match change {
+                SyncOperation::Upsert { base_rev: None, .. } => TodoEventKind::Created,
+                SyncOperation::Upsert { .. } => TodoEventKind::Updated,
+                SyncOperation::Delete { .. } => TodoEventKind::Deleted,
+            })
+            .collect::<Vec<_>>();
+        for (index, change) in changes.iter().enumerate() {
+            if let SyncOperation::Upsert { todo, .. } = change {
+                self.quota
+                    .check_content(Some(&todo.text), Some(&todo.group))
+                    .map_err(|source| AppError::BatchOperation {
+                        index,
+                        source: Box::new(source),
+                    })?;
+            }
+        }
+        let changes = changes
+            .into_iter()
+            .map(|change| match change {
+                SyncOperation::Upsert { todo, base_rev } => ClientChange::Upsert { todo, base_rev },
+                SyncOperation::Delete { id, base_rev } => ClientChange::Delete { id, base_rev },
+            })
+            .collect();
+
+        let results = measure_and_record_service("apply_todo_changes", || async {
+            self.storage.apply_changes(user.id, changes).await
+        })
+        .await?;
+
+        let applied = kinds
+            .into_iter()
+            .zip(&results)
+            .filter_map(|(kind, result)| match result {
+                SyncResult::Applied { id, .. } => Some((kind, *id)),
+                SyncResult::Conflict { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        self.notify(user, applied).await;
+        Ok(results)
+    }
+
+    #[instrument(name = "Service::todo::restore", skip_all)]
+    pub(crate) async fn restore(&self, user: &User, todo_id: TodoId) -> Result<(), AppError> {
+        info!(todo_id = %todo_id, "restore todo");
+
+        measure_and_record_service("restore_todo", || async {
+            self.storage.restore(user.id, todo_id).await
+        })
+        .await?;
+
+        self.notify(user, vec![(TodoEventKind::Created, todo_id)])
+            .await;
+        Ok(())
+    }
+
+    /// Permanently removes todos which stayed in the trash longer than `retention_sec`.
+    #[instrument(name = "Service::todo::purge_trash", skip_all)]
+    pub(crate) async fn purge_trash(&self, retention_sec: i64) -> Result<usize, AppError> {
+        let deleted_before = chrono::Utc::now().timestamp() - retention_sec;
+        info!(deleted_before, "purge trash");
+
+        measure_and_record_service("purge_trash", || async {
+            self.storage.purge_trash(deleted_before).await
+        })
         .await
         .map_err(Into::into)
     }
@@ -92,7 +526,18 @@ impl ServiceTodoRef {
         measure_and_record_service("delete_todo", || async {
             self.storage.delete(user.id, todo_id).await
         })
-        .await
-        .map_err(Into::into)
+        .await?;
+
+        self.notify(user, vec![(TodoEventKind::Deleted, todo_id)])
+            .await;
+        Ok(())
+    }
+}
+
+fn event_of(result: &TodoOpResult) -> (TodoEventKind, TodoId) {
+    match result {
+        TodoOpResult::Created { id } => (TodoEventKind::Created, *id),
+        TodoOpResult::Updated { id } => (TodoEventKind::Updated, *id),
+        TodoOpResult::Deleted { id } => (TodoEventKind::Deleted, *id),
     }
 }
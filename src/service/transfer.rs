@@ -0,0 +1,468 @@
+mod ical;
+
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    handlers::error::AppError,
+    storage::{Todo, TodoId},
+};
+
+const CSV_HEADER: &str = "id,text,completed,group,due\n";
+const MARKDOWN_TITLE: &str = "# Todos\n";
+
+/// Text format of `GET /todos/export` and `POST /todos/import`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TransferFormat {
+    Csv,
+    Ndjson,
+    Markdown,
+    TodoTxt,
+    Ical,
+}
+
+impl TransferFormat {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::TodoTxt => "text/plain; charset=utf-8",
+            Self::Ical => "text/calendar; charset=utf-8",
+        }
+    }
+
+    pub(crate) fn file_name(self) -> &'static str {
+        match self {
+            Self::Csv => "todos.csv",
+            Self::Ndjson => "todos.ndjson",
+            Self::Markdown => "todos.md",
+            Self::TodoTxt => "todo.txt",
+            Self::Ical => "todos.ics",
+        }
+    }
+}
+
+/// Problem found in a single line of an imported file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct ImportLineError {
+    /// 1-based line number in the imported file.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Renders todos page by page, remembering what has to carry over between
+/// pages: the file header, the Markdown section of the previous todo and
+/// the calendar footer written by [`Exporter::finish`].
+pub(crate) struct Exporter {
+    format: TransferFormat,
+    started: bool,
+    group: String,
+}
+
+impl Exporter {
+    pub(crate) fn new(format: TransferFormat) -> Self {
+        Self {
+            format,
+            started: false,
+            group: String::new(),
+        }
+    }
+
+    pub(crate) fn render(&mut self, todos: &[Todo]) -> Result<String, AppError> {
+        let mut out = String::new();
+        if !self.started {
+            self.started = true;
+            match self.format {
+                TransferFormat::Csv => out.push_str(CSV_HEADER),
+                TransferFormat::Markdown => out.push_str(MARKDOWN_TITLE),
+                TransferFormat::Ical => out.push_str(ical::HEADER),
+                TransferFormat::Ndjson | TransferFormat::TodoTxt => {}
+            }
+        }
+
+        for todo in todos {
+            if self.format == TransferFormat::Ical {
+                out.push_str(&ical::render(todo));
+                continue;
+            }
+            match self.format {
+                TransferFormat::Csv => {
+                    let fields = [
+                        todo.id.to_string(),
+                        csv_field(&todo.text),
+                        todo.completed.to_string(),
+                        csv_field(&todo.group),
+                        todo.due.and_then(rfc3339).unwrap_or_default(),
+                    ];
+                    out.push_str(&fields.join(","));
+                }
+                TransferFormat::Ndjson => out.push_str(&serde_json::to_string(todo)?),
+                TransferFormat::Markdown => {
+                    // todos come ordered by id, so a group may open several sections
+                    if todo.group != self.group {
+                        self.group.clone_from(&todo.group);
+                        if todo.group.is_empty() {
+                            out.push_str("\n---\n\n");
+                        } else {
+                            out.push_str(&format!("\n## {}\n\n", single_line(&todo.group)));
+                        }
+                    }
+                    let mark = if todo.completed { 'x' } else { ' ' };
+                    out.push_str(&format!("- [{mark}] {}", single_line(&todo.text)));
+                }
+                TransferFormat::TodoTxt => {
+                    if todo.completed {
+                        out.push_str("x ");
+                    }
+                    out.push_str(&single_line(&todo.text));
+                    if !todo.group.is_empty() {
+                        let project = todo.group.split_whitespace().collect::<Vec<_>>();
+                        out.push_str(&format!(" +{}", project.join("_")));
+                    }
+                    if let Some(due) = todo.due.and_then(|due| DateTime::from_timestamp(due, 0)) {
+                        out.push_str(&format!(" due:{}", due.format("%Y-%m-%d")));
+                    }
+                }
+                TransferFormat::Ical => {}
+            }
+            out.push('\n');
+        }
+
+        Ok(out)
+    }
+
+    /// Closes the file once the last page was rendered.
+    pub(crate) fn finish(&mut self) -> &'static str {
+        match self.format {
+            TransferFormat::Ical => ical::FOOTER,
+            _ => "",
+        }
+    }
+}
+
+fn rfc3339(timestamp: i64) -> Option<String> {
+    DateTime::from_timestamp(timestamp, 0).map(|time| time.to_rfc3339())
+}
+
+/// Accepts a full RFC 3339 time or a plain date, meaning its start in UTC.
+fn parse_due(value: &str) -> Result<Option<i64>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(time.timestamp()));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| Some(time.and_utc().timestamp()))
+        .ok_or_else(|| format!("invalid due date `{value}`"))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Line based formats can't keep line breaks of a text.
+fn single_line(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Parses an imported file into new todos. Nothing is returned unless every
+/// line is valid, so that a fixed file can be imported again without duplicates.
+pub(crate) fn parse(
+    format: TransferFormat,
+    input: &str,
+) -> Result<Vec<Todo>, Vec<ImportLineError>> {
+    let mut todos = Vec::new();
+    let mut errors = Vec::new();
+
+    let mut push =
+        |line: usize, row: Result<ImportRow, String>| match row.and_then(ImportRow::validate) {
+            Ok(row) => todos.push(row.into_todo()),
+            Err(message) => errors.push(ImportLineError { line, message }),
+        };
+
+    match format {
+        TransferFormat::Csv => parse_csv(input, &mut push),
+        TransferFormat::Ndjson => {
+            for (line, text) in numbered_lines(input) {
+                push(
+                    line,
+                    serde_json::from_str::<ImportRow>(text).map_err(|e| e.to_string()),
+                );
+            }
+        }
+        TransferFormat::Markdown => {
+            let mut group = String::new();
+            for (line, text) in numbered_lines(input) {
+                if let Some(row) = parse_markdown_line(text, &mut group) {
+                    push(line, row);
+                }
+            }
+        }
+        TransferFormat::TodoTxt => {
+            for (line, text) in numbered_lines(input) {
+                push(line, parse_todo_txt_line(text));
+            }
+        }
+        TransferFormat::Ical => ical::parse(input, &mut push),
+    }
+
+    if errors.is_empty() {
+        Ok(todos)
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportRow {
+    text: String,
+    #[serde(default)]
+    completed: bool,
+    #[serde(default)]
+    group: String,
+    #[serde(default)]
+    due: Option<i64>,
+}
+
+impl ImportRow {
+    fn validate(self) -> Result<Self, String> {
+        if self.text.trim().is_empty() {
+            return Err("text must not be empty".to_owned());
+        }
+        Ok(self)
+    }
+
+    fn into_todo(self) -> Todo {
+        let mut todo = Todo::new(TodoId::new(), self.text.trim());
+        todo.completed = self.completed;
+        todo.group = self.group.trim().to_owned();
+        todo.due = self.due;
+        todo
+    }
+}
+
+/// Non blank lines with their 1-based numbers.
+fn numbered_lines(input: &str) -> impl Iterator<Item = (usize, &str)> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn parse_csv(input: &str, push: &mut impl FnMut(usize, Result<ImportRow, String>)) {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+
+    let columns = match reader.headers() {
+        Ok(headers) => {
+            let find = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+            (find("text"), find("completed"), find("group"), find("due"))
+        }
+        Err(e) => return push(1, Err(e.to_string())),
+    };
+    let (Some(text), completed, group, due) = columns else {
+        return push(1, Err("header must have a `text` column".to_owned()));
+    };
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line() as usize);
+                push(line, Err(e.to_string()));
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |p| p.line() as usize);
+        let field = |column: Option<usize>| column.and_then(|c| record.get(c)).unwrap_or_default();
+
+        let row = parse_flag(field(completed)).and_then(|completed| {
+            Ok(ImportRow {
+                text: field(Some(text)).to_owned(),
+                completed,
+                group: field(group).to_owned(),
+                due: parse_due(field(due))?,
+            })
+        });
+        push(line, row);
+    }
+}
+
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "" | "false" | "no" | "0" => Ok(false),
+        "true" | "yes" | "1" => Ok(true),
+        _ => Err(format!("invalid completed value `{value}`")),
+    }
+}
+
+/// Headings of level two and below name the group of the items under them,
+/// the level one title and `---` go back to no group. Other text is skipped.
+fn parse_markdown_line(line: &str, group: &mut String) -> Option<Result<ImportRow, String>> {
+    if line.starts_with("---") || (line.starts_with("# ") && !line.starts_with("##")) {
+        group.clear();
+        return None;
+    }
+    if line.starts_with("##") {
+        *group = line.trim_start_matches('#').trim().to_owned();
+        return None;
+    }
+
+    let item = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))?
+        .trim_start();
+    let (completed, text) = if let Some(text) = item.strip_prefix("[ ]") {
+        (false, text)
+    } else if let Some(text) = item
+        .strip_prefix("[x]")
+        .or_else(|| item.strip_prefix("[X]"))
+    {
+        (true, text)
+    } else {
+        return Some(Err("list item must start with `[ ]` or `[x]`".to_owned()));
+    };
+
+    Some(Ok(ImportRow {
+        text: text.to_owned(),
+        completed,
+        group: group.clone(),
+        due: None,
+    }))
+}
+
+/// Follows <https://github.com/todotxt/todo.txt>: the first `+project` tag
+/// becomes the group, a `due:` tag the due date, priority and dates are dropped.
+fn parse_todo_txt_line(line: &str) -> Result<ImportRow, String> {
+    let mut words = line.split_whitespace().peekable();
+
+    let completed = words.next_if_eq(&"x").is_some();
+    let is_date = |word: &&str| chrono::NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok();
+    let is_priority = |word: &&str| {
+        let bytes = word.as_bytes();
+        bytes.len() == 3 && bytes[0] == b'(' && bytes[1].is_ascii_uppercase() && bytes[2] == b')'
+    };
+    words.next_if(is_priority);
+    // completion and creation dates
+    words.next_if(is_date);
+    words.next_if(is_date);
+
+    let mut group = String::new();
+    let mut due = None;
+    let mut text = Vec::new();
+    for word in words {
+        if let Some(date) = word.strip_prefix("due:") {
+            due = parse_due(date)?;
+            continue;
+        }
+        match word.strip_prefix('+') {
+            Some(project) if group.is_empty() && !project.is_empty() => {
+                group = project.to_owned();
+            }
+            _ => text.push(word),
+        }
+    }
+
+    Ok(ImportRow {
+        text: text.join(" "),
+        completed,
+        group,
+        due,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn todo(text: &str, completed: bool, group: &str) -> Todo {
+        let mut todo = Todo::new(TodoId::new(), text);
+        todo.completed = completed;
+        todo.group = group.to_owned();
+        todo
+    }
+
+    fn summary(todos: &[Todo]) -> Vec<(String, bool, String)> {
+        todos
+            .iter()
+            .map(|t| (t.text.clone(), t.completed, t.group.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let mut todos = vec![
+            todo("buy milk, eggs", false, ""),
+            todo("say \"hi\"", true, "home"),
+            todo("call bob", false, "home"),
+            todo("fix bug", true, ""),
+        ];
+        // midnight, so that the date only formats keep it as well
+        todos[2].due = Some(1_700_006_400);
+
+        for format in [
+            TransferFormat::Csv,
+            TransferFormat::Ndjson,
+            TransferFormat::Markdown,
+            TransferFormat::TodoTxt,
+            TransferFormat::Ical,
+        ] {
+            let mut exporter = Exporter::new(format);
+            let mut out = exporter.render(&todos[..2]).unwrap();
+            out.push_str(&exporter.render(&todos[2..]).unwrap());
+            out.push_str(exporter.finish());
+
+            let imported = parse(format, &out).unwrap();
+            assert_eq!(summary(&imported), summary(&todos), "{format:?}:\n{out}");
+            if format != TransferFormat::Markdown {
+                assert_eq!(imported[2].due, todos[2].due, "{format:?}:\n{out}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_reports_every_invalid_line() {
+        let input = "text,completed\nfirst,true\n,false\nthird,maybe\n";
+        let errors = parse(TransferFormat::Csv, input).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+
+        let input = "{\"text\":\"ok\"}\n\nnot json\n";
+        let errors = parse(TransferFormat::Ndjson, input).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+
+        let input = "- [ ] fine\n- [?] broken\nsome notes\n";
+        let errors = parse(TransferFormat::Markdown, input).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+    }
+
+    #[test]
+    fn test_parse_todo_txt() {
+        let input = "x 2024-01-02 2024-01-01 ship release +work @office\n(A) call mom +family\n";
+        let todos = parse(TransferFormat::TodoTxt, input).unwrap();
+        assert_eq!(
+            summary(&todos),
+            vec![
+                ("ship release @office".to_owned(), true, "work".to_owned()),
+                ("call mom".to_owned(), false, "family".to_owned()),
+            ]
+        );
+    }
+}
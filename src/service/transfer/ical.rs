@@ -0,0 +1,212 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+use super::ImportRow;
+use crate::storage::Todo;
+
+pub(super) const HEADER: &str =
+    "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//todo_app//todos//EN\r\n";
+pub(super) const FOOTER: &str = "END:VCALENDAR\r\n";
+
+const UTC_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const LOCAL_FORMAT: &str = "%Y%m%dT%H%M%S";
+const DATE_FORMAT: &str = "%Y%m%d";
+/// RFC 5545 limits content lines to 75 octets, longer ones are folded.
+const MAX_LINE_LEN: usize = 75;
+
+/// Renders a todo as a VTODO component.
+pub(super) fn render(todo: &Todo) -> String {
+    let mut out = String::new();
+
+    push_line(&mut out, "BEGIN:VTODO");
+    push_line(&mut out, &format!("UID:{}", todo.id));
+    push_line(
+        &mut out,
+        &format!("DTSTAMP:{}", Utc::now().format(UTC_FORMAT)),
+    );
+    push_line(&mut out, &format!("SUMMARY:{}", escape(&todo.text)));
+    if !todo.group.is_empty() {
+        push_line(&mut out, &format!("CATEGORIES:{}", escape(&todo.group)));
+    }
+    if let Some(due) = todo.due.and_then(|due| DateTime::from_timestamp(due, 0)) {
+        push_line(&mut out, &format!("DUE:{}", due.format(UTC_FORMAT)));
+    }
+    let status = if todo.completed {
+        "COMPLETED"
+    } else {
+        "NEEDS-ACTION"
+    };
+    push_line(&mut out, &format!("STATUS:{status}"));
+    push_line(&mut out, "END:VTODO");
+
+    out
+}
+
+fn push_line(out: &mut String, line: &str) {
+    let mut len = 0;
+    for ch in line.chars() {
+        if len + ch.len_utf8() > MAX_LINE_LEN {
+            out.push_str("\r\n ");
+            // the leading space of a continuation line counts as well
+            len = 1;
+        }
+        out.push(ch);
+        len += ch.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' | ';' | ',' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Unescapes a text value up to its first unescaped `separator`.
+fn unescape_first(value: &str, separator: Option<char>) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => out.push('\n'),
+                Some(escaped) => out.push(escaped),
+                None => {}
+            },
+            _ if Some(ch) == separator => break,
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Joins folded lines back, keeping the number of the line each one starts at.
+fn unfold(input: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, last))) => last.push_str(rest),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push((i + 1, line.to_owned())),
+        }
+    }
+    lines
+}
+
+/// Times with a `TZID` or without any zone are read as UTC, there is no time
+/// zone database to resolve them with.
+fn parse_time(value: &str, params: &str) -> Result<i64, String> {
+    let time = if params.to_ascii_uppercase().contains("VALUE=DATE") || value.len() == 8 {
+        NaiveDate::parse_from_str(value, DATE_FORMAT)
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    } else if value.ends_with('Z') {
+        NaiveDateTime::parse_from_str(value, UTC_FORMAT).ok()
+    } else {
+        NaiveDateTime::parse_from_str(value, LOCAL_FORMAT).ok()
+    };
+
+    time.map(|time| time.and_utc().timestamp())
+        .ok_or_else(|| format!("invalid date `{value}`"))
+}
+
+/// Reads VTODO components, other components of the calendar are skipped.
+/// Each todo is reported at the line of its `BEGIN:VTODO`.
+pub(super) fn parse(input: &str, push: &mut impl FnMut(usize, Result<ImportRow, String>)) {
+    // start line of the open VTODO, its row is `None` once an error was reported
+    let mut current: Option<(usize, Option<ImportRow>)> = None;
+
+    for (line, content) in unfold(input) {
+        let Some((name, value)) = content.split_once(':') else {
+            push(line, Err("content line must have a `:`".to_owned()));
+            continue;
+        };
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+        let name = name.to_ascii_uppercase();
+        let is_vtodo = value.eq_ignore_ascii_case("VTODO");
+
+        match (name.as_str(), &mut current) {
+            ("BEGIN", None) if is_vtodo => {
+                let row = ImportRow {
+                    text: String::new(),
+                    completed: false,
+                    group: String::new(),
+                    due: None,
+                };
+                current = Some((line, Some(row)));
+            }
+            ("BEGIN", Some(_)) if is_vtodo => push(line, Err("nested VTODO".to_owned())),
+            ("END", Some(_)) if is_vtodo => {
+                if let Some((start, Some(row))) = current.take() {
+                    push(start, Ok(row));
+                }
+            }
+            (_, Some((_, Some(row)))) => match name.as_str() {
+                "SUMMARY" => row.text = unescape_first(value, None),
+                "CATEGORIES" => row.group = unescape_first(value, Some(',')),
+                "STATUS" => row.completed = value.eq_ignore_ascii_case("COMPLETED"),
+                "COMPLETED" => row.completed = true,
+                "DUE" => match parse_time(value, params) {
+                    Ok(due) => row.due = Some(due),
+                    Err(message) => {
+                        push(line, Err(message));
+                        current = current.map(|(start, _)| (start, None));
+                    }
+                },
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    if let Some((start, _)) = current {
+        push(start, Err("VTODO is not closed".to_owned()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TodoId;
+
+    #[test]
+    fn test_long_lines_are_folded() {
+        let mut todo = Todo::new(TodoId::new(), &"долгий текст; ".repeat(20));
+        todo.due = Some(1_700_000_000);
+
+        let out = render(&todo);
+        assert!(out.split("\r\n").all(|line| line.len() <= MAX_LINE_LEN));
+        assert!(out.contains("DUE:20231114T221320Z\r\n"));
+
+        let mut rows = Vec::new();
+        parse(&out, &mut |line, row| rows.push((line, row.unwrap())));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, 1);
+        assert_eq!(rows[0].1.text, todo.text);
+        assert_eq!(rows[0].1.due, todo.due);
+    }
+
+    #[test]
+    fn test_parse_reports_invalid_due() {
+        let input = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nSUMMARY:a\r\nDUE;VALUE=DATE:2024-01-01\r\n\
+                     END:VTODO\r\nBEGIN:VTODO\r\nSUMMARY:b\r\nDUE;VALUE=DATE:20240101\r\n\
+                     END:VTODO\r\nEND:VCALENDAR\r\n";
+
+        let mut rows = Vec::new();
+        parse(input, &mut |line, row| rows.push((line, row)));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 4);
+        assert!(rows[0].1.is_err());
+        assert_eq!(rows[1].0, 6);
+        assert_eq!(rows[1].1.as_ref().unwrap().due, Some(1_704_067_200));
+    }
+}
@@ -1,16 +1,21 @@
 use std::sync::Arc;
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{rng, RngCore};
 use tracing::{info, instrument};
 
 use crate::{
     handlers::{error::AppError, DisplayUser, RegisterUser},
-    storage::{Pagination, Role, User, UserId, UserStorage},
+    storage::{CalendarToken, Pagination, Role, User, UserId, UserStorage},
     utils::measure_metrics::measure_and_record_service,
     Settings,
 };
 
 use super::{password::create_password_hash, UserCache};
 
+/// Random bytes of a calendar feed token.
+const CALENDAR_TOKEN_LEN: usize = 32;
+
 pub struct ServiceUserRef {
     storage: Arc<dyn UserStorage>,
     user_cache: Arc<UserCache>,
@@ -62,6 +67,18 @@ impl ServiceUserRef {
         .await
     }
 
+    /// Creates an admin outside of the configured ones, e.g. from the operator CLI.
+    #[instrument(name = "Service::user::add_admin", skip_all)]
+    pub(crate) async fn add_admin(
+        &self,
+        new_admin: RegisterUser,
+        settings: &Settings,
+    ) -> Result<(), AppError> {
+        info!(email = %new_admin.email, "create admin");
+
+        self.create_new_user(new_admin, Role::Admin, settings).await
+    }
+
     #[instrument(name = "Service::user::get", skip_all)]
     pub(crate) async fn get(&self, id: UserId) -> Result<User, AppError> {
         info!(user_id = %id, "get user by id");
@@ -84,6 +101,48 @@ impl ServiceUserRef {
         Ok(result?)
     }
 
+    /// Issues a new calendar feed token, the previous one stops working.
+    #[instrument(name = "Service::user::create_calendar_token", skip_all)]
+    pub(crate) async fn create_calendar_token(&self, user: &User) -> Result<String, AppError> {
+        info!(user_id = %user.id, "create calendar token");
+
+        let mut bytes = [0u8; CALENDAR_TOKEN_LEN];
+        rng().fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+
+        measure_and_record_service("create_calendar_token", || async {
+            self.storage
+                .put_calendar_token(CalendarToken::new(user.id, &token))
+                .await
+        })
+        .await?;
+
+        Ok(token)
+    }
+
+    #[instrument(name = "Service::user::revoke_calendar_token", skip_all)]
+    pub(crate) async fn revoke_calendar_token(&self, user: &User) -> Result<(), AppError> {
+        info!(user_id = %user.id, "revoke calendar token");
+
+        measure_and_record_service("revoke_calendar_token", || async {
+            self.storage.delete_calendar_token(user.id).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::user::get_by_calendar_token", skip_all)]
+    pub(crate) async fn get_by_calendar_token(&self, token: &str) -> Result<User, AppError> {
+        let token = measure_and_record_service("get_calendar_token", || async {
+            self.storage
+                .get_calendar_token(&CalendarToken::hash(token))
+                .await
+        })
+        .await?;
+
+        self.get(token.user_id).await
+    }
+
     #[instrument(name = "Service::user::get_by_email", skip_all)]
     pub(crate) async fn get_by_email(&self, email: &str) -> Result<User, AppError> {
         info!(email = %email, "get user by email");
@@ -116,12 +175,17 @@ impl ServiceUserRef {
     ) -> Result<(Vec<DisplayUser>, Option<UserId>), AppError> {
         info!(page_after = ?page.after, "get all todos with page");
 
-        let (users, cursor) = measure_and_record_service("get_users", || async {
-            self.storage.get_all(user.id, page).await
-        })
-        .await?;
+        self.get_page(Some(user.id), page).await
+    }
 
-        Ok((users.into_iter().map(Into::into).collect(), cursor))
+    /// Page of all users, including the admin asking for them.
+    #[instrument(name = "Service::user::list", skip_all, fields(after_is_some = page.after.is_some(),
+    limit = page.limit))]
+    pub(crate) async fn list(
+        &self,
+        page: Pagination<UserId>,
+    ) -> Result<(Vec<DisplayUser>, Option<UserId>), AppError> {
+        self.get_page(None, page).await
     }
 
     #[instrument(name = "Service::user::update", skip_all, fields(role = ?role))]
@@ -137,10 +201,16 @@ impl ServiceUserRef {
             return Err(AppError::Forbidden);
         }
 
+        self.set_role(update_user_id, role).await
+    }
+
+    /// Changes the role without checking who asks for it.
+    #[instrument(name = "Service::user::set_role", skip_all, fields(role = ?role))]
+    pub(crate) async fn set_role(&self, user_id: UserId, role: Role) -> Result<(), AppError> {
         let result = measure_and_record_service("change_user_role", || async {
-            let result = self.storage.update_role(update_user_id, role).await;
+            let result = self.storage.update_role(user_id, role).await;
             if result.is_ok() {
-                if let Some(user) = self.user_cache.by_id.get(&update_user_id).await {
+                if let Some(user) = self.user_cache.by_id.get(&user_id).await {
                     self.invalidate_user_in_cache(&user).await;
                 }
             }
@@ -175,6 +245,19 @@ impl ServiceUserRef {
 }
 
 impl ServiceUserRef {
+    async fn get_page(
+        &self,
+        exclude: Option<UserId>,
+        page: Pagination<UserId>,
+    ) -> Result<(Vec<DisplayUser>, Option<UserId>), AppError> {
+        let (users, cursor) = measure_and_record_service("get_users", || async {
+            self.storage.get_all(exclude, page).await
+        })
+        .await?;
+
+        Ok((users.into_iter().map(Into::into).collect(), cursor))
+    }
+
     async fn insert_user_into_cache(&self, user: &User) {
         self.user_cache.by_id.insert(user.id, user.clone()).await;
         self.user_cache
@@ -0,0 +1,448 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures_util::future::join_all;
+use rand::{rng, RngCore};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use ring::hmac;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    config::types::WebhookConfig,
+    handlers::{error::AppError, CreateWebhook},
+    storage::{
+        Delivery, DeliveryId, Pagination, Role, StorageError, User, Webhook, WebhookId,
+        WebhookStorage,
+    },
+    utils::measure_metrics::measure_and_record_service,
+};
+
+/// Webhooks a user can register.
+const MAX_WEBHOOKS: usize = 10;
+/// Random bytes of a signing secret.
+const SECRET_LEN: usize = 32;
+
+pub(crate) const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub(crate) const EVENT_ID_HEADER: &str = "X-Webhook-Id";
+pub(crate) const EVENT_TYPE_HEADER: &str = "X-Webhook-Event";
+
+pub struct ServiceWebhookRef {
+    storage: Arc<dyn WebhookStorage>,
+}
+
+impl ServiceWebhookRef {
+    pub(crate) fn new(storage: Arc<dyn WebhookStorage>) -> Self {
+        Self { storage }
+    }
+
+    /// Registers an endpoint and returns it together with its signing secret.
+    #[instrument(name = "Service::webhook::register", skip_all)]
+    pub(crate) async fn register(
+        &self,
+        user: &User,
+        new_webhook: CreateWebhook,
+        config: &WebhookConfig,
+    ) -> Result<Webhook, AppError> {
+        info!(user_id = %user.id, url = %new_webhook.url, "register webhook");
+
+        let url = reqwest::Url::parse(&new_webhook.url)
+            .map_err(|_| AppError::InvalidWebhook("url must be absolute"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AppError::InvalidWebhook("url must use http or https"));
+        }
+        check_target(&url, config)
+            .await
+            .map_err(AppError::InvalidWebhook)?;
+        if new_webhook.events.is_empty() {
+            return Err(AppError::InvalidWebhook("events must not be empty"));
+        }
+        if user.role != Role::Admin && new_webhook.events.iter().any(|kind| kind.is_user_event()) {
+            return Err(AppError::Forbidden);
+        }
+
+        let mut events = new_webhook.events;
+        events.sort_by_key(|kind| kind.as_str());
+        events.dedup();
+
+        let mut bytes = [0u8; SECRET_LEN];
+        rng().fill_bytes(&mut bytes);
+        let webhook = Webhook {
+            id: WebhookId::new(),
+            owner: user.id,
+            url: url.to_string(),
+            secret: URL_SAFE_NO_PAD.encode(bytes),
+            events,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+
+        measure_and_record_service("register_webhook", || async {
+            if self.storage.get_webhooks(user.id).await?.len() >= MAX_WEBHOOKS {
+                return Err(AppError::InvalidWebhook("too many webhooks"));
+            }
+            self.storage.put_webhook(webhook.clone()).await?;
+            Ok(())
+        })
+        .await?;
+
+        Ok(webhook)
+    }
+
+    #[instrument(name = "Service::webhook::get_all", skip_all)]
+    pub(crate) async fn get_all(&self, user: &User) -> Result<Vec<Webhook>, AppError> {
+        measure_and_record_service("get_webhooks", || async {
+            self.storage.get_webhooks(user.id).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::webhook::delete", skip_all)]
+    pub(crate) async fn delete(&self, user: &User, id: WebhookId) -> Result<(), AppError> {
+        info!(user_id = %user.id, webhook_id = %id, "delete webhook");
+
+        measure_and_record_service("delete_webhook", || async {
+            self.storage.delete_webhook(user.id, id).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    #[instrument(name = "Service::webhook::get_dead_letters", skip_all)]
+    pub(crate) async fn get_dead_letters(
+        &self,
+        user: &User,
+        pagination: Pagination<DeliveryId>,
+    ) -> Result<(Vec<Delivery>, Option<DeliveryId>), AppError> {
+        measure_and_record_service("get_dead_letters", || async {
+            self.storage.get_dead_letters(user.id, pagination).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Schedules a dead letter for immediate delivery with fresh attempts.
+    #[instrument(name = "Service::webhook::retry_dead_letter", skip_all)]
+    pub(crate) async fn retry_dead_letter(
+        &self,
+        user: &User,
+        id: DeliveryId,
+    ) -> Result<(), AppError> {
+        info!(user_id = %user.id, delivery_id = %id, "retry dead letter");
+
+        measure_and_record_service("retry_dead_letter", || async {
+            let now_ms = chrono::Utc::now().timestamp_millis();
+            self.storage.retry_dead_letter(user.id, id, now_ms).await
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Turns new outbox events into deliveries and sends the due ones.
+    /// Returns how many deliveries were attempted.
+    #[instrument(name = "Service::webhook::dispatch", skip_all)]
+    pub(crate) async fn dispatch(
+        &self,
+        client: &reqwest::Client,
+        config: &WebhookConfig,
+    ) -> Result<usize, AppError> {
+        let due = measure_and_record_service("dispatch_webhooks", || async {
+            self.storage.fan_out_events(config.batch_size).await?;
+            let now_ms = chrono::Utc::now().timestamp_millis();
+            self.storage
+                .get_due_deliveries(now_ms, config.batch_size)
+                .await
+        })
+        .await?;
+
+        let count = due.len();
+        let results = join_all(
+            due.into_iter()
+                .map(|delivery| self.deliver(client, delivery, config)),
+        )
+        .await;
+        for result in results {
+            result?;
+        }
+        Ok(count)
+    }
+
+    async fn deliver(
+        &self,
+        client: &reqwest::Client,
+        mut delivery: Delivery,
+        config: &WebhookConfig,
+    ) -> Result<(), AppError> {
+        let webhook = match self
+            .storage
+            .get_webhook(delivery.owner, delivery.webhook_id)
+            .await
+        {
+            Ok(webhook) => webhook,
+            Err(StorageError::NotFound) => {
+                info!(delivery_id = %delivery.id, "webhook of delivery is gone");
+                return Ok(self.storage.remove_delivery(delivery).await?);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match send(client, &webhook, &delivery, config).await {
+            Ok(()) => {
+                info!(delivery_id = %delivery.id, url = %webhook.url, "delivered webhook event");
+                self.storage.remove_delivery(delivery).await?;
+            }
+            Err(error) => {
+                delivery.attempts += 1;
+                warn!(
+                    delivery_id = %delivery.id,
+                    url = %webhook.url,
+                    attempts = delivery.attempts,
+                    error = %error,
+                    "webhook delivery failed"
+                );
+                delivery.last_error = Some(error);
+
+                let next_attempt_at = (delivery.attempts < config.max_attempts).then(|| {
+                    chrono::Utc::now().timestamp_millis() + backoff(delivery.attempts, config)
+                });
+                self.storage
+                    .fail_delivery(delivery, next_attempt_at)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Client for deliveries. It doesn't follow redirects, those could lead to
+/// targets that were never checked.
+pub(crate) fn client(config: &WebhookConfig) -> reqwest::Client {
+    let builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    let builder = if config.block_private_targets {
+        // checked again on connect, a name may resolve differently than at the check
+        builder.dns_resolver(Arc::new(PublicResolver))
+    } else {
+        builder
+    };
+    builder.build().expect("webhook client must build")
+}
+
+/// Resolver refusing names that point to addresses which aren't public.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(format!("{} resolves to a private address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Refuses urls whose host resolves to an address that isn't public, unless
+/// `block_private_targets` is off.
+async fn check_target(url: &reqwest::Url, config: &WebhookConfig) -> Result<(), &'static str> {
+    if !config.block_private_targets {
+        return Ok(());
+    }
+    let host = url.host_str().ok_or("url must have a host")?;
+    // ipv6 literals keep their brackets in urls
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| "url host can't be resolved")?
+        .collect();
+    if addrs.is_empty() {
+        return Err("url host can't be resolved");
+    }
+    if addrs.iter().any(|addr| !is_public(addr.ip())) {
+        return Err("url must not point to a private address");
+    }
+    Ok(())
+}
+
+/// Whether the address is reachable on the internet, as opposed to this host,
+/// its networks, or the link-local metadata services of cloud providers.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "this network" 0.0.0.0/8 and carrier-grade NAT 100.64.0.0/10
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Delay after the given number of failed attempts.
+fn backoff(attempts: u32, config: &WebhookConfig) -> i64 {
+    let factor = 1i64 << attempts.saturating_sub(1).min(62);
+    config
+        .backoff_base_ms
+        .saturating_mul(factor)
+        .min(config.backoff_max_ms)
+}
+
+/// Signature over `{timestamp}.{body}`, receivers should reject stale timestamps
+/// to stop replays.
+pub(crate) fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{timestamp}.{body}").as_bytes());
+    let hex: String = tag
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("t={timestamp},v1={hex}")
+}
+
+async fn send(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    delivery: &Delivery,
+    config: &WebhookConfig,
+) -> Result<(), String> {
+    let url = reqwest::Url::parse(&webhook.url).map_err(|e| e.to_string())?;
+    check_target(&url, config).await?;
+
+    let event = &delivery.event;
+    let data = event.data.to_json().map_err(|e| e.to_string())?;
+    let body = serde_json::json!({
+        "id": event.id,
+        "type": event.kind,
+        "occurred_at": event.occurred_at,
+        "user_id": event.user_id,
+        "data": data,
+    })
+    .to_string();
+    let timestamp = chrono::Utc::now().timestamp();
+
+    let response = client
+        .post(url)
+        .timeout(Duration::from_millis(config.timeout_ms))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, timestamp, &body))
+        .header(EVENT_ID_HEADER, event.id.to_string())
+        .header(EVENT_TYPE_HEADER, event.kind.as_str())
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("endpoint responded with {}", response.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let config = WebhookConfig {
+            poll_interval_ms: 10,
+            timeout_ms: 10,
+            max_attempts: 10,
+            backoff_base_ms: 100,
+            backoff_max_ms: 1_000,
+            batch_size: 10,
+            block_private_targets: true,
+        };
+
+        let delays: Vec<_> = (1..=6).map(|attempts| backoff(attempts, &config)).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(backoff(200, &config), 1_000);
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.215.14", "8.8.8.8", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_target() {
+        let mut config = WebhookConfig {
+            poll_interval_ms: 10,
+            timeout_ms: 10,
+            max_attempts: 10,
+            backoff_base_ms: 100,
+            backoff_max_ms: 1_000,
+            batch_size: 10,
+            block_private_targets: true,
+        };
+        let check = |url: &str, config: WebhookConfig| {
+            let url = reqwest::Url::parse(url).unwrap();
+            async move { check_target(&url, &config).await }
+        };
+
+        for url in [
+            "http://localhost:8080/hook",
+            "http://127.0.0.1/hook",
+            "http://[::1]/hook",
+            "http://169.254.169.254/latest/meta-data/",
+            "https://10.0.0.1/hook",
+        ] {
+            assert_eq!(
+                check(url, config.clone()).await,
+                Err("url must not point to a private address"),
+                "{url}"
+            );
+        }
+        assert_eq!(
+            check("https://93.184.215.14/hook", config.clone()).await,
+            Ok(())
+        );
+
+        config.block_private_targets = false;
+        assert_eq!(check("http://127.0.0.1/hook", config).await, Ok(()));
+    }
+}
@@ -0,0 +1,33 @@
+use bincode::{Decode, Encode};
+use chrono::Utc;
+
+use super::UserId;
+
+/// Grants read access to a user's calendar feed. Calendar clients can't
+/// refresh a JWT, so the feed URL carries this long lived token instead; only
+/// its SHA-256 hash is stored and a user has at most one at a time.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct CalendarToken {
+    pub(crate) user_id: UserId,
+    /// Hex encoded SHA-256 of the token.
+    pub(crate) token_hash: String,
+    pub(crate) created_at: i64,
+}
+
+impl CalendarToken {
+    pub(crate) fn new(user_id: UserId, token: &str) -> Self {
+        Self {
+            user_id,
+            token_hash: Self::hash(token),
+            created_at: Utc::now().timestamp(),
+        }
+    }
+
+    pub(crate) fn hash(token: &str) -> String {
+        ring::digest::digest(&ring::digest::SHA256, token.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
@@ -14,12 +14,34 @@ pub enum StorageError {
     #[error("Failed to parse id from string")]
     ParseIdFromString(#[from] uuid::Error),
 
+    #[error("Failed to parse sequence number from string")]
+    ParseSeqFromString(#[from] std::num::ParseIntError),
+
     #[error("Failed to create expiration date for session")]
     InvalidTtl,
 
     #[error("Session expired")]
     SessionExpired,
 
+    #[error("Dependency would create a cycle")]
+    DependencyCycle,
+
+    #[error("Todo is blocked by unfinished todos")]
+    TodoBlocked,
+
+    #[error("The limit of {max} {quota} is reached")]
+    QuotaExceeded { quota: &'static str, max: usize },
+
+    #[error("Batch operation {index} failed")]
+    BatchOperation {
+        index: usize,
+        #[source]
+        source: Box<StorageError>,
+    },
+
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+
     #[error("Internal storage error")]
     Internal(#[source] SledStorageError),
 
@@ -0,0 +1,637 @@
+//! Storage decorator that makes calls of the wrapped storage slow or fail,
+//! to see how the layers above cope. Faults are picked per call by a script
+//! or by chance, and can be changed while the app runs through `Faults`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tracing::warn;
+
+use crate::config::types::LimitsConfig;
+
+use super::{
+    sled::error::SledStorageError, BackupManifest, CalendarToken, ClientChange, Delivery,
+    DeliveryId, FlushStorage, HistoryEntry, HistorySeq, Jti, MaintenanceStorage, MigrationReport,
+    Pagination, Role, RotationReport, Session, SessionId, SessionStorage, StorageError,
+    StorageReport, StorageStats, SyncChange, SyncResult, SyncSeq, Todo, TodoId, TodoLinks, TodoOp,
+    TodoOpResult, TodoStorage, TrashedTodo, UndoResult, UpdateTodo, User, UserId, UserStorage,
+    Webhook, WebhookId, WebhookStorage,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Delays the call, which then runs as usual.
+    Latency(Duration),
+    /// Fails with `StorageError::Internal` without running the call.
+    Internal,
+    /// Fails with `StorageError::JoinError`, as if the blocking task of the
+    /// call was cancelled.
+    Join,
+    /// Runs the call and fails with `StorageError::Internal` anyway, whatever
+    /// the call wrote stays written.
+    Partial,
+}
+
+struct Rule {
+    pattern: String,
+    probability: f64,
+    fault: Fault,
+}
+
+struct Script {
+    pattern: String,
+    steps: VecDeque<Option<Fault>>,
+}
+
+struct State {
+    rules: Vec<Rule>,
+    scripts: Vec<Script>,
+    rng: StdRng,
+    injected: HashMap<&'static str, usize>,
+}
+
+/// Faults of the storages wrapped with it, shared by clones. Operations are
+/// named `<trait>::<method>` like `todo::put` or `maintenance::backup`, a
+/// pattern is an operation, a prefix ending in `*` like `todo::*`, or `*`.
+#[derive(Clone)]
+pub struct Faults {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self::seeded(rand::random())
+    }
+}
+
+impl Faults {
+    /// Faults whose chance picks repeat from run to run.
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                rules: Vec::new(),
+                scripts: Vec::new(),
+                rng: StdRng::seed_from_u64(seed),
+                injected: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Injects `fault` into calls matching `pattern` with the given
+    /// probability, from 0 to 1. The first matching rule that hits wins.
+    pub fn inject(&self, pattern: &str, probability: f64, fault: Fault) -> &Self {
+        self.lock().rules.push(Rule {
+            pattern: pattern.to_string(),
+            probability: probability.clamp(0.0, 1.0),
+            fault,
+        });
+        self
+    }
+
+    /// Calls matching `pattern` take the steps in turn, `None` lets a call
+    /// through. Scripts go before rules, a finished script is dropped.
+    pub fn script(&self, pattern: &str, steps: impl IntoIterator<Item = Option<Fault>>) -> &Self {
+        self.lock().scripts.push(Script {
+            pattern: pattern.to_string(),
+            steps: steps.into_iter().collect(),
+        });
+        self
+    }
+
+    /// Drops all rules and scripts, the counts of injected faults stay.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.rules.clear();
+        state.scripts.clear();
+    }
+
+    /// Faults injected into `operation` so far.
+    pub fn injected(&self, operation: &str) -> usize {
+        self.lock()
+            .injected
+            .get(operation)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("faults lock poisoned")
+    }
+
+    fn pick(&self, operation: &'static str) -> Option<Fault> {
+        let mut state = self.lock();
+        let scripted = state
+            .scripts
+            .iter()
+            .position(|script| matches(&script.pattern, operation));
+        let fault = match scripted {
+            Some(index) => {
+                let step = state.scripts[index].steps.pop_front().flatten();
+                if state.scripts[index].steps.is_empty() {
+                    state.scripts.remove(index);
+                }
+                step
+            }
+            None => {
+                let State { rules, rng, .. } = &mut *state;
+                rules
+                    .iter()
+                    .filter(|rule| matches(&rule.pattern, operation))
+                    .find(|rule| rng.random_bool(rule.probability))
+                    .map(|rule| rule.fault.clone())
+            }
+        };
+        if fault.is_some() {
+            *state.injected.entry(operation).or_default() += 1;
+        }
+        fault
+    }
+
+    async fn run<T>(
+        &self,
+        operation: &'static str,
+        call: impl std::future::Future<Output = Result<T, StorageError>>,
+    ) -> Result<T, StorageError> {
+        let Some(fault) = self.pick(operation) else {
+            return call.await;
+        };
+        warn!(operation, fault = ?fault, "injecting storage fault");
+        match fault {
+            Fault::Latency(delay) => {
+                tokio::time::sleep(delay).await;
+                call.await
+            }
+            Fault::Internal => Err(injected_error()),
+            Fault::Join => Err(join_error().await),
+            Fault::Partial => {
+                let _ = call.await;
+                Err(injected_error())
+            }
+        }
+    }
+}
+
+fn matches(pattern: &str, operation: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => operation.starts_with(prefix),
+        None => pattern == operation,
+    }
+}
+
+fn injected_error() -> StorageError {
+    let io = std::io::Error::other("injected fault");
+    StorageError::Internal(SledStorageError::Sled(sled::Error::Io(io)))
+}
+
+async fn join_error() -> StorageError {
+    let task = tokio::spawn(std::future::pending::<()>());
+    task.abort();
+    match task.await {
+        Err(e) => StorageError::JoinError(e),
+        Ok(()) => unreachable!("a pending task can't finish"),
+    }
+}
+
+/// Wraps any storage, see `Faults`.
+pub(crate) struct FaultyStorage<S: ?Sized> {
+    inner: Arc<S>,
+    faults: Faults,
+}
+
+impl<S: ?Sized> FaultyStorage<S> {
+    pub(crate) fn new(inner: Arc<S>, faults: &Faults) -> Self {
+        Self {
+            inner,
+            faults: faults.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: TodoStorage + ?Sized> TodoStorage for FaultyStorage<S> {
+    fn set_limits(&self, limits: LimitsConfig) {
+        self.inner.set_limits(limits);
+    }
+
+    async fn get(&self, user_id: UserId, id: TodoId) -> Result<Todo, StorageError> {
+        self.faults
+            .run("todo::get", self.inner.get(user_id, id))
+            .await
+    }
+
+    async fn put(&self, user_id: UserId, id: TodoId, item: Todo) -> Result<(), StorageError> {
+        self.faults
+            .run("todo::put", self.inner.put(user_id, id, item))
+            .await
+    }
+
+    async fn delete(&self, user_id: UserId, id: TodoId) -> Result<(), StorageError> {
+        self.faults
+            .run("todo::delete", self.inner.delete(user_id, id))
+            .await
+    }
+
+    async fn update(
+        &self,
+        user_id: UserId,
+        id: TodoId,
+        patch: UpdateTodo,
+    ) -> Result<(), StorageError> {
+        self.faults
+            .run("todo::update", self.inner.update(user_id, id, patch))
+            .await
+    }
+
+    async fn get_all(
+        &self,
+        user_id: UserId,
+        page: Pagination<TodoId>,
+    ) -> Result<(Vec<Todo>, Option<TodoId>), StorageError> {
+        self.faults
+            .run("todo::get_all", self.inner.get_all(user_id, page))
+            .await
+    }
+
+    async fn delete_all(&self, user_id: UserId) -> Result<Vec<TodoId>, StorageError> {
+        self.faults
+            .run("todo::delete_all", self.inner.delete_all(user_id))
+            .await
+    }
+
+    async fn batch(
+        &self,
+        user_id: UserId,
+        ops: Vec<TodoOp>,
+    ) -> Result<Vec<TodoOpResult>, StorageError> {
+        self.faults
+            .run("todo::batch", self.inner.batch(user_id, ops))
+            .await
+    }
+
+    async fn update_group(
+        &self,
+        user_id: UserId,
+        group: String,
+        patch: UpdateTodo,
+    ) -> Result<Vec<TodoId>, StorageError> {
+        self.faults
+            .run(
+                "todo::update_group",
+                self.inner.update_group(user_id, group, patch),
+            )
+            .await
+    }
+
+    async fn get_links(&self, user_id: UserId, id: TodoId) -> Result<TodoLinks, StorageError> {
+        self.faults
+            .run("todo::get_links", self.inner.get_links(user_id, id))
+            .await
+    }
+
+    async fn add_link(
+        &self,
+        user_id: UserId,
+        id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "todo::add_link",
+                self.inner.add_link(user_id, id, blocker_id),
+            )
+            .await
+    }
+
+    async fn remove_link(
+        &self,
+        user_id: UserId,
+        id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "todo::remove_link",
+                self.inner.remove_link(user_id, id, blocker_id),
+            )
+            .await
+    }
+
+    async fn get_trash(
+        &self,
+        user_id: UserId,
+        page: Pagination<TodoId>,
+    ) -> Result<(Vec<TrashedTodo>, Option<TodoId>), StorageError> {
+        self.faults
+            .run("todo::get_trash", self.inner.get_trash(user_id, page))
+            .await
+    }
+
+    async fn restore(&self, user_id: UserId, id: TodoId) -> Result<(), StorageError> {
+        self.faults
+            .run("todo::restore", self.inner.restore(user_id, id))
+            .await
+    }
+
+    async fn purge_trash(&self, deleted_before: i64) -> Result<usize, StorageError> {
+        self.faults
+            .run("todo::purge_trash", self.inner.purge_trash(deleted_before))
+            .await
+    }
+
+    async fn get_history(
+        &self,
+        user_id: UserId,
+        id: TodoId,
+        page: Pagination<HistorySeq>,
+    ) -> Result<(Vec<HistoryEntry>, Option<HistorySeq>), StorageError> {
+        self.faults
+            .run(
+                "todo::get_history",
+                self.inner.get_history(user_id, id, page),
+            )
+            .await
+    }
+
+    async fn undo(&self, user_id: UserId) -> Result<(UndoResult, Vec<TodoId>), StorageError> {
+        self.faults
+            .run("todo::undo", self.inner.undo(user_id))
+            .await
+    }
+
+    async fn get_changes(
+        &self,
+        user_id: UserId,
+        page: Pagination<SyncSeq>,
+    ) -> Result<(Vec<SyncChange>, Option<SyncSeq>), StorageError> {
+        self.faults
+            .run("todo::get_changes", self.inner.get_changes(user_id, page))
+            .await
+    }
+
+    async fn apply_changes(
+        &self,
+        user_id: UserId,
+        changes: Vec<ClientChange>,
+    ) -> Result<Vec<SyncResult>, StorageError> {
+        self.faults
+            .run(
+                "todo::apply_changes",
+                self.inner.apply_changes(user_id, changes),
+            )
+            .await
+    }
+}
+
+#[async_trait]
+impl<S: UserStorage + ?Sized> UserStorage for FaultyStorage<S> {
+    async fn get_by_email(&self, email: &str) -> Result<User, StorageError> {
+        self.faults
+            .run("user::get_by_email", self.inner.get_by_email(email))
+            .await
+    }
+
+    async fn get(&self, id: UserId) -> Result<User, StorageError> {
+        self.faults.run("user::get", self.inner.get(id)).await
+    }
+
+    async fn put(&self, id: UserId, user: User) -> Result<(), StorageError> {
+        self.faults.run("user::put", self.inner.put(id, user)).await
+    }
+
+    async fn delete(&self, id: UserId) -> Result<(), StorageError> {
+        self.faults.run("user::delete", self.inner.delete(id)).await
+    }
+
+    async fn update_role(&self, id: UserId, role: Role) -> Result<(), StorageError> {
+        self.faults
+            .run("user::update_role", self.inner.update_role(id, role))
+            .await
+    }
+
+    async fn get_all(
+        &self,
+        exclude: Option<UserId>,
+        page: Pagination<UserId>,
+    ) -> Result<(Vec<User>, Option<UserId>), StorageError> {
+        self.faults
+            .run("user::get_all", self.inner.get_all(exclude, page))
+            .await
+    }
+
+    async fn put_calendar_token(&self, token: CalendarToken) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "user::put_calendar_token",
+                self.inner.put_calendar_token(token),
+            )
+            .await
+    }
+
+    async fn get_calendar_token(&self, token_hash: &str) -> Result<CalendarToken, StorageError> {
+        self.faults
+            .run(
+                "user::get_calendar_token",
+                self.inner.get_calendar_token(token_hash),
+            )
+            .await
+    }
+
+    async fn delete_calendar_token(&self, user_id: UserId) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "user::delete_calendar_token",
+                self.inner.delete_calendar_token(user_id),
+            )
+            .await
+    }
+}
+
+#[async_trait]
+impl<S: WebhookStorage + ?Sized> WebhookStorage for FaultyStorage<S> {
+    async fn put_webhook(&self, webhook: Webhook) -> Result<(), StorageError> {
+        self.faults
+            .run("webhook::put_webhook", self.inner.put_webhook(webhook))
+            .await
+    }
+
+    async fn get_webhook(&self, owner: UserId, id: WebhookId) -> Result<Webhook, StorageError> {
+        self.faults
+            .run("webhook::get_webhook", self.inner.get_webhook(owner, id))
+            .await
+    }
+
+    async fn get_webhooks(&self, owner: UserId) -> Result<Vec<Webhook>, StorageError> {
+        self.faults
+            .run("webhook::get_webhooks", self.inner.get_webhooks(owner))
+            .await
+    }
+
+    async fn delete_webhook(&self, owner: UserId, id: WebhookId) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "webhook::delete_webhook",
+                self.inner.delete_webhook(owner, id),
+            )
+            .await
+    }
+
+    async fn fan_out_events(&self, limit: usize) -> Result<usize, StorageError> {
+        self.faults
+            .run("webhook::fan_out_events", self.inner.fan_out_events(limit))
+            .await
+    }
+
+    async fn get_due_deliveries(
+        &self,
+        now_ms: i64,
+        limit: usize,
+    ) -> Result<Vec<Delivery>, StorageError> {
+        self.faults
+            .run(
+                "webhook::get_due_deliveries",
+                self.inner.get_due_deliveries(now_ms, limit),
+            )
+            .await
+    }
+
+    async fn remove_delivery(&self, delivery: Delivery) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "webhook::remove_delivery",
+                self.inner.remove_delivery(delivery),
+            )
+            .await
+    }
+
+    async fn fail_delivery(
+        &self,
+        delivery: Delivery,
+        next_attempt_at: Option<i64>,
+    ) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "webhook::fail_delivery",
+                self.inner.fail_delivery(delivery, next_attempt_at),
+            )
+            .await
+    }
+
+    async fn get_dead_letters(
+        &self,
+        owner: UserId,
+        page: Pagination<DeliveryId>,
+    ) -> Result<(Vec<Delivery>, Option<DeliveryId>), StorageError> {
+        self.faults
+            .run(
+                "webhook::get_dead_letters",
+                self.inner.get_dead_letters(owner, page),
+            )
+            .await
+    }
+
+    async fn retry_dead_letter(
+        &self,
+        owner: UserId,
+        id: DeliveryId,
+        now_ms: i64,
+    ) -> Result<(), StorageError> {
+        self.faults
+            .run(
+                "webhook::retry_dead_letter",
+                self.inner.retry_dead_letter(owner, id, now_ms),
+            )
+            .await
+    }
+}
+
+#[async_trait]
+impl<S: SessionStorage + ?Sized> SessionStorage for FaultyStorage<S> {
+    async fn get(&self, id: SessionId) -> Result<Session, StorageError> {
+        self.faults.run("session::get", self.inner.get(id)).await
+    }
+
+    async fn put(&self, id: SessionId, session: Session) -> Result<(), StorageError> {
+        self.faults
+            .run("session::put", self.inner.put(id, session))
+            .await
+    }
+
+    async fn delete(&self, id: SessionId) -> Result<(), StorageError> {
+        self.faults
+            .run("session::delete", self.inner.delete(id))
+            .await
+    }
+
+    async fn update(&self, id: SessionId, refresh_jti: Jti) -> Result<(), StorageError> {
+        self.faults
+            .run("session::update", self.inner.update(id, refresh_jti))
+            .await
+    }
+
+    async fn purge(&self, now: i64) -> Result<usize, StorageError> {
+        self.faults
+            .run("session::purge", self.inner.purge(now))
+            .await
+    }
+}
+
+#[async_trait]
+impl<S: FlushStorage + ?Sized> FlushStorage for FaultyStorage<S> {
+    async fn flush(&self) -> Result<(), StorageError> {
+        self.faults.run("flush::flush", self.inner.flush()).await
+    }
+}
+
+#[async_trait]
+impl<S: MaintenanceStorage + ?Sized> MaintenanceStorage for FaultyStorage<S> {
+    async fn backup(&self, path: PathBuf) -> Result<BackupManifest, StorageError> {
+        self.faults
+            .run("maintenance::backup", self.inner.backup(path))
+            .await
+    }
+
+    async fn inspect(&self, path: PathBuf) -> Result<BackupManifest, StorageError> {
+        self.faults
+            .run("maintenance::inspect", self.inner.inspect(path))
+            .await
+    }
+
+    async fn restore(&self, path: PathBuf) -> Result<BackupManifest, StorageError> {
+        self.faults
+            .run("maintenance::restore", self.inner.restore(path))
+            .await
+    }
+
+    async fn verify(&self, repair: bool) -> Result<StorageReport, StorageError> {
+        self.faults
+            .run("maintenance::verify", self.inner.verify(repair))
+            .await
+    }
+
+    async fn migrate(&self, dry_run: bool) -> Result<MigrationReport, StorageError> {
+        self.faults
+            .run("maintenance::migrate", self.inner.migrate(dry_run))
+            .await
+    }
+
+    async fn rotate_keys(&self, batch_size: usize) -> Result<RotationReport, StorageError> {
+        self.faults
+            .run(
+                "maintenance::rotate_keys",
+                self.inner.rotate_keys(batch_size),
+            )
+            .await
+    }
+
+    async fn stats(&self, top_users: usize) -> Result<StorageStats, StorageError> {
+        self.faults
+            .run("maintenance::stats", self.inner.stats(top_users))
+            .await
+    }
+}
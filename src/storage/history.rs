@@ -0,0 +1,199 @@
+use std::{fmt::Display, str::FromStr};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{page::HasId, Sealed, SealedRecord, StorageError, Todo, UserId};
+
+/// Position of a history entry, increases with every recorded change.
+#[derive(
+    Encode, Decode, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(transparent)]
+pub struct HistorySeq(pub(crate) u64);
+
+impl Display for HistorySeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for HistorySeq {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.parse().map_err(StorageError::ParseSeqFromString)?))
+    }
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoField {
+    Text,
+    Completed,
+    Group,
+    Due,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Text(String),
+    Flag(bool),
+    /// Unix timestamp (seconds).
+    Time(i64),
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct FieldChange {
+    pub field: TodoField,
+    pub old: Option<FieldValue>,
+    pub new: Option<FieldValue>,
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct HistoryEntry {
+    #[schema(value_type = u64)]
+    pub seq: HistorySeq,
+    pub action: HistoryAction,
+    #[schema(value_type = String)]
+    pub actor: UserId,
+    /// Unix timestamp (seconds) of the change.
+    pub at: i64,
+    pub changes: Vec<FieldChange>,
+}
+
+impl HasId<HistorySeq> for HistoryEntry {
+    fn id(&self) -> HistorySeq {
+        self.seq
+    }
+}
+
+/// How a `HistoryEntry` is stored, see `UserVersion`.
+#[derive(Encode, Decode, Debug, Clone)]
+pub(crate) enum HistoryVersion {
+    V1 {
+        seq: HistorySeq,
+        action: HistoryAction,
+        actor: UserId,
+        at: i64,
+        changes: Vec<FieldChange>,
+    },
+    /// Changes encrypted at rest, they hold the text and group of the todo.
+    Sealed {
+        seq: HistorySeq,
+        action: HistoryAction,
+        actor: UserId,
+        at: i64,
+        changes: Sealed,
+    },
+}
+
+impl TryFrom<HistoryVersion> for HistoryEntry {
+    type Error = SealedRecord;
+
+    fn try_from(value: HistoryVersion) -> Result<Self, Self::Error> {
+        match value {
+            HistoryVersion::V1 {
+                seq,
+                action,
+                actor,
+                at,
+                changes,
+            } => Ok(Self {
+                seq,
+                action,
+                actor,
+                at,
+                changes,
+            }),
+            HistoryVersion::Sealed { .. } => Err(SealedRecord),
+        }
+    }
+}
+
+impl From<HistoryEntry> for HistoryVersion {
+    fn from(value: HistoryEntry) -> Self {
+        Self::V1 {
+            seq: value.seq,
+            action: value.action,
+            actor: value.actor,
+            at: value.at,
+            changes: value.changes,
+        }
+    }
+}
+
+const FIELDS: [TodoField; 4] = [
+    TodoField::Text,
+    TodoField::Completed,
+    TodoField::Group,
+    TodoField::Due,
+];
+
+fn value(todo: &Todo, field: TodoField) -> Option<FieldValue> {
+    match field {
+        TodoField::Text => Some(FieldValue::Text(todo.text.clone())),
+        TodoField::Completed => Some(FieldValue::Flag(todo.completed)),
+        TodoField::Group => Some(FieldValue::Text(todo.group.clone())),
+        TodoField::Due => todo.due.map(FieldValue::Time),
+    }
+}
+
+/// Lists fields that differ between two states of a todo; a missing state
+/// means the todo didn't exist before (or after) the change.
+pub(crate) fn diff(before: Option<&Todo>, after: Option<&Todo>) -> Vec<FieldChange> {
+    FIELDS
+        .into_iter()
+        .filter_map(|field| {
+            let old = before.and_then(|todo| value(todo, field));
+            let new = after.and_then(|todo| value(todo, field));
+
+            (old != new).then_some(FieldChange { field, old, new })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TodoId;
+
+    #[test]
+    fn test_diff() {
+        let todo = Todo::new(TodoId::new(), "aaa");
+        let mut updated = todo.clone();
+        updated.completed = true;
+
+        let changes = diff(Some(&todo), Some(&updated));
+        assert_eq!(
+            changes,
+            vec![FieldChange {
+                field: TodoField::Completed,
+                old: Some(FieldValue::Flag(false)),
+                new: Some(FieldValue::Flag(true)),
+            }]
+        );
+
+        assert!(diff(Some(&todo), Some(&todo)).is_empty());
+
+        updated.due = Some(1_700_000_000);
+        let changes = diff(Some(&todo), Some(&updated));
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].field, TodoField::Due);
+        assert_eq!(changes[1].old, None);
+
+        assert_eq!(diff(None, Some(&todo)).len(), 3);
+        assert!(diff(Some(&todo), None).iter().all(|c| c.new.is_none()));
+    }
+}
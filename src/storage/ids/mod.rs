@@ -5,3 +5,4 @@ define_uuid_id!(UserId);
 define_uuid_id!(TodoId);
 define_uuid_id!(SessionId);
 define_uuid_id!(Jti);
+define_uuid_id!(WebhookId);
@@ -0,0 +1,140 @@
+use bincode::{Decode, Encode};
+use strum_macros::AsRefStr;
+
+use super::UserId;
+
+/// Upper bounds of the buckets users are counted in by how many todos they
+/// have, the last bucket takes everyone above.
+pub const TODO_BUCKETS: [usize; 5] = [0, 10, 100, 1_000, 10_000];
+
+/// Describes the content of a backup archive.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct BackupManifest {
+    /// Unix timestamp the snapshot was taken at.
+    pub created_at: i64,
+    pub trees: Vec<BackupTree>,
+}
+
+impl BackupManifest {
+    pub fn records(&self) -> u64 {
+        self.trees.iter().map(|tree| tree.records).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct BackupTree {
+    pub name: String,
+    pub records: u64,
+    /// CRC32 of the keys and values of the tree, in key order.
+    pub checksum: u32,
+}
+
+/// Schema migrations run, or only planned on a dry run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Schema version the storage had.
+    pub from: u32,
+    /// Schema version the code writes.
+    pub to: u32,
+    pub steps: Vec<MigrationStep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStep {
+    /// Schema version the step brings the storage to.
+    pub version: u32,
+    pub description: &'static str,
+    /// Records the step inserts, replaces or removes.
+    pub writes: usize,
+}
+
+/// Outcome of `MaintenanceStorage::rotate_keys`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RotationReport {
+    /// Users that got a new data key.
+    pub rotated: usize,
+    /// Records sealed again in each tree.
+    pub records: Vec<(&'static str, usize)>,
+    /// Records that could not be opened, old data keys are kept while any is left.
+    pub skipped: usize,
+    /// Data keys removed once no record needed them.
+    pub retired: usize,
+    /// Data keys wrapped again with the current master key.
+    pub rewrapped: usize,
+}
+
+/// Outcome of `MaintenanceStorage::stats`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Unix timestamp the stats were collected at.
+    pub collected_at: i64,
+    /// Bytes the database takes on disk, its write-ahead log included.
+    pub size_on_disk: u64,
+    /// Records in each tree.
+    pub records: Vec<(&'static str, usize)>,
+    /// Users with the most todos, most first.
+    pub top_users: Vec<(UserId, usize)>,
+    /// Users counted in `TODO_BUCKETS`.
+    pub todos_per_user: Vec<TodoBucket>,
+    pub active_sessions: usize,
+    pub expired_sessions: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoBucket {
+    /// Most todos a user of the bucket has, `None` for the last bucket.
+    pub up_to: Option<usize>,
+    pub users: usize,
+}
+
+/// Outcome of `MaintenanceStorage::verify`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StorageReport {
+    /// Records checked in each tree.
+    pub records: Vec<(&'static str, usize)>,
+    pub problems: Vec<StorageProblem>,
+}
+
+impl StorageReport {
+    /// No problem is left, found ones may have been repaired.
+    pub fn is_ok(&self) -> bool {
+        self.problems.iter().all(|problem| problem.repaired)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageProblem {
+    pub tree: &'static str,
+    /// Key of the record, lossily decoded as UTF-8.
+    pub key: String,
+    pub kind: ProblemKind,
+    pub detail: String,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ProblemKind {
+    /// `Key::from_bytes` rejects the key. Repair removes the record.
+    MalformedKey,
+    /// The value doesn't decode as the record of its tree. Repair removes the record.
+    UndecodableValue,
+    /// The `emails` entry of a user's email is missing or points at a user
+    /// without that email, so the user can't log in. Repair points it at the user.
+    MissingEmail,
+    /// An `emails` entry pointing at a missing user, or at a user with another
+    /// email. Repair removes the entry.
+    DanglingEmail,
+    /// The `emails` entry of a user's email points at another user with the
+    /// same email. Only reported, repair can't tell which user owns it.
+    TakenEmail,
+    /// A todo of a missing user. Repair removes it with its links.
+    OrphanTodo,
+    /// A session of a missing user. Repair removes it.
+    OrphanSession,
+    /// Usage counts of a missing user. Repair removes them.
+    OrphanUsage,
+    /// Usage counts that don't match the todos of the user. Repair removes
+    /// them, the next write of the user's todos counts them again.
+    WrongUsage,
+}
@@ -1,29 +1,67 @@
+mod calendar;
 mod error;
+#[cfg(feature = "integration_tests")]
+mod faults;
+mod history;
 mod ids;
+mod maintenance;
 mod page;
+mod sealed;
 mod session;
 mod sled;
+mod sync;
 mod todo;
+mod undo;
+mod usage;
 mod user;
+mod webhook;
 
 #[cfg(feature = "integration_tests")]
 pub use sled::test_util;
 pub(crate) use sled::{error::SledStartupError, SledStorage};
 
+use std::path::PathBuf;
+
+use crate::config::types::LimitsConfig;
 use async_trait::async_trait;
+pub(crate) use calendar::CalendarToken;
 pub(crate) use error::StorageError;
+#[cfg(feature = "integration_tests")]
+pub(crate) use faults::FaultyStorage;
+#[cfg(feature = "integration_tests")]
+pub use faults::{Fault, Faults};
+pub(crate) use history::{diff, HistoryVersion};
+pub use history::{FieldChange, HistoryAction, HistoryEntry, HistorySeq};
+pub use maintenance::{
+    BackupManifest, BackupTree, MigrationReport, MigrationStep, ProblemKind, RotationReport,
+    StorageProblem, StorageReport, StorageStats, TodoBucket, TODO_BUCKETS,
+};
 pub(crate) use page::Pagination;
+pub(crate) use sealed::{Sealed, SealedRecord, NONCE_LEN};
 pub use session::Session;
+pub(crate) use session::SessionVersion;
+pub(crate) use sync::SyncRecord;
+pub use sync::{ClientChange, SyncChange, SyncResult, SyncSeq};
 pub use todo::Todo;
-pub(crate) use todo::{TodoVersion, UpdateTodo};
+pub use todo::{TodoLinks, TodoOpResult, TrashedTodo};
+pub(crate) use todo::{TodoOp, TodoVersion, TrashRecord, UpdateTodo};
+pub use undo::{UndoOperation, UndoResult};
+pub(crate) use undo::{UndoRecord, UndoStep};
+pub(crate) use usage::{Usage, UsageVersion};
 pub(crate) use user::Role;
 pub use user::User;
-pub(crate) use user::{HashedPassword, HASH_LEN, SALT_LEN};
+pub(crate) use user::{HashedPassword, UserVersion, HASH_LEN, SALT_LEN};
+pub(crate) use webhook::{Delivery, Webhook, WebhookData, WebhookEvent};
+pub use webhook::{DeliveryId, WebhookEventKind};
 
-pub use ids::{Jti, SessionId, TodoId, UserId};
+pub use ids::{Jti, SessionId, TodoId, UserId, WebhookId};
 
 #[async_trait]
 pub trait TodoStorage: Send + Sync {
+    /// Limits what each user can store, writes going past a limit fail with
+    /// `QuotaExceeded`. Nothing is limited until it is called.
+    fn set_limits(&self, limits: LimitsConfig);
+
     async fn get(&self, user_id: UserId, id: TodoId) -> Result<Todo, StorageError>;
     async fn put(&self, user_id: UserId, id: TodoId, item: Todo) -> Result<(), StorageError>;
     async fn delete(&self, user_id: UserId, id: TodoId) -> Result<(), StorageError>;
@@ -39,7 +77,71 @@ pub trait TodoStorage: Send + Sync {
         user_id: UserId,
         page: Pagination<TodoId>,
     ) -> Result<(Vec<Todo>, Option<TodoId>), StorageError>;
-    async fn delete_all(&self, user_id: UserId) -> Result<(), StorageError>;
+    /// Moves every todo of the user to the trash and returns their ids.
+    async fn delete_all(&self, user_id: UserId) -> Result<Vec<TodoId>, StorageError>;
+    /// Applies all operations or none of them.
+    async fn batch(
+        &self,
+        user_id: UserId,
+        ops: Vec<TodoOp>,
+    ) -> Result<Vec<TodoOpResult>, StorageError>;
+    /// Patches every todo of the group at once and returns the ones that changed.
+    async fn update_group(
+        &self,
+        user_id: UserId,
+        group: String,
+        patch: UpdateTodo,
+    ) -> Result<Vec<TodoId>, StorageError>;
+
+    async fn get_links(&self, user_id: UserId, id: TodoId) -> Result<TodoLinks, StorageError>;
+    async fn add_link(
+        &self,
+        user_id: UserId,
+        id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), StorageError>;
+    async fn remove_link(
+        &self,
+        user_id: UserId,
+        id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), StorageError>;
+
+    async fn get_trash(
+        &self,
+        user_id: UserId,
+        page: Pagination<TodoId>,
+    ) -> Result<(Vec<TrashedTodo>, Option<TodoId>), StorageError>;
+    async fn restore(&self, user_id: UserId, id: TodoId) -> Result<(), StorageError>;
+    /// Permanently removes trashed todos of all users deleted before `deleted_before`
+    /// (unix seconds) and returns how many were removed.
+    async fn purge_trash(&self, deleted_before: i64) -> Result<usize, StorageError>;
+
+    async fn get_history(
+        &self,
+        user_id: UserId,
+        id: TodoId,
+        page: Pagination<HistorySeq>,
+    ) -> Result<(Vec<HistoryEntry>, Option<HistorySeq>), StorageError>;
+
+    /// Reverts the latest operation from the user's undo log, together with
+    /// the todos it touched.
+    async fn undo(&self, user_id: UserId) -> Result<(UndoResult, Vec<TodoId>), StorageError>;
+
+    /// Returns the latest change of every todo changed after `page.after`,
+    /// in the order the changes were made.
+    async fn get_changes(
+        &self,
+        user_id: UserId,
+        page: Pagination<SyncSeq>,
+    ) -> Result<(Vec<SyncChange>, Option<SyncSeq>), StorageError>;
+    /// Applies changes made by a client in one transaction. Changes based on
+    /// an outdated revision are skipped and reported as conflicts.
+    async fn apply_changes(
+        &self,
+        user_id: UserId,
+        changes: Vec<ClientChange>,
+    ) -> Result<Vec<SyncResult>, StorageError>;
 }
 
 #[async_trait]
@@ -49,11 +151,56 @@ pub trait UserStorage: Send + Sync {
     async fn put(&self, id: UserId, user: User) -> Result<(), StorageError>;
     async fn delete(&self, id: UserId) -> Result<(), StorageError>;
     async fn update_role(&self, id: UserId, role: Role) -> Result<(), StorageError>;
+    /// Pages through all users but `exclude`, usually the admin asking.
     async fn get_all(
         &self,
-        user_id: UserId,
+        exclude: Option<UserId>,
         page: Pagination<UserId>,
     ) -> Result<(Vec<User>, Option<UserId>), StorageError>;
+
+    /// Stores the calendar token of its user, revoking the previous one.
+    async fn put_calendar_token(&self, token: CalendarToken) -> Result<(), StorageError>;
+    async fn get_calendar_token(&self, token_hash: &str) -> Result<CalendarToken, StorageError>;
+    async fn delete_calendar_token(&self, user_id: UserId) -> Result<(), StorageError>;
+}
+
+#[async_trait]
+pub trait WebhookStorage: Send + Sync {
+    async fn put_webhook(&self, webhook: Webhook) -> Result<(), StorageError>;
+    async fn get_webhook(&self, owner: UserId, id: WebhookId) -> Result<Webhook, StorageError>;
+    async fn get_webhooks(&self, owner: UserId) -> Result<Vec<Webhook>, StorageError>;
+    async fn delete_webhook(&self, owner: UserId, id: WebhookId) -> Result<(), StorageError>;
+
+    /// Turns up to `limit` outbox events into deliveries for every webhook
+    /// subscribed to them and returns how many events were taken.
+    async fn fan_out_events(&self, limit: usize) -> Result<usize, StorageError>;
+    /// Deliveries whose next attempt is due at `now_ms`, the earliest first.
+    async fn get_due_deliveries(
+        &self,
+        now_ms: i64,
+        limit: usize,
+    ) -> Result<Vec<Delivery>, StorageError>;
+    /// Drops a delivery that succeeded or whose webhook is gone.
+    async fn remove_delivery(&self, delivery: Delivery) -> Result<(), StorageError>;
+    /// Stores a failed attempt. The delivery still carries the time of that
+    /// attempt, it moves to the dead letters when `next_attempt_at` is `None`.
+    async fn fail_delivery(
+        &self,
+        delivery: Delivery,
+        next_attempt_at: Option<i64>,
+    ) -> Result<(), StorageError>;
+    async fn get_dead_letters(
+        &self,
+        owner: UserId,
+        page: Pagination<DeliveryId>,
+    ) -> Result<(Vec<Delivery>, Option<DeliveryId>), StorageError>;
+    /// Schedules a dead letter for delivery again with a fresh number of attempts.
+    async fn retry_dead_letter(
+        &self,
+        owner: UserId,
+        id: DeliveryId,
+        now_ms: i64,
+    ) -> Result<(), StorageError>;
 }
 
 #[async_trait]
@@ -62,9 +209,41 @@ pub trait SessionStorage: Send + Sync {
     async fn put(&self, id: SessionId, session: Session) -> Result<(), StorageError>;
     async fn delete(&self, id: SessionId) -> Result<(), StorageError>;
     async fn update(&self, id: SessionId, refresh_jti: Jti) -> Result<(), StorageError>;
+    /// Removes sessions that expired before `now` (unix seconds) and sessions
+    /// of deleted users, returns how many were removed.
+    async fn purge(&self, now: i64) -> Result<usize, StorageError>;
 }
 
 #[async_trait]
 pub trait FlushStorage: Send + Sync {
     async fn flush(&self) -> Result<(), StorageError>;
 }
+
+/// Whole-database operations run by operators rather than by users.
+#[async_trait]
+pub trait MaintenanceStorage: Send + Sync {
+    /// Writes a point-in-time snapshot of every tree to an archive at `path`.
+    async fn backup(&self, path: PathBuf) -> Result<BackupManifest, StorageError>;
+    /// Reads the archive at `path` and checks its manifest and records without
+    /// touching the storage.
+    async fn inspect(&self, path: PathBuf) -> Result<BackupManifest, StorageError>;
+    /// Replaces all records with the ones of the archive at `path`, once the
+    /// whole archive passed `inspect`. The trees are replaced in batches, so
+    /// nothing else may use the storage meanwhile; an interrupted restore
+    /// keeps the storage from opening until it is run again.
+    async fn restore(&self, path: PathBuf) -> Result<BackupManifest, StorageError>;
+    /// Checks that every key is well formed, that user, session and todo
+    /// records decode and that they agree across trees. With `repair` the
+    /// problems that can be fixed are.
+    async fn verify(&self, repair: bool) -> Result<StorageReport, StorageError>;
+    /// Runs the schema migrations the storage is missing, or only lists them
+    /// with `dry_run`. Opening the storage already ran them.
+    async fn migrate(&self, dry_run: bool) -> Result<MigrationReport, StorageError>;
+    /// Seals every record with a new data key of its user, `batch_size`
+    /// records at a time, then drops the old data keys and wraps the rest with
+    /// the current master key. Fails when encryption is not configured.
+    async fn rotate_keys(&self, batch_size: usize) -> Result<RotationReport, StorageError>;
+    /// Counts records, todos per user and sessions, and reads the size of the
+    /// database on disk. Scans every tree, so it is meant for background jobs.
+    async fn stats(&self, top_users: usize) -> Result<StorageStats, StorageError>;
+}
@@ -0,0 +1,22 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Length of the AES-GCM nonce of a sealed value.
+pub(crate) const NONCE_LEN: usize = 12;
+
+/// Value encrypted with a data key of its owner. Only the storage that sealed
+/// it holds the key to open it again.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Sealed {
+    /// Generation of the owner's data key the value was sealed with.
+    pub(crate) generation: u64,
+    pub(crate) nonce: [u8; NONCE_LEN],
+    /// Ciphertext followed by the authentication tag.
+    pub(crate) ciphertext: Vec<u8>,
+}
+
+/// A sealed record was found where its plain content is needed.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Record is encrypted")]
+pub struct SealedRecord;
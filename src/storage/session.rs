@@ -15,6 +15,50 @@ pub struct Session {
     pub current_refresh_jti: Jti,
 }
 
+/// How a `Session` is stored, see `UserVersion`.
+#[derive(Encode, Decode, Debug, Clone)]
+pub(crate) enum SessionVersion {
+    V1 {
+        id: SessionId,
+        user_id: UserId,
+        created_at: i64,
+        expires_at: i64,
+        current_refresh_jti: Jti,
+    },
+}
+
+impl From<SessionVersion> for Session {
+    fn from(value: SessionVersion) -> Self {
+        match value {
+            SessionVersion::V1 {
+                id,
+                user_id,
+                created_at,
+                expires_at,
+                current_refresh_jti,
+            } => Self {
+                id,
+                user_id,
+                created_at,
+                expires_at,
+                current_refresh_jti,
+            },
+        }
+    }
+}
+
+impl From<Session> for SessionVersion {
+    fn from(value: Session) -> Self {
+        Self::V1 {
+            id: value.id,
+            user_id: value.user_id,
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+            current_refresh_jti: value.current_refresh_jti,
+        }
+    }
+}
+
 impl Session {
     pub(crate) fn new(
         user_id: &UserId,
@@ -0,0 +1,106 @@
+//! Encoding of stored values. Values are bincode encoded, and with
+//! compression configured the ones at least `threshold` bytes long are stored
+//! as a zstd frame behind a header byte. Values without the header are read
+//! as they are, so compression can be turned on or off at any time.
+
+use std::borrow::Cow;
+
+use bincode::{config::Configuration, Decode, Encode};
+
+use super::{error::SledStorageError, BINCODE_CONFIG};
+use crate::config::types::CompressionConfig;
+
+/// Starts a compressed value. No bincode encoded record starts with it, a
+/// varint never does.
+const COMPRESSED: u8 = 0xFF;
+
+/// Start of every zstd frame, checked as well so that a value whose first
+/// field happens to be a raw `0xFF` byte isn't taken for a compressed one.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+#[derive(Debug, Clone, Copy)]
+struct Compression {
+    threshold: usize,
+    level: i32,
+}
+
+/// How values are turned into bytes and back. Cheap to copy.
+#[derive(Clone, Copy)]
+pub(crate) struct BincodeConfig {
+    bincode: Configuration,
+    compression: Option<Compression>,
+}
+
+impl Default for BincodeConfig {
+    fn default() -> Self {
+        Self {
+            bincode: BINCODE_CONFIG,
+            compression: None,
+        }
+    }
+}
+
+impl BincodeConfig {
+    pub(super) fn new(compression: Option<&CompressionConfig>) -> Self {
+        Self {
+            compression: compression.map(|config| Compression {
+                threshold: config.threshold,
+                level: config.level,
+            }),
+            ..Self::default()
+        }
+    }
+
+    pub(super) fn encode<T: Encode>(&self, value: &T) -> Result<Vec<u8>, SledStorageError> {
+        let bytes = bincode::encode_to_vec(value, self.bincode)?;
+        match self.compression {
+            Some(compression) if bytes.len() >= compression.threshold => {
+                compress(bytes, compression.level)
+            }
+            _ => Ok(bytes),
+        }
+    }
+
+    /// Decodes a value that may be followed by more bytes.
+    pub(super) fn decode<T: Decode<()>>(&self, bytes: &[u8]) -> Result<T, SledStorageError> {
+        let bytes = decompress(bytes)?;
+        Ok(bincode::decode_from_slice(&bytes, self.bincode)?.0)
+    }
+
+    /// Decodes a value that must take all the bytes.
+    pub(super) fn decode_exact<T: Decode<()>>(&self, bytes: &[u8]) -> Result<T, SledStorageError> {
+        let bytes = decompress(bytes)?;
+        let (value, len) = bincode::decode_from_slice(&bytes, self.bincode)?;
+        if len != bytes.len() {
+            return Err(bincode::error::DecodeError::Other("trailing bytes after value").into());
+        }
+        Ok(value)
+    }
+}
+
+fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&COMPRESSED) && bytes[1..].starts_with(&ZSTD_MAGIC)
+}
+
+fn compress(bytes: Vec<u8>, level: i32) -> Result<Vec<u8>, SledStorageError> {
+    let mut compressed = vec![COMPRESSED];
+    zstd::stream::copy_encode(bytes.as_slice(), &mut compressed, level)
+        .map_err(SledStorageError::Compress)?;
+    // text that doesn't compress is kept as it is
+    if compressed.len() >= bytes.len() {
+        return Ok(bytes);
+    }
+    Ok(compressed)
+}
+
+fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>, SledStorageError> {
+    if !is_compressed(bytes) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    zstd::stream::decode_all(&bytes[1..])
+        .map(Cow::Owned)
+        .map_err(SledStorageError::Decompress)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,127 @@
+use super::*;
+
+use crate::{
+    config::types::SledConfig,
+    storage::{
+        sled::{test_util::ADMIN_UUID, todo_key, SledStorage},
+        Todo, TodoId, TodoStorage, TodoVersion, UserId,
+    },
+};
+
+fn compressing() -> BincodeConfig {
+    BincodeConfig::new(Some(&CompressionConfig {
+        threshold: 64,
+        level: 3,
+    }))
+}
+
+fn long_todo() -> TodoVersion {
+    TodoVersion::from(Todo::new(TodoId::new(), &"pasted notes ".repeat(200)))
+}
+
+fn plain_bytes(todo: &TodoVersion) -> Vec<u8> {
+    BincodeConfig::default().encode(todo).unwrap()
+}
+
+fn settings(compression: Option<CompressionConfig>) -> SledConfig {
+    SledConfig {
+        path: std::path::PathBuf::new(),
+        delete_batch_size: 10,
+        undo_log_size: 5,
+        encryption: None,
+        compression,
+    }
+}
+
+#[test]
+fn test_large_values_are_compressed() {
+    let config = compressing();
+    let todo = long_todo();
+    let plain = plain_bytes(&todo);
+
+    let bytes = config.encode(&todo).unwrap();
+    assert!(is_compressed(&bytes));
+    assert!(bytes.len() < plain.len() / 10, "{} bytes", bytes.len());
+    let decoded: TodoVersion = config.decode(&bytes).unwrap();
+    assert_eq!(plain_bytes(&decoded), plain);
+    // reading doesn't depend on the config, only on the header
+    let decoded: TodoVersion = BincodeConfig::default().decode(&bytes).unwrap();
+    assert_eq!(plain_bytes(&decoded), plain);
+}
+
+#[test]
+fn test_small_and_old_values_stay_plain() {
+    let config = compressing();
+    let small = TodoVersion::from(Todo::new(TodoId::new(), "short"));
+    let bytes = config.encode(&small).unwrap();
+    assert!(!is_compressed(&bytes));
+    assert_eq!(bytes, plain_bytes(&small));
+
+    // written before compression was configured
+    let old = plain_bytes(&long_todo());
+    let decoded: TodoVersion = config.decode(&old).unwrap();
+    assert_eq!(plain_bytes(&decoded), old);
+}
+
+#[test]
+fn test_incompressible_values_stay_plain() {
+    let config = compressing();
+    // xorshift, no dictionary helps with it
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let random: Vec<u8> = (0..4096)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let bytes = config.encode(&random).unwrap();
+    assert!(!is_compressed(&bytes));
+    assert_eq!(config.decode::<Vec<u8>>(&bytes).unwrap(), random);
+}
+
+#[test]
+fn test_decode_exact_rejects_trailing_bytes() {
+    let config = compressing();
+    let user_id = UserId::new();
+    let mut bytes = config.encode(&user_id).unwrap();
+    assert_eq!(config.decode_exact::<UserId>(&bytes).unwrap(), user_id);
+    bytes.push(0);
+    assert!(config.decode_exact::<UserId>(&bytes).is_err());
+}
+
+#[tokio::test]
+async fn test_storage_reads_values_of_either_setting() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let user_id: UserId = ADMIN_UUID.into();
+    let compression = CompressionConfig {
+        threshold: 256,
+        level: 3,
+    };
+    let storage = SledStorage::from_db(&db, &settings(Some(compression))).unwrap();
+    let long = Todo::new(TodoId::new(), &"pasted notes ".repeat(200));
+    TodoStorage::put(&storage, user_id, long.id, long.clone())
+        .await
+        .unwrap();
+    let stored = storage
+        .todo_tree
+        .get(todo_key(&user_id, &long.id).as_bytes())
+        .unwrap()
+        .unwrap();
+    assert!(is_compressed(&stored));
+
+    let storage = SledStorage::from_db(&db, &settings(None)).unwrap();
+    let short = Todo::new(TodoId::new(), "short");
+    TodoStorage::put(&storage, user_id, short.id, short.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        TodoStorage::get(&storage, user_id, long.id).await.unwrap(),
+        long
+    );
+    assert_eq!(
+        TodoStorage::get(&storage, user_id, short.id).await.unwrap(),
+        short
+    );
+}
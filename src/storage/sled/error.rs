@@ -4,12 +4,24 @@ use sled::transaction::TransactionError;
 use strum_macros::AsRefStr;
 use thiserror::Error;
 
-use crate::storage::StorageError;
+use crate::storage::{SealedRecord, StorageError};
 
 #[derive(Error, Debug, AsRefStr)]
 pub enum SledStartupError {
     #[error("Failed to open sled storage")]
     OpenSledStorageError(#[source] sled::Error),
+
+    #[error("Failed to migrate sled storage")]
+    Migration(#[source] SledStorageError),
+
+    #[error("Failed to load master key: {0}")]
+    MasterKey(String),
+
+    #[error("A restore of the storage was interrupted, run it again")]
+    InterruptedRestore,
+
+    #[error("Failed to restore sled storage")]
+    Restore(#[source] SledStorageError),
 }
 
 #[derive(Error, Debug, AsRefStr)]
@@ -20,6 +32,22 @@ pub enum SledStorageError {
     #[error("Content for key not found")]
     NoContent,
 
+    #[error("Dependency would create a cycle")]
+    DependencyCycle,
+
+    #[error("Todo is blocked by unfinished todos")]
+    TodoBlocked,
+
+    #[error("The limit of {max} {quota} is reached")]
+    QuotaExceeded { quota: &'static str, max: usize },
+
+    #[error("Batch operation {index} failed")]
+    BatchOperation {
+        index: usize,
+        #[source]
+        source: Box<SledStorageError>,
+    },
+
     #[error("Failed to encode data")]
     Encode(#[from] bincode::error::EncodeError),
 
@@ -38,6 +66,9 @@ pub enum SledStorageError {
     #[error("Key without prefix: {0}")]
     InvalidKey(String),
 
+    #[error("Key segment of {0} bytes is longer than a key can hold")]
+    KeyTooLong(usize),
+
     #[error("TreeScan used without either 'within' or 'until_pagination' parameters or both: {0}")]
     UninitializedTreeScan(&'static str),
 
@@ -49,6 +80,45 @@ pub enum SledStorageError {
 
     #[error("sled conflictable transaction error")]
     ConflicatableTransaction(#[from] sled::transaction::ConflictableTransactionError),
+
+    #[error("Failed to access backup file")]
+    BackupFile(#[from] std::io::Error),
+
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+
+    #[error("Storage kept changing during {0} snapshot attempts")]
+    SnapshotConflict(usize),
+
+    #[error("Emails of users differ only in case, rename all but one of each group: {0}")]
+    EmailCollision(String),
+
+    #[error("Emails are indexed case-insensitively already")]
+    EmailIndexMigrated,
+
+    #[error("Storage has schema version {0}, newer than this build supports")]
+    SchemaTooNew(u32),
+
+    #[error("Record is encrypted and no master key is configured")]
+    Sealed(#[from] SealedRecord),
+
+    #[error("Data key {0} is missing or wrapped with an unknown master key")]
+    DataKey(String),
+
+    #[error("Failed to open sealed record")]
+    Unseal,
+
+    #[error("Failed to seal record")]
+    Seal,
+
+    #[error("Encryption at rest is not configured")]
+    NotEncrypted,
+
+    #[error("Failed to compress value")]
+    Compress(#[source] std::io::Error),
+
+    #[error("Failed to decompress value")]
+    Decompress(#[source] std::io::Error),
 }
 
 impl From<SledStorageError> for sled::transaction::ConflictableTransactionError<SledStorageError> {
@@ -77,6 +147,33 @@ impl From<SledStorageError> for StorageError {
                 tracing::warn!(error = ?value, error_type = %value.as_ref(), "No content for id");
                 Self::NoContent
             }
+            SledStorageError::DependencyCycle => {
+                tracing::warn!(error = ?value, error_type = %value.as_ref(), "Dependency cycle");
+                Self::DependencyCycle
+            }
+            SledStorageError::TodoBlocked => {
+                tracing::warn!(error = ?value, error_type = %value.as_ref(), "Todo is blocked");
+                Self::TodoBlocked
+            }
+            SledStorageError::QuotaExceeded { quota, max } => {
+                tracing::warn!(quota, max, "Limit reached");
+                Self::QuotaExceeded { quota, max }
+            }
+            SledStorageError::InvalidBackup(reason) => {
+                tracing::warn!(reason, "Invalid backup");
+                Self::InvalidBackup(reason)
+            }
+            SledStorageError::BackupFile(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!(error = ?e, "Backup file not found");
+                Self::NotFound
+            }
+            SledStorageError::BatchOperation { index, source } => {
+                tracing::warn!(index, "Batch operation failed");
+                Self::BatchOperation {
+                    index,
+                    source: Box::new((*source).into()),
+                }
+            }
             _ => {
                 tracing::error!(error = ?value, error_type = %value.as_ref(), "Storage error");
                 Self::Internal(value)
@@ -3,8 +3,10 @@ use super::SledStorage;
 use crate::{
     storage::{
         sled::{
-            internal::span_wrappers::flush_tree_in_span, SLED_EMAIL_TREE, SLED_SESSION_TREE,
-            SLED_TODO_TREE, SLED_USER_TREE,
+            internal::span_wrappers::flush_tree_in_span, SLED_CALENDAR_TREE, SLED_EMAIL_TREE,
+            SLED_HISTORY_TREE, SLED_LINK_TREE, SLED_META_TREE, SLED_OUTBOX_TREE, SLED_SESSION_TREE,
+            SLED_SYNC_TREE, SLED_TODO_TREE, SLED_TRASH_TREE, SLED_UNDO_TREE, SLED_USER_TREE,
+            SLED_WEBHOOK_TREE,
         },
         FlushStorage, StorageError,
     },
@@ -39,6 +41,51 @@ impl FlushStorage for SledStorage {
                 "failed to flush todo_tree"
             )?;
 
+            trace_err!(
+                flush_tree_in_span(&self.link_tree, SLED_LINK_TREE),
+                "failed to flush link_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.trash_tree, SLED_TRASH_TREE),
+                "failed to flush trash_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.history_tree, SLED_HISTORY_TREE),
+                "failed to flush history_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.undo_tree, SLED_UNDO_TREE),
+                "failed to flush undo_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.calendar_tree, SLED_CALENDAR_TREE),
+                "failed to flush calendar_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.sync_tree, SLED_SYNC_TREE),
+                "failed to flush sync_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.outbox_tree, SLED_OUTBOX_TREE),
+                "failed to flush outbox_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.webhook_tree, SLED_WEBHOOK_TREE),
+                "failed to flush webhook_tree"
+            )?;
+
+            trace_err!(
+                flush_tree_in_span(&self.meta_tree, SLED_META_TREE),
+                "failed to flush meta_tree"
+            )?;
+
             Ok::<(), SledStorageError>(())
         })
         .map_err(Into::into)
@@ -1,101 +1,201 @@
-use std::fmt::Display;
-use std::str::FromStr;
-
-use sled::Tree;
-use tracing::{debug, instrument};
-
-use crate::storage::{page::HasId, sled::error::SledStorageError};
-use strum::AsRefStr;
+//! Keys of the sled trees. A key is the one byte tag of its kind followed by
+//! segments, each a big endian `u16` length and the segment bytes. Ids are
+//! stored as their 16 bytes and numbers big endian, so keys sort like the
+//! values they hold and a prefix made of whole segments scans exactly the keys
+//! under it.
+
+use std::fmt::Write as _;
+
+use sled::{IVec, Tree};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::storage::{
+    page::HasId, sled::error::SledStorageError, SessionId, TodoId, UserId, WebhookId,
+};
+use strum::{AsRefStr, IntoEnumIterator};
 use strum_macros::{Display, EnumIter, EnumString};
 
+/// Kind of a key, its tag is the first byte. Tags are stored, so they never
+/// change once released.
 #[derive(Debug, EnumString, EnumIter, AsRefStr, Display, PartialEq, Eq, Copy, Clone)]
 #[strum(serialize_all = "lowercase")]
+#[repr(u8)]
 pub(crate) enum PrefixKind {
-    User,
-    Email,
-    Todo,
-    Session,
+    User = 1,
+    Email = 2,
+    Todo = 3,
+    Session = 4,
+    Link = 5,
+    Trash = 6,
+    History = 7,
+    Undo = 8,
+    Calendar = 9,
+    Sync = 10,
+    Webhook = 11,
+    Outbox = 12,
+    Delivery = 13,
+    DeadLetter = 14,
+    Meta = 15,
+    DataKey = 16,
+    Usage = 17,
+}
+
+impl PrefixKind {
+    fn from_tag(tag: u8) -> Option<Self> {
+        Self::iter().find(|kind| *kind as u8 == tag)
+    }
+}
+
+/// Longest segment a key can hold, its length is stored as a `u16`.
+pub(crate) const MAX_SEGMENT_LEN: usize = u16::MAX as usize;
+
+/// Value of bounded size that can be a segment of a key. Bytes of any length,
+/// like emails, go through the fallible `KeyPrefix::try_and` instead.
+pub(crate) trait KeySegment {
+    fn write_segment(&self, out: &mut Vec<u8>);
+}
+
+fn push_segment(out: &mut Vec<u8>, bytes: &[u8]) -> Result<(), SledStorageError> {
+    let len = u16::try_from(bytes.len()).map_err(|_| SledStorageError::KeyTooLong(bytes.len()))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn push_fixed<const N: usize>(out: &mut Vec<u8>, bytes: &[u8; N]) {
+    const { assert!(N <= MAX_SEGMENT_LEN) };
+    out.extend_from_slice(&(N as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+impl KeySegment for Uuid {
+    fn write_segment(&self, out: &mut Vec<u8>) {
+        push_fixed(out, self.as_bytes());
+    }
+}
+
+macro_rules! uuid_segment {
+    ($($id_type:ty),*) => {
+        $(impl KeySegment for $id_type {
+            fn write_segment(&self, out: &mut Vec<u8>) {
+                Uuid::from(*self).write_segment(out);
+            }
+        })*
+    };
+}
+
+uuid_segment!(UserId, TodoId, SessionId, WebhookId);
+
+impl KeySegment for u64 {
+    fn write_segment(&self, out: &mut Vec<u8>) {
+        push_fixed(out, &self.to_be_bytes());
+    }
+}
+
+// The sign bit is flipped so that negative values sort first.
+impl KeySegment for i64 {
+    fn write_segment(&self, out: &mut Vec<u8>) {
+        ((*self as u64) ^ (1 << 63)).write_segment(out);
+    }
+}
+
+// Labels like `token` or `head` are literals of this crate, never input.
+impl KeySegment for &'static str {
+    fn write_segment(&self, out: &mut Vec<u8>) {
+        debug_assert!(self.len() <= MAX_SEGMENT_LEN, "key label too long");
+        out.extend_from_slice(&(self.len() as u16).to_be_bytes());
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl<T: KeySegment + ?Sized> KeySegment for &T {
+    fn write_segment(&self, out: &mut Vec<u8>) {
+        (**self).write_segment(out);
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct KeyPrefix {
-    prefix: String,
+    prefix: Vec<u8>,
 }
 
 impl KeyPrefix {
-    pub fn new(kind: PrefixKind, value: impl Display) -> Self {
-        Self {
-            prefix: format!("{}:{}:", kind.as_ref(), value),
-        }
+    pub fn new(kind: PrefixKind, value: impl KeySegment) -> Self {
+        Self::from_kind(kind).and(value)
     }
 
     pub fn from_kind(kind: PrefixKind) -> Self {
         Self {
-            prefix: format!("{}:", kind.as_ref()),
+            prefix: vec![kind as u8],
         }
     }
 
-    pub fn from_parts(parts: &[&str]) -> Self {
-        Self {
-            prefix: parts.join(":") + ":",
-        }
+    /// Appends a segment.
+    pub fn and(mut self, value: impl KeySegment) -> Self {
+        value.write_segment(&mut self.prefix);
+        self
+    }
+
+    /// Appends a segment of any length, fails when it is longer than
+    /// `MAX_SEGMENT_LEN`.
+    pub fn try_and(mut self, value: impl AsRef<[u8]>) -> Result<Self, SledStorageError> {
+        push_segment(&mut self.prefix, value.as_ref())?;
+        Ok(self)
     }
 
-    pub(crate) fn as_str(&self) -> &str {
-        self.prefix.as_str()
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.prefix
     }
 }
 
 impl std::fmt::Display for KeyPrefix {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.prefix.fmt(f)
+        render(&self.prefix, f)
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Key {
-    prefix: KeyPrefix,
-    full_key: String,
+    bytes: IVec,
 }
 
 impl Key {
-    pub fn new(prefix: KeyPrefix, value: impl Display) -> Self {
-        Self {
-            full_key: format!("{}{}", prefix.as_str(), value),
-            prefix,
-        }
+    pub fn new(prefix: KeyPrefix, value: impl KeySegment) -> Self {
+        Self::from_prefix(prefix.and(value))
+    }
+
+    /// Key ending in a segment of any length, see `KeyPrefix::try_and`.
+    pub fn try_new(prefix: KeyPrefix, value: impl AsRef<[u8]>) -> Result<Self, SledStorageError> {
+        Ok(Self::from_prefix(prefix.try_and(value)?))
     }
 
     pub fn from_prefix(prefix: KeyPrefix) -> Self {
         Self {
-            full_key: prefix.as_str().to_string(),
-            prefix,
+            bytes: prefix.prefix.into(),
         }
     }
 
-    #[instrument(name = "Key::from_bytes", skip_all, level = "debug")]
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, SledStorageError> {
-        let full_key = std::str::from_utf8(bytes)
-            .map_err(SledStorageError::Conversion)?
-            .to_string();
-
-        let parts: Vec<&str> = full_key.split(':').collect();
+        Self::from_ivec(bytes.into())
+    }
 
-        if parts.len() >= 2 {
-            if PrefixKind::from_str(parts[0]).is_err() {
-                return Err(SledStorageError::InvalidKey(full_key));
-            }
-            if parts.iter().any(|v| v.is_empty()) {
-                return Err(SledStorageError::InvalidKey(full_key));
+    /// Checks the layout of a key read from a tree, without copying it.
+    #[instrument(name = "Key::from_ivec", skip_all, level = "debug")]
+    pub fn from_ivec(bytes: IVec) -> Result<Self, SledStorageError> {
+        let invalid = || SledStorageError::InvalidKey(bytes.escape_ascii().to_string());
+        let (tag, mut rest) = bytes.split_first().ok_or_else(invalid)?;
+        PrefixKind::from_tag(*tag).ok_or_else(invalid)?;
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+        while !rest.is_empty() {
+            match split_segment(rest) {
+                Some((segment, tail)) if !segment.is_empty() => rest = tail,
+                _ => return Err(invalid()),
             }
-            let prefix = KeyPrefix::from_parts(&parts[..parts.len() - 1]);
-
-            debug!(key = %full_key, prefix = %prefix, "created key from bytes");
-
-            Ok(Self { prefix, full_key })
-        } else {
-            Err(SledStorageError::InvalidKey(full_key))
         }
+        Ok(Self { bytes })
     }
 
     pub fn exists_in(&self, tree: &Tree) -> Result<(), SledStorageError> {
@@ -106,11 +206,24 @@ impl Key {
     }
 
     pub fn as_bytes(&self) -> &[u8] {
-        self.full_key.as_bytes()
+        &self.bytes
     }
 
-    pub fn starts_with(&self, prefix: &KeyPrefix) -> bool {
-        self.full_key.starts_with(prefix.as_str())
+    pub fn kind(&self) -> Option<PrefixKind> {
+        PrefixKind::from_tag(*self.bytes.first()?)
+    }
+
+    /// Returns the `index`-th segment after the kind.
+    pub fn segment(&self, index: usize) -> Option<&[u8]> {
+        segments(self.bytes.get(1..)?).nth(index)
+    }
+
+    pub fn id_segment<T: From<Uuid>>(&self, index: usize) -> Option<T> {
+        Uuid::from_slice(self.segment(index)?).ok().map(T::from)
+    }
+
+    pub fn u64_segment(&self, index: usize) -> Option<u64> {
+        Some(u64::from_be_bytes(self.segment(index)?.try_into().ok()?))
     }
 }
 
@@ -122,41 +235,121 @@ impl HasId<Key> for Key {
 
 impl std::fmt::Display for Key {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.full_key.fmt(f)
+        render(&self.bytes, f)
     }
 }
 
+/// Text of a key read from a tree, which may not be a valid key.
+pub(crate) fn display_key(bytes: &[u8]) -> String {
+    match Key::from_bytes(bytes) {
+        Ok(key) => key.to_string(),
+        Err(_) => bytes.escape_ascii().to_string(),
+    }
+}
+
+fn split_segment(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = bytes.split_first_chunk::<2>()?;
+    let len = u16::from_be_bytes(*len) as usize;
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+fn segments(mut bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        let (segment, rest) = split_segment(bytes)?;
+        bytes = rest;
+        Some(segment)
+    })
+}
+
+/// Writes a key as `kind:segment:…` for logs and reports. The layout doesn't
+/// say what a segment holds, so it is guessed from its bytes.
+fn render(bytes: &[u8], f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let Some((tag, rest)) = bytes.split_first() else {
+        return Ok(());
+    };
+    match PrefixKind::from_tag(*tag) {
+        Some(kind) => f.write_str(kind.as_ref())?,
+        None => return write!(f, "{}", bytes.escape_ascii()),
+    }
+    for segment in segments(rest) {
+        f.write_char(':')?;
+        match std::str::from_utf8(segment) {
+            Ok(text) if !text.chars().any(char::is_control) => f.write_str(text)?,
+            _ if segment.len() == 16 => write!(f, "{}", Uuid::from_slice(segment).unwrap())?,
+            _ if segment.len() == 8 => {
+                write!(f, "{}", u64::from_be_bytes(segment.try_into().unwrap()))?
+            }
+            _ => write!(f, "{}", segment.escape_ascii())?,
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_key() {
-        let key1 = Key::new(KeyPrefix::new(PrefixKind::User, "xxx"), "xxx");
-        assert_eq!(key1.full_key, "user:xxx:xxx");
-
-        let key2 = Key::new(KeyPrefix::from_kind(PrefixKind::User), "xxx");
-        assert_eq!(key2.full_key, "user:xxx");
-
-        let key3 = Key::new(KeyPrefix::from_parts(&["todo", "xxx"]), "xxx");
-        assert_eq!(key3.full_key, "todo:xxx:xxx");
-
-        let key4 = Key::from_prefix(KeyPrefix::from_parts(&["todo", "xxx"]));
-        assert_eq!(key4.full_key, "todo:xxx:");
-
-        let key5 = Key::from_prefix(KeyPrefix::from_kind(PrefixKind::Email));
-        assert_eq!(key5.full_key, "email:");
-
-        let key6 = Key::from_bytes("todo:xxx:xxx".as_bytes()).unwrap();
-        assert_eq!(key6.prefix.as_str(), "todo:xxx:");
-
-        let key7 = Key::from_bytes("todo:xxx".as_bytes()).unwrap();
-        assert_eq!(key7.prefix.as_str(), "todo:");
-
-        let key8 = Key::from_bytes("todo".as_bytes());
-        assert!(key8.is_err());
+        let user_id = UserId::new();
+        let todo_id = TodoId::new();
+        let key1 = Key::new(KeyPrefix::new(PrefixKind::Todo, user_id), todo_id);
+        assert_eq!(key1.as_bytes().len(), 1 + 2 * (2 + 16));
+        assert_eq!(key1.to_string(), format!("todo:{user_id}:{todo_id}"));
+        assert_eq!(key1.kind(), Some(PrefixKind::Todo));
+        assert_eq!(key1.id_segment(0), Some(user_id));
+        assert_eq!(key1.id_segment(1), Some(todo_id));
+        assert_eq!(key1.segment(2), None);
+        let prefix = KeyPrefix::new(PrefixKind::Todo, user_id);
+        assert!(key1.as_bytes().starts_with(prefix.as_bytes()));
+
+        let key2 = Key::new(KeyPrefix::from_kind(PrefixKind::Email), "a:b@gmail.com");
+        assert_eq!(key2.to_string(), "email:a:b@gmail.com");
+        assert_eq!(key2.segment(0), Some("a:b@gmail.com".as_bytes()));
+        assert_eq!(Key::from_bytes(key2.as_bytes()).unwrap(), key2);
+
+        let key3 = Key::new(KeyPrefix::new(PrefixKind::Sync, user_id).and("log"), 7u64);
+        assert_eq!(key3.to_string(), format!("sync:{user_id}:log:7"));
+        assert_eq!(key3.u64_segment(2), Some(7));
+        assert_eq!(Key::from_bytes(key3.as_bytes()).unwrap(), key3);
+
+        let key4 = Key::from_prefix(KeyPrefix::from_kind(PrefixKind::Email));
+        assert_eq!(key4.as_bytes(), [PrefixKind::Email as u8]);
+
+        assert!(Key::from_bytes(b"").is_err());
+        assert!(Key::from_bytes(key4.as_bytes()).is_err());
+        // unknown tag
+        assert!(Key::from_bytes(&[0, 0, 1, b'x']).is_err());
+        // segment cut short, empty segment
+        assert!(Key::from_bytes(&key1.as_bytes()[..30]).is_err());
+        assert!(Key::from_bytes(&[PrefixKind::User as u8, 0, 0]).is_err());
+        // string keys of older versions
+        assert!(Key::from_bytes(b"todo:xxx:xxx").is_err());
+
+        let prefix = KeyPrefix::from_kind(PrefixKind::Email);
+        let longest = "a".repeat(MAX_SEGMENT_LEN);
+        let key5 = Key::try_new(prefix.clone(), &longest).unwrap();
+        assert_eq!(key5.segment(0), Some(longest.as_bytes()));
+        assert!(matches!(
+            Key::try_new(prefix, format!("{longest}a")),
+            Err(SledStorageError::KeyTooLong(len)) if len == MAX_SEGMENT_LEN + 1
+        ));
+    }
 
-        let key9 = Key::from_bytes("ddd:fff:eee".as_bytes());
-        assert!(key9.is_err());
+    #[test]
+    fn test_key_order() {
+        let user_id = UserId::new();
+        let key = |seq: u64| Key::new(KeyPrefix::new(PrefixKind::Undo, user_id), seq);
+        assert!(key(9).as_bytes() < key(10).as_bytes());
+        assert!(key(255).as_bytes() < key(256).as_bytes());
+
+        let key = |at: i64| Key::new(KeyPrefix::from_kind(PrefixKind::Delivery), at);
+        assert!(key(-1).as_bytes() < key(0).as_bytes());
+        assert!(key(0).as_bytes() < key(i64::MAX).as_bytes());
+
+        let first = Key::new(KeyPrefix::from_kind(PrefixKind::Todo), user_id);
+        let prefix = KeyPrefix::new(PrefixKind::Todo, user_id);
+        assert_eq!(first.as_bytes(), prefix.as_bytes());
+        assert!(first.as_bytes() < Key::new(prefix, TodoId::new()).as_bytes());
     }
 }
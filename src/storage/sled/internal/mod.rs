@@ -2,5 +2,5 @@ mod key;
 pub(crate) mod span_wrappers;
 mod tree_scan;
 
-pub(crate) use key::{Key, KeyPrefix, PrefixKind};
-pub(crate) use tree_scan::TreeScan;
+pub(crate) use key::{display_key, Key, KeyPrefix, PrefixKind};
+pub(crate) use tree_scan::{for_each_page, TreeScan};
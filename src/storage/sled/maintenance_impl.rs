@@ -0,0 +1,445 @@
+mod archive;
+mod fsck;
+mod rotation;
+mod stats;
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use sled::{transaction::ConflictableTransactionError, Batch, IVec, Tree};
+use tracing::{info, info_span, instrument, warn, Span};
+
+use super::{
+    error::SledStorageError,
+    internal::{display_key, Key, KeyPrefix, PrefixKind},
+    migrations, BincodeConfig, FromBytesWithConfig, SledStorage, Vault, SLED_EMAIL_TREE,
+    SLED_META_TREE, SLED_SESSION_TREE, SLED_SYNC_TREE, SLED_TODO_TREE, SLED_USER_TREE,
+};
+use crate::{
+    storage::{
+        BackupManifest, MaintenanceStorage, MigrationReport, ProblemKind, RotationReport, Session,
+        StorageError, StorageReport, StorageStats, TodoVersion, UsageVersion, UserId, UserVersion,
+    },
+    trace_err,
+    utils::{blocking_task_guard::BlockingTaskGuard, measure_metrics::measure_and_record_storage},
+};
+use archive::TreeDump;
+
+/// Snapshots retried before a backup gives up on a storage that keeps changing.
+const SNAPSHOT_ATTEMPTS: usize = 5;
+
+/// Records a restore writes at once.
+const RESTORE_BATCH_SIZE: usize = 1_000;
+
+// Left in the `meta` tree while a restore replaces the trees.
+pub(super) fn restore_marker_key() -> Key {
+    Key::new(KeyPrefix::from_kind(PrefixKind::Meta), "restoring")
+}
+
+#[async_trait]
+impl MaintenanceStorage for SledStorage {
+    #[instrument(name = "SledStorage::backup", skip_all)]
+    async fn backup(&self, path: PathBuf) -> Result<BackupManifest, StorageError> {
+        let db = self.db.clone();
+        let names = self.tree_names();
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("backup");
+            span.in_scope(|| backup(&path, &db, &names))
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::inspect", skip_all)]
+    async fn inspect(&self, path: PathBuf) -> Result<BackupManifest, StorageError> {
+        let names = self.tree_names();
+        let bincode_config = self.bincode_config;
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("inspect_backup");
+            span.in_scope(|| {
+                let result = measure_and_record_storage("SledStorage::inspect", || {
+                    read_archive(&path, &names, &bincode_config)
+                });
+                Ok(result?.0)
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::restore", skip_all)]
+    async fn restore(&self, path: PathBuf) -> Result<BackupManifest, StorageError> {
+        let (trees, bincode_config) = self.cloned_trees();
+        let vault = self.vault.clone();
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("restore");
+            span.in_scope(|| {
+                let manifest = restore(&path, &trees, &bincode_config);
+                // data keys came from the backup too
+                vault.forget();
+                manifest
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::verify", skip_all)]
+    async fn verify(&self, repair: bool) -> Result<StorageReport, StorageError> {
+        let (trees, bincode_config) = self.cloned_trees();
+        let vault = self.vault.clone();
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("verify");
+            span.in_scope(|| verify(&trees, &bincode_config, &vault, repair))
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::migrate", skip_all)]
+    async fn migrate(&self, dry_run: bool) -> Result<MigrationReport, StorageError> {
+        let (trees, bincode_config) = self.cloned_trees();
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("migrate");
+            span.in_scope(|| {
+                let result = measure_and_record_storage("SledStorage::migrate", || {
+                    migrations::migrate(&trees, &bincode_config, dry_run)
+                });
+                Ok(result?)
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::rotate_keys", skip_all, fields(batch_size))]
+    async fn rotate_keys(&self, batch_size: usize) -> Result<RotationReport, StorageError> {
+        let (trees, bincode_config) = self.cloned_trees();
+        let vault = self.vault.clone();
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("rotate_keys");
+            span.in_scope(|| {
+                let result = measure_and_record_storage("SledStorage::rotate_keys", || {
+                    rotation::rotate(&trees, &bincode_config, &vault, batch_size)
+                });
+                Ok(result?)
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::stats", skip_all, fields(top_users))]
+    async fn stats(&self, top_users: usize) -> Result<StorageStats, StorageError> {
+        let db = self.db.clone();
+        let (trees, bincode_config) = self.cloned_trees();
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("storage_stats");
+            span.in_scope(|| {
+                let result = measure_and_record_storage("SledStorage::stats", || {
+                    stats::collect(&db, &trees, &bincode_config, top_users)
+                });
+                Ok(result?)
+            })
+        })
+        .await?
+    }
+}
+
+impl SledStorage {
+    fn cloned_trees(&self) -> (Vec<(&'static str, Tree)>, BincodeConfig) {
+        info_span!("Cloning trees and config")
+            .in_scope(|| (self.owned_trees(), self.bincode_config))
+    }
+
+    fn tree_names(&self) -> Vec<&'static str> {
+        self.trees().into_iter().map(|(name, _)| name).collect()
+    }
+}
+
+fn backup(
+    path: &Path,
+    db: &sled::Db,
+    names: &[&'static str],
+) -> Result<BackupManifest, StorageError> {
+    info!(path = ?path, "backup storage");
+
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::backup", || {
+            let trees = snapshot(db, names)?;
+            let (bytes, manifest) = archive::encode(trees, chrono::Utc::now().timestamp())?;
+
+            // a crash while writing leaves the previous backup in place
+            let partial = path.with_extension("partial");
+            trace_err!(
+                fs::File::create(&partial)
+                    .and_then(|mut file| file.write_all(&bytes).and_then(|_| file.sync_all())),
+                "failed to write backup file"
+            )?;
+            trace_err!(fs::rename(&partial, path), "failed to move backup file")?;
+
+            info!(records = manifest.records(), "backup written");
+            Ok(manifest)
+        });
+    Ok(result?)
+}
+
+/// Reads every tree while hashing the whole database the way `Db::checksum`
+/// does. That call scans under sled's write lock, so when both hashes match no
+/// write landed in between and the dumps are a point-in-time snapshot.
+fn snapshot(db: &sled::Db, names: &[&'static str]) -> Result<Vec<TreeDump>, SledStorageError> {
+    for attempt in 1..=SNAPSHOT_ATTEMPTS {
+        let mut hasher = crc32fast::Hasher::new();
+        let mut trees = Vec::with_capacity(names.len());
+        // in name order, like `Db::checksum`
+        let all_trees: BTreeSet<IVec> = db.tree_names().into_iter().collect();
+        for tree_name in all_trees {
+            hasher.update(&tree_name);
+            let known = names.iter().find(|name| name.as_bytes() == &*tree_name);
+
+            let mut entries = Vec::new();
+            for entry in db.open_tree(&tree_name)?.iter() {
+                let (key, value) = entry?;
+                hasher.update(&key);
+                hasher.update(&value);
+                if known.is_some() {
+                    entries.push((key.to_vec(), value.to_vec()));
+                }
+            }
+            if let Some(name) = known {
+                trees.push(TreeDump {
+                    name: name.to_string(),
+                    entries,
+                });
+            }
+        }
+
+        if hasher.finalize() == db.checksum()? {
+            return Ok(trees);
+        }
+        warn!(attempt, "storage changed while taking the snapshot");
+    }
+    Err(SledStorageError::SnapshotConflict(SNAPSHOT_ATTEMPTS))
+}
+
+/// Reads an archive and checks that it only holds known trees whose records
+/// would pass `verify`. In an archive taken at an older schema version, keys
+/// may still be in the older layout and values a migration still rewrites
+/// aren't checked.
+fn read_archive(
+    path: &Path,
+    names: &[&'static str],
+    bincode_config: &BincodeConfig,
+) -> Result<(BackupManifest, Vec<TreeDump>), SledStorageError> {
+    let bytes = trace_err!(fs::read(path), "failed to read backup file")?;
+    let (manifest, trees) = archive::decode(&bytes)?;
+
+    let meta = trees.iter().find(|tree| tree.name == SLED_META_TREE);
+    let version = migrations::archive_version(
+        meta.map_or(&[][..], |tree| tree.entries.as_slice()),
+        bincode_config,
+    )
+    .map_err(|_| SledStorageError::InvalidBackup("undecodable schema version".to_string()))?;
+    if version > migrations::SCHEMA_VERSION {
+        return Err(SledStorageError::InvalidBackup(format!(
+            "schema version {version} is newer than this build supports"
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    for tree in &trees {
+        let Some(name) = names.iter().find(|name| **name == tree.name) else {
+            return Err(SledStorageError::InvalidBackup(format!(
+                "unknown tree {}",
+                tree.name
+            )));
+        };
+        if !seen.insert(name) {
+            return Err(SledStorageError::InvalidBackup(format!(
+                "tree {name} is stored twice"
+            )));
+        }
+        let outdated = migrations::rewrites(version, name);
+        for (key, value) in &tree.entries {
+            let problem = match migrations::check_key(version, key) {
+                Err(e) => Some(Problem::new(ProblemKind::MalformedKey, e.to_string())),
+                Ok(()) if outdated => None,
+                Ok(()) => check_value(name, key, value, bincode_config),
+            };
+            if let Some(problem) = problem {
+                return Err(SledStorageError::InvalidBackup(format!(
+                    "{name} {}: {}: {}",
+                    display_key(key),
+                    problem.kind.as_ref(),
+                    problem.detail
+                )));
+            }
+        }
+    }
+    Ok((manifest, trees))
+}
+
+fn restore(
+    path: &Path,
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+) -> Result<BackupManifest, StorageError> {
+    Ok(restore_trees(path, trees, bincode_config)?)
+}
+
+/// Replaces the trees with the archive once all of it is checked. The trees
+/// are cleared and filled in batches, so nothing else may use the storage
+/// meanwhile. The `meta` tree goes last, taking the restore marker with it:
+/// until then opening the storage fails, an interrupted restore is run again.
+pub(super) fn restore_trees(
+    path: &Path,
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+) -> Result<BackupManifest, SledStorageError> {
+    info!(path = ?path, "restore storage");
+
+    measure_and_record_storage("SledStorage::restore", || {
+        let names: Vec<_> = trees.iter().map(|(name, _)| *name).collect();
+        let (manifest, dumps) = read_archive(path, &names, bincode_config)?;
+        let restored = |name: &str| {
+            // trees missing from the archive end up empty
+            dumps
+                .iter()
+                .find(|dump| dump.name == name)
+                .map_or(&[][..], |dump| dump.entries.as_slice())
+        };
+
+        let (_, meta) = trees
+            .iter()
+            .find(|(name, _)| *name == SLED_META_TREE)
+            .ok_or(SledStorageError::NotFound)?;
+        meta.insert(restore_marker_key().as_bytes(), &[])?;
+        meta.flush()?;
+
+        for (name, tree) in trees.iter().filter(|(name, _)| *name != SLED_META_TREE) {
+            info_span!("sled::restore_tree", tree = name).in_scope(|| {
+                trace_err!(
+                    replace_tree(tree, restored(name)),
+                    "failed to replace tree with the backup"
+                )
+            })?;
+        }
+        let current = meta.iter().keys().collect::<Result<Vec<_>, _>>()?;
+        trace_err!(
+            meta.transaction(|meta| {
+                for key in &current {
+                    meta.remove(key)?;
+                }
+                for (key, value) in restored(SLED_META_TREE) {
+                    meta.insert(key.as_slice(), value.as_slice())?;
+                }
+                Ok::<_, ConflictableTransactionError<SledStorageError>>(())
+            }),
+            "failed to replace meta tree with the backup"
+        )?;
+
+        trace_err!(
+            migrations::migrate(trees, bincode_config, false),
+            "failed to migrate restored backup"
+        )?;
+        info!(records = manifest.records(), "backup restored");
+        Ok(manifest)
+    })
+}
+
+fn replace_tree(tree: &Tree, entries: &[(Vec<u8>, Vec<u8>)]) -> Result<(), SledStorageError> {
+    tree.clear()?;
+    for chunk in entries.chunks(RESTORE_BATCH_SIZE) {
+        let mut batch = Batch::default();
+        for (key, value) in chunk {
+            batch.insert(key.as_slice(), value.as_slice());
+        }
+        tree.apply_batch(batch)?;
+    }
+    Ok(tree.flush().map(|_| ())?)
+}
+
+/// What is wrong with a record.
+struct Problem {
+    kind: ProblemKind,
+    detail: String,
+    /// Whether repair may fix it.
+    fixable: bool,
+}
+
+impl Problem {
+    fn new(kind: ProblemKind, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            detail: detail.into(),
+            fixable: true,
+        }
+    }
+}
+
+/// Checks a record on its own: its key and whether its value decodes.
+fn check_record(
+    tree: &str,
+    key: &[u8],
+    value: &[u8],
+    bincode_config: &BincodeConfig,
+) -> Option<Problem> {
+    if let Err(e) = Key::from_bytes(key) {
+        return Some(Problem::new(ProblemKind::MalformedKey, e.to_string()));
+    }
+    check_value(tree, key, value, bincode_config)
+}
+
+fn check_value(
+    tree: &str,
+    key: &[u8],
+    value: &[u8],
+    bincode_config: &BincodeConfig,
+) -> Option<Problem> {
+    let kind = Key::from_bytes(key).ok().and_then(|key| key.kind());
+    let decoded = match tree {
+        n if n == SLED_USER_TREE => UserVersion::from_bytes(value, bincode_config).map(|_| ()),
+        n if n == SLED_EMAIL_TREE => UserId::from_bytes(value, bincode_config).map(|_| ()),
+        n if n == SLED_SESSION_TREE => Session::from_bytes(value, bincode_config).map(|_| ()),
+        n if n == SLED_TODO_TREE => TodoVersion::from_bytes(value, bincode_config).map(|_| ()),
+        n if n == SLED_SYNC_TREE && kind == Some(PrefixKind::Usage) => {
+            UsageVersion::from_bytes(value, bincode_config).map(|_| ())
+        }
+        _ => Ok(()),
+    };
+    decoded
+        .err()
+        .map(|e| Problem::new(ProblemKind::UndecodableValue, e.to_string()))
+}
+
+fn verify(
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    repair: bool,
+) -> Result<StorageReport, StorageError> {
+    info!(repair, "verify storage");
+
+    let result = measure_and_record_storage("SledStorage::verify", || {
+        let report = fsck::check(trees, bincode_config, vault, repair)?;
+        info!(problems = report.problems.len(), "storage verified");
+        Ok::<_, SledStorageError>(report)
+    });
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests;
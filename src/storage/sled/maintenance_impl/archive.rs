@@ -0,0 +1,113 @@
+//! Layout of a backup archive: `MAGIC`, the format version and the CRC32 of
+//! the body as little-endian integers, then the bincode encoded body holding
+//! the manifest and the records of each tree.
+
+use bincode::{config::Configuration, Decode, Encode};
+
+use crate::storage::{sled::error::SledStorageError, BackupManifest, BackupTree};
+
+const MAGIC: &[u8; 8] = b"TODOBKUP";
+const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+/// Fixed rather than the storage config, so archives stay readable when that changes.
+const ARCHIVE_CONFIG: Configuration = bincode::config::standard()
+    .with_variable_int_encoding()
+    .with_little_endian();
+
+#[derive(Encode, Decode)]
+pub(super) struct TreeDump {
+    pub name: String,
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TreeDump {
+    fn checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        for (key, value) in &self.entries {
+            hasher.update(key);
+            hasher.update(value);
+        }
+        hasher.finalize()
+    }
+}
+
+#[derive(Encode, Decode)]
+struct Body {
+    manifest: BackupManifest,
+    trees: Vec<TreeDump>,
+}
+
+pub(super) fn encode(
+    trees: Vec<TreeDump>,
+    created_at: i64,
+) -> Result<(Vec<u8>, BackupManifest), SledStorageError> {
+    let manifest = BackupManifest {
+        created_at,
+        trees: trees
+            .iter()
+            .map(|tree| BackupTree {
+                name: tree.name.clone(),
+                records: tree.entries.len() as u64,
+                checksum: tree.checksum(),
+            })
+            .collect(),
+    };
+    let body = bincode::encode_to_vec(
+        Body {
+            manifest: manifest.clone(),
+            trees,
+        },
+        ARCHIVE_CONFIG,
+    )?;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    Ok((bytes, manifest))
+}
+
+/// Decodes an archive and checks it against its checksums. Which trees and
+/// records it may contain is up to the caller.
+pub(super) fn decode(bytes: &[u8]) -> Result<(BackupManifest, Vec<TreeDump>), SledStorageError> {
+    let invalid = |reason: String| SledStorageError::InvalidBackup(reason);
+
+    let Some(header) = bytes.first_chunk::<HEADER_LEN>() else {
+        return Err(invalid("not a backup archive".into()));
+    };
+    let (magic, rest) = header.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Err(invalid("not a backup archive".into()));
+    }
+    let (version, checksum) = rest.split_at(2);
+    let version = u16::from_le_bytes([version[0], version[1]]);
+    if version != FORMAT_VERSION {
+        return Err(invalid(format!("unsupported format version {version}")));
+    }
+
+    // checked before decoding, so a damaged length can't ask for a huge buffer
+    let body = &bytes[HEADER_LEN..];
+    let checksum = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if crc32fast::hash(body) != checksum {
+        return Err(invalid("checksum mismatch, the archive is damaged".into()));
+    }
+    let (Body { manifest, trees }, _) = bincode::decode_from_slice(body, ARCHIVE_CONFIG)
+        .map_err(|e| invalid(format!("failed to decode archive: {e}")))?;
+
+    if manifest.trees.len() != trees.len() {
+        return Err(invalid("manifest doesn't list every tree".into()));
+    }
+    for (listed, tree) in manifest.trees.iter().zip(&trees) {
+        if listed.name != tree.name
+            || listed.records != tree.entries.len() as u64
+            || listed.checksum != tree.checksum()
+        {
+            return Err(invalid(format!(
+                "tree {} doesn't match the manifest",
+                tree.name
+            )));
+        }
+    }
+    Ok((manifest, trees))
+}
@@ -0,0 +1,437 @@
+//! Checks of `MaintenanceStorage::verify`. The scan reads the trees one after
+//! another, so a write running next to it can look like a problem: every
+//! finding is confirmed in a transaction before it is reported or repaired.
+
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionalTree},
+    IVec, Transactional, Tree,
+};
+use tracing::{info, info_span};
+
+use super::{check_record, Problem};
+use crate::{
+    storage::{
+        sled::{
+            error::SledStorageError,
+            internal::{display_key, Key, PrefixKind},
+            link_key, normalized_email,
+            todos_impl::counted_usage,
+            user_key, BincodeConfig, FromBytesWithConfig, ToBytesWithConfig, Vault,
+            SLED_EMAIL_TREE, SLED_LINK_TREE, SLED_SESSION_TREE, SLED_SYNC_TREE, SLED_TODO_TREE,
+            SLED_USER_TREE,
+        },
+        ProblemKind, Session, StorageProblem, StorageReport, TodoId, UsageVersion, User, UserId,
+        UserVersion,
+    },
+    trace_err,
+};
+
+type TxResult<T> = Result<T, ConflictableTransactionError<SledStorageError>>;
+
+/// Trees the cross-tree checks look into.
+struct Trees<'a> {
+    users: &'a Tree,
+    emails: &'a Tree,
+    todos: &'a Tree,
+    links: &'a Tree,
+    sessions: &'a Tree,
+    sync: &'a Tree,
+}
+
+/// Problem seen by the scan, not confirmed yet.
+struct Finding {
+    tree: &'static str,
+    key: IVec,
+    /// Value of the record when it was scanned.
+    value: IVec,
+    problem: Problem,
+}
+
+pub(super) fn check(
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    repair: bool,
+) -> Result<StorageReport, SledStorageError> {
+    let tree = |name: &str| {
+        trees
+            .iter()
+            .find(|(tree_name, _)| *tree_name == name)
+            .map(|(_, tree)| tree)
+            .ok_or_else(|| SledStorageError::InvalidKey(format!("no {name} tree")))
+    };
+    let named = Trees {
+        users: tree(SLED_USER_TREE)?,
+        emails: tree(SLED_EMAIL_TREE)?,
+        todos: tree(SLED_TODO_TREE)?,
+        links: tree(SLED_LINK_TREE)?,
+        sessions: tree(SLED_SESSION_TREE)?,
+        sync: tree(SLED_SYNC_TREE)?,
+    };
+
+    let mut report = StorageReport::default();
+    let mut findings = Vec::new();
+    info_span!("sled::scan_trees").in_scope(|| {
+        for (name, tree) in trees {
+            let mut records = 0;
+            for entry in tree.iter() {
+                let (key, value) = entry?;
+                records += 1;
+
+                let problem = match check_record(name, &key, &value, bincode_config) {
+                    Some(problem) => Some(problem),
+                    None => cross_check(name, &key, &value, &named, bincode_config, vault)?,
+                };
+                if let Some(problem) = problem {
+                    findings.push(Finding {
+                        tree: name,
+                        key,
+                        value,
+                        problem,
+                    });
+                }
+            }
+            report.records.push((*name, records));
+        }
+        Ok::<_, SledStorageError>(())
+    })?;
+
+    for finding in findings {
+        let settled = trace_err!(
+            settle(&finding, trees, &named, bincode_config, vault, repair),
+            "failed to confirm storage problem"
+        )?;
+        let Some(repaired) = settled else {
+            info!(key = ?finding.key, "problem went away while checking");
+            continue;
+        };
+        report.problems.push(StorageProblem {
+            tree: finding.tree,
+            key: display_key(&finding.key),
+            kind: finding.problem.kind,
+            detail: finding.problem.detail,
+            repaired,
+        });
+    }
+    Ok(report)
+}
+
+/// Checks a well formed record against the other trees.
+fn cross_check(
+    tree: &str,
+    key: &[u8],
+    value: &[u8],
+    trees: &Trees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Option<Problem>, SledStorageError> {
+    let problem = match tree {
+        n if n == SLED_USER_TREE => {
+            let user = open_user(value, bincode_config, vault)?;
+            let mut entry = None;
+            for email_key in vault.email_keys(&user.email)? {
+                entry = trees.emails.get(email_key.as_bytes())?;
+                if entry.is_some() {
+                    break;
+                }
+            }
+            let holder = |id: &UserId| trees.users.get(user_key(id).as_bytes());
+            user_problem(&user, entry, holder, bincode_config, vault)?
+        }
+        n if n == SLED_EMAIL_TREE => {
+            let user_id = UserId::from_bytes(value, bincode_config)?;
+            let user = trees.users.get(user_key(&user_id).as_bytes())?;
+            email_problem(key, &user_id, user, bincode_config, vault)
+        }
+        n if n == SLED_SESSION_TREE => {
+            let session = Session::from_bytes(value, bincode_config)?;
+            let exists = trees
+                .users
+                .contains_key(user_key(&session.user_id).as_bytes())?;
+            (!exists).then(|| orphan_problem(ProblemKind::OrphanSession, &session.user_id))
+        }
+        n if n == SLED_TODO_TREE => match todo_ids(key) {
+            Some((owner, _)) if !trees.users.contains_key(user_key(&owner).as_bytes())? => {
+                Some(orphan_problem(ProblemKind::OrphanTodo, &owner))
+            }
+            _ => None,
+        },
+        n if n == SLED_SYNC_TREE => match usage_owner(key) {
+            Some(owner) if !trees.users.contains_key(user_key(&owner).as_bytes())? => {
+                Some(orphan_problem(ProblemKind::OrphanUsage, &owner))
+            }
+            Some(owner) => usage_problem(&owner, value, trees, bincode_config, vault)?,
+            None => None,
+        },
+        _ => None,
+    };
+    Ok(problem)
+}
+
+/// Problem of the usage counts of `owner`, recounted from its todos.
+fn usage_problem(
+    owner: &UserId,
+    value: &[u8],
+    trees: &Trees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Option<Problem>, SledStorageError> {
+    let usage = vault.open_usage(owner, UsageVersion::from_bytes(value, bincode_config)?)?;
+    // undecodable todos are reported on their own
+    let Ok(counted) = counted_usage(owner, trees.todos, bincode_config, vault) else {
+        return Ok(None);
+    };
+    Ok((usage != counted).then(|| {
+        Problem::new(
+            ProblemKind::WrongUsage,
+            format!(
+                "{} todos in {} groups counted, {} in {} stored",
+                counted.todos,
+                counted.groups.len(),
+                usage.todos,
+                usage.groups.len()
+            ),
+        )
+    }))
+}
+
+/// Problem of a `users` record, given the `emails` entry of its email and a
+/// lookup of the user that entry points at.
+fn user_problem<E>(
+    user: &User,
+    entry: Option<IVec>,
+    mut holder: impl FnMut(&UserId) -> Result<Option<IVec>, E>,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Option<Problem>, E> {
+    let Some(entry) = entry else {
+        return Ok(Some(Problem::new(
+            ProblemKind::MissingEmail,
+            format!("no entry for {}", user.email),
+        )));
+    };
+    // an undecodable entry is reported on its own
+    let Ok(holder_id) = UserId::from_bytes(&entry, bincode_config) else {
+        return Ok(None);
+    };
+    if holder_id == user.id {
+        return Ok(None);
+    }
+
+    let holder =
+        holder(&holder_id)?.and_then(|bytes| open_user(&bytes, bincode_config, vault).ok());
+    let problem = match holder {
+        Some(holder) if same_email(&holder.email, &user.email) => Problem {
+            kind: ProblemKind::TakenEmail,
+            detail: format!("{} belongs to user {holder_id}", user.email),
+            fixable: false,
+        },
+        _ => Problem::new(
+            ProblemKind::MissingEmail,
+            format!("entry points at user {holder_id}"),
+        ),
+    };
+    Ok(Some(problem))
+}
+
+/// Problem of an `emails` entry, given the `users` record it points at.
+fn email_problem(
+    key: &[u8],
+    user_id: &UserId,
+    user: Option<IVec>,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Option<Problem> {
+    let Some(user) = user else {
+        return Some(orphan_problem(ProblemKind::DanglingEmail, user_id));
+    };
+    let user = open_user(&user, bincode_config, vault).ok()?;
+    let email_keys = vault.email_keys(&user.email).ok()?;
+    (!email_keys
+        .iter()
+        .any(|email_key| email_key.as_bytes() == key))
+    .then(|| {
+        Problem::new(
+            ProblemKind::DanglingEmail,
+            format!("user {} has email {}", user.id, user.email),
+        )
+    })
+}
+
+fn same_email(a: &str, b: &str) -> bool {
+    normalized_email(a) == normalized_email(b)
+}
+
+fn open_user(
+    bytes: &[u8],
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<User, SledStorageError> {
+    vault.open_user(UserVersion::from_bytes(bytes, bincode_config)?)
+}
+
+fn orphan_problem(kind: ProblemKind, user_id: &UserId) -> Problem {
+    Problem::new(kind, format!("user {user_id} doesn't exist"))
+}
+
+/// Owner and id of a `todo:<user id>:<todo id>` key.
+fn todo_ids(key: &[u8]) -> Option<(UserId, TodoId)> {
+    let key = Key::from_bytes(key).ok()?;
+    if key.kind()? != PrefixKind::Todo {
+        return None;
+    }
+    Some((key.id_segment(0)?, key.id_segment(1)?))
+}
+
+/// Owner of a usage key.
+fn usage_owner(key: &[u8]) -> Option<UserId> {
+    let key = Key::from_bytes(key).ok()?;
+    if key.kind()? != PrefixKind::Usage {
+        return None;
+    }
+    key.id_segment(0)
+}
+
+/// Checks a finding again, in a transaction when it spans trees, and repairs
+/// it when asked to. `None` means the problem is gone, otherwise whether it
+/// was repaired.
+fn settle(
+    finding: &Finding,
+    all: &[(&'static str, Tree)],
+    trees: &Trees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    repair: bool,
+) -> Result<Option<bool>, SledStorageError> {
+    let fix = repair && finding.problem.fixable;
+    let key = &finding.key;
+
+    let settled = match finding.problem.kind {
+        ProblemKind::MalformedKey | ProblemKind::UndecodableValue => {
+            let Some((_, tree)) = all.iter().find(|(name, _)| *name == finding.tree) else {
+                return Ok(None);
+            };
+            if fix {
+                // removes the record only if nobody wrote it since the scan
+                let swapped =
+                    tree.compare_and_swap(key, Some(&finding.value), None as Option<&[u8]>)?;
+                swapped.is_ok().then_some(true)
+            } else {
+                (tree.get(key)?.as_ref() == Some(&finding.value)).then_some(false)
+            }
+        }
+        ProblemKind::MissingEmail | ProblemKind::TakenEmail => (trees.users, trees.emails)
+            .transaction(|(users, emails)| -> TxResult<Option<bool>> {
+                let Some(bytes) = users.get(key)? else {
+                    return Ok(None);
+                };
+                let Ok(user) = open_user(&bytes, bincode_config, vault) else {
+                    return Ok(None);
+                };
+                let mut entry = None;
+                for email_key in vault.email_keys(&user.email)? {
+                    entry = emails.get(email_key.as_bytes())?;
+                    if entry.is_some() {
+                        break;
+                    }
+                }
+                let holder = |id: &UserId| users.get(user_key(id).as_bytes());
+                let Some(problem) = user_problem(&user, entry, holder, bincode_config, vault)?
+                else {
+                    return Ok(None);
+                };
+                if fix && problem.fixable {
+                    let user_id = user.id.to_bytes(bincode_config)?;
+                    emails.insert(vault.email_key(&user.email)?.as_bytes(), user_id)?;
+                    return Ok(Some(true));
+                }
+                Ok(Some(false))
+            })?,
+        ProblemKind::DanglingEmail => (trees.users, trees.emails).transaction(
+            |(users, emails)| -> TxResult<Option<bool>> {
+                let Some(bytes) = emails.get(key)? else {
+                    return Ok(None);
+                };
+                let Ok(user_id) = UserId::from_bytes(&bytes, bincode_config) else {
+                    return Ok(None);
+                };
+                let user = users.get(user_key(&user_id).as_bytes())?;
+                if email_problem(key, &user_id, user, bincode_config, vault).is_none() {
+                    return Ok(None);
+                }
+                remove_if(fix, emails, key)
+            },
+        )?,
+        ProblemKind::OrphanTodo => {
+            let Some((owner, todo_id)) = todo_ids(key) else {
+                return Ok(None);
+            };
+            (trees.users, trees.todos, trees.links).transaction(
+                |(users, todos, links)| -> TxResult<Option<bool>> {
+                    if users.get(user_key(&owner).as_bytes())?.is_some()
+                        || todos.get(key)?.is_none()
+                    {
+                        return Ok(None);
+                    }
+                    if fix {
+                        links.remove(link_key(&owner, &todo_id).as_bytes())?;
+                    }
+                    remove_if(fix, todos, key)
+                },
+            )?
+        }
+        ProblemKind::OrphanUsage => {
+            let Some(owner) = usage_owner(key) else {
+                return Ok(None);
+            };
+            (trees.users, trees.sync).transaction(|(users, sync)| -> TxResult<Option<bool>> {
+                if users.get(user_key(&owner).as_bytes())?.is_some() || sync.get(key)?.is_none() {
+                    return Ok(None);
+                }
+                remove_if(fix, sync, key)
+            })?
+        }
+        // the todos can't be scanned in a transaction, the counts are removed
+        // only if no write changed them since they were recounted
+        ProblemKind::WrongUsage => {
+            let Some(owner) = usage_owner(key) else {
+                return Ok(None);
+            };
+            if usage_problem(&owner, &finding.value, trees, bincode_config, vault)?.is_none() {
+                return Ok(None);
+            }
+            if fix {
+                let swapped = trees.sync.compare_and_swap(
+                    key,
+                    Some(&finding.value),
+                    None as Option<&[u8]>,
+                )?;
+                swapped.is_ok().then_some(true)
+            } else {
+                (trees.sync.get(key)?.as_ref() == Some(&finding.value)).then_some(false)
+            }
+        }
+        ProblemKind::OrphanSession => (trees.users, trees.sessions).transaction(
+            |(users, sessions)| -> TxResult<Option<bool>> {
+                let Some(bytes) = sessions.get(key)? else {
+                    return Ok(None);
+                };
+                let Ok(session) = Session::from_bytes(&bytes, bincode_config) else {
+                    return Ok(None);
+                };
+                if users.get(user_key(&session.user_id).as_bytes())?.is_some() {
+                    return Ok(None);
+                }
+                remove_if(fix, sessions, key)
+            },
+        )?,
+    };
+    Ok(settled)
+}
+
+fn remove_if(fix: bool, tree: &TransactionalTree, key: &IVec) -> TxResult<Option<bool>> {
+    if fix {
+        tree.remove(key)?;
+    }
+    Ok(Some(fix))
+}
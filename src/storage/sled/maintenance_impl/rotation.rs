@@ -0,0 +1,381 @@
+//! Key rotation: every owner gets a new data key generation, then the trees
+//! are scanned in batches and each record sealed with an older generation,
+//! or not sealed the way the config asks for, is sealed again. A record is
+//! swapped only if it didn't change since it was read, one written in between
+//! is sealed with the new generation already. Old generations are removed
+//! once a whole pass finds nothing left to seal.
+
+use std::ops::Bound;
+
+use sled::{transaction::ConflictableTransactionError, IVec, Tree};
+use tracing::{info, info_span, warn};
+
+use super::super::{
+    error::SledStorageError,
+    internal::{Key, PrefixKind},
+    key_todo_id, BincodeConfig, FromBytesWithConfig, ToBytesWithConfig, Vault, SLED_EMAIL_TREE,
+    SLED_HISTORY_TREE, SLED_OUTBOX_TREE, SLED_SYNC_TREE, SLED_TODO_TREE, SLED_TRASH_TREE,
+    SLED_UNDO_TREE, SLED_USER_TREE, SLED_WEBHOOK_TREE,
+};
+use crate::storage::{
+    Delivery, HistoryVersion, RotationReport, Sealed, TodoId, TodoVersion, TrashRecord, UndoRecord,
+    UndoStep, UsageVersion, UserId, UserVersion, WebhookData, WebhookEvent,
+};
+
+/// Trees holding sealed records, users last so that their events are done
+/// before the email index is rewritten.
+const SEALED_TREES: [&str; 8] = [
+    SLED_TODO_TREE,
+    SLED_TRASH_TREE,
+    SLED_HISTORY_TREE,
+    SLED_UNDO_TREE,
+    SLED_SYNC_TREE,
+    SLED_OUTBOX_TREE,
+    SLED_WEBHOOK_TREE,
+    SLED_USER_TREE,
+];
+
+pub(super) fn rotate(
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    batch_size: usize,
+) -> Result<RotationReport, SledStorageError> {
+    if !vault.is_enabled() {
+        return Err(SledStorageError::NotEncrypted);
+    }
+    let tree = |name: &str| {
+        trees
+            .iter()
+            .find(|(tree_name, _)| *tree_name == name)
+            .map(|(_, tree)| tree)
+            .ok_or_else(|| SledStorageError::InvalidKey(format!("no {name} tree")))
+    };
+    let batch_size = batch_size.max(1);
+
+    let mut report = RotationReport::default();
+    for owner in vault.owners()? {
+        vault.add_generation(&owner)?;
+        report.rotated += 1;
+    }
+
+    for name in SEALED_TREES {
+        let tree = tree(name)?;
+        let mut resealed = 0;
+        loop {
+            let pass = info_span!("sled::reseal_tree", tree = name)
+                .in_scope(|| reseal_tree(name, tree, bincode_config, vault, batch_size))?;
+            resealed += pass.resealed;
+            // the last pass counts the records that can't be opened
+            if pass.stale == 0 {
+                report.skipped += pass.skipped;
+                break;
+            }
+        }
+        info!(tree = name, records = resealed, "tree resealed");
+        report.records.push((name, resealed));
+    }
+
+    let moved = move_email_entries(
+        tree(SLED_USER_TREE)?,
+        tree(SLED_EMAIL_TREE)?,
+        bincode_config,
+        vault,
+    )?;
+    report.records.push((SLED_EMAIL_TREE, moved));
+
+    if report.skipped == 0 {
+        for owner in vault.owners()? {
+            report.retired += vault.retire(&owner)?;
+        }
+    } else {
+        warn!(
+            skipped = report.skipped,
+            "records left unopened, old data keys kept"
+        );
+    }
+    report.rewrapped = vault.rewrap()?;
+    Ok(report)
+}
+
+#[derive(Default)]
+struct Pass {
+    /// Records found sealed with an old key, swapped or not.
+    stale: usize,
+    resealed: usize,
+    skipped: usize,
+}
+
+fn reseal_tree(
+    name: &str,
+    tree: &Tree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    batch_size: usize,
+) -> Result<Pass, SledStorageError> {
+    let mut pass = Pass::default();
+    let mut after: Option<IVec> = None;
+    loop {
+        let range = match &after {
+            Some(key) => tree.range::<IVec, _>((Bound::Excluded(key.clone()), Bound::Unbounded)),
+            None => tree.iter(),
+        };
+        let batch = range.take(batch_size).collect::<Result<Vec<_>, _>>()?;
+        let Some((last, _)) = batch.last() else {
+            return Ok(pass);
+        };
+        after = Some(last.clone());
+
+        for (key, value) in batch {
+            // malformed keys are for fsck to report
+            let Ok(parsed) = Key::from_bytes(&key) else {
+                continue;
+            };
+            let bytes = match reseal(name, &parsed, &value, bincode_config, vault) {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!(tree = name, key = %parsed, error = %e, "record not resealed");
+                    pass.skipped += 1;
+                    continue;
+                }
+            };
+            pass.stale += 1;
+            if tree
+                .compare_and_swap(&key, Some(&value), Some(bytes))?
+                .is_ok()
+            {
+                pass.resealed += 1;
+            }
+        }
+    }
+}
+
+/// New value of a record that isn't sealed the way new records are.
+fn reseal(
+    name: &str,
+    key: &Key,
+    value: &[u8],
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Option<Vec<u8>>, SledStorageError> {
+    let owner = || {
+        key.id_segment::<UserId>(0)
+            .ok_or(SledStorageError::InvalidKey(key.to_string()))
+    };
+    match name {
+        n if n == SLED_TODO_TREE => {
+            let version = TodoVersion::from_bytes(value, bincode_config)?;
+            reseal_todo(vault, &owner()?, &key_todo_id(key)?, version)?
+                .map(|version| version.to_bytes(bincode_config))
+                .transpose()
+        }
+        n if n == SLED_TRASH_TREE => {
+            let record = TrashRecord::from_bytes(value, bincode_config)?;
+            reseal_todo(vault, &owner()?, &key_todo_id(key)?, record.todo)?
+                .map(|todo| TrashRecord { todo, ..record }.to_bytes(bincode_config))
+                .transpose()
+        }
+        n if n == SLED_HISTORY_TREE => {
+            let owner = owner()?;
+            let version = HistoryVersion::from_bytes(value, bincode_config)?;
+            let sealed = match &version {
+                HistoryVersion::Sealed { changes, .. } => Some(changes),
+                HistoryVersion::V1 { .. } => None,
+            };
+            if !is_stale(vault, &owner, sealed, true)? {
+                return Ok(None);
+            }
+            let todo_id = key_todo_id(key)?;
+            let entry = vault.open_history(&owner, &todo_id, version)?;
+            Some(
+                vault
+                    .seal_history(&owner, &todo_id, entry)?
+                    .to_bytes(bincode_config),
+            )
+            .transpose()
+        }
+        n if n == SLED_UNDO_TREE => {
+            let record = UndoRecord::from_bytes(value, bincode_config)?;
+            match reseal_step(vault, &owner()?, record.step)? {
+                (step, true) => {
+                    Some(UndoRecord { step, ..record }.to_bytes(bincode_config)).transpose()
+                }
+                (_, false) => Ok(None),
+            }
+        }
+        // only the usage of a user is sealed, next to its change log
+        n if n == SLED_SYNC_TREE && key.kind() == Some(PrefixKind::Usage) => {
+            let owner = owner()?;
+            let version = UsageVersion::from_bytes(value, bincode_config)?;
+            let sealed = match &version {
+                UsageVersion::Sealed(sealed) => Some(sealed),
+                UsageVersion::Plain(_) => None,
+            };
+            if !is_stale(vault, &owner, sealed, true)? {
+                return Ok(None);
+            }
+            let usage = vault.open_usage(&owner, version)?;
+            Some(vault.seal_usage(&owner, usage)?.to_bytes(bincode_config)).transpose()
+        }
+        n if n == SLED_OUTBOX_TREE => {
+            let event = WebhookEvent::from_bytes(value, bincode_config)?;
+            reseal_event(vault, event)?
+                .map(|event| event.to_bytes(bincode_config))
+                .transpose()
+        }
+        n if n == SLED_WEBHOOK_TREE => match key.kind() {
+            Some(PrefixKind::Delivery | PrefixKind::DeadLetter) => {
+                let delivery = Delivery::from_bytes(value, bincode_config)?;
+                reseal_event(vault, delivery.event)?
+                    .map(|event| Delivery { event, ..delivery }.to_bytes(bincode_config))
+                    .transpose()
+            }
+            _ => Ok(None),
+        },
+        n if n == SLED_USER_TREE => {
+            let version = UserVersion::from_bytes(value, bincode_config)?;
+            let (id, sealed) = match &version {
+                UserVersion::Sealed { id, email, .. } => (*id, Some(email)),
+                UserVersion::V1 { id, .. } => (*id, None),
+            };
+            if !is_stale(vault, &id, sealed, vault.seals_emails())? {
+                return Ok(None);
+            }
+            let user = vault.open_user(version)?;
+            Some(vault.seal_user(user)?.to_bytes(bincode_config)).transpose()
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Whether a value sealed as given must be sealed again, `seal` tells whether
+/// new values of its kind are sealed at all.
+fn is_stale(
+    vault: &Vault,
+    owner: &UserId,
+    sealed: Option<&Sealed>,
+    seal: bool,
+) -> Result<bool, SledStorageError> {
+    match sealed {
+        Some(sealed) => Ok(!seal || !vault.is_current(owner, sealed)?),
+        None => Ok(seal),
+    }
+}
+
+fn sealed_content(version: &TodoVersion) -> Option<&Sealed> {
+    match version {
+        TodoVersion::Sealed { content, .. } => Some(content),
+        _ => None,
+    }
+}
+
+fn reseal_todo(
+    vault: &Vault,
+    owner: &UserId,
+    todo_id: &TodoId,
+    version: TodoVersion,
+) -> Result<Option<TodoVersion>, SledStorageError> {
+    if !is_stale(vault, owner, sealed_content(&version), true)? {
+        return Ok(None);
+    }
+    let todo = vault.open_todo(owner, todo_id, version)?;
+    vault.seal_todo(owner, todo).map(Some)
+}
+
+/// The step with its todos sealed again, and whether any was.
+fn reseal_step(
+    vault: &Vault,
+    owner: &UserId,
+    step: UndoStep,
+) -> Result<(UndoStep, bool), SledStorageError> {
+    match step {
+        UndoStep::Updated { before } => {
+            if !is_stale(vault, owner, sealed_content(&before), true)? {
+                return Ok((UndoStep::Updated { before }, false));
+            }
+            let todo = vault.open_todo(owner, &before.id(), before)?;
+            let before = vault.seal_todo(owner, todo)?;
+            Ok((UndoStep::Updated { before }, true))
+        }
+        UndoStep::Batch { steps } => {
+            let mut changed = false;
+            let mut resealed = Vec::with_capacity(steps.len());
+            for step in steps {
+                let (step, step_changed) = reseal_step(vault, owner, step)?;
+                changed |= step_changed;
+                resealed.push(step);
+            }
+            Ok((UndoStep::Batch { steps: resealed }, changed))
+        }
+        step => Ok((step, false)),
+    }
+}
+
+fn reseal_event(
+    vault: &Vault,
+    event: WebhookEvent,
+) -> Result<Option<WebhookEvent>, SledStorageError> {
+    let stale = match &event.data {
+        WebhookData::Todo(version) => {
+            reseal_todo(vault, &event.user_id, &version.id(), version.clone())?.is_some()
+        }
+        WebhookData::User { id, .. } => is_stale(vault, id, None, vault.seals_emails())?,
+        WebhookData::SealedUser { id, email, .. } => {
+            is_stale(vault, id, Some(email), vault.seals_emails())?
+        }
+    };
+    if !stale {
+        return Ok(None);
+    }
+    let event = vault.open_event(event)?;
+    vault.seal_event(event).map(Some)
+}
+
+/// Moves the email index entries of users to the key the config asks for,
+/// blind when emails are sealed and plain otherwise. Returns how many moved.
+fn move_email_entries(
+    users: &Tree,
+    emails: &Tree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<usize, SledStorageError> {
+    let mut moved = 0;
+    for value in users.iter().values() {
+        let Ok(user) = UserVersion::from_bytes(&value?, bincode_config)
+            .and_then(|version| vault.open_user(version))
+        else {
+            continue;
+        };
+        // creates the blind index key on the first sealed email
+        let wanted = vault.email_key(&user.email)?;
+        if emails.contains_key(wanted.as_bytes())? {
+            continue;
+        }
+        for other in vault.email_keys(&user.email)? {
+            if other.as_bytes() == wanted.as_bytes() {
+                continue;
+            }
+            let Some(entry) = emails.get(other.as_bytes())? else {
+                continue;
+            };
+            if UserId::from_bytes(&entry, bincode_config).ok() != Some(user.id) {
+                continue;
+            }
+            let swapped = emails.transaction(|emails| {
+                // the user may have been removed since
+                if emails.get(other.as_bytes())?.as_ref() != Some(&entry) {
+                    return Ok(false);
+                }
+                emails.insert(wanted.as_bytes(), entry.clone())?;
+                emails.remove(other.as_bytes())?;
+                Ok::<_, ConflictableTransactionError<SledStorageError>>(true)
+            })?;
+            if swapped {
+                moved += 1;
+            }
+            break;
+        }
+    }
+    Ok(moved)
+}
@@ -0,0 +1,99 @@
+//! Storage statistics. Every tree is counted and the todo and session trees
+//! are scanned, nothing is cached between runs.
+
+use std::collections::HashMap;
+
+use sled::Tree;
+use tracing::{info_span, warn};
+
+use super::super::{
+    error::SledStorageError,
+    internal::{Key, PrefixKind},
+    BincodeConfig, FromBytesWithConfig, SLED_SESSION_TREE, SLED_TODO_TREE, SLED_USER_TREE,
+};
+use crate::storage::{Session, StorageStats, TodoBucket, UserId, TODO_BUCKETS};
+
+pub(super) fn collect(
+    db: &sled::Db,
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+    top_users: usize,
+) -> Result<StorageStats, SledStorageError> {
+    let now = chrono::Utc::now().timestamp();
+    let mut stats = StorageStats {
+        collected_at: now,
+        size_on_disk: db.size_on_disk()?,
+        ..StorageStats::default()
+    };
+
+    let mut users = 0;
+    let mut todos = HashMap::new();
+    for (name, tree) in trees {
+        let records = info_span!("sled::count_tree", tree = name).in_scope(|| tree.len());
+        stats.records.push((*name, records));
+        match *name {
+            n if n == SLED_USER_TREE => users = records,
+            n if n == SLED_TODO_TREE => todos = todos_per_user(tree)?,
+            n if n == SLED_SESSION_TREE => {
+                for value in tree.iter().values() {
+                    // undecodable sessions are for fsck to report
+                    let Ok(session) = Session::from_bytes(&value?, bincode_config) else {
+                        continue;
+                    };
+                    if session.expires_at > now {
+                        stats.active_sessions += 1;
+                    } else {
+                        stats.expired_sessions += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    stats.todos_per_user = buckets(&todos, users);
+    stats.top_users = top(todos, top_users);
+    Ok(stats)
+}
+
+fn todos_per_user(todos: &Tree) -> Result<HashMap<UserId, usize>, SledStorageError> {
+    let mut counts = HashMap::new();
+    for key in todos.iter().keys() {
+        let key = key?;
+        let owner = Key::from_bytes(&key)
+            .ok()
+            .filter(|key| key.kind() == Some(PrefixKind::Todo))
+            .and_then(|key| key.id_segment::<UserId>(0));
+        match owner {
+            Some(owner) => *counts.entry(owner).or_insert(0) += 1,
+            None => warn!("todo with a malformed key not counted"),
+        }
+    }
+    Ok(counts)
+}
+
+/// Users of each bucket, the ones without a todo are the users that don't
+/// appear in `todos`.
+fn buckets(todos: &HashMap<UserId, usize>, users: usize) -> Vec<TodoBucket> {
+    let mut counts = vec![0; TODO_BUCKETS.len() + 1];
+    counts[0] = users.saturating_sub(todos.len());
+    for count in todos.values() {
+        let bucket = TODO_BUCKETS.partition_point(|up_to| up_to < count);
+        counts[bucket] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, users)| TodoBucket {
+            up_to: TODO_BUCKETS.get(i).copied(),
+            users,
+        })
+        .collect()
+}
+
+/// The `n` users with the most todos, ties in id order.
+fn top(todos: HashMap<UserId, usize>, n: usize) -> Vec<(UserId, usize)> {
+    let mut todos: Vec<_> = todos.into_iter().collect();
+    todos.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    todos.truncate(n);
+    todos
+}
@@ -0,0 +1,628 @@
+use super::*;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{
+    config::types::{EncryptionConfig, SledConfig},
+    storage::{
+        sled::{
+            email_key, error::SledStartupError, link_key, session_key, test_util::ADMIN_UUID,
+            todo_key, usage_key, user_key, ToBytesWithConfig, SLED_TRASH_TREE,
+        },
+        HashedPassword, Jti, Pagination, Role, RotationReport, Session, SessionId, Todo, TodoId,
+        TodoStorage, Usage, User, UserId, UserStorage,
+    },
+};
+
+fn settings(encryption: Option<EncryptionConfig>) -> SledConfig {
+    SledConfig {
+        path: PathBuf::new(),
+        delete_batch_size: 10,
+        undo_log_size: 5,
+        encryption,
+        compression: None,
+    }
+}
+
+fn storage() -> SledStorage {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    SledStorage::from_db(&db, &settings(None)).unwrap()
+}
+
+fn backup_path() -> PathBuf {
+    std::env::temp_dir().join(format!("todo-backup-{}.bin", uuid::Uuid::new_v4()))
+}
+
+fn user(email: &str) -> User {
+    User {
+        id: UserId::new(),
+        email: email.to_string(),
+        hashed_password: HashedPassword {
+            salt: vec![1; 4],
+            hash: vec![2; 4],
+        },
+        role: Role::User,
+    }
+}
+
+async fn todos(storage: &SledStorage, user_id: UserId) -> Vec<Todo> {
+    let page = Pagination {
+        after: None,
+        limit: 10,
+    };
+    TodoStorage::get_all(storage, user_id, page)
+        .await
+        .unwrap()
+        .0
+}
+
+#[tokio::test]
+async fn test_backup_and_restore() {
+    let storage = storage();
+    let user_id: UserId = ADMIN_UUID.into();
+    let user = user("user@gmail.com");
+    UserStorage::put(&storage, user.id, user.clone())
+        .await
+        .unwrap();
+    let kept = Todo::new(TodoId::new(), "kept");
+    TodoStorage::put(&storage, user_id, kept.id, kept.clone())
+        .await
+        .unwrap();
+
+    let path = backup_path();
+    let manifest = storage.backup(path.clone()).await.unwrap();
+    assert!(manifest.records() >= 3, "{manifest:?}");
+    assert_eq!(manifest.trees.len(), storage.trees().len());
+    assert_eq!(
+        MaintenanceStorage::inspect(&storage, path.clone())
+            .await
+            .unwrap(),
+        manifest
+    );
+
+    let later = Todo::new(TodoId::new(), "later");
+    TodoStorage::put(&storage, user_id, later.id, later)
+        .await
+        .unwrap();
+    TodoStorage::delete(&storage, user_id, kept.id)
+        .await
+        .unwrap();
+    UserStorage::delete(&storage, user.id).await.unwrap();
+
+    assert_eq!(
+        MaintenanceStorage::restore(&storage, path.clone())
+            .await
+            .unwrap(),
+        manifest
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(todos(&storage, user_id).await, vec![kept]);
+    assert_eq!(storage.get_by_email("user@gmail.com").await.unwrap(), user);
+}
+
+#[tokio::test]
+async fn test_interrupted_restore_is_run_again() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let storage = SledStorage::from_db(&db, &settings(None)).unwrap();
+    let user_id: UserId = ADMIN_UUID.into();
+    let todo = Todo::new(TodoId::new(), "aaa");
+    TodoStorage::put(&storage, user_id, todo.id, todo.clone())
+        .await
+        .unwrap();
+    let path = backup_path();
+    MaintenanceStorage::backup(&storage, path.clone())
+        .await
+        .unwrap();
+
+    // a restore stopped before the meta tree was replaced
+    storage
+        .meta_tree
+        .insert(restore_marker_key().as_bytes(), &[])
+        .unwrap();
+    assert!(matches!(
+        SledStorage::from_db(&db, &settings(None)),
+        Err(SledStartupError::InterruptedRestore)
+    ));
+
+    restore_trees(&path, &storage.owned_trees(), &storage.bincode_config).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let storage = SledStorage::from_db(&db, &settings(None)).unwrap();
+    assert_eq!(todos(&storage, user_id).await, vec![todo]);
+}
+
+#[tokio::test]
+async fn test_restore_rejects_invalid_backup() {
+    let storage = storage();
+    let user_id: UserId = ADMIN_UUID.into();
+    let todo = Todo::new(TodoId::new(), "aaa");
+    TodoStorage::put(&storage, user_id, todo.id, todo.clone())
+        .await
+        .unwrap();
+
+    let path = backup_path();
+    let restore_from = async |bytes: Vec<u8>| {
+        std::fs::write(&path, bytes).unwrap();
+        let result = MaintenanceStorage::restore(&storage, path.clone()).await;
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Err(StorageError::InvalidBackup(reason)) => reason,
+            other => panic!("{other:?}"),
+        }
+    };
+
+    let reason = restore_from(b"not a backup".to_vec()).await;
+    assert_eq!(reason, "not a backup archive");
+
+    let dump = |name: &str, entries: Vec<(Vec<u8>, Vec<u8>)>| TreeDump {
+        name: name.to_string(),
+        entries,
+    };
+    let (mut bytes, _) = archive::encode(vec![dump(SLED_TODO_TREE, Vec::new())], 0).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    let reason = restore_from(bytes).await;
+    assert!(reason.contains("checksum mismatch"), "{reason}");
+
+    let (bytes, _) = archive::encode(vec![dump("unknown", Vec::new())], 0).unwrap();
+    assert_eq!(restore_from(bytes).await, "unknown tree unknown");
+
+    let entries = vec![(
+        todo_key(&user_id, &TodoId::new()).as_bytes().to_vec(),
+        vec![0xff; 3],
+    )];
+    let (bytes, _) = archive::encode(vec![dump(SLED_TODO_TREE, entries)], 0).unwrap();
+    let reason = restore_from(bytes).await;
+    assert!(reason.contains("undecodable_value"), "{reason}");
+
+    let result = MaintenanceStorage::restore(&storage, backup_path()).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+
+    // nothing was replaced
+    assert_eq!(todos(&storage, user_id).await, vec![todo]);
+}
+
+#[tokio::test]
+async fn test_verify() {
+    let storage = storage();
+    let user = user("user@gmail.com");
+    UserStorage::put(&storage, user.id, user.clone())
+        .await
+        .unwrap();
+    let todo = Todo::new(TodoId::new(), "aaa");
+    TodoStorage::put(&storage, user.id, todo.id, todo.clone())
+        .await
+        .unwrap();
+
+    let report = storage.verify(false).await.unwrap();
+    assert!(report.is_ok(), "{report:?}");
+    assert!(report.records.contains(&(SLED_USER_TREE, 1)));
+    assert!(report.records.contains(&(SLED_TODO_TREE, 1)));
+
+    storage.todo_tree.insert("no_kind:abc", vec![0]).unwrap();
+    storage
+        .user_tree
+        .insert(user_key(&user.id).as_bytes(), vec![0xff; 3])
+        .unwrap();
+    let other = TodoId::new();
+    storage
+        .todo_tree
+        .insert(todo_key(&user.id, &other).as_bytes(), vec![])
+        .unwrap();
+
+    let report = storage.verify(false).await.unwrap();
+    let mut problems: Vec<(&str, String)> = report
+        .problems
+        .iter()
+        .map(|problem| (problem.tree, problem.key.clone()))
+        .collect();
+    problems.sort();
+    assert_eq!(
+        problems,
+        vec![
+            (SLED_TODO_TREE, "no_kind:abc".to_string()),
+            (SLED_TODO_TREE, todo_key(&user.id, &other).to_string()),
+            (SLED_USER_TREE, user_key(&user.id).to_string()),
+        ]
+    );
+}
+
+fn kinds(report: &StorageReport) -> Vec<(ProblemKind, String, bool)> {
+    let mut kinds: Vec<_> = report
+        .problems
+        .iter()
+        .map(|problem| (problem.kind, problem.key.clone(), problem.repaired))
+        .collect();
+    kinds.sort_by(|a, b| a.1.cmp(&b.1));
+    kinds
+}
+
+#[tokio::test]
+async fn test_verify_cross_tree() {
+    let storage = storage();
+    let missing = user("missing@gmail.com");
+    let redirected = user("redirected@gmail.com");
+    let taken = user("taken@gmail.com");
+    for user in [&missing, &redirected, &taken] {
+        UserStorage::put(&storage, user.id, user.clone())
+            .await
+            .unwrap();
+    }
+    let ghost = user("ghost@gmail.com");
+    let ghost_id = ghost.id.to_bytes(&storage.bincode_config).unwrap();
+
+    storage
+        .email_tree
+        .remove(email_key(&missing.email).unwrap().as_bytes())
+        .unwrap();
+    storage
+        .email_tree
+        .insert(
+            email_key(&redirected.email).unwrap().as_bytes(),
+            ghost_id.clone(),
+        )
+        .unwrap();
+    // emails differing in case share the index entry
+    let thief = user("Taken@Gmail.com");
+    storage
+        .user_tree
+        .insert(
+            user_key(&thief.id).as_bytes(),
+            thief.to_bytes(&storage.bincode_config).unwrap(),
+        )
+        .unwrap();
+    storage
+        .email_tree
+        .insert(email_key(&ghost.email).unwrap().as_bytes(), ghost_id)
+        .unwrap();
+
+    let todo = Todo::new(TodoId::new(), "orphan");
+    TodoStorage::put(&storage, ghost.id, todo.id, todo.clone())
+        .await
+        .unwrap();
+    storage
+        .link_tree
+        .insert(link_key(&ghost.id, &todo.id).as_bytes(), vec![0])
+        .unwrap();
+    let session = Session {
+        id: SessionId::new(),
+        user_id: ghost.id,
+        created_at: 0,
+        expires_at: i64::MAX,
+        current_refresh_jti: Jti::new(),
+    };
+    storage
+        .session_tree
+        .insert(
+            session_key(&session.id).as_bytes(),
+            session.to_bytes(&storage.bincode_config).unwrap(),
+        )
+        .unwrap();
+    // counts that lost track of a todo
+    let counted = Todo::new(TodoId::new(), "counted");
+    TodoStorage::put(&storage, taken.id, counted.id, counted)
+        .await
+        .unwrap();
+    storage
+        .sync_tree
+        .insert(
+            usage_key(&taken.id).as_bytes(),
+            UsageVersion::Plain(Usage::default())
+                .to_bytes(&storage.bincode_config)
+                .unwrap(),
+        )
+        .unwrap();
+
+    let sorted = |problems: Vec<(ProblemKind, Key, bool)>| {
+        let mut problems: Vec<_> = problems
+            .into_iter()
+            .map(|(kind, key, repaired)| (kind, key.to_string(), repaired))
+            .collect();
+        problems.sort_by(|a, b| a.1.cmp(&b.1));
+        problems
+    };
+    let found = |repaired: bool| {
+        sorted(vec![
+            (ProblemKind::MissingEmail, user_key(&missing.id), repaired),
+            (
+                ProblemKind::MissingEmail,
+                user_key(&redirected.id),
+                repaired,
+            ),
+            (ProblemKind::TakenEmail, user_key(&thief.id), false),
+            (
+                ProblemKind::DanglingEmail,
+                email_key(&ghost.email).unwrap(),
+                repaired,
+            ),
+            (
+                ProblemKind::OrphanTodo,
+                todo_key(&ghost.id, &todo.id),
+                repaired,
+            ),
+            (
+                ProblemKind::OrphanSession,
+                session_key(&session.id),
+                repaired,
+            ),
+            (ProblemKind::OrphanUsage, usage_key(&ghost.id), repaired),
+            (ProblemKind::WrongUsage, usage_key(&taken.id), repaired),
+        ])
+    };
+    let mut expected = found(false);
+    expected.push((
+        ProblemKind::DanglingEmail,
+        email_key(&redirected.email).unwrap().to_string(),
+        false,
+    ));
+    expected.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let report = storage.verify(false).await.unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(kinds(&report), expected);
+    // checking doesn't change anything
+    assert_eq!(kinds(&storage.verify(false).await.unwrap()), expected);
+
+    // pointing the entry back at its user also settles the dangling one
+    let report = storage.verify(true).await.unwrap();
+    assert_eq!(kinds(&report), found(true));
+    assert!(!report.is_ok());
+
+    assert_eq!(storage.get_by_email(&missing.email).await.unwrap(), missing);
+    assert_eq!(
+        storage.get_by_email(&redirected.email).await.unwrap(),
+        redirected
+    );
+    assert_eq!(storage.get_by_email(&thief.email).await.unwrap(), taken);
+    assert!(storage.get_by_email(&ghost.email).await.is_err());
+    assert!(todos(&storage, ghost.id).await.is_empty());
+    assert!(!storage
+        .link_tree
+        .contains_key(link_key(&ghost.id, &todo.id).as_bytes())
+        .unwrap());
+    assert!(!storage
+        .session_tree
+        .contains_key(session_key(&session.id).as_bytes())
+        .unwrap());
+    // the next write counts the usage again
+    assert!(!storage
+        .sync_tree
+        .contains_key(usage_key(&taken.id).as_bytes())
+        .unwrap());
+    let todo = Todo::new(TodoId::new(), "recounted");
+    TodoStorage::put(&storage, taken.id, todo.id, todo)
+        .await
+        .unwrap();
+
+    let report = storage.verify(false).await.unwrap();
+    assert_eq!(
+        kinds(&report),
+        vec![(
+            ProblemKind::TakenEmail,
+            user_key(&thief.id).to_string(),
+            false
+        )]
+    );
+}
+
+#[tokio::test]
+async fn test_repair_removes_unreadable_records() {
+    let storage = storage();
+    let user = user("user@gmail.com");
+    UserStorage::put(&storage, user.id, user.clone())
+        .await
+        .unwrap();
+    storage.todo_tree.insert("no_kind:abc", vec![0]).unwrap();
+    let broken = todo_key(&user.id, &TodoId::new());
+    storage
+        .todo_tree
+        .insert(broken.as_bytes(), vec![0xff; 3])
+        .unwrap();
+
+    let report = storage.verify(true).await.unwrap();
+    assert!(report.is_ok(), "{report:?}");
+    let mut kinds: Vec<_> = report.problems.iter().map(|p| p.kind).collect();
+    kinds.sort_by_key(|kind| kind.as_ref().to_string());
+    assert_eq!(
+        kinds,
+        vec![ProblemKind::MalformedKey, ProblemKind::UndecodableValue]
+    );
+    assert!(storage.todo_tree.get("no_kind:abc").unwrap().is_none());
+    assert!(storage.todo_tree.get(broken.as_bytes()).unwrap().is_none());
+
+    let report = storage.verify(false).await.unwrap();
+    assert!(report.problems.is_empty(), "{report:?}");
+    let report = storage.migrate(true).await.unwrap();
+    assert_eq!(report.from, migrations::SCHEMA_VERSION);
+}
+
+#[tokio::test]
+async fn test_restore_upgrades_email_index() {
+    let storage = storage();
+    let user = user("Old@gmail.com");
+    let encoded = storage.bincode_config.encode(&user).unwrap();
+    // taken before the schema had a version, when users were stored bare and
+    // the emails tree held full copies of them
+    let dumps = vec![
+        TreeDump {
+            name: SLED_USER_TREE.to_string(),
+            entries: vec![(user_key(&user.id).as_bytes().to_vec(), encoded.clone())],
+        },
+        TreeDump {
+            name: SLED_EMAIL_TREE.to_string(),
+            entries: vec![(b"email:Old@gmail.com".to_vec(), encoded)],
+        },
+    ];
+    let (bytes, _) = archive::encode(dumps, 0).unwrap();
+    let path = backup_path();
+    std::fs::write(&path, bytes).unwrap();
+
+    MaintenanceStorage::restore(&storage, path.clone())
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(storage.get_by_email("old@gmail.com").await.unwrap(), user);
+    let report = storage.verify(false).await.unwrap();
+    assert!(report.problems.is_empty(), "{report:?}");
+}
+
+fn encrypted(db: &sled::Db, master: &Path, previous: &[PathBuf]) -> SledStorage {
+    let encryption = EncryptionConfig {
+        master_key_file: Some(master.to_path_buf()),
+        previous_master_key_files: previous.to_vec(),
+        emails: true,
+    };
+    SledStorage::from_db(db, &settings(Some(encryption))).unwrap()
+}
+
+fn master_key_file(seed: u8) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("todo-master-key-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, STANDARD.encode([seed; 32])).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn test_rotate_keys() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let plain = SledStorage::from_db(&db, &settings(None)).unwrap();
+    let user = user("Someone@gmail.com");
+    UserStorage::put(&plain, user.id, user.clone())
+        .await
+        .unwrap();
+    let kept = Todo::new(TodoId::new(), "secret plans");
+    let trashed = Todo::new(TodoId::new(), "secret trash");
+    for todo in [&kept, &trashed] {
+        TodoStorage::put(&plain, user.id, todo.id, todo.clone())
+            .await
+            .unwrap();
+    }
+    TodoStorage::delete(&plain, user.id, trashed.id)
+        .await
+        .unwrap();
+    assert!(MaintenanceStorage::rotate_keys(&plain, 1).await.is_err());
+    let is_plain = |storage: &SledStorage| {
+        storage
+            .owned_trees()
+            .iter()
+            .flat_map(|(_, tree)| tree.iter().values())
+            .any(|value| {
+                let value = value.unwrap();
+                value.windows(6).any(|window| window == b"secret")
+                    || value.windows(7).any(|window| window == b"Someone")
+            })
+    };
+    assert!(is_plain(&plain));
+
+    // records written before encryption was configured are sealed by the first rotation
+    let first = master_key_file(1);
+    let storage = encrypted(&db, &first, &[]);
+    let report = MaintenanceStorage::rotate_keys(&storage, 1).await.unwrap();
+    assert_eq!(report.rotated, 0);
+    assert_eq!(report.skipped, 0);
+    assert!(!is_plain(&storage), "{report:?}");
+    let sealed = |report: &RotationReport, tree: &str| {
+        report
+            .records
+            .iter()
+            .find(|(name, _)| *name == tree)
+            .map(|(_, records)| *records)
+    };
+    assert_eq!(sealed(&report, SLED_TODO_TREE), Some(1));
+    assert_eq!(sealed(&report, SLED_USER_TREE), Some(1));
+    assert_eq!(sealed(&report, SLED_EMAIL_TREE), Some(1));
+    assert_eq!(todos(&storage, user.id).await, vec![kept.clone()]);
+    assert_eq!(
+        storage.get_by_email("someone@gmail.com").await.unwrap(),
+        user
+    );
+
+    // a new master key, the old one is only needed until the keys are rewrapped
+    let second = master_key_file(2);
+    let storage = encrypted(&db, &second, std::slice::from_ref(&first));
+    let report = MaintenanceStorage::rotate_keys(&storage, 2).await.unwrap();
+    assert_eq!(report.rotated, 1);
+    assert_eq!(report.retired, 1);
+    assert_eq!(report.rewrapped, 1);
+    assert_eq!(sealed(&report, SLED_TODO_TREE), Some(1));
+    assert_eq!(sealed(&report, SLED_TRASH_TREE), Some(1));
+    assert_eq!(sealed(&report, SLED_USER_TREE), Some(1));
+    assert_eq!(sealed(&report, SLED_EMAIL_TREE), Some(0));
+    std::fs::remove_file(&first).unwrap();
+
+    let storage = encrypted(&db, &second, &[]);
+    assert_eq!(todos(&storage, user.id).await, vec![kept]);
+    assert_eq!(
+        storage.get_by_email("someone@gmail.com").await.unwrap(),
+        user
+    );
+    let report = storage.verify(false).await.unwrap();
+    assert!(report.problems.is_empty(), "{report:?}");
+    std::fs::remove_file(&second).unwrap();
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let storage = storage();
+    let busy = user("busy@gmail.com");
+    let idle = user("idle@gmail.com");
+    let none = user("none@gmail.com");
+    for user in [&busy, &idle, &none] {
+        UserStorage::put(&storage, user.id, user.clone())
+            .await
+            .unwrap();
+    }
+    for (user, count) in [(&busy, 12), (&idle, 1)] {
+        for i in 0..count {
+            let todo = Todo::new(TodoId::new(), &format!("todo {i}"));
+            TodoStorage::put(&storage, user.id, todo.id, todo)
+                .await
+                .unwrap();
+        }
+    }
+    for expires_at in [i64::MAX, 0] {
+        let session = Session {
+            id: SessionId::new(),
+            user_id: busy.id,
+            created_at: 0,
+            expires_at,
+            current_refresh_jti: Jti::new(),
+        };
+        storage
+            .session_tree
+            .insert(
+                session_key(&session.id).as_bytes(),
+                session.to_bytes(&storage.bincode_config).unwrap(),
+            )
+            .unwrap();
+    }
+
+    let stats = storage.stats(1).await.unwrap();
+    assert!(stats.size_on_disk > 0);
+    assert_eq!(stats.records.len(), storage.trees().len());
+    let records = |tree: &str| {
+        stats
+            .records
+            .iter()
+            .find(|(name, _)| *name == tree)
+            .map(|(_, records)| *records)
+    };
+    assert_eq!(records(SLED_TODO_TREE), Some(13));
+    assert_eq!(records(SLED_USER_TREE), Some(3));
+    assert_eq!(stats.top_users, vec![(busy.id, 12)]);
+    let buckets: Vec<_> = stats
+        .todos_per_user
+        .iter()
+        .map(|bucket| (bucket.up_to, bucket.users))
+        .collect();
+    assert_eq!(
+        buckets,
+        vec![
+            (Some(0), 1),
+            (Some(10), 1),
+            (Some(100), 1),
+            (Some(1_000), 0),
+            (Some(10_000), 0),
+            (None, 0),
+        ]
+    );
+    assert_eq!((stats.active_sessions, stats.expired_sessions), (1, 1));
+}
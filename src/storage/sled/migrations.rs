@@ -0,0 +1,556 @@
+//! Schema migrations of the sled storage. The `meta` tree holds the version
+//! of the schema the data is written in, and every step above it runs in
+//! order. A step converts one record at a time, and the writes are applied in
+//! transactions of up to `MIGRATION_BATCH_SIZE` records. Each of them also
+//! stores the last record converted, so an interrupted step resumes after it
+//! instead of converting a record twice. The version goes up once the step
+//! went through every record, and a dry run counts the writes without
+//! applying them.
+
+use std::{collections::BTreeMap, ops::Bound, str::FromStr};
+
+use bincode::{Decode, Encode};
+use sled::{transaction::ConflictableTransactionError, IVec, Transactional, Tree};
+use tracing::{info, info_span, instrument, warn};
+
+use super::{
+    email_key,
+    error::SledStorageError,
+    internal::{display_key, Key, KeyPrefix, PrefixKind},
+    normalized_email, BincodeConfig, FromBytesWithConfig, ToBytesWithConfig, SLED_EMAIL_TREE,
+    SLED_META_TREE, SLED_SESSION_TREE, SLED_USER_TREE,
+};
+use crate::storage::{MigrationReport, MigrationStep, Session, User, UserId};
+
+/// Records a step converts in one transaction.
+const MIGRATION_BATCH_SIZE: usize = 1_000;
+
+/// Writes converting one record of a tree.
+type Convert = fn(&'static str, IVec, IVec, &BincodeConfig) -> Result<Vec<Write>, SledStorageError>;
+
+/// Looks at the whole storage before a step converts anything.
+type Check = fn(&[(&'static str, Tree)], &BincodeConfig) -> Result<(), SledStorageError>;
+
+struct Migration {
+    description: &'static str,
+    /// Trees whose values the step may rewrite.
+    trees: &'static [&'static str],
+    /// Whether the step goes through the keys of every tree instead.
+    rekeys: bool,
+    check: Option<Check>,
+    convert: Convert,
+}
+
+/// Step `i` brings the storage to version `i + 1`. Steps are never changed
+/// or removed once released, a fix is a new step.
+const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        description: "email index holds user ids",
+        trees: &[SLED_EMAIL_TREE],
+        rekeys: false,
+        check: Some(unique_emails),
+        convert: email_index,
+    },
+    Migration {
+        description: "users and sessions in versioned envelopes",
+        trees: &[SLED_USER_TREE, SLED_SESSION_TREE],
+        rekeys: false,
+        check: None,
+        convert: envelopes,
+    },
+    Migration {
+        description: "binary keys",
+        trees: &[],
+        rekeys: true,
+        check: None,
+        convert: binary_keys,
+    },
+];
+
+/// First version whose keys are binary, including the one of the version.
+const BINARY_KEYS: u32 = 3;
+
+/// Key of the version in storages older than `BINARY_KEYS`.
+const LEGACY_VERSION_KEY: &[u8] = b"meta:schema_version";
+
+/// Schema version of the data this code writes.
+pub(super) const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Whether migrating from `version` may rewrite the records of `tree`.
+pub(super) fn rewrites(version: u32, tree: &str) -> bool {
+    MIGRATIONS
+        .iter()
+        .skip(version as usize)
+        .any(|migration| migration.trees.contains(&tree))
+}
+
+/// Record a step inserts or, without a value, removes.
+struct Write {
+    tree: &'static str,
+    key: IVec,
+    value: Option<Vec<u8>>,
+}
+
+/// The last record an unfinished step converted.
+#[derive(Encode, Decode, Debug, PartialEq)]
+struct Resume {
+    version: u32,
+    tree: String,
+    key: Vec<u8>,
+}
+
+fn schema_version_key() -> Key {
+    Key::new(KeyPrefix::from_kind(PrefixKind::Meta), "schema_version")
+}
+
+fn resume_key() -> Key {
+    Key::new(KeyPrefix::from_kind(PrefixKind::Meta), "migration_resume")
+}
+
+fn version_key(version: u32) -> IVec {
+    if version < BINARY_KEYS {
+        LEGACY_VERSION_KEY.into()
+    } else {
+        schema_version_key().as_bytes().into()
+    }
+}
+
+/// Version stored in the `meta` tree, `None` when it has none.
+pub(super) fn stored_version(
+    meta: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<Option<u32>, SledStorageError> {
+    let bytes = match meta.get(schema_version_key().as_bytes())? {
+        Some(bytes) => Some(bytes),
+        None => meta.get(LEGACY_VERSION_KEY)?,
+    };
+    bytes
+        .map(|bytes| decode_version(&bytes, bincode_config))
+        .transpose()
+}
+
+/// Version of the schema the records of an archive are written in.
+pub(super) fn archive_version(
+    meta_entries: &[(Vec<u8>, Vec<u8>)],
+    bincode_config: &BincodeConfig,
+) -> Result<u32, SledStorageError> {
+    let key = schema_version_key();
+    meta_entries
+        .iter()
+        .find(|(entry_key, _)| entry_key.as_slice() == key.as_bytes())
+        .or_else(|| {
+            meta_entries
+                .iter()
+                .find(|(entry_key, _)| entry_key.as_slice() == LEGACY_VERSION_KEY)
+        })
+        .map_or(Ok(0), |(_, value)| decode_version(value, bincode_config))
+}
+
+/// Checks the key of a record written at `version`, which may still be a
+/// string key that a migration turns binary.
+pub(super) fn check_key(version: u32, key: &[u8]) -> Result<(), SledStorageError> {
+    match Key::from_bytes(key) {
+        Err(_) if version < BINARY_KEYS && legacy_key(key).is_some() => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+fn decode_version(bytes: &[u8], bincode_config: &BincodeConfig) -> Result<u32, SledStorageError> {
+    bincode_config.decode(bytes)
+}
+
+#[instrument(name = "sled::migrate", skip_all, fields(dry_run))]
+pub(super) fn migrate(
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+    dry_run: bool,
+) -> Result<MigrationReport, SledStorageError> {
+    let meta = tree(trees, SLED_META_TREE)?;
+    let from = match stored_version(meta, bincode_config)? {
+        Some(version) => version,
+        // nothing to migrate in a new storage
+        None if trees.iter().all(|(_, tree)| tree.is_empty()) => SCHEMA_VERSION,
+        // data written before the schema had a version
+        None => 0,
+    };
+    if from > SCHEMA_VERSION {
+        return Err(SledStorageError::SchemaTooNew(from));
+    }
+
+    let mut report = MigrationReport {
+        from,
+        to: SCHEMA_VERSION,
+        steps: Vec::new(),
+    };
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        let version = index as u32 + 1;
+        let writes = info_span!("sled::run_migration", version)
+            .in_scope(|| run(migration, version, trees, bincode_config, dry_run))?;
+        report.steps.push(MigrationStep {
+            version,
+            description: migration.description,
+            writes,
+        });
+        if !dry_run {
+            info!(
+                version,
+                description = migration.description,
+                writes,
+                "storage migrated"
+            );
+        }
+    }
+
+    if !dry_run && stored_version(meta, bincode_config)?.is_none() {
+        // a new storage starts at the latest version
+        meta.insert(
+            schema_version_key().as_bytes(),
+            bincode_config.encode(&SCHEMA_VERSION)?,
+        )?;
+    }
+    Ok(report)
+}
+
+/// Converts the records of a step in batches, after the ones an interrupted
+/// run of it converted already, and returns the number of writes.
+fn run(
+    migration: &Migration,
+    version: u32,
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+    dry_run: bool,
+) -> Result<usize, SledStorageError> {
+    if let Some(check) = migration.check {
+        check(trees, bincode_config)?;
+    }
+    let meta = tree(trees, SLED_META_TREE)?;
+    let resume = meta
+        .get(resume_key().as_bytes())?
+        .map(|bytes| bincode_config.decode::<Resume>(&bytes))
+        .transpose()?
+        .filter(|resume| resume.version == version);
+    let scanned: Vec<_> = trees
+        .iter()
+        .filter(|(name, _)| migration.rekeys || migration.trees.contains(name))
+        .collect();
+    let first = resume
+        .as_ref()
+        .and_then(|resume| scanned.iter().position(|(name, _)| *name == resume.tree))
+        .unwrap_or(0);
+    if let Some(resume) = &resume {
+        info!(version, tree = resume.tree, "resume migration");
+    }
+
+    let mut writes = 0;
+    for (position, (name, tree)) in scanned.iter().enumerate().skip(first) {
+        let mut after = resume
+            .as_ref()
+            .filter(|_| position == first)
+            .map(|resume| resume.key.clone());
+        loop {
+            let records = match &after {
+                Some(key) => {
+                    tree.range::<&[u8], _>((Bound::Excluded(key.as_slice()), Bound::Unbounded))
+                }
+                None => tree.iter(),
+            };
+            let mut batch = Vec::new();
+            let mut last = None;
+            for entry in records.take(MIGRATION_BATCH_SIZE) {
+                let (key, value) = entry?;
+                last = Some(key.to_vec());
+                batch.extend((migration.convert)(name, key, value, bincode_config)?);
+            }
+            let Some(last) = last else {
+                break;
+            };
+            writes += batch.len();
+            if !dry_run {
+                let resume = Resume {
+                    version,
+                    tree: name.to_string(),
+                    key: last.clone(),
+                };
+                apply(trees, &batch, &resume, bincode_config)?;
+            }
+            after = Some(last);
+        }
+    }
+
+    if !dry_run {
+        let encoded_version = bincode_config.encode(&version)?;
+        meta.transaction(|meta| {
+            meta.insert(version_key(version), encoded_version.as_slice())?;
+            meta.remove(resume_key().as_bytes())?;
+            Ok::<_, ConflictableTransactionError<SledStorageError>>(())
+        })?;
+    }
+    Ok(writes)
+}
+
+fn apply(
+    trees: &[(&'static str, Tree)],
+    writes: &[Write],
+    resume: &Resume,
+    bincode_config: &BincodeConfig,
+) -> Result<(), SledStorageError> {
+    let live: Vec<Tree> = trees.iter().map(|(_, tree)| tree.clone()).collect();
+    let position = |name: &str| trees.iter().position(|(tree_name, _)| *tree_name == name);
+    let meta = position(SLED_META_TREE).ok_or(SledStorageError::NotFound)?;
+    let encoded_resume = bincode_config.encode(resume)?;
+
+    info_span!("sled::migrate_in_transaction", writes = writes.len()).in_scope(|| {
+        live.as_slice().transaction(|txs| {
+            for write in writes {
+                let Some(index) = position(write.tree) else {
+                    return Err(ConflictableTransactionError::Abort(
+                        SledStorageError::InvalidKey(format!("no {} tree", write.tree)),
+                    ));
+                };
+                match &write.value {
+                    Some(value) => txs[index].insert(&write.key, value.as_slice())?,
+                    None => txs[index].remove(&write.key)?,
+                };
+            }
+            txs[meta].insert(resume_key().as_bytes(), encoded_resume.as_slice())?;
+            Ok(())
+        })
+    })?;
+    Ok(())
+}
+
+fn tree<'a>(trees: &'a [(&'static str, Tree)], name: &str) -> Result<&'a Tree, SledStorageError> {
+    trees
+        .iter()
+        .find(|(tree_name, _)| *tree_name == name)
+        .map(|(_, tree)| tree)
+        .ok_or_else(|| SledStorageError::InvalidKey(format!("no {name} tree")))
+}
+
+/// Decodes a record written before it had a versioned envelope.
+fn decode_bare<T: Decode<()>>(bytes: &[u8], bincode_config: &BincodeConfig) -> Option<T> {
+    bincode_config.decode(bytes).ok()
+}
+
+/// Version 1: the `emails` tree held a full copy of each user under the email
+/// as registered. It now maps the normalised email to the user id. Entries
+/// holding an id are converted already.
+fn email_index(
+    tree: &'static str,
+    key: IVec,
+    value: IVec,
+    bincode_config: &BincodeConfig,
+) -> Result<Vec<Write>, SledStorageError> {
+    if UserId::from_bytes(&value, bincode_config).is_ok() {
+        return Ok(Vec::new());
+    }
+    let Some(user) = decode_bare::<User>(&value, bincode_config) else {
+        return Ok(Vec::new());
+    };
+    // removal first, the new key can be the old one
+    Ok(vec![
+        Write {
+            tree,
+            key,
+            value: None,
+        },
+        Write {
+            tree,
+            key: format!("email:{}", normalized_email(&user.email))
+                .as_bytes()
+                .into(),
+            value: Some(user.id.to_bytes(bincode_config)?),
+        },
+    ])
+}
+
+/// Addresses that differ only in case would leave one of the users unable to
+/// log in, so the email index step fails with their ids until an operator
+/// gives all but one of them another email with [`rename_legacy_email`].
+fn unique_emails(
+    trees: &[(&'static str, Tree)],
+    bincode_config: &BincodeConfig,
+) -> Result<(), SledStorageError> {
+    let mut owners: BTreeMap<String, Vec<UserId>> = BTreeMap::new();
+    for entry in tree(trees, SLED_EMAIL_TREE)?.iter() {
+        let (key, value) = entry?;
+        if let Ok(user_id) = UserId::from_bytes(&value, bincode_config) {
+            let email = String::from_utf8_lossy(&key).into_owned();
+            owners.entry(email).or_default().push(user_id);
+        } else if let Some(user) = decode_bare::<User>(&value, bincode_config) {
+            let email = format!("email:{}", normalized_email(&user.email));
+            owners.entry(email).or_default().push(user.id);
+        }
+    }
+
+    let colliding: Vec<String> = owners
+        .values()
+        .filter(|users| users.len() > 1)
+        .map(|users| {
+            let users: Vec<String> = users.iter().map(ToString::to_string).collect();
+            users.join(", ")
+        })
+        .collect();
+    if !colliding.is_empty() {
+        return Err(SledStorageError::EmailCollision(colliding.join("; ")));
+    }
+    Ok(())
+}
+
+/// Gives a user another email in a storage the email index step didn't
+/// migrate yet, which holds users bare under string keys. Returns the user as
+/// it was.
+pub(super) fn rename_legacy_email(
+    trees: &[(&'static str, Tree)],
+    user_id: UserId,
+    email: &str,
+    bincode_config: &BincodeConfig,
+) -> Result<User, SledStorageError> {
+    if stored_version(tree(trees, SLED_META_TREE)?, bincode_config)?.is_some_and(|v| v >= 1) {
+        return Err(SledStorageError::EmailIndexMigrated);
+    }
+    // the step will index the new email
+    email_key(email)?;
+
+    let users = tree(trees, SLED_USER_TREE)?;
+    let emails = tree(trees, SLED_EMAIL_TREE)?;
+    let user_key = format!("user:{user_id}");
+    let bytes = users.get(&user_key)?.ok_or(SledStorageError::NotFound)?;
+    let user: User = bincode_config.decode(&bytes)?;
+    for entry in emails.iter() {
+        let (_, value) = entry?;
+        let Some(other) = decode_bare::<User>(&value, bincode_config) else {
+            continue;
+        };
+        if other.id != user_id && normalized_email(&other.email) == normalized_email(email) {
+            return Err(SledStorageError::EmailCollision(format!(
+                "{}, {user_id}",
+                other.id
+            )));
+        }
+    }
+
+    let renamed = bincode_config.encode(&User {
+        email: email.to_string(),
+        ..user.clone()
+    })?;
+    (users, emails).transaction(|(users, emails)| {
+        emails.remove(format!("email:{}", user.email).as_bytes())?;
+        emails.insert(format!("email:{email}").as_bytes(), renamed.as_slice())?;
+        users.insert(user_key.as_bytes(), renamed.as_slice())?;
+        Ok::<_, ConflictableTransactionError<SledStorageError>>(())
+    })?;
+    info!(user_id = %user_id, "renamed email before the email index migration");
+    Ok(user)
+}
+
+/// Version 2: users and sessions were encoded bare, so no field could be
+/// added to them. Records that don't decode are left for `verify` to report.
+fn envelopes(
+    tree: &'static str,
+    key: IVec,
+    value: IVec,
+    bincode_config: &BincodeConfig,
+) -> Result<Vec<Write>, SledStorageError> {
+    let value = if tree == SLED_USER_TREE {
+        decode_bare::<User>(&value, bincode_config)
+            .map(|user| user.to_bytes(bincode_config))
+            .transpose()?
+    } else {
+        decode_bare::<Session>(&value, bincode_config)
+            .map(|session| session.to_bytes(bincode_config))
+            .transpose()?
+    };
+    if value.is_none() {
+        warn!(tree, key = %display_key(&key), "record not migrated");
+    }
+    Ok(value
+        .map(|value| Write {
+            tree,
+            key,
+            value: Some(value),
+        })
+        .into_iter()
+        .collect())
+}
+
+/// Version 3: keys were `kind:part:…` strings, parsed again on every read and
+/// ambiguous for emails holding `:`. Keys that are neither binary nor a known
+/// string layout are left for `verify` to report.
+fn binary_keys(
+    tree: &'static str,
+    key: IVec,
+    value: IVec,
+    _: &BincodeConfig,
+) -> Result<Vec<Write>, SledStorageError> {
+    if Key::from_bytes(&key).is_ok() {
+        return Ok(Vec::new());
+    }
+    let Some(new_key) = legacy_key(&key) else {
+        warn!(tree, key = %display_key(&key), "key not migrated");
+        return Ok(Vec::new());
+    };
+    Ok(vec![
+        Write {
+            tree,
+            key,
+            value: None,
+        },
+        Write {
+            tree,
+            key: new_key.as_bytes().into(),
+            value: Some(value.to_vec()),
+        },
+    ])
+}
+
+/// Binary key of a string key written before version 3.
+fn legacy_key(bytes: &[u8]) -> Option<Key> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    let (kind, rest) = text.split_once(':')?;
+    let kind = PrefixKind::from_str(kind).ok()?;
+    let prefix = KeyPrefix::from_kind(kind);
+    let id = |part: &str| uuid::Uuid::parse_str(part).ok();
+    let number = |part: &str| part.parse::<u64>().ok();
+
+    let parts: Vec<&str> = rest.split(':').collect();
+    let key = match (kind, parts.as_slice()) {
+        // emails may hold ':'
+        (PrefixKind::Email | PrefixKind::Meta, _) if !rest.is_empty() => {
+            Key::try_new(prefix, rest).ok()?
+        }
+        (PrefixKind::User | PrefixKind::Session, [record]) => Key::new(prefix, id(record)?),
+        (
+            PrefixKind::Todo | PrefixKind::Link | PrefixKind::Trash | PrefixKind::Webhook,
+            [owner, record],
+        ) => Key::new(prefix.and(id(owner)?), id(record)?),
+        (PrefixKind::History, [owner, todo, seq]) => {
+            Key::new(prefix.and(id(owner)?).and(id(todo)?), number(seq)?)
+        }
+        (PrefixKind::Undo | PrefixKind::DeadLetter, [owner, seq]) => {
+            Key::new(prefix.and(id(owner)?), number(seq)?)
+        }
+        (PrefixKind::Calendar, ["token", hash]) if !hash.is_empty() => {
+            Key::try_new(prefix.and("token"), hash).ok()?
+        }
+        (PrefixKind::Calendar, ["user", owner]) => Key::new(prefix.and("user"), id(owner)?),
+        (PrefixKind::Sync, [owner, marker @ ("head" | "backfill")]) => {
+            Key::try_new(prefix.and(id(owner)?), marker).ok()?
+        }
+        (PrefixKind::Sync, [owner, "log", seq]) => {
+            Key::new(prefix.and(id(owner)?).and("log"), number(seq)?)
+        }
+        (PrefixKind::Sync, [owner, "rev", todo]) => {
+            Key::new(prefix.and(id(owner)?).and("rev"), id(todo)?)
+        }
+        (PrefixKind::Outbox, [event]) => Key::new(prefix, number(event)?),
+        (PrefixKind::Delivery, [at, delivery]) => {
+            Key::new(prefix.and(at.parse::<i64>().ok()?), number(delivery)?)
+        }
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,451 @@
+use super::*;
+
+use crate::{
+    config::types::SledConfig,
+    init::StartupError,
+    ops::{run, OpsCommand, OpsError},
+    storage::{
+        sled::{
+            calendar_token_key, calendar_user_key, dead_letter_key, delivery_key, email_key,
+            error::SledStartupError, history_key, link_key, outbox_key, session_key,
+            sync_backfill_key, sync_head_key, sync_log_key, sync_rev_key, test_util::ADMIN_UUID,
+            todo_key, trash_key, undo_key, user_key, webhook_key, SledStorage, SLED_TODO_TREE,
+        },
+        DeliveryId, HashedPassword, HistorySeq, Jti, MaintenanceStorage, Role, SessionId, SyncSeq,
+        Todo, TodoId, TodoStorage, TodoVersion, UserStorage, WebhookId,
+    },
+    Settings,
+};
+
+fn settings() -> SledConfig {
+    SledConfig {
+        path: std::path::PathBuf::new(),
+        delete_batch_size: 10,
+        undo_log_size: 5,
+        encryption: None,
+        compression: None,
+    }
+}
+
+fn user(email: &str) -> User {
+    User {
+        id: UserId::new(),
+        email: email.to_string(),
+        hashed_password: HashedPassword {
+            salt: vec![1; 4],
+            hash: vec![2; 4],
+        },
+        role: Role::User,
+    }
+}
+
+fn bare<T: bincode::Encode>(value: &T, storage: &SledStorage) -> Vec<u8> {
+    storage.bincode_config.encode(value).unwrap()
+}
+
+/// Storage holding the given users the way it was written before the schema
+/// had a version.
+fn legacy_storage(db: &sled::Db, users: &[&User]) -> SledStorage {
+    let storage = SledStorage::from_db(db, &settings()).unwrap();
+    storage.meta_tree.clear().unwrap();
+    for user in users {
+        storage
+            .user_tree
+            .insert(format!("user:{}", user.id), bare(*user, &storage))
+            .unwrap();
+        // keyed by the email as registered
+        storage
+            .email_tree
+            .insert(format!("email:{}", user.email), bare(*user, &storage))
+            .unwrap();
+    }
+    storage
+}
+
+#[tokio::test]
+async fn test_new_storage_starts_at_latest_version() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let storage = SledStorage::from_db(&db, &settings()).unwrap();
+    let version = stored_version(&storage.meta_tree, &storage.bincode_config).unwrap();
+    assert_eq!(version, Some(SCHEMA_VERSION));
+
+    let report = migrate(&storage.owned_trees(), &storage.bincode_config, true).unwrap();
+    assert_eq!(report.from, SCHEMA_VERSION);
+    assert!(report.steps.is_empty());
+}
+
+#[tokio::test]
+async fn test_migrate_legacy_records() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let user = user("Old.User@gmail.com");
+    let storage = legacy_storage(&db, &[&user]);
+    let session = Session {
+        id: SessionId::new(),
+        user_id: user.id,
+        created_at: 0,
+        expires_at: i64::MAX,
+        current_refresh_jti: Jti::new(),
+    };
+    storage
+        .session_tree
+        .insert(format!("session:{}", session.id), bare(&session, &storage))
+        .unwrap();
+    let todo = Todo::new(TodoId::new(), "old");
+    storage
+        .todo_tree
+        .insert(
+            format!("todo:{}:{}", user.id, todo.id),
+            TodoVersion::from(todo.clone())
+                .to_bytes(&storage.bincode_config)
+                .unwrap(),
+        )
+        .unwrap();
+    let trees = storage.owned_trees();
+
+    let report = migrate(&trees, &storage.bincode_config, true).unwrap();
+    let steps: Vec<_> = report
+        .steps
+        .iter()
+        .map(|step| (step.version, step.writes))
+        .collect();
+    // the email entry moves to its normalised key, user and session are
+    // rewritten, then the keys of all four records
+    assert_eq!((report.from, report.to), (0, SCHEMA_VERSION));
+    assert_eq!(steps, vec![(1, 2), (2, 2), (3, 8)]);
+    // a dry run writes nothing
+    assert!(storage.get_by_email("old.user@gmail.com").await.is_err());
+    assert_eq!(
+        stored_version(&storage.meta_tree, &storage.bincode_config).unwrap(),
+        None
+    );
+
+    // runs when the storage is opened
+    let storage = SledStorage::from_db(&db, &settings()).unwrap();
+    assert_eq!(
+        storage.get_by_email("old.user@gmail.com").await.unwrap(),
+        user
+    );
+    let bytes = storage
+        .session_tree
+        .get(session_key(&session.id).as_bytes())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Session::from_bytes(&bytes, &storage.bincode_config).unwrap(),
+        session
+    );
+    assert_eq!(
+        TodoStorage::get(&storage, user.id, todo.id).await.unwrap(),
+        todo
+    );
+    assert_eq!(
+        stored_version(&storage.meta_tree, &storage.bincode_config).unwrap(),
+        Some(SCHEMA_VERSION)
+    );
+
+    let report = migrate(&trees, &storage.bincode_config, false).unwrap();
+    assert!(report.steps.is_empty());
+    let report = storage.verify(false).await.unwrap();
+    assert!(report.problems.is_empty(), "{report:?}");
+}
+
+#[tokio::test]
+async fn test_migrate_in_batches() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let users: Vec<User> = (0..MIGRATION_BATCH_SIZE * 2 + 1)
+        .map(|index| user(&format!("User{index}@gmail.com")))
+        .collect();
+    legacy_storage(&db, &users.iter().collect::<Vec<_>>());
+
+    let storage = SledStorage::from_db(&db, &settings()).unwrap();
+    for user in [
+        &users[0],
+        &users[MIGRATION_BATCH_SIZE],
+        users.last().unwrap(),
+    ] {
+        let email = user.email.to_lowercase();
+        assert_eq!(&storage.get_by_email(&email).await.unwrap(), user);
+    }
+    assert!(storage
+        .meta_tree
+        .get(resume_key().as_bytes())
+        .unwrap()
+        .is_none());
+    let report = storage.verify(false).await.unwrap();
+    assert!(report.problems.is_empty(), "{report:?}");
+}
+
+#[tokio::test]
+async fn test_migrate_resumes_interrupted_step() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let storage = legacy_storage(&db, &[]);
+    let mut users = vec![user("first@gmail.com"), user("second@gmail.com")];
+    users.sort_by_key(|user| format!("user:{}", user.id));
+    for user in &users {
+        storage
+            .user_tree
+            .insert(format!("user:{}", user.id), bare(user, &storage))
+            .unwrap();
+    }
+    // the envelope step stopped after the first user
+    let first = format!("user:{}", users[0].id);
+    storage
+        .user_tree
+        .insert(
+            first.as_bytes(),
+            users[0].to_bytes(&storage.bincode_config).unwrap(),
+        )
+        .unwrap();
+    let resume = Resume {
+        version: 2,
+        tree: SLED_USER_TREE.to_string(),
+        key: first.into_bytes(),
+    };
+    storage
+        .meta_tree
+        .insert(version_key(1), bare(&1u32, &storage))
+        .unwrap();
+    storage
+        .meta_tree
+        .insert(resume_key().as_bytes(), bare(&resume, &storage))
+        .unwrap();
+
+    let report = migrate(&storage.owned_trees(), &storage.bincode_config, true).unwrap();
+    assert_eq!(report.steps[0].version, 2);
+    // only the second user is left to wrap
+    assert_eq!(report.steps[0].writes, 1);
+
+    let storage = SledStorage::from_db(&db, &settings()).unwrap();
+    for user in &users {
+        assert_eq!(&UserStorage::get(&storage, user.id).await.unwrap(), user);
+    }
+    assert!(storage
+        .meta_tree
+        .get(resume_key().as_bytes())
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_migrate_email_index_with_case_duplicates() {
+    let sled_config = SledConfig {
+        path: std::env::temp_dir().join(format!("todo-emails-{}", uuid::Uuid::new_v4())),
+        ..settings()
+    };
+    let mut app_settings = Settings::from_file("test").unwrap();
+    app_settings.storage.sled = Some(sled_config.clone());
+    let first = user("Same@gmail.com");
+    let second = user("same@gmail.com");
+    let other = user("Other@gmail.com");
+    let db = sled::Config::new().path(&sled_config.path).open().unwrap();
+    legacy_storage(&db, &[&first, &second, &other]);
+    db.flush().unwrap();
+    drop(db);
+
+    // neither user loses the entry, the storage doesn't open until resolved
+    wait_for_unlock(&sled_config.path);
+    let result = SledStorage::new(&sled_config);
+    let Err(SledStartupError::Migration(SledStorageError::EmailCollision(users))) = result else {
+        panic!("migration must refuse colliding emails");
+    };
+    assert!(users.contains(&first.id.to_string()), "{users}");
+    assert!(users.contains(&second.id.to_string()), "{users}");
+    assert!(!users.contains(&other.id.to_string()), "{users}");
+    wait_for_unlock(&sled_config.path);
+    let db = sled::Config::new().path(&sled_config.path).open().unwrap();
+    assert_eq!(storage_version(&db), None);
+    assert_eq!(db.open_tree(SLED_EMAIL_TREE).unwrap().len(), 3);
+    drop(db);
+
+    let rename = async |email: &str| {
+        wait_for_unlock(&sled_config.path);
+        let mut out = Vec::new();
+        let command = OpsCommand::RenameEmail {
+            user: second.id,
+            email: email.to_string(),
+        };
+        run(command, &app_settings, &mut out)
+            .await
+            .map(|_| String::from_utf8(out).unwrap())
+    };
+    let result = rename("OTHER@gmail.com").await;
+    assert!(
+        matches!(
+            result,
+            Err(OpsError::Startup(StartupError::OpenSledStorage(
+                SledStartupError::Migration(SledStorageError::EmailCollision(_))
+            )))
+        ),
+        "{result:?}"
+    );
+    let out = rename("same.2@gmail.com").await.unwrap();
+    assert_eq!(out, "renamed same@gmail.com to same.2@gmail.com\n");
+
+    wait_for_unlock(&sled_config.path);
+    let storage = SledStorage::new(&sled_config).unwrap();
+    assert_eq!(storage.get_by_email(&second.email).await.unwrap(), first);
+    let renamed = User {
+        email: "same.2@gmail.com".to_string(),
+        ..second.clone()
+    };
+    assert_eq!(
+        storage.get_by_email("SAME.2@gmail.com").await.unwrap(),
+        renamed
+    );
+    assert_eq!(
+        storage.get_by_email("other@gmail.com").await.unwrap(),
+        other
+    );
+    drop(storage);
+
+    // the storage is migrated, emails can no longer collide
+    let result = rename("same.3@gmail.com").await;
+    assert!(
+        matches!(
+            result,
+            Err(OpsError::Startup(StartupError::OpenSledStorage(
+                SledStartupError::Migration(SledStorageError::EmailIndexMigrated)
+            )))
+        ),
+        "{result:?}"
+    );
+    std::fs::remove_dir_all(&sled_config.path).unwrap();
+}
+
+// sled lets go of its lock from a background thread after the last handle is
+// dropped, reopening the path right away can find it still locked
+fn wait_for_unlock(path: &std::path::Path) {
+    let file = std::fs::File::open(path.join("db")).unwrap();
+    for _ in 0..250 {
+        if file.try_lock().is_ok() {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    panic!("{path:?} is still locked");
+}
+
+fn storage_version(db: &sled::Db) -> Option<u32> {
+    let meta = db.open_tree(SLED_META_TREE).unwrap();
+    stored_version(&meta, &BincodeConfig::default()).unwrap()
+}
+
+#[tokio::test]
+async fn test_refuses_newer_schema() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let storage = SledStorage::from_db(&db, &settings()).unwrap();
+    let admin = User {
+        id: ADMIN_UUID.into(),
+        ..user("admin@gmail.com")
+    };
+    UserStorage::put(&storage, admin.id, admin).await.unwrap();
+    storage
+        .meta_tree
+        .insert(
+            schema_version_key().as_bytes(),
+            bare(&(SCHEMA_VERSION + 1), &storage),
+        )
+        .unwrap();
+
+    let result = SledStorage::from_db(&db, &settings());
+    assert!(matches!(
+        result,
+        Err(SledStartupError::Migration(SledStorageError::SchemaTooNew(version)))
+            if version == SCHEMA_VERSION + 1
+    ));
+}
+
+#[test]
+fn test_rewrites_trees_of_missing_steps() {
+    assert!(rewrites(0, SLED_EMAIL_TREE));
+    assert!(rewrites(1, SLED_USER_TREE));
+    assert!(!rewrites(1, SLED_EMAIL_TREE));
+    assert!(!rewrites(0, SLED_TODO_TREE));
+    assert!(!rewrites(SCHEMA_VERSION, SLED_SESSION_TREE));
+}
+
+#[test]
+fn test_legacy_keys() {
+    let user_id = UserId::new();
+    let todo_id = TodoId::new();
+    let webhook_id = WebhookId::new();
+    let cases = [
+        (format!("user:{user_id}"), user_key(&user_id)),
+        // an email may hold ':'
+        (
+            "email:a:b@gmail.com".to_string(),
+            email_key("a:b@gmail.com").unwrap(),
+        ),
+        (
+            format!("todo:{user_id}:{todo_id}"),
+            todo_key(&user_id, &todo_id),
+        ),
+        (
+            format!("link:{user_id}:{todo_id}"),
+            link_key(&user_id, &todo_id),
+        ),
+        (
+            format!("trash:{user_id}:{todo_id}"),
+            trash_key(&user_id, &todo_id),
+        ),
+        (
+            format!("history:{user_id}:{todo_id}:00000000000000000012"),
+            history_key(&user_id, &todo_id, HistorySeq(12)),
+        ),
+        (
+            format!("undo:{user_id}:00000000000000000003"),
+            undo_key(&user_id, 3),
+        ),
+        (
+            "calendar:token:ab12".to_string(),
+            calendar_token_key("ab12").unwrap(),
+        ),
+        (
+            format!("calendar:user:{user_id}"),
+            calendar_user_key(&user_id),
+        ),
+        (format!("sync:{user_id}:head"), sync_head_key(&user_id)),
+        (
+            format!("sync:{user_id}:backfill"),
+            sync_backfill_key(&user_id),
+        ),
+        (
+            format!("sync:{user_id}:log:00000000000000000007"),
+            sync_log_key(&user_id, SyncSeq(7)),
+        ),
+        (
+            format!("sync:{user_id}:rev:{todo_id}"),
+            sync_rev_key(&user_id, &todo_id),
+        ),
+        ("outbox:00000000000000000009".to_string(), outbox_key(9)),
+        (
+            "delivery:00000001700000000000:00000000000000000004".to_string(),
+            delivery_key(1_700_000_000_000, DeliveryId(4)),
+        ),
+        (
+            format!("deadletter:{user_id}:00000000000000000005"),
+            dead_letter_key(&user_id, DeliveryId(5)),
+        ),
+        (
+            format!("webhook:{user_id}:{webhook_id}"),
+            webhook_key(&user_id, &webhook_id),
+        ),
+        ("meta:schema_version".to_string(), schema_version_key()),
+    ];
+    for (legacy, key) in cases {
+        assert_eq!(legacy_key(legacy.as_bytes()), Some(key), "{legacy}");
+    }
+
+    for invalid in [
+        "todo:xxx:xxx",
+        "user:",
+        "email:",
+        "unknown:1",
+        "sync:x:log:1",
+    ] {
+        assert_eq!(legacy_key(invalid.as_bytes()), None, "{invalid}");
+    }
+    assert!(check_key(0, format!("user:{user_id}").as_bytes()).is_ok());
+    assert!(check_key(0, user_key(&user_id).as_bytes()).is_ok());
+    assert!(check_key(SCHEMA_VERSION, format!("user:{user_id}").as_bytes()).is_err());
+}
@@ -1,35 +1,58 @@
+mod codec;
 pub(super) mod error;
 mod flush_impl;
 mod internal;
+mod maintenance_impl;
+mod migrations;
 mod session_impl;
 mod todos_impl;
 mod users_impl;
+mod vault;
+mod webhooks_impl;
 
 #[cfg(feature = "integration_tests")]
 pub mod test_util;
 
 use super::{
-    Pagination, Session, SessionId, StorageError, Todo, TodoId, TodoStorage, TodoVersion,
-    UpdateTodo, User, UserId, UserStorage,
+    BackupManifest, CalendarToken, Delivery, DeliveryId, HistorySeq, HistoryVersion,
+    MigrationReport, Pagination, Session, SessionId, SessionVersion, StorageError, SyncRecord,
+    SyncSeq, Todo, TodoId, TodoLinks, TodoStorage, TodoVersion, TrashRecord, UndoRecord,
+    UpdateTodo, UsageVersion, User, UserId, UserStorage, UserVersion, Webhook, WebhookEvent,
+    WebhookId,
+};
+use crate::{
+    config::types::{LimitsConfig, SledConfig},
+    trace_err,
+    utils::measure_metrics::measure_and_record_storage,
 };
-use crate::{config::types::SledConfig, utils::measure_metrics::measure_and_record_storage};
 use bincode::config::{self};
+use codec::BincodeConfig;
 use error::{SledStartupError, SledStorageError};
 use internal::{Key, KeyPrefix, PrefixKind};
+use std::{path::Path, sync::RwLock};
 use tracing::{info, info_span, instrument};
+use vault::Vault;
 
 pub(crate) static SLED_TODO_TREE: &str = "todos";
 pub(crate) static SLED_USER_TREE: &str = "users";
 pub(crate) static SLED_EMAIL_TREE: &str = "emails";
 pub(crate) static SLED_SESSION_TREE: &str = "sessions";
+pub(crate) static SLED_LINK_TREE: &str = "todo_links";
+pub(crate) static SLED_TRASH_TREE: &str = "todo_trash";
+pub(crate) static SLED_HISTORY_TREE: &str = "todo_history";
+pub(crate) static SLED_UNDO_TREE: &str = "todo_undo";
+pub(crate) static SLED_CALENDAR_TREE: &str = "calendar_tokens";
+pub(crate) static SLED_SYNC_TREE: &str = "todo_sync";
+pub(crate) static SLED_OUTBOX_TREE: &str = "webhook_outbox";
+pub(crate) static SLED_WEBHOOK_TREE: &str = "webhooks";
+pub(crate) static SLED_META_TREE: &str = "meta";
+pub(crate) static SLED_DATA_KEY_TREE: &str = "data_keys";
 const BINCODE_CONFIG: config::Configuration = config::standard()
     .with_variable_int_encoding()
     .with_little_endian();
 
 use bincode::{Decode, Encode};
 
-type BincodeConfig = bincode::config::Configuration;
-
 trait ToBytesWithConfig: Encode {
     type Error;
 
@@ -43,92 +66,322 @@ trait FromBytesWithConfig: Decode<()> {
 }
 
 pub(crate) struct SledStorage {
+    db: sled::Db,
     todo_tree: sled::Tree,
     user_tree: sled::Tree,
     email_tree: sled::Tree,
     session_tree: sled::Tree,
-    bincode_config: config::Configuration,
+    link_tree: sled::Tree,
+    trash_tree: sled::Tree,
+    history_tree: sled::Tree,
+    undo_tree: sled::Tree,
+    calendar_tree: sled::Tree,
+    sync_tree: sled::Tree,
+    outbox_tree: sled::Tree,
+    webhook_tree: sled::Tree,
+    meta_tree: sled::Tree,
+    data_key_tree: sled::Tree,
+    vault: Vault,
+    bincode_config: BincodeConfig,
     storage_settings: SledConfig,
+    /// What a user can store, checked by the transactions writing todos.
+    limits: RwLock<LimitsConfig>,
 }
 
 impl SledStorage {
     #[instrument(name = "Storage::new")]
     pub fn new(sled_config: &SledConfig) -> Result<Self, SledStartupError> {
-        let result: Result<_, SledStartupError> = measure_and_record_storage(
-            "Storage::new",
-            || {
-                let db = info_span!("sled::open_db").in_scope(|| {
-                    let config = sled::Config::default().path(&sled_config.path);
-                    config.open().map_err(|e| {
-                        tracing::error!(error = %e, path = ?sled_config.path,"failed to open db");
-                        SledStartupError::OpenSledStorageError(e)
-                    })
-                })?;
-
-                let todo_tree = info_span!("sled::open_todo_tree").in_scope(|| {
-                    db.open_tree(SLED_TODO_TREE).map_err(|e| {
-                        tracing::error!(error = %e, tree_name = SLED_TODO_TREE, "failed to open todo tree");
-                        SledStartupError::OpenSledStorageError(e)
-                    })
-                })?;
-
-                let user_tree = info_span!("sled::open_user_tree").in_scope(|| {
-                    db.open_tree(SLED_USER_TREE)
-                        .map_err(|e| {
-                            tracing::error!(error = %e, tree_name = SLED_USER_TREE, "failed to open user tree");
-                            SledStartupError::OpenSledStorageError(e) })
-                })?;
-
-                let email_tree = info_span!("sled::open_email_tree").in_scope(|| {
-                    db.open_tree(SLED_EMAIL_TREE)
-                        .map_err(|e| {
-                            tracing::error!(error = %e, tree_name = SLED_EMAIL_TREE, "failed to open email tree");
-                            SledStartupError::OpenSledStorageError(e)})
-                })?;
-
-                let session_tree = info_span!("sled::open_session_tree").in_scope(|| {
-                    db.open_tree(SLED_SESSION_TREE)
-                        .map_err(|e| {
-                            tracing::error!(error = %e, tree_name = SLED_SESSION_TREE, "failed to open session tree");
-                            SledStartupError::OpenSledStorageError(e)})
-                })?;
-
-                Ok(Self {
-                    todo_tree,
-                    user_tree,
-                    email_tree,
-                    session_tree,
-                    bincode_config: BINCODE_CONFIG,
-                    storage_settings: sled_config.clone(),
-                })
-            },
-        );
+        let result: Result<_, SledStartupError> =
+            measure_and_record_storage("Storage::new", || {
+                let db = open_db(sled_config)?;
+                Self::from_db(&db, sled_config)
+            });
         result
     }
+
+    /// Runs the schema migrations the storage is missing, or only lists them
+    /// on a dry run. `new` runs them too.
+    #[instrument(name = "Storage::migrate")]
+    pub fn migrate(
+        sled_config: &SledConfig,
+        dry_run: bool,
+    ) -> Result<MigrationReport, SledStartupError> {
+        let db = open_db(sled_config)?;
+        let storage = Self::open_trees(&db, sled_config)?;
+        trace_err!(
+            migrations::migrate(&storage.owned_trees(), &storage.bincode_config, dry_run),
+            "failed to migrate storage"
+        )
+        .map_err(SledStartupError::Migration)
+    }
+
+    /// Gives a user another email without running the migrations, which
+    /// refuse emails that differ only in case. Returns the user as it was.
+    #[instrument(name = "Storage::rename_email")]
+    pub fn rename_email(
+        sled_config: &SledConfig,
+        user_id: UserId,
+        email: &str,
+    ) -> Result<User, SledStartupError> {
+        let db = open_db(sled_config)?;
+        let storage = Self::open_trees(&db, sled_config)?;
+        trace_err!(
+            migrations::rename_legacy_email(
+                &storage.owned_trees(),
+                user_id,
+                email,
+                &storage.bincode_config
+            ),
+            "failed to rename email"
+        )
+        .map_err(SledStartupError::Migration)
+    }
+
+    /// Replaces all records with the ones of the archive at `path`, see
+    /// `MaintenanceStorage::restore`. Holding the database, it can't run next
+    /// to a server.
+    #[instrument(name = "Storage::restore")]
+    pub fn restore(
+        sled_config: &SledConfig,
+        path: &Path,
+    ) -> Result<BackupManifest, SledStartupError> {
+        let db = open_db(sled_config)?;
+        let storage = Self::open_trees(&db, sled_config)?;
+        maintenance_impl::restore_trees(path, &storage.owned_trees(), &storage.bincode_config)
+            .map_err(SledStartupError::Restore)
+    }
+
+    pub(crate) fn from_db(
+        db: &sled::Db,
+        sled_config: &SledConfig,
+    ) -> Result<Self, SledStartupError> {
+        let mut storage = Self::open_trees(db, sled_config)?;
+        let marker = maintenance_impl::restore_marker_key();
+        if storage
+            .meta_tree
+            .contains_key(marker.as_bytes())
+            .map_err(SledStartupError::OpenSledStorageError)?
+        {
+            tracing::error!("found the marker of an interrupted restore");
+            return Err(SledStartupError::InterruptedRestore);
+        }
+        storage.vault = Vault::open(
+            sled_config.encryption.as_ref(),
+            &storage.data_key_tree,
+            storage.bincode_config,
+        )?;
+        trace_err!(
+            migrations::migrate(&storage.owned_trees(), &storage.bincode_config, false),
+            "failed to migrate storage"
+        )
+        .map_err(SledStartupError::Migration)?;
+        Ok(storage)
+    }
+
+    fn open_trees(db: &sled::Db, sled_config: &SledConfig) -> Result<Self, SledStartupError> {
+        let open_tree = |tree_name: &'static str| {
+            info_span!("sled::open_tree", tree_name).in_scope(|| {
+                db.open_tree(tree_name).map_err(|e| {
+                    tracing::error!(error = %e, tree_name, "failed to open tree");
+                    SledStartupError::OpenSledStorageError(e)
+                })
+            })
+        };
+
+        Ok(Self {
+            db: db.clone(),
+            todo_tree: open_tree(SLED_TODO_TREE)?,
+            user_tree: open_tree(SLED_USER_TREE)?,
+            email_tree: open_tree(SLED_EMAIL_TREE)?,
+            session_tree: open_tree(SLED_SESSION_TREE)?,
+            link_tree: open_tree(SLED_LINK_TREE)?,
+            trash_tree: open_tree(SLED_TRASH_TREE)?,
+            history_tree: open_tree(SLED_HISTORY_TREE)?,
+            undo_tree: open_tree(SLED_UNDO_TREE)?,
+            calendar_tree: open_tree(SLED_CALENDAR_TREE)?,
+            sync_tree: open_tree(SLED_SYNC_TREE)?,
+            outbox_tree: open_tree(SLED_OUTBOX_TREE)?,
+            webhook_tree: open_tree(SLED_WEBHOOK_TREE)?,
+            meta_tree: open_tree(SLED_META_TREE)?,
+            data_key_tree: open_tree(SLED_DATA_KEY_TREE)?,
+            vault: Vault::default(),
+            bincode_config: BincodeConfig::new(sled_config.compression.as_ref()),
+            storage_settings: sled_config.clone(),
+            limits: RwLock::new(LimitsConfig::default()),
+        })
+    }
+
+    /// Every tree together with its name.
+    fn trees(&self) -> [(&'static str, &sled::Tree); 14] {
+        [
+            (SLED_TODO_TREE, &self.todo_tree),
+            (SLED_USER_TREE, &self.user_tree),
+            (SLED_EMAIL_TREE, &self.email_tree),
+            (SLED_SESSION_TREE, &self.session_tree),
+            (SLED_LINK_TREE, &self.link_tree),
+            (SLED_TRASH_TREE, &self.trash_tree),
+            (SLED_HISTORY_TREE, &self.history_tree),
+            (SLED_UNDO_TREE, &self.undo_tree),
+            (SLED_CALENDAR_TREE, &self.calendar_tree),
+            (SLED_SYNC_TREE, &self.sync_tree),
+            (SLED_OUTBOX_TREE, &self.outbox_tree),
+            (SLED_WEBHOOK_TREE, &self.webhook_tree),
+            (SLED_META_TREE, &self.meta_tree),
+            (SLED_DATA_KEY_TREE, &self.data_key_tree),
+        ]
+    }
+
+    fn owned_trees(&self) -> Vec<(&'static str, sled::Tree)> {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        self.trees()
+            .into_iter()
+            .map(|(name, tree)| (name, tree.clone()))
+            .collect()
+    }
+}
+
+fn open_db(sled_config: &SledConfig) -> Result<sled::Db, SledStartupError> {
+    info_span!("sled::open_db").in_scope(|| {
+        let config = sled::Config::default().path(&sled_config.path);
+        config.open().map_err(|e| {
+            tracing::error!(error = %e, path = ?sled_config.path,"failed to open db");
+            SledStartupError::OpenSledStorageError(e)
+        })
+    })
 }
 
 fn todo_key(user_id: &UserId, todo_id: &TodoId) -> Key {
     Key::new(KeyPrefix::new(PrefixKind::Todo, user_id), todo_id)
 }
 
+// Todos, trashed todos and their history are keyed by owner, then todo.
+fn key_todo_id(key: &Key) -> Result<TodoId, SledStorageError> {
+    key.id_segment(1)
+        .ok_or_else(|| SledStorageError::InvalidKey(key.to_string()))
+}
+
 fn user_key(user_id: &UserId) -> Key {
     Key::new(KeyPrefix::from_kind(PrefixKind::User), user_id)
 }
 
-fn email_key(email: &str) -> Key {
-    Key::new(KeyPrefix::from_kind(PrefixKind::Email), email)
+// Emails are compared case-insensitively, the user record keeps the address
+// as it was registered.
+fn normalized_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn email_key(email: &str) -> Result<Key, SledStorageError> {
+    Key::try_new(
+        KeyPrefix::from_kind(PrefixKind::Email),
+        normalized_email(email),
+    )
 }
 
 fn session_key(session_id: &SessionId) -> Key {
     Key::new(KeyPrefix::from_kind(PrefixKind::Session), session_id)
 }
 
+fn link_key(user_id: &UserId, todo_id: &TodoId) -> Key {
+    Key::new(KeyPrefix::new(PrefixKind::Link, user_id), todo_id)
+}
+
+fn trash_key(user_id: &UserId, todo_id: &TodoId) -> Key {
+    Key::new(KeyPrefix::new(PrefixKind::Trash, user_id), todo_id)
+}
+
+fn history_prefix(user_id: &UserId, todo_id: &TodoId) -> KeyPrefix {
+    KeyPrefix::new(PrefixKind::History, user_id).and(todo_id)
+}
+
+fn history_key(user_id: &UserId, todo_id: &TodoId, seq: HistorySeq) -> Key {
+    Key::new(history_prefix(user_id, todo_id), seq.0)
+}
+
+// Calendar tokens are stored under both the token hash and the user id,
+// like users are under both their id and email.
+fn calendar_token_key(token_hash: &str) -> Result<Key, SledStorageError> {
+    Key::try_new(KeyPrefix::new(PrefixKind::Calendar, "token"), token_hash)
+}
+
+fn calendar_user_key(user_id: &UserId) -> Key {
+    Key::new(KeyPrefix::new(PrefixKind::Calendar, "user"), user_id)
+}
+
+fn sync_prefix(user_id: &UserId) -> KeyPrefix {
+    KeyPrefix::new(PrefixKind::Sync, user_id)
+}
+
+// Latest sequence handed out to the user.
+fn sync_head_key(user_id: &UserId) -> Key {
+    Key::new(sync_prefix(user_id), "head")
+}
+
+// Set once todos written before the change log existed got their revisions.
+fn sync_backfill_key(user_id: &UserId) -> Key {
+    Key::new(sync_prefix(user_id), "backfill")
+}
+
+// Counts of what the user stores, see `Usage`. They live in the `sync` tree,
+// which every transaction writing todos holds already.
+fn usage_key(user_id: &UserId) -> Key {
+    Key::new(KeyPrefix::from_kind(PrefixKind::Usage), user_id)
+}
+
+fn sync_log_prefix(user_id: &UserId) -> KeyPrefix {
+    sync_prefix(user_id).and("log")
+}
+
+fn sync_log_key(user_id: &UserId, seq: SyncSeq) -> Key {
+    Key::new(sync_log_prefix(user_id), seq.0)
+}
+
+// Revision of a todo, points at its entry in the change log.
+fn sync_rev_key(user_id: &UserId, todo_id: &TodoId) -> Key {
+    Key::new(sync_prefix(user_id).and("rev"), todo_id)
+}
+
+fn webhook_prefix(owner: &UserId) -> KeyPrefix {
+    KeyPrefix::new(PrefixKind::Webhook, owner)
+}
+
+fn webhook_key(owner: &UserId, webhook_id: &WebhookId) -> Key {
+    Key::new(webhook_prefix(owner), webhook_id)
+}
+
+fn outbox_key(event_id: u64) -> Key {
+    Key::new(KeyPrefix::from_kind(PrefixKind::Outbox), event_id)
+}
+
+// Deliveries sort by the time of their next attempt, due ones come first.
+fn delivery_key(next_attempt_at: i64, delivery_id: DeliveryId) -> Key {
+    Key::new(
+        KeyPrefix::new(PrefixKind::Delivery, next_attempt_at),
+        delivery_id.0,
+    )
+}
+
+fn dead_letter_prefix(owner: &UserId) -> KeyPrefix {
+    KeyPrefix::new(PrefixKind::DeadLetter, owner)
+}
+
+fn dead_letter_key(owner: &UserId, delivery_id: DeliveryId) -> Key {
+    Key::new(dead_letter_prefix(owner), delivery_id.0)
+}
+
+fn undo_prefix(user_id: &UserId) -> KeyPrefix {
+    KeyPrefix::new(PrefixKind::Undo, user_id)
+}
+
+fn undo_key(user_id: &UserId, seq: u64) -> Key {
+    Key::new(undo_prefix(user_id), seq)
+}
+
 impl ToBytesWithConfig for User {
     type Error = SledStorageError;
 
     #[instrument(name = "User::to_bytes", skip_all)]
     fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
-        Ok(bincode::encode_to_vec(self, *config)?)
+        config.encode(&UserVersion::from(self.clone()))
     }
 }
 
@@ -137,7 +390,8 @@ impl FromBytesWithConfig for User {
 
     #[instrument(name = "User::from_bytes", skip_all)]
     fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
-        let (user, _len) = bincode::decode_from_slice::<User, _>(bytes, *config)?;
+        let user = config.decode::<UserVersion>(bytes)?;
+        let user = User::try_from(user)?;
 
         info!(user_email = %user.email, "created User from bytes");
 
@@ -145,12 +399,51 @@ impl FromBytesWithConfig for User {
     }
 }
 
+impl ToBytesWithConfig for UserVersion {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UserVersion::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for UserVersion {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UserVersion::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let user = config.decode::<UserVersion>(bytes)?;
+        Ok(user)
+    }
+}
+
+// The `emails` tree only points at the user record.
+impl ToBytesWithConfig for UserId {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UserId::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for UserId {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UserId::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        // a full `User` starts with its id too
+        config.decode_exact(bytes)
+    }
+}
+
 impl FromBytesWithConfig for TodoVersion {
     type Error = SledStorageError;
 
     #[instrument(name = "TodoVersion::from_bytes", skip_all)]
     fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
-        let (todo, _len) = bincode::decode_from_slice::<TodoVersion, _>(bytes, *config)?;
+        let todo = config.decode::<TodoVersion>(bytes)?;
         Ok(todo)
     }
 }
@@ -160,17 +453,35 @@ impl ToBytesWithConfig for TodoVersion {
 
     #[instrument(name = "TodoVersion::to_bytes", skip_all)]
     fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
-        let bytes = bincode::encode_to_vec(self, *config)?;
+        let bytes = config.encode(self)?;
         Ok(bytes)
     }
 }
 
+impl FromBytesWithConfig for UsageVersion {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UsageVersion::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        config.decode(bytes)
+    }
+}
+
+impl ToBytesWithConfig for UsageVersion {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UsageVersion::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
 impl ToBytesWithConfig for Session {
     type Error = SledStorageError;
 
     #[instrument(name = "Session::to_bytes", skip_all)]
     fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
-        Ok(bincode::encode_to_vec(self, *config)?)
+        config.encode(&SessionVersion::from(self.clone()))
     }
 }
 
@@ -179,10 +490,201 @@ impl FromBytesWithConfig for Session {
 
     #[instrument(name = "Session::from_bytes", skip_all)]
     fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
-        let (session, _len) = bincode::decode_from_slice::<Session, _>(bytes, *config)?;
+        let session = config.decode::<SessionVersion>(bytes)?;
+        let session = Session::from(session);
 
         info!(session_id = %session.id, "created Session obj from bytes");
 
         Ok(session)
     }
 }
+
+impl ToBytesWithConfig for TodoLinks {
+    type Error = SledStorageError;
+
+    #[instrument(name = "TodoLinks::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for TodoLinks {
+    type Error = SledStorageError;
+
+    #[instrument(name = "TodoLinks::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let links = config.decode::<TodoLinks>(bytes)?;
+        Ok(links)
+    }
+}
+
+impl ToBytesWithConfig for TrashRecord {
+    type Error = SledStorageError;
+
+    #[instrument(name = "TrashRecord::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for TrashRecord {
+    type Error = SledStorageError;
+
+    #[instrument(name = "TrashRecord::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let record = config.decode::<TrashRecord>(bytes)?;
+        Ok(record)
+    }
+}
+
+impl ToBytesWithConfig for HistoryVersion {
+    type Error = SledStorageError;
+
+    #[instrument(name = "HistoryVersion::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for HistoryVersion {
+    type Error = SledStorageError;
+
+    #[instrument(name = "HistoryVersion::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let entry = config.decode::<HistoryVersion>(bytes)?;
+        Ok(entry)
+    }
+}
+
+impl ToBytesWithConfig for CalendarToken {
+    type Error = SledStorageError;
+
+    #[instrument(name = "CalendarToken::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for CalendarToken {
+    type Error = SledStorageError;
+
+    #[instrument(name = "CalendarToken::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let token = config.decode::<CalendarToken>(bytes)?;
+        Ok(token)
+    }
+}
+
+impl ToBytesWithConfig for UndoRecord {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UndoRecord::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for UndoRecord {
+    type Error = SledStorageError;
+
+    #[instrument(name = "UndoRecord::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let record = config.decode::<UndoRecord>(bytes)?;
+        Ok(record)
+    }
+}
+
+impl ToBytesWithConfig for SyncSeq {
+    type Error = SledStorageError;
+
+    #[instrument(name = "SyncSeq::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for SyncSeq {
+    type Error = SledStorageError;
+
+    #[instrument(name = "SyncSeq::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let seq = config.decode::<SyncSeq>(bytes)?;
+        Ok(seq)
+    }
+}
+
+impl ToBytesWithConfig for SyncRecord {
+    type Error = SledStorageError;
+
+    #[instrument(name = "SyncRecord::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for SyncRecord {
+    type Error = SledStorageError;
+
+    #[instrument(name = "SyncRecord::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let record = config.decode::<SyncRecord>(bytes)?;
+        Ok(record)
+    }
+}
+
+impl ToBytesWithConfig for Webhook {
+    type Error = SledStorageError;
+
+    #[instrument(name = "Webhook::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for Webhook {
+    type Error = SledStorageError;
+
+    #[instrument(name = "Webhook::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let webhook = config.decode::<Webhook>(bytes)?;
+        Ok(webhook)
+    }
+}
+
+impl ToBytesWithConfig for WebhookEvent {
+    type Error = SledStorageError;
+
+    #[instrument(name = "WebhookEvent::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for WebhookEvent {
+    type Error = SledStorageError;
+
+    #[instrument(name = "WebhookEvent::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let event = config.decode::<WebhookEvent>(bytes)?;
+        Ok(event)
+    }
+}
+
+impl ToBytesWithConfig for Delivery {
+    type Error = SledStorageError;
+
+    #[instrument(name = "Delivery::to_bytes", skip_all)]
+    fn to_bytes(&self, config: &BincodeConfig) -> Result<Vec<u8>, Self::Error> {
+        config.encode(self)
+    }
+}
+
+impl FromBytesWithConfig for Delivery {
+    type Error = SledStorageError;
+
+    #[instrument(name = "Delivery::from_bytes", skip_all)]
+    fn from_bytes(bytes: &[u8], config: &BincodeConfig) -> Result<Self, Self::Error> {
+        let delivery = config.decode::<Delivery>(bytes)?;
+        Ok(delivery)
+    }
+}
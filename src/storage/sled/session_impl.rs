@@ -1,26 +1,33 @@
 use crate::trace_err;
 use async_trait::async_trait;
-use tracing::{info, instrument};
+use sled::Tree;
+use tracing::{info, info_span, instrument, Span};
 
 use crate::{
     storage::{
+        page::HasId,
         session::Session,
         sled::{
             error::SledStorageError,
-            internal::span_wrappers::{
-                deserialize_in_span, deserialize_in_transaction_with_span,
-                get_value_in_transaction_with_span, get_value_with_span,
-                insert_value_in_transaction_with_span, insert_value_with_span,
-                remove_value_with_span, serialize_in_span, serialize_in_transaction_with_span,
+            internal::{
+                for_each_page,
+                span_wrappers::{
+                    deserialize_in_span, deserialize_in_transaction_with_span,
+                    get_value_in_transaction_with_span, get_value_with_span,
+                    insert_value_in_transaction_with_span, insert_value_with_span,
+                    remove_batch_in_transaction_with_span, remove_value_with_span,
+                    serialize_in_span, serialize_in_transaction_with_span,
+                },
+                Key, KeyPrefix, PrefixKind,
             },
-            session_key,
+            session_key, user_key,
         },
         Jti, SessionId, SessionStorage, StorageError,
     },
-    utils::measure_metrics::measure_and_record_storage,
+    utils::{blocking_task_guard::BlockingTaskGuard, measure_metrics::measure_and_record_storage},
 };
 
-use super::SledStorage;
+use super::{BincodeConfig, FromBytesWithConfig, SledStorage};
 
 #[async_trait]
 impl SessionStorage for SledStorage {
@@ -120,6 +127,102 @@ impl SessionStorage for SledStorage {
 
         Ok(())
     }
+
+    #[instrument(name = "SledStorage::session::purge", skip_all)]
+    async fn purge(&self, now: i64) -> Result<usize, StorageError> {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        let (session_tree, user_tree, bincode_config, page_size) =
+            info_span!("Cloning trees and config").in_scope(|| {
+                (
+                    self.session_tree.clone(),
+                    self.user_tree.clone(),
+                    self.bincode_config,
+                    self.storage_settings.delete_batch_size,
+                )
+            });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("purge_sessions");
+            span.in_scope(|| {
+                purge_sessions(now, &session_tree, &user_tree, &bincode_config, page_size)
+            })
+        })
+        .await?
+    }
+}
+
+/// Session read during a tree scan together with the key it's stored under.
+struct StoredSession {
+    key: Key,
+    session: Session,
+}
+
+impl HasId<Key> for StoredSession {
+    fn id(&self) -> Key {
+        self.key.clone()
+    }
+}
+
+fn purge_sessions(
+    now: i64,
+    session_tree: &Tree,
+    user_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    page_size: usize,
+) -> Result<usize, StorageError> {
+    info!(now, "purge sessions");
+
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::purge_sessions", || {
+            let key_prefix = KeyPrefix::from_kind(PrefixKind::Session);
+            let first_key = Key::from_prefix(key_prefix.clone());
+            let mut purged = 0;
+
+            trace_err!(
+                for_each_page(
+                    session_tree,
+                    &first_key,
+                    &key_prefix,
+                    page_size,
+                    bincode_config,
+                    |key, bytes, config| {
+                        Ok(StoredSession {
+                            key: key.clone(),
+                            session: Session::from_bytes(bytes, config)?,
+                        })
+                    },
+                    |page, _| {
+                        let mut stale = Vec::new();
+                        for item in page {
+                            let user_exists = user_tree
+                                .contains_key(user_key(&item.session.user_id).as_bytes())?;
+                            if item.session.expires_at <= now || !user_exists {
+                                stale.push(item.key.clone());
+                            }
+                        }
+                        if stale.is_empty() {
+                            return Ok(());
+                        }
+
+                        session_tree.transaction(|session_tx| {
+                            trace_err!(
+                                remove_batch_in_transaction_with_span(&stale, session_tx),
+                                "failed to remove page of stale sessions"
+                            )?;
+                            Ok(())
+                        })?;
+                        purged += stale.len();
+                        Ok(())
+                    }
+                ),
+                "failed to do tree scan to get page of sessions"
+            )?;
+
+            info!(count = purged, "purged sessions");
+            Ok(purged)
+        });
+    Ok(result?)
 }
 
 #[cfg(test)]
@@ -1,8 +1,10 @@
 use super::*;
 
 use crate::{
-    config::Settings,
-    storage::{sled::test_util::TestStorageBuilder, Jti, UserId},
+    config::{types::SledConfig, Settings},
+    storage::{
+        sled::test_util::TestStorageBuilder, HashedPassword, Jti, Role, User, UserId, UserStorage,
+    },
 };
 
 #[tokio::test]
@@ -57,3 +59,64 @@ async fn test_update() {
     let res = storage.get(session.id).await.unwrap();
     assert_eq!(res.current_refresh_jti, new_refresh_jti);
 }
+
+#[tokio::test]
+async fn test_purge_removes_expired_and_orphaned_sessions() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let sled_config = SledConfig {
+        path: std::path::PathBuf::new(),
+        // the sessions span several pages
+        delete_batch_size: 2,
+        undo_log_size: 5,
+        encryption: None,
+        compression: None,
+    };
+    let storage = SledStorage::from_db(&db, &sled_config).unwrap();
+    let settings = Settings::new().unwrap();
+
+    let user = User {
+        id: UserId::new(),
+        email: "user@gmail.com".to_string(),
+        hashed_password: HashedPassword {
+            salt: vec![1; 4],
+            hash: vec![2; 4],
+        },
+        role: Role::User,
+    };
+    UserStorage::put(&storage, user.id, user.clone())
+        .await
+        .unwrap();
+
+    let mut kept = Vec::new();
+    for _ in 0..3 {
+        let session = Session::new(&user.id, &Jti::new(), &settings.jwt).unwrap();
+        SessionStorage::put(&storage, session.id, session.clone())
+            .await
+            .unwrap();
+        kept.push(session);
+    }
+    let mut expired = Session::new(&user.id, &Jti::new(), &settings.jwt).unwrap();
+    expired.expires_at = expired.created_at - 1;
+    let orphaned = Session::new(&UserId::new(), &Jti::new(), &settings.jwt).unwrap();
+    for session in [&expired, &orphaned] {
+        SessionStorage::put(&storage, session.id, session.clone())
+            .await
+            .unwrap();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    assert_eq!(storage.purge(now).await.unwrap(), 2);
+    for session in &kept {
+        assert_eq!(
+            &SessionStorage::get(&storage, session.id).await.unwrap(),
+            session
+        );
+    }
+    for session in [&expired, &orphaned] {
+        assert!(matches!(
+            SessionStorage::get(&storage, session.id).await,
+            Err(StorageError::NotFound)
+        ));
+    }
+    assert_eq!(storage.purge(now).await.unwrap(), 0);
+}
@@ -5,10 +5,8 @@ use crate::{
     config::types::SledConfig,
     service::password::create_password_hash,
     storage::{
-        sled::{
-            BINCODE_CONFIG, SLED_EMAIL_TREE, SLED_SESSION_TREE, SLED_TODO_TREE, SLED_USER_TREE,
-        },
-        FlushStorage, Role, SessionStorage, Todo, TodoId, TodoStorage, User, UserId, UserStorage,
+        Faults, FaultyStorage, FlushStorage, MaintenanceStorage, Role, SessionStorage, Todo,
+        TodoId, TodoStorage, User, UserId, UserStorage, WebhookStorage,
     },
     Settings,
 };
@@ -26,23 +24,27 @@ pub struct TestStorageBuilder {
     user_storage: Arc<dyn UserStorage>,
     session_storage: Arc<dyn SessionStorage>,
     flush_storage: Arc<dyn FlushStorage>,
+    webhook_storage: Arc<dyn WebhookStorage>,
+    maintenance_storage: Arc<dyn MaintenanceStorage>,
 }
 
 impl TestStorageBuilder {
     pub fn new() -> Self {
         let config = Config::new().temporary(true);
         let db = config.open().unwrap();
-        let sled_storage = Arc::new(SledStorage {
-            todo_tree: db.open_tree(SLED_TODO_TREE).unwrap(),
-            user_tree: db.open_tree(SLED_USER_TREE).unwrap(),
-            email_tree: db.open_tree(SLED_EMAIL_TREE).unwrap(),
-            session_tree: db.open_tree(SLED_SESSION_TREE).unwrap(),
-            bincode_config: BINCODE_CONFIG,
-            storage_settings: SledConfig {
-                path: PathBuf::from(""),
-                delete_batch_size: 10,
-            },
-        });
+        let sled_storage = Arc::new(
+            SledStorage::from_db(
+                &db,
+                &SledConfig {
+                    path: PathBuf::from(""),
+                    delete_batch_size: 10,
+                    undo_log_size: 5,
+                    encryption: None,
+                    compression: None,
+                },
+            )
+            .unwrap(),
+        );
         Self {
             todos: Vec::new(),
             users: Vec::new(),
@@ -50,6 +52,8 @@ impl TestStorageBuilder {
             user_storage: sled_storage.clone() as Arc<dyn UserStorage>,
             session_storage: sled_storage.clone() as Arc<dyn SessionStorage>,
             flush_storage: sled_storage.clone() as Arc<dyn FlushStorage>,
+            webhook_storage: sled_storage.clone() as Arc<dyn WebhookStorage>,
+            maintenance_storage: sled_storage.clone() as Arc<dyn MaintenanceStorage>,
         }
     }
 
@@ -60,11 +64,23 @@ impl TestStorageBuilder {
                 text: format!("todo {i}"),
                 completed: false,
                 group: String::from("group"),
+                due: None,
             })
             .collect();
         self
     }
 
+    /// Wraps every storage with the faults, see `Faults`.
+    pub fn with_faults(mut self, faults: &Faults) -> Self {
+        self.todo_storage = Arc::new(FaultyStorage::new(self.todo_storage, faults));
+        self.user_storage = Arc::new(FaultyStorage::new(self.user_storage, faults));
+        self.session_storage = Arc::new(FaultyStorage::new(self.session_storage, faults));
+        self.flush_storage = Arc::new(FaultyStorage::new(self.flush_storage, faults));
+        self.webhook_storage = Arc::new(FaultyStorage::new(self.webhook_storage, faults));
+        self.maintenance_storage = Arc::new(FaultyStorage::new(self.maintenance_storage, faults));
+        self
+    }
+
     pub async fn with_users(mut self, count: usize) -> Self {
         let hash = create_password_hash("password", &test_settings().auth)
             .await
@@ -99,6 +115,14 @@ impl TestStorageBuilder {
         self.flush_storage.clone()
     }
 
+    pub async fn build_webhook(&self) -> Arc<dyn WebhookStorage> {
+        self.webhook_storage.clone()
+    }
+
+    pub async fn build_maintenance(&self) -> Arc<dyn MaintenanceStorage> {
+        self.maintenance_storage.clone()
+    }
+
     pub async fn build_user(&self) -> Arc<dyn UserStorage> {
         for user in &self.users {
             self.user_storage.put(user.id, user.clone()).await.unwrap();
@@ -1,6 +1,16 @@
-use crate::config::types::SledConfig;
-use crate::storage::sled::internal::TreeScan;
-use crate::storage::{TodoId, UserId};
+mod history;
+mod sync;
+mod undo;
+mod usage;
+
+use crate::config::types::{LimitsConfig, SledConfig};
+use crate::storage::page::HasId;
+use crate::storage::sled::internal::{for_each_page, TreeScan};
+use crate::storage::{
+    diff, ClientChange, HistoryAction, HistoryEntry, HistorySeq, HistoryVersion, SyncChange,
+    SyncResult, SyncSeq, TodoId, TodoLinks, TodoOp, TodoOpResult, TrashRecord, TrashedTodo,
+    UndoOperation, UndoResult, UndoStep, Usage, UserId,
+};
 use crate::trace_err;
 use crate::utils::blocking_task_guard::BlockingTaskGuard;
 use crate::utils::measure_metrics::measure_and_record_storage;
@@ -10,21 +20,151 @@ use super::internal::{
     span_wrappers::{
         deserialize_in_span, deserialize_in_transaction_with_span,
         get_value_in_transaction_with_span, get_value_with_span,
-        insert_value_in_transaction_with_span, insert_value_with_span,
-        remove_batch_in_transaction_with_span, remove_value_with_span, serialize_in_span,
-        serialize_in_transaction_with_span,
+        insert_value_in_transaction_with_span, remove_batch_in_transaction_with_span,
+        remove_value_in_transaction_with_span, serialize_in_transaction_with_span,
     },
     Key, KeyPrefix, PrefixKind,
 };
-use super::{todo_key, FromBytesWithConfig};
-use super::{BincodeConfig, SledStorage};
+use super::webhooks_impl::enqueue_todo_change_in_transaction;
+use super::{
+    history_key, history_prefix, key_todo_id, link_key, todo_key, trash_key, FromBytesWithConfig,
+};
+use super::{BincodeConfig, SledStorage, Vault};
 use super::{Pagination, StorageError, Todo, TodoStorage, TodoVersion, UpdateTodo};
 use async_trait::async_trait;
-use sled::Tree;
-use tracing::{debug, info, info_span, instrument, Span};
+pub(super) use history::remove_user_history;
+use history::{append_history_in_transaction, remove_todo_history};
+use sled::transaction::{ConflictableTransactionResult, TransactionResult, TransactionalTree};
+use sled::{Transactional, Tree};
+use std::cell::RefCell;
+use std::collections::HashSet;
+pub(super) use sync::remove_user_sync_log;
+use sync::{apply_changes, get_changes, record_change_in_transaction};
+use tracing::{info, info_span, instrument, Span};
+pub(super) use undo::remove_user_undo_log;
+use undo::{push_undo_in_transaction, trim_undo_log, undo_last_operation};
+pub(super) use usage::counted_usage;
+use usage::{count_usage, get_usage_in_transaction, put_usage_in_transaction};
+
+/// Trees written together with the todos, owned so that they can be moved
+/// into a blocking task.
+pub(super) struct TodoTrees {
+    pub(super) todo: Tree,
+    pub(super) link: Tree,
+    pub(super) trash: Tree,
+    pub(super) history: Tree,
+    pub(super) sync: Tree,
+    pub(super) outbox: Tree,
+    pub(super) undo: Tree,
+    /// Checked against the usage each transaction leaves.
+    pub(super) limits: LimitsConfig,
+}
+
+/// The [`TodoTrees`] inside of a transaction.
+pub(super) struct TodoTx<'a> {
+    pub(super) todo: &'a TransactionalTree,
+    pub(super) link: &'a TransactionalTree,
+    pub(super) trash: &'a TransactionalTree,
+    pub(super) history: &'a TransactionalTree,
+    pub(super) sync: &'a TransactionalTree,
+    pub(super) outbox: &'a TransactionalTree,
+    pub(super) undo: &'a TransactionalTree,
+    /// Usage of the user, counted along with the writes.
+    usage: RefCell<Usage>,
+}
+
+impl TodoTx<'_> {
+    /// Counts a todo that replaces `before` with `after`, either may be missing.
+    pub(super) fn count(&self, before: Option<&Todo>, after: Option<&Todo>) {
+        let mut usage = self.usage.borrow_mut();
+        if let Some(before) = before {
+            usage.remove(before);
+        }
+        if let Some(after) = after {
+            usage.add(after);
+        }
+    }
+}
+
+impl TodoTrees {
+    /// Runs `f` in one transaction over all the trees, which fails when the
+    /// todos of the user end up over a limit.
+    pub(super) fn transaction<A>(
+        &self,
+        user_id: &UserId,
+        bincode_config: &BincodeConfig,
+        vault: &Vault,
+        f: impl Fn(&TodoTx<'_>) -> ConflictableTransactionResult<A, SledStorageError>,
+    ) -> Result<A, SledStorageError> {
+        trace_err!(
+            count_usage(user_id, self, bincode_config, vault),
+            "failed to count todo usage"
+        )?;
+
+        let result: TransactionResult<A, SledStorageError> = (
+            &self.todo,
+            &self.link,
+            &self.trash,
+            &self.history,
+            &self.sync,
+            &self.outbox,
+            &self.undo,
+        )
+            .transaction(|(todo, link, trash, history, sync, outbox, undo)| {
+                let before = get_usage_in_transaction(user_id, sync, bincode_config, vault)?;
+                let tx = TodoTx {
+                    todo,
+                    link,
+                    trash,
+                    history,
+                    sync,
+                    outbox,
+                    undo,
+                    usage: RefCell::new(before.clone()),
+                };
+                let result = f(&tx)?;
+
+                let usage = tx.usage.into_inner();
+                if usage != before {
+                    put_usage_in_transaction(
+                        user_id,
+                        usage,
+                        &before,
+                        &self.limits,
+                        sync,
+                        bincode_config,
+                        vault,
+                    )?;
+                }
+                Ok(result)
+            });
+        Ok(result?)
+    }
+}
+
+impl SledStorage {
+    fn todo_trees(&self) -> TodoTrees {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        TodoTrees {
+            todo: self.todo_tree.clone(),
+            link: self.link_tree.clone(),
+            trash: self.trash_tree.clone(),
+            history: self.history_tree.clone(),
+            sync: self.sync_tree.clone(),
+            outbox: self.outbox_tree.clone(),
+            undo: self.undo_tree.clone(),
+            limits: self.limits.read().expect("limits lock poisoned").clone(),
+        }
+    }
+}
 
 #[async_trait]
 impl TodoStorage for SledStorage {
+    fn set_limits(&self, limits: LimitsConfig) {
+        info!(limits = ?limits, "set todo limits");
+        *self.limits.write().expect("limits lock poisoned") = limits;
+    }
+
     #[instrument(name = "SledStorage::get_todo", skip_all)]
     async fn get(&self, user_id: UserId, todo_id: TodoId) -> Result<Todo, StorageError> {
         info!(user_id = %user_id, todo_id = %todo_id, "get todo");
@@ -37,12 +177,13 @@ impl TodoStorage for SledStorage {
                 "failed to read todo from storage"
             )?;
 
-            Ok::<Todo, SledStorageError>(
-                trace_err!(
-                    deserialize_in_span::<TodoVersion>(&self.bincode_config, &value),
-                    "failed to bin decode todo"
-                )?
-                .into(),
+            let todo = trace_err!(
+                deserialize_in_span::<TodoVersion>(&self.bincode_config, &value),
+                "failed to bin decode todo"
+            )?;
+            trace_err!(
+                self.vault.open_todo(&user_id, &todo_id, todo),
+                "failed to open sealed todo"
             )
         })
         .map_err(Into::into)
@@ -50,39 +191,54 @@ impl TodoStorage for SledStorage {
 
     #[instrument(name = "SledStorage::put_todo", skip_all)]
     async fn put(&self, user_id: UserId, todo_id: TodoId, item: Todo) -> Result<(), StorageError> {
-        info!(user_id = %user_id, todo_id = %todo_id, "put todo");
-
-        measure_and_record_storage("SledStorage::put_todo", || {
-            let key = todo_key(&user_id, &todo_id);
-
-            let todo_version: TodoVersion = item.into();
-
-            let encoded: Vec<u8> = trace_err!(
-                serialize_in_span(&self.bincode_config, &todo_version),
-                "failed to bin decode todo"
-            )?;
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_trees(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
 
-            trace_err!(
-                insert_value_with_span(&key, &encoded, &self.todo_tree),
-                "failed to write todo into storage"
-            )
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("add_todo");
+            span.in_scope(|| {
+                add_todo(
+                    user_id,
+                    todo_id,
+                    item,
+                    &trees,
+                    &bincode_config,
+                    &vault,
+                    &settings,
+                )
+            })
         })
-        .map_err(Into::into)
+        .await?
     }
 
     #[instrument(name = "SledStorage::delete_todo", skip_all)]
     async fn delete(&self, user_id: UserId, todo_id: TodoId) -> Result<(), StorageError> {
-        info!(user_id = %user_id, todo_id = %todo_id, "delete todo");
-
-        measure_and_record_storage("SledStorage::delete_todo", || {
-            let key = todo_key(&user_id, &todo_id);
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_trees(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
 
-            trace_err!(
-                remove_value_with_span(&key, &self.todo_tree),
-                "failed to remove todo from storage"
-            )
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("delete_todo");
+            span.in_scope(|| {
+                delete_todo(user_id, todo_id, &trees, &bincode_config, &vault, &settings)
+            })
         })
-        .map_err(Into::into)
+        .await?
     }
 
     #[instrument(name = "SledStorage::update_todo", skip_all)]
@@ -92,14 +248,30 @@ impl TodoStorage for SledStorage {
         todo_id: TodoId,
         patch: UpdateTodo,
     ) -> Result<(), StorageError> {
-        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
-        let (todo_tree, bincode_config) = info_span!("Cloning trees and config")
-            .in_scope(|| (self.todo_tree.clone(), self.bincode_config));
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_trees(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
 
         let span = Span::current();
         tokio::task::spawn_blocking(move || {
             let _guard = BlockingTaskGuard::new("update_todo");
-            span.in_scope(|| update_todo(user_id, todo_id, patch, &todo_tree, &bincode_config))
+            span.in_scope(|| {
+                update_todo(
+                    user_id,
+                    todo_id,
+                    patch,
+                    &trees,
+                    &bincode_config,
+                    &vault,
+                    &settings,
+                )
+            })
         })
         .await?
     }
@@ -127,8 +299,12 @@ impl TodoStorage for SledStorage {
                                 .with_pagination(pagination)
                                 .collect(
                                     &self.bincode_config,
-                                    |_, bytes, config| {
-                                        Ok(Todo::from(TodoVersion::from_bytes(bytes, config)?))
+                                    |key, bytes, config| {
+                                        self.vault.open_todo(
+                                            &user_id,
+                                            &key_todo_id(key)?,
+                                            TodoVersion::from_bytes(bytes, config)?,
+                                        )
                                     },
                                     None,
                                 ),
@@ -142,13 +318,13 @@ impl TodoStorage for SledStorage {
     }
 
     #[instrument(name = "SledStorage::delete_all_todos", skip_all)]
-    async fn delete_all(&self, user_id: UserId) -> Result<(), StorageError> {
-        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
-        let (todo_tree, bincode_config, settings) = info_span!("Cloning trees and config")
+    async fn delete_all(&self, user_id: UserId) -> Result<Vec<TodoId>, StorageError> {
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
             .in_scope(|| {
                 (
-                    self.todo_tree.clone(),
+                    self.todo_trees(),
                     self.bincode_config,
+                    self.vault.clone(),
                     self.storage_settings.clone(),
                 )
             });
@@ -156,120 +332,1589 @@ impl TodoStorage for SledStorage {
         let span = Span::current();
         tokio::task::spawn_blocking(move || {
             let _guard = BlockingTaskGuard::new("delete_all_todos");
-            span.in_scope(|| delete_all_todos(user_id, &todo_tree, &bincode_config, &settings))
+            span.in_scope(|| delete_all_todos(user_id, &trees, &bincode_config, &vault, &settings))
         })
         .await?
     }
-}
 
-#[instrument(name = "SledStorage::delete_all_todos", skip_all)]
-fn delete_all_todos(
-    user_id: UserId,
-    todo_tree: &Tree,
-    bincode_config: &BincodeConfig,
-    settings: &SledConfig,
-) -> Result<(), StorageError> {
-    info!(user_id = %user_id, "delete all todo");
+    #[instrument(name = "SledStorage::batch_todos", skip_all)]
+    async fn batch(
+        &self,
+        user_id: UserId,
+        ops: Vec<TodoOp>,
+    ) -> Result<Vec<TodoOpResult>, StorageError> {
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_trees(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
 
-    let result: Result<_, SledStorageError> = measure_and_record_storage(
-        "SledStorage::delete_all_user_todos",
-        || {
-            let first_key = Key::new(KeyPrefix::from_kind(PrefixKind::Todo), user_id);
-            let key_prefix = KeyPrefix::new(PrefixKind::Todo, user_id);
-            let mut after: Option<Key> = None;
-            let mut deleted_items = 0;
-            loop {
-                let after_key = after.as_ref().unwrap_or(&first_key);
-
-                info!(after_key = %after_key, key_prefix = %key_prefix, "TreeScan input");
-                let mut page = trace_err!(
-                    TreeScan::scan_from(todo_tree, after_key)
-                        .within(key_prefix.clone())
-                        .with_pagination(Pagination {
-                            after: after.clone(),
-                            limit: settings.delete_batch_size,
-                        })
-                        .collect(bincode_config, |key, _, _| Ok(key.clone()), None),
-                    "failed to do tree scan to get page of todo-s to delete"
-                )?;
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("batch_todos");
+            span.in_scope(|| batch_todos(user_id, ops, &trees, &bincode_config, &vault, &settings))
+        })
+        .await?
+    }
 
-                // Delete cursor from prev iteration
-                if let Some(prev_cursor) = after {
-                    debug!("insert prev cursor into page.items");
-                    page.items.insert(0, prev_cursor);
-                }
-                // Keep cursor for next iteration
-                if page.next_cursor.is_some() {
-                    debug!("page.next_cursor is some. pop cursor for the next iteration from page.items");
-                    page.items.pop();
-                }
+    #[instrument(name = "SledStorage::update_group", skip_all)]
+    async fn update_group(
+        &self,
+        user_id: UserId,
+        group: String,
+        patch: UpdateTodo,
+    ) -> Result<Vec<TodoId>, StorageError> {
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_trees(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
 
-                todo_tree.transaction(|tx| {
-                    trace_err!(
-                        remove_batch_in_transaction_with_span(&page.items, tx),
-                        "Failed to remove batch of todo-s"
-                    )?;
-                    Ok(())
-                })?;
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("update_group");
+            span.in_scope(|| {
+                update_group(
+                    user_id,
+                    group,
+                    patch,
+                    &trees,
+                    &bincode_config,
+                    &vault,
+                    &settings,
+                )
+            })
+        })
+        .await?
+    }
 
-                deleted_items += page.items.len();
-                match page.next_cursor {
-                    Some(cursor) => after = Some(cursor),
-                    None => break,
-                }
+    #[instrument(name = "SledStorage::get_todo_links", skip_all)]
+    async fn get_links(&self, user_id: UserId, todo_id: TodoId) -> Result<TodoLinks, StorageError> {
+        info!(user_id = %user_id, todo_id = %todo_id, "get todo links");
+
+        measure_and_record_storage("SledStorage::get_todo_links", || {
+            let key = link_key(&user_id, &todo_id);
+
+            match get_value_with_span(&key, &self.link_tree) {
+                Ok(value) => trace_err!(
+                    deserialize_in_span::<TodoLinks>(&self.bincode_config, &value),
+                    "failed to bin decode todo links"
+                ),
+                Err(SledStorageError::NotFound) => Ok(TodoLinks::default()),
+                Err(e) => Err(e),
             }
-            info!(count = deleted_items, "deleted todos");
-            Ok(())
-        },
-    );
-    Ok(result?)
-}
+        })
+        .map_err(Into::into)
+    }
 
-#[instrument(name = "update_todo", skip_all)]
-fn update_todo(
-    user_id: UserId,
-    todo_id: TodoId,
-    patch: UpdateTodo,
-    todo_tree: &Tree,
-    bincode_config: &BincodeConfig,
-) -> Result<(), StorageError> {
-    info!(user_id = %user_id, todo_id = %todo_id, "update todo");
+    #[instrument(name = "SledStorage::add_todo_link", skip_all)]
+    async fn add_link(
+        &self,
+        user_id: UserId,
+        todo_id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), StorageError> {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        let (todo_tree, link_tree, bincode_config) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_tree.clone(),
+                    self.link_tree.clone(),
+                    self.bincode_config,
+                )
+            });
 
-    measure_and_record_storage("SledStorage::update_todo_in_transaction", || {
-        todo_tree.transaction(|tx| {
-            let key = todo_key(&user_id, &todo_id);
-            let value = trace_err!(
-                get_value_in_transaction_with_span(&key, tx),
-                "failed to read todo from storage"
-            )?;
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("add_todo_link");
+            span.in_scope(|| {
+                add_todo_link(
+                    user_id,
+                    todo_id,
+                    blocker_id,
+                    &todo_tree,
+                    &link_tree,
+                    &bincode_config,
+                )
+            })
+        })
+        .await?
+    }
 
-            if let Some(value) = value {
-                let mut todo: Todo = trace_err!(
-                    deserialize_in_transaction_with_span::<TodoVersion>(bincode_config, &value,),
-                    "failed to bin decode todo"
-                )?
-                .into();
+    #[instrument(name = "SledStorage::remove_todo_link", skip_all)]
+    async fn remove_link(
+        &self,
+        user_id: UserId,
+        todo_id: TodoId,
+        blocker_id: TodoId,
+    ) -> Result<(), StorageError> {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        let (link_tree, bincode_config) = info_span!("Cloning trees and config")
+            .in_scope(|| (self.link_tree.clone(), self.bincode_config));
 
-                todo.apply(&patch);
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("remove_todo_link");
+            span.in_scope(|| {
+                remove_todo_link(user_id, todo_id, blocker_id, &link_tree, &bincode_config)
+            })
+        })
+        .await?
+    }
 
-                let encoded = trace_err!(
-                    serialize_in_transaction_with_span(bincode_config, &TodoVersion::from(todo),),
-                    "failed to bin encode todo"
-                )?;
+    #[instrument(name = "SledStorage::get_trash", skip_all)]
+    async fn get_trash(
+        &self,
+        user_id: UserId,
+        pagination: Pagination<TodoId>,
+    ) -> Result<(Vec<TrashedTodo>, Option<TodoId>), StorageError> {
+        info!(user_id = %user_id, pagination = ?pagination, "get trashed todos");
 
-                trace_err!(
-                    insert_value_in_transaction_with_span(&key, &encoded, tx),
-                    "failed to write todo into storage"
-                )?;
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::get_trash", || {
+                let after_key = match pagination.after {
+                    Some(todo_id) => trash_key(&user_id, &todo_id),
+                    None => Key::new(KeyPrefix::from_kind(PrefixKind::Trash), user_id),
+                };
 
-                Ok(())
-            } else {
-                tracing::error!("failed to find todo in the storage");
-                Err(SledStorageError::NotFound.into())
-            }
+                let page = info_span!("TreeScan::scan_from::within::until_pagination::collect")
+                    .in_scope(|| {
+                        trace_err!(
+                            TreeScan::scan_from(&self.trash_tree, &after_key)
+                                .within(KeyPrefix::new(PrefixKind::Trash, user_id))
+                                .with_pagination(pagination)
+                                .collect(
+                                    &self.bincode_config,
+                                    |key, bytes, config| {
+                                        let record = TrashRecord::from_bytes(bytes, config)?;
+                                        let todo_id = key_todo_id(key)?;
+                                        Ok(TrashedTodo {
+                                            todo: self.vault.open_todo(
+                                                &user_id,
+                                                &todo_id,
+                                                record.todo,
+                                            )?,
+                                            deleted_at: record.deleted_at,
+                                        })
+                                    },
+                                    None,
+                                ),
+                            "failed to do tree scan to get page of trashed todo-s"
+                        )
+                    })?;
+                Ok((page.items, page.next_cursor))
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::restore_todo", skip_all)]
+    async fn restore(&self, user_id: UserId, todo_id: TodoId) -> Result<(), StorageError> {
+        let (trees, bincode_config, vault) = info_span!("Cloning trees and config")
+            .in_scope(|| (self.todo_trees(), self.bincode_config, self.vault.clone()));
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("restore_todo");
+            span.in_scope(|| restore_todo(user_id, todo_id, &trees, &bincode_config, &vault))
         })
-    })
-    .map_err(SledStorageError::from)?;
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::purge_trash", skip_all)]
+    async fn purge_trash(&self, deleted_before: i64) -> Result<usize, StorageError> {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        let (trash_tree, history_tree, bincode_config, settings) =
+            info_span!("Cloning trees and config").in_scope(|| {
+                (
+                    self.trash_tree.clone(),
+                    self.history_tree.clone(),
+                    self.bincode_config,
+                    self.storage_settings.clone(),
+                )
+            });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("purge_trash");
+            span.in_scope(|| {
+                purge_trash(
+                    deleted_before,
+                    &trash_tree,
+                    &history_tree,
+                    &bincode_config,
+                    &settings,
+                )
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::get_todo_history", skip_all)]
+    async fn get_history(
+        &self,
+        user_id: UserId,
+        todo_id: TodoId,
+        pagination: Pagination<HistorySeq>,
+    ) -> Result<(Vec<HistoryEntry>, Option<HistorySeq>), StorageError> {
+        info!(user_id = %user_id, todo_id = %todo_id, pagination = ?pagination, "get todo history");
+
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::get_todo_history", || {
+                let prefix = history_prefix(&user_id, &todo_id);
+                let after_key = match pagination.after {
+                    Some(seq) => history_key(&user_id, &todo_id, seq),
+                    None => Key::from_prefix(prefix.clone()),
+                };
+
+                let page = info_span!("TreeScan::scan_from::within::until_pagination::collect")
+                    .in_scope(|| {
+                        trace_err!(
+                            TreeScan::scan_from(&self.history_tree, &after_key)
+                                .within(prefix)
+                                .with_pagination(pagination)
+                                .collect(
+                                    &self.bincode_config,
+                                    |_, bytes, config| {
+                                        self.vault.open_history(
+                                            &user_id,
+                                            &todo_id,
+                                            HistoryVersion::from_bytes(bytes, config)?,
+                                        )
+                                    },
+                                    None,
+                                ),
+                            "failed to do tree scan to get page of history entries"
+                        )
+                    })?;
+                Ok((page.items, page.next_cursor))
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::undo", skip_all)]
+    async fn undo(&self, user_id: UserId) -> Result<(UndoResult, Vec<TodoId>), StorageError> {
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_trees(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("undo");
+            span.in_scope(|| {
+                undo_last_operation(user_id, &trees, &bincode_config, &vault, &settings)
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::get_changes", skip_all)]
+    async fn get_changes(
+        &self,
+        user_id: UserId,
+        pagination: Pagination<SyncSeq>,
+    ) -> Result<(Vec<SyncChange>, Option<SyncSeq>), StorageError> {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        let (todo_tree, sync_tree, bincode_config, vault, settings) =
+            info_span!("Cloning trees and config").in_scope(|| {
+                (
+                    self.todo_tree.clone(),
+                    self.sync_tree.clone(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("get_changes");
+            span.in_scope(|| {
+                get_changes(
+                    user_id,
+                    pagination,
+                    (&todo_tree, &sync_tree),
+                    &bincode_config,
+                    &vault,
+                    &settings,
+                )
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::apply_changes", skip_all)]
+    async fn apply_changes(
+        &self,
+        user_id: UserId,
+        changes: Vec<ClientChange>,
+    ) -> Result<Vec<SyncResult>, StorageError> {
+        let (trees, bincode_config, vault, settings) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.todo_trees(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                    self.storage_settings.clone(),
+                )
+            });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("apply_changes");
+            span.in_scope(|| {
+                apply_changes(user_id, changes, &trees, &bincode_config, &vault, &settings)
+            })
+        })
+        .await?
+    }
+}
+
+/// Todo read during a tree scan together with the key it's stored under.
+pub(super) struct StoredTodo {
+    pub(super) key: Key,
+    pub(super) todo: Todo,
+}
+
+impl HasId<Key> for StoredTodo {
+    fn id(&self) -> Key {
+        self.key.clone()
+    }
+}
+
+impl StoredTodo {
+    pub(super) fn from_bytes(
+        key: &Key,
+        bytes: &[u8],
+        config: &BincodeConfig,
+        vault: &Vault,
+    ) -> Result<Self, SledStorageError> {
+        let owner = key
+            .id_segment(0)
+            .ok_or_else(|| SledStorageError::InvalidKey(key.to_string()))?;
+        Ok(Self {
+            key: key.clone(),
+            todo: vault.open_todo(
+                &owner,
+                &key_todo_id(key)?,
+                TodoVersion::from_bytes(bytes, config)?,
+            )?,
+        })
+    }
+}
+
+/// Removes a page of todos together with their links records.
+pub(super) fn remove_todos_in_transaction(
+    user_id: &UserId,
+    todos: &[StoredTodo],
+    todo_tx: &TransactionalTree,
+    link_tx: &TransactionalTree,
+) -> Result<(), SledStorageError> {
+    let todo_keys: Vec<Key> = todos.iter().map(|item| item.key.clone()).collect();
+    let link_keys: Vec<Key> = todos
+        .iter()
+        .map(|item| link_key(user_id, &item.todo.id))
+        .collect();
+
+    remove_batch_in_transaction_with_span(&todo_keys, todo_tx)?;
+    remove_batch_in_transaction_with_span(&link_keys, link_tx)?;
+
+    Ok(())
+}
+
+fn put_in_trash_in_transaction(
+    user_id: &UserId,
+    todo: Todo,
+    deleted_at: i64,
+    trash_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    let key = trash_key(user_id, &todo.id);
+    let record = TrashRecord {
+        todo: vault.seal_todo(user_id, todo)?,
+        deleted_at,
+    };
+    let encoded = serialize_in_transaction_with_span(bincode_config, &record)?;
+    insert_value_in_transaction_with_span(&key, &encoded, trash_tx)
+}
+
+/// Trashed todo read during a tree scan together with the key it's stored under.
+pub(super) struct StoredTrash {
+    key: Key,
+    user_id: UserId,
+    todo_id: TodoId,
+    deleted_at: i64,
+}
+
+impl HasId<Key> for StoredTrash {
+    fn id(&self) -> Key {
+        self.key.clone()
+    }
+}
+
+impl StoredTrash {
+    fn from_bytes(
+        key: &Key,
+        bytes: &[u8],
+        config: &BincodeConfig,
+    ) -> Result<Self, SledStorageError> {
+        let record = TrashRecord::from_bytes(bytes, config)?;
+        let invalid = || SledStorageError::InvalidKey(key.to_string());
+        Ok(Self {
+            key: key.clone(),
+            user_id: key.id_segment(0).ok_or_else(invalid)?,
+            todo_id: key.id_segment(1).ok_or_else(invalid)?,
+            deleted_at: record.deleted_at,
+        })
+    }
+}
+
+/// Permanently removes every trashed todo of a user, used when the user is deleted.
+pub(super) fn remove_user_trash(
+    user_id: &UserId,
+    trash_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    settings: &SledConfig,
+) -> Result<usize, SledStorageError> {
+    let first_key = Key::new(KeyPrefix::from_kind(PrefixKind::Trash), user_id);
+    let key_prefix = KeyPrefix::new(PrefixKind::Trash, user_id);
+
+    for_each_page(
+        trash_tree,
+        &first_key,
+        &key_prefix,
+        settings.delete_batch_size,
+        bincode_config,
+        StoredTrash::from_bytes,
+        |page, _| {
+            let keys: Vec<Key> = page.iter().map(|item| item.key.clone()).collect();
+            trash_tree.transaction(|trash_tx| {
+                trace_err!(
+                    remove_batch_in_transaction_with_span(&keys, trash_tx),
+                    "failed to remove page of trashed todo-s"
+                )?;
+                Ok(())
+            })?;
+            Ok(())
+        },
+    )
+}
+
+fn get_links_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    link_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+) -> Result<TodoLinks, SledStorageError> {
+    let key = link_key(user_id, todo_id);
+    match get_value_in_transaction_with_span(&key, link_tx)? {
+        Some(value) => deserialize_in_transaction_with_span::<TodoLinks>(bincode_config, &value),
+        None => Ok(TodoLinks::default()),
+    }
+}
+
+fn put_links_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    links: &TodoLinks,
+    link_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+) -> Result<(), SledStorageError> {
+    let key = link_key(user_id, todo_id);
+    if links.is_empty() {
+        return remove_value_in_transaction_with_span(&key, link_tx);
+    }
+
+    let encoded = serialize_in_transaction_with_span(bincode_config, links)?;
+    insert_value_in_transaction_with_span(&key, &encoded, link_tx)
+}
+
+/// Drops every link of `todo_id`, fixing up the records on the other side.
+fn unlink_todo_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    link_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+) -> Result<(), SledStorageError> {
+    let links = get_links_in_transaction(user_id, todo_id, link_tx, bincode_config)?;
+    if links.is_empty() {
+        return Ok(());
+    }
+
+    for blocker_id in &links.blocked_by {
+        let mut other = get_links_in_transaction(user_id, blocker_id, link_tx, bincode_config)?;
+        other.blocking.retain(|id| id != todo_id);
+        put_links_in_transaction(user_id, blocker_id, &other, link_tx, bincode_config)?;
+    }
+    for blocked_id in &links.blocking {
+        let mut other = get_links_in_transaction(user_id, blocked_id, link_tx, bincode_config)?;
+        other.blocked_by.retain(|id| id != todo_id);
+        put_links_in_transaction(user_id, blocked_id, &other, link_tx, bincode_config)?;
+    }
+
+    remove_value_in_transaction_with_span(&link_key(user_id, todo_id), link_tx)
+}
+
+/// Walks "blocked by" edges starting at `blocker_id`: if `todo_id` can be
+/// reached, making `todo_id` blocked by `blocker_id` would close a cycle.
+fn creates_cycle_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    blocker_id: &TodoId,
+    link_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+) -> Result<bool, SledStorageError> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![*blocker_id];
+
+    while let Some(current) = stack.pop() {
+        if current == *todo_id {
+            return Ok(true);
+        }
+        if visited.insert(current) {
+            let links = get_links_in_transaction(user_id, &current, link_tx, bincode_config)?;
+            stack.extend(links.blocked_by);
+        }
+    }
+
+    Ok(false)
+}
+
+fn ensure_todo_exists_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    todo_tx: &TransactionalTree,
+) -> Result<(), SledStorageError> {
+    if get_value_in_transaction_with_span(&todo_key(user_id, todo_id), todo_tx)?.is_none() {
+        tracing::error!(todo_id = %todo_id, "failed to find todo in the storage");
+        return Err(SledStorageError::NotFound);
+    }
+    Ok(())
+}
+
+#[instrument(name = "SledStorage::delete_todo", skip_all)]
+fn delete_todo(
+    user_id: UserId,
+    todo_id: TodoId,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<(), StorageError> {
+    info!(user_id = %user_id, todo_id = %todo_id, "delete todo");
+
+    let deleted_at = chrono::Utc::now().timestamp();
+    measure_and_record_storage("SledStorage::delete_todo", || {
+        trees.transaction(&user_id, bincode_config, vault, |tx| {
+            let step =
+                delete_in_transaction(&user_id, &todo_id, deleted_at, tx, bincode_config, vault)?;
+
+            trace_err!(
+                push_undo_in_transaction(
+                    &user_id,
+                    None,
+                    UndoOperation::Delete,
+                    step,
+                    tx.undo,
+                    bincode_config,
+                    vault
+                ),
+                "failed to write undo record"
+            )?;
+
+            Ok(())
+        })
+    })?;
+
+    trace_err!(
+        trim_undo_log(
+            &user_id,
+            &trees.undo,
+            bincode_config,
+            settings.undo_log_size
+        ),
+        "failed to trim undo log"
+    )?;
+
+    Ok(())
+}
+
+/// Moves a todo to the trash and returns the step that brings it back.
+fn delete_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    deleted_at: i64,
+    tx: &TodoTx<'_>,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<UndoStep, SledStorageError> {
+    let key = todo_key(user_id, todo_id);
+
+    let Some(value) = tx.todo.remove(key.as_bytes())? else {
+        tracing::warn!(key = %key, "Tried to remove non-existing key");
+        return Err(SledStorageError::NoContent);
+    };
+    let todo = trace_err!(
+        deserialize_in_transaction_with_span::<TodoVersion>(bincode_config, &value),
+        "failed to bin decode todo"
+    )?;
+    let todo = trace_err!(
+        vault.open_todo(user_id, todo_id, todo),
+        "failed to open sealed todo"
+    )?;
+    tx.count(Some(&todo), None);
+
+    trace_err!(
+        append_history_in_transaction(
+            user_id,
+            todo_id,
+            HistoryAction::Deleted,
+            diff(Some(&todo), None),
+            deleted_at,
+            tx.history,
+            bincode_config,
+            vault
+        ),
+        "failed to append todo history"
+    )?;
+    trace_err!(
+        record_change_in_transaction(user_id, todo_id, true, tx.sync, bincode_config),
+        "failed to record todo change"
+    )?;
+    trace_err!(
+        enqueue_todo_change_in_transaction(
+            user_id,
+            Some(&todo),
+            None,
+            deleted_at,
+            tx.outbox,
+            bincode_config,
+            vault
+        ),
+        "failed to write webhook event"
+    )?;
+    trace_err!(
+        put_in_trash_in_transaction(user_id, todo, deleted_at, tx.trash, bincode_config, vault),
+        "failed to move todo to trash"
+    )?;
+
+    trace_err!(
+        unlink_todo_in_transaction(user_id, todo_id, tx.link, bincode_config),
+        "failed to remove todo links"
+    )?;
+
+    Ok(UndoStep::Deleted {
+        todo_ids: vec![*todo_id],
+    })
+}
+
+#[instrument(name = "SledStorage::add_todo_link", skip_all)]
+fn add_todo_link(
+    user_id: UserId,
+    todo_id: TodoId,
+    blocker_id: TodoId,
+    todo_tree: &Tree,
+    link_tree: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<(), StorageError> {
+    info!(user_id = %user_id, todo_id = %todo_id, blocker_id = %blocker_id, "add todo link");
+
+    measure_and_record_storage("SledStorage::add_todo_link", || {
+        (todo_tree, link_tree).transaction(|(todo_tx, link_tx)| {
+            ensure_todo_exists_in_transaction(&user_id, &todo_id, todo_tx)?;
+            ensure_todo_exists_in_transaction(&user_id, &blocker_id, todo_tx)?;
+
+            if creates_cycle_in_transaction(
+                &user_id,
+                &todo_id,
+                &blocker_id,
+                link_tx,
+                bincode_config,
+            )? {
+                tracing::warn!(todo_id = %todo_id, blocker_id = %blocker_id, "link would create a cycle");
+                return Err(SledStorageError::DependencyCycle.into());
+            }
+
+            let mut links = get_links_in_transaction(&user_id, &todo_id, link_tx, bincode_config)?;
+            if links.blocked_by.contains(&blocker_id) {
+                info!("link already exists");
+                return Ok(());
+            }
+            links.blocked_by.push(blocker_id);
+            trace_err!(
+                put_links_in_transaction(&user_id, &todo_id, &links, link_tx, bincode_config),
+                "failed to write todo links"
+            )?;
+
+            let mut blocker_links =
+                get_links_in_transaction(&user_id, &blocker_id, link_tx, bincode_config)?;
+            blocker_links.blocking.push(todo_id);
+            trace_err!(
+                put_links_in_transaction(
+                    &user_id,
+                    &blocker_id,
+                    &blocker_links,
+                    link_tx,
+                    bincode_config
+                ),
+                "failed to write blocker links"
+            )?;
+
+            Ok(())
+        })
+    })
+    .map_err(SledStorageError::from)?;
+
+    Ok(())
+}
+
+#[instrument(name = "SledStorage::remove_todo_link", skip_all)]
+fn remove_todo_link(
+    user_id: UserId,
+    todo_id: TodoId,
+    blocker_id: TodoId,
+    link_tree: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<(), StorageError> {
+    info!(user_id = %user_id, todo_id = %todo_id, blocker_id = %blocker_id, "remove todo link");
+
+    measure_and_record_storage("SledStorage::remove_todo_link", || {
+        link_tree.transaction(|link_tx| {
+            let mut links = get_links_in_transaction(&user_id, &todo_id, link_tx, bincode_config)?;
+            if !links.blocked_by.contains(&blocker_id) {
+                tracing::warn!("tried to remove non-existing link");
+                return Err(SledStorageError::NoContent.into());
+            }
+            links.blocked_by.retain(|id| *id != blocker_id);
+            trace_err!(
+                put_links_in_transaction(&user_id, &todo_id, &links, link_tx, bincode_config),
+                "failed to write todo links"
+            )?;
+
+            let mut blocker_links =
+                get_links_in_transaction(&user_id, &blocker_id, link_tx, bincode_config)?;
+            blocker_links.blocking.retain(|id| *id != todo_id);
+            trace_err!(
+                put_links_in_transaction(
+                    &user_id,
+                    &blocker_id,
+                    &blocker_links,
+                    link_tx,
+                    bincode_config
+                ),
+                "failed to write blocker links"
+            )?;
+
+            Ok(())
+        })
+    })
+    .map_err(SledStorageError::from)?;
+
+    Ok(())
+}
+
+#[instrument(name = "SledStorage::delete_all_todos", skip_all)]
+fn delete_all_todos(
+    user_id: UserId,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<Vec<TodoId>, StorageError> {
+    info!(user_id = %user_id, "delete all todo");
+
+    let deleted_at = chrono::Utc::now().timestamp();
+
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::delete_all_user_todos", || {
+            let first_key = Key::new(KeyPrefix::from_kind(PrefixKind::Todo), user_id);
+            let key_prefix = KeyPrefix::new(PrefixKind::Todo, user_id);
+            // every batch gets its own undo record, all of them under one op
+            let mut op = None;
+            let mut deleted = Vec::new();
+
+            let deleted_items = trace_err!(
+                for_each_page(
+                    &trees.todo,
+                    &first_key,
+                    &key_prefix,
+                    settings.delete_batch_size,
+                    bincode_config,
+                    |key, bytes, config| StoredTodo::from_bytes(key, bytes, config, vault),
+                    |page, _| {
+                        if page.is_empty() {
+                            return Ok(());
+                        }
+                        let page_op = trees.transaction(&user_id, bincode_config, vault, |tx| {
+                            trace_err!(
+                                remove_todos_in_transaction(&user_id, page, tx.todo, tx.link),
+                                "Failed to remove batch of todo-s"
+                            )?;
+                            for item in page {
+                                tx.count(Some(&item.todo), None);
+                                trace_err!(
+                                    append_history_in_transaction(
+                                        &user_id,
+                                        &item.todo.id,
+                                        HistoryAction::Deleted,
+                                        diff(Some(&item.todo), None),
+                                        deleted_at,
+                                        tx.history,
+                                        bincode_config,
+                                        vault
+                                    ),
+                                    "failed to append todo history"
+                                )?;
+                                trace_err!(
+                                    record_change_in_transaction(
+                                        &user_id,
+                                        &item.todo.id,
+                                        true,
+                                        tx.sync,
+                                        bincode_config
+                                    ),
+                                    "failed to record todo change"
+                                )?;
+                                trace_err!(
+                                    enqueue_todo_change_in_transaction(
+                                        &user_id,
+                                        Some(&item.todo),
+                                        None,
+                                        deleted_at,
+                                        tx.outbox,
+                                        bincode_config,
+                                        vault
+                                    ),
+                                    "failed to write webhook event"
+                                )?;
+                                trace_err!(
+                                    put_in_trash_in_transaction(
+                                        &user_id,
+                                        item.todo.clone(),
+                                        deleted_at,
+                                        tx.trash,
+                                        bincode_config,
+                                        vault
+                                    ),
+                                    "failed to move todo to trash"
+                                )?;
+                            }
+                            let page_op = trace_err!(
+                                push_undo_in_transaction(
+                                    &user_id,
+                                    op,
+                                    UndoOperation::DeleteAll,
+                                    UndoStep::Deleted {
+                                        todo_ids: page.iter().map(|item| item.todo.id).collect(),
+                                    },
+                                    tx.undo,
+                                    bincode_config,
+                                    vault
+                                ),
+                                "failed to write undo record"
+                            )?;
+                            Ok(page_op)
+                        })?;
+                        op = Some(page_op);
+                        deleted.extend(page.iter().map(|item| item.todo.id));
+                        Ok(())
+                    }
+                ),
+                "failed to do tree scan to get page of todo-s to delete"
+            )?;
+
+            info!(count = deleted_items, "deleted todos");
+            trace_err!(
+                trim_undo_log(
+                    &user_id,
+                    &trees.undo,
+                    bincode_config,
+                    settings.undo_log_size
+                ),
+                "failed to trim undo log"
+            )?;
+            Ok(deleted)
+        });
+    Ok(result?)
+}
+
+#[instrument(name = "SledStorage::restore_todo", skip_all)]
+fn restore_todo(
+    user_id: UserId,
+    todo_id: TodoId,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), StorageError> {
+    info!(user_id = %user_id, todo_id = %todo_id, "restore todo");
+
+    let restored_at = chrono::Utc::now().timestamp();
+    measure_and_record_storage("SledStorage::restore_todo", || {
+        trees.transaction(&user_id, bincode_config, vault, |tx| {
+            let restored =
+                restore_in_transaction(&user_id, &todo_id, restored_at, tx, bincode_config, vault)?;
+            if !restored {
+                tracing::error!(todo_id = %todo_id, "failed to find todo in the trash");
+                return Err(SledStorageError::NotFound.into());
+            }
+
+            Ok(())
+        })
+    })?;
+
+    Ok(())
+}
+
+/// Moves a todo from the trash back to the todo list. Returns `false` when
+/// the todo isn't in the trash.
+fn restore_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    restored_at: i64,
+    tx: &TodoTx<'_>,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<bool, SledStorageError> {
+    let key = trash_key(user_id, todo_id);
+
+    let Some(value) = tx.trash.remove(key.as_bytes())? else {
+        return Ok(false);
+    };
+    let record = trace_err!(
+        deserialize_in_transaction_with_span::<TrashRecord>(bincode_config, &value),
+        "failed to bin decode trashed todo"
+    )?;
+
+    let encoded = trace_err!(
+        serialize_in_transaction_with_span(bincode_config, &record.todo),
+        "failed to bin encode todo"
+    )?;
+    let todo = trace_err!(
+        vault.open_todo(user_id, todo_id, record.todo),
+        "failed to open sealed todo"
+    )?;
+    tx.count(None, Some(&todo));
+    trace_err!(
+        append_history_in_transaction(
+            user_id,
+            todo_id,
+            HistoryAction::Restored,
+            diff(None, Some(&todo)),
+            restored_at,
+            tx.history,
+            bincode_config,
+            vault
+        ),
+        "failed to append todo history"
+    )?;
+    trace_err!(
+        record_change_in_transaction(user_id, todo_id, false, tx.sync, bincode_config),
+        "failed to record todo change"
+    )?;
+    trace_err!(
+        enqueue_todo_change_in_transaction(
+            user_id,
+            None,
+            Some(&todo),
+            restored_at,
+            tx.outbox,
+            bincode_config,
+            vault
+        ),
+        "failed to write webhook event"
+    )?;
+    trace_err!(
+        insert_value_in_transaction_with_span(&todo_key(user_id, todo_id), &encoded, tx.todo),
+        "failed to write restored todo"
+    )?;
+
+    Ok(true)
+}
+
+#[instrument(name = "SledStorage::purge_trash", skip_all)]
+fn purge_trash(
+    deleted_before: i64,
+    trash_tree: &Tree,
+    history_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    settings: &SledConfig,
+) -> Result<usize, StorageError> {
+    info!(deleted_before, "purge trash");
+
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::purge_trash", || {
+            let key_prefix = KeyPrefix::from_kind(PrefixKind::Trash);
+            let first_key = Key::from_prefix(key_prefix.clone());
+            let mut purged = 0;
+
+            trace_err!(
+                for_each_page(
+                    trash_tree,
+                    &first_key,
+                    &key_prefix,
+                    settings.delete_batch_size,
+                    bincode_config,
+                    StoredTrash::from_bytes,
+                    |page, _| {
+                        let expired: Vec<&StoredTrash> = page
+                            .iter()
+                            .filter(|item| item.deleted_at < deleted_before)
+                            .collect();
+                        if expired.is_empty() {
+                            return Ok(());
+                        }
+
+                        let keys: Vec<Key> = expired.iter().map(|item| item.key.clone()).collect();
+                        trash_tree.transaction(|trash_tx| {
+                            trace_err!(
+                                remove_batch_in_transaction_with_span(&keys, trash_tx),
+                                "failed to remove page of expired todo-s"
+                            )?;
+                            Ok(())
+                        })?;
+                        for item in &expired {
+                            trace_err!(
+                                remove_todo_history(
+                                    &item.user_id,
+                                    &item.todo_id,
+                                    history_tree,
+                                    bincode_config,
+                                    settings
+                                ),
+                                "failed to remove history of purged todo"
+                            )?;
+                        }
+                        purged += expired.len();
+                        Ok(())
+                    }
+                ),
+                "failed to do tree scan to get page of trashed todo-s"
+            )?;
+
+            info!(count = purged, "purged trashed todos");
+            Ok(purged)
+        });
+    Ok(result?)
+}
+
+#[instrument(name = "update_todo", skip_all)]
+fn update_todo(
+    user_id: UserId,
+    todo_id: TodoId,
+    patch: UpdateTodo,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<(), StorageError> {
+    info!(user_id = %user_id, todo_id = %todo_id, "update todo");
+
+    let updated_at = chrono::Utc::now().timestamp();
+    measure_and_record_storage("SledStorage::update_todo_in_transaction", || {
+        trees.transaction(&user_id, bincode_config, vault, |tx| {
+            let before = update_in_transaction(
+                &user_id,
+                &todo_id,
+                &patch,
+                updated_at,
+                tx,
+                bincode_config,
+                vault,
+            )?;
+
+            if let Some(before) = before {
+                trace_err!(
+                    push_undo_in_transaction(
+                        &user_id,
+                        None,
+                        UndoOperation::Update,
+                        UndoStep::Updated {
+                            before: before.into()
+                        },
+                        tx.undo,
+                        bincode_config,
+                        vault
+                    ),
+                    "failed to write undo record"
+                )?;
+            }
+
+            Ok(())
+        })
+    })?;
+
+    trace_err!(
+        trim_undo_log(
+            &user_id,
+            &trees.undo,
+            bincode_config,
+            settings.undo_log_size
+        ),
+        "failed to trim undo log"
+    )?;
+
+    Ok(())
+}
+
+/// Applies the patch to a stored todo. Returns the todo as it was before the
+/// patch, or `None` when the patch didn't change anything.
+fn update_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    patch: &UpdateTodo,
+    updated_at: i64,
+    tx: &TodoTx<'_>,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Option<Todo>, SledStorageError> {
+    let key = todo_key(user_id, todo_id);
+    let value = trace_err!(
+        get_value_in_transaction_with_span(&key, tx.todo),
+        "failed to read todo from storage"
+    )?;
+
+    let Some(value) = value else {
+        tracing::error!("failed to find todo in the storage");
+        return Err(SledStorageError::NotFound);
+    };
+    let todo = trace_err!(
+        deserialize_in_transaction_with_span::<TodoVersion>(bincode_config, &value),
+        "failed to bin decode todo"
+    )?;
+    let mut todo = trace_err!(
+        vault.open_todo(user_id, todo_id, todo),
+        "failed to open sealed todo"
+    )?;
+
+    if patch.completed == Some(true) && !todo.completed && !patch.ignore_blockers {
+        trace_err!(
+            ensure_not_blocked_in_transaction(
+                user_id,
+                todo_id,
+                &HashSet::new(),
+                tx.todo,
+                tx.link,
+                bincode_config,
+                vault
+            ),
+            "failed to complete todo"
+        )?;
+    }
+
+    let before = todo.clone();
+    todo.apply(patch);
+
+    let changes = diff(Some(&before), Some(&todo));
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    tx.count(Some(&before), Some(&todo));
+    trace_err!(
+        append_history_in_transaction(
+            user_id,
+            todo_id,
+            HistoryAction::Updated,
+            changes,
+            updated_at,
+            tx.history,
+            bincode_config,
+            vault
+        ),
+        "failed to append todo history"
+    )?;
+    trace_err!(
+        record_change_in_transaction(user_id, todo_id, false, tx.sync, bincode_config),
+        "failed to record todo change"
+    )?;
+    trace_err!(
+        enqueue_todo_change_in_transaction(
+            user_id,
+            Some(&before),
+            Some(&todo),
+            updated_at,
+            tx.outbox,
+            bincode_config,
+            vault
+        ),
+        "failed to write webhook event"
+    )?;
+
+    let encoded = trace_err!(
+        serialize_in_transaction_with_span(bincode_config, &vault.seal_todo(user_id, todo)?),
+        "failed to bin encode todo"
+    )?;
+
+    trace_err!(
+        insert_value_in_transaction_with_span(&key, &encoded, tx.todo),
+        "failed to write todo into storage"
+    )?;
+
+    Ok(Some(before))
+}
+
+#[instrument(name = "SledStorage::add_todo", skip_all)]
+fn add_todo(
+    user_id: UserId,
+    todo_id: TodoId,
+    item: Todo,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<(), StorageError> {
+    info!(user_id = %user_id, todo_id = %todo_id, "put todo");
+
+    let created_at = chrono::Utc::now().timestamp();
+    measure_and_record_storage("SledStorage::put_todo", || {
+        trees.transaction(&user_id, bincode_config, vault, |tx| {
+            let step =
+                create_in_transaction(&user_id, &item, created_at, tx, bincode_config, vault)?;
+
+            trace_err!(
+                push_undo_in_transaction(
+                    &user_id,
+                    None,
+                    UndoOperation::Create,
+                    step,
+                    tx.undo,
+                    bincode_config,
+                    vault
+                ),
+                "failed to write undo record"
+            )?;
+
+            Ok(())
+        })
+    })?;
+
+    trace_err!(
+        trim_undo_log(
+            &user_id,
+            &trees.undo,
+            bincode_config,
+            settings.undo_log_size
+        ),
+        "failed to trim undo log"
+    )?;
+
+    Ok(())
+}
+
+/// Writes a new todo and returns the step that removes it again.
+fn create_in_transaction(
+    user_id: &UserId,
+    todo: &Todo,
+    created_at: i64,
+    tx: &TodoTx<'_>,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<UndoStep, SledStorageError> {
+    let key = todo_key(user_id, &todo.id);
+    tx.count(None, Some(todo));
+
+    trace_err!(
+        append_history_in_transaction(
+            user_id,
+            &todo.id,
+            HistoryAction::Created,
+            diff(None, Some(todo)),
+            created_at,
+            tx.history,
+            bincode_config,
+            vault
+        ),
+        "failed to append todo history"
+    )?;
+    trace_err!(
+        record_change_in_transaction(user_id, &todo.id, false, tx.sync, bincode_config),
+        "failed to record todo change"
+    )?;
+    trace_err!(
+        enqueue_todo_change_in_transaction(
+            user_id,
+            None,
+            Some(todo),
+            created_at,
+            tx.outbox,
+            bincode_config,
+            vault
+        ),
+        "failed to write webhook event"
+    )?;
+
+    let encoded: Vec<u8> = trace_err!(
+        serialize_in_transaction_with_span(
+            bincode_config,
+            &vault.seal_todo(user_id, todo.clone())?
+        ),
+        "failed to bin encode todo"
+    )?;
+
+    trace_err!(
+        insert_value_in_transaction_with_span(&key, &encoded, tx.todo),
+        "failed to write todo into storage"
+    )?;
+
+    Ok(UndoStep::Created { todo_id: todo.id })
+}
+
+#[instrument(name = "SledStorage::batch_todos", skip_all)]
+fn batch_todos(
+    user_id: UserId,
+    ops: Vec<TodoOp>,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<Vec<TodoOpResult>, StorageError> {
+    info!(user_id = %user_id, count = ops.len(), "batch todo operations");
+
+    let at = chrono::Utc::now().timestamp();
+    let results = measure_and_record_storage("SledStorage::batch_todos", || {
+        trees.transaction(&user_id, bincode_config, vault, |tx| {
+            let mut results = Vec::with_capacity(ops.len());
+            let mut steps = Vec::with_capacity(ops.len());
+
+            for (index, op) in ops.iter().enumerate() {
+                let applied = match op {
+                    TodoOp::Create(todo) => {
+                        create_in_transaction(&user_id, todo, at, tx, bincode_config, vault)
+                            .map(|step| (TodoOpResult::Created { id: todo.id }, Some(step)))
+                    }
+                    TodoOp::Update { id, patch } => {
+                        update_in_transaction(&user_id, id, patch, at, tx, bincode_config, vault)
+                            .map(|before| {
+                                let step = before.map(|before| UndoStep::Updated {
+                                    before: before.into(),
+                                });
+                                (TodoOpResult::Updated { id: *id }, step)
+                            })
+                    }
+                    TodoOp::Delete { id } => {
+                        delete_in_transaction(&user_id, id, at, tx, bincode_config, vault)
+                            // a missing todo fails the batch instead of being a no-op
+                            .map_err(|e| match e {
+                                SledStorageError::NoContent => SledStorageError::NotFound,
+                                e => e,
+                            })
+                            .map(|step| (TodoOpResult::Deleted { id: *id }, Some(step)))
+                    }
+                };
+
+                let (result, step) = applied.map_err(|e| SledStorageError::BatchOperation {
+                    index,
+                    source: Box::new(e),
+                })?;
+                results.push(result);
+                steps.extend(step);
+            }
+
+            if !steps.is_empty() {
+                trace_err!(
+                    push_undo_in_transaction(
+                        &user_id,
+                        None,
+                        UndoOperation::Batch,
+                        UndoStep::Batch { steps },
+                        tx.undo,
+                        bincode_config,
+                        vault
+                    ),
+                    "failed to write undo record"
+                )?;
+            }
+
+            Ok(results)
+        })
+    })?;
+
+    trace_err!(
+        trim_undo_log(
+            &user_id,
+            &trees.undo,
+            bincode_config,
+            settings.undo_log_size
+        ),
+        "failed to trim undo log"
+    )?;
+
+    Ok(results)
+}
+
+#[instrument(name = "SledStorage::update_group", skip_all)]
+fn update_group(
+    user_id: UserId,
+    group: String,
+    patch: UpdateTodo,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<Vec<TodoId>, StorageError> {
+    info!(user_id = %user_id, group = %group, "update todo group");
+
+    let updated_at = chrono::Utc::now().timestamp();
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::update_group", || {
+            let first_key = Key::new(KeyPrefix::from_kind(PrefixKind::Todo), user_id);
+            let key_prefix = KeyPrefix::new(PrefixKind::Todo, user_id);
+
+            let mut todo_ids = Vec::new();
+            trace_err!(
+                for_each_page(
+                    &trees.todo,
+                    &first_key,
+                    &key_prefix,
+                    settings.delete_batch_size,
+                    bincode_config,
+                    |key, bytes, config| StoredTodo::from_bytes(key, bytes, config, vault),
+                    |page, _| {
+                        todo_ids.extend(
+                            page.iter()
+                                .filter(|item| item.todo.group == group)
+                                .map(|item| item.todo.id),
+                        );
+                        Ok(())
+                    }
+                ),
+                "failed to do tree scan to find todo-s of the group"
+            )?;
+
+            // Todos blocking each other inside the group are completed
+            // together, so only blockers outside of it count. The whole group
+            // is patched in one transaction, a blocked todo leaves it untouched
+            let members: HashSet<TodoId> = todo_ids.iter().copied().collect();
+            let unchecked = UpdateTodo {
+                ignore_blockers: true,
+                ..patch.clone()
+            };
+            let updated = trees.transaction(&user_id, bincode_config, vault, |tx| {
+                let mut steps = Vec::new();
+                let mut updated = Vec::new();
+                let mut completed = Vec::new();
+                for todo_id in &todo_ids {
+                    let before = match update_in_transaction(
+                        &user_id,
+                        todo_id,
+                        &unchecked,
+                        updated_at,
+                        tx,
+                        bincode_config,
+                        vault,
+                    ) {
+                        Ok(before) => before,
+                        // removed after the scan
+                        Err(SledStorageError::NotFound) => None,
+                        Err(e) => return Err(e.into()),
+                    };
+                    let Some(before) = before else {
+                        continue;
+                    };
+
+                    if patch.completed == Some(true) && !before.completed {
+                        completed.push(*todo_id);
+                    }
+                    updated.push(*todo_id);
+                    steps.push(UndoStep::Updated {
+                        before: before.into(),
+                    });
+                }
+
+                if !patch.ignore_blockers {
+                    for todo_id in &completed {
+                        trace_err!(
+                            ensure_not_blocked_in_transaction(
+                                &user_id,
+                                todo_id,
+                                &members,
+                                tx.todo,
+                                tx.link,
+                                bincode_config,
+                                vault
+                            ),
+                            "failed to complete todo of the group"
+                        )?;
+                    }
+                }
+
+                if !steps.is_empty() {
+                    trace_err!(
+                        push_undo_in_transaction(
+                            &user_id,
+                            None,
+                            UndoOperation::UpdateGroup,
+                            UndoStep::Batch { steps },
+                            tx.undo,
+                            bincode_config,
+                            vault
+                        ),
+                        "failed to write undo record"
+                    )?;
+                }
+                Ok(updated)
+            })?;
+
+            trace_err!(
+                trim_undo_log(
+                    &user_id,
+                    &trees.undo,
+                    bincode_config,
+                    settings.undo_log_size
+                ),
+                "failed to trim undo log"
+            )?;
+
+            info!(count = updated.len(), "updated todos of the group");
+            Ok(updated)
+        });
+    Ok(result?)
+}
+
+/// Fails with `TodoBlocked` while any todo blocking `todo_id` is still open.
+/// Blockers in `completed_with` are completed by the same operation.
+fn ensure_not_blocked_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    completed_with: &HashSet<TodoId>,
+    todo_tx: &TransactionalTree,
+    link_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    let links = get_links_in_transaction(user_id, todo_id, link_tx, bincode_config)?;
+
+    for blocker_id in links
+        .blocked_by
+        .iter()
+        .filter(|id| !completed_with.contains(id))
+    {
+        let value = get_value_in_transaction_with_span(&todo_key(user_id, blocker_id), todo_tx)?;
+        if let Some(value) = value {
+            let blocker =
+                deserialize_in_transaction_with_span::<TodoVersion>(bincode_config, &value)?;
+            let blocker = vault.open_todo(user_id, blocker_id, blocker)?;
+            if !blocker.completed {
+                tracing::warn!(blocker_id = %blocker_id, "todo is blocked by open todo");
+                return Err(SledStorageError::TodoBlocked);
+            }
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,108 @@
+use sled::transaction::TransactionalTree;
+use sled::Tree;
+
+use crate::config::types::SledConfig;
+use crate::storage::sled::error::SledStorageError;
+use crate::storage::sled::internal::{
+    for_each_page,
+    span_wrappers::{insert_value_in_transaction_with_span, remove_batch_in_transaction_with_span},
+    Key, KeyPrefix, PrefixKind,
+};
+use crate::storage::sled::{history_key, history_prefix, BincodeConfig, Vault};
+use crate::storage::{
+    sled::internal::span_wrappers::serialize_in_transaction_with_span, FieldChange, HistoryAction,
+    HistoryEntry, HistorySeq, TodoId, UserId,
+};
+use crate::trace_err;
+
+/// Appends an entry to the todo history. Nothing is written when the change
+/// didn't touch any field.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn append_history_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    action: HistoryAction,
+    changes: Vec<FieldChange>,
+    at: i64,
+    history_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let seq = HistorySeq(history_tx.generate_id()?);
+    let entry = HistoryEntry {
+        seq,
+        action,
+        actor: *user_id,
+        at,
+        changes,
+    };
+
+    let encoded = serialize_in_transaction_with_span(
+        bincode_config,
+        &vault.seal_history(user_id, todo_id, entry)?,
+    )?;
+    insert_value_in_transaction_with_span(&history_key(user_id, todo_id, seq), &encoded, history_tx)
+}
+
+fn remove_history_within(
+    prefix: KeyPrefix,
+    history_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    settings: &SledConfig,
+) -> Result<usize, SledStorageError> {
+    let first_key = Key::from_prefix(prefix.clone());
+
+    for_each_page(
+        history_tree,
+        &first_key,
+        &prefix,
+        settings.delete_batch_size,
+        bincode_config,
+        |key, _, _| Ok(key.clone()),
+        |page, _| {
+            history_tree.transaction(|history_tx| {
+                trace_err!(
+                    remove_batch_in_transaction_with_span(page, history_tx),
+                    "failed to remove page of history entries"
+                )?;
+                Ok(())
+            })?;
+            Ok(())
+        },
+    )
+}
+
+/// Drops the whole history of a todo once it's removed for good.
+pub(super) fn remove_todo_history(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    history_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    settings: &SledConfig,
+) -> Result<usize, SledStorageError> {
+    remove_history_within(
+        history_prefix(user_id, todo_id),
+        history_tree,
+        bincode_config,
+        settings,
+    )
+}
+
+/// Drops history of every todo of a user, used when the user is deleted.
+pub(in crate::storage::sled) fn remove_user_history(
+    user_id: &UserId,
+    history_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    settings: &SledConfig,
+) -> Result<usize, SledStorageError> {
+    remove_history_within(
+        KeyPrefix::new(PrefixKind::History, user_id),
+        history_tree,
+        bincode_config,
+        settings,
+    )
+}
@@ -0,0 +1,399 @@
+use sled::transaction::TransactionalTree;
+use sled::Tree;
+use tracing::{info, instrument};
+
+use super::{
+    create_in_transaction, delete_in_transaction, push_undo_in_transaction, trim_undo_log,
+    update_in_transaction, StoredTodo, TodoTrees,
+};
+use crate::config::types::SledConfig;
+use crate::storage::sled::error::SledStorageError;
+use crate::storage::sled::internal::{
+    for_each_page,
+    span_wrappers::{
+        deserialize_in_transaction_with_span, get_value_in_transaction_with_span,
+        get_value_with_span, insert_value_in_transaction_with_span,
+        remove_batch_in_transaction_with_span, remove_value_in_transaction_with_span,
+        serialize_in_transaction_with_span,
+    },
+    Key, KeyPrefix, PrefixKind, TreeScan,
+};
+use crate::storage::sled::{
+    sync_backfill_key, sync_head_key, sync_log_key, sync_log_prefix, sync_prefix, sync_rev_key,
+    todo_key, usage_key, BincodeConfig, FromBytesWithConfig, Vault,
+};
+use crate::storage::{
+    ClientChange, Pagination, StorageError, SyncChange, SyncRecord, SyncResult, SyncSeq, Todo,
+    TodoId, TodoVersion, UndoOperation, UndoStep, UpdateTodo, UserId,
+};
+use crate::trace_err;
+use crate::utils::measure_metrics::measure_and_record_storage;
+
+fn get_rev_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    sync_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+) -> Result<Option<SyncSeq>, SledStorageError> {
+    get_value_in_transaction_with_span(&sync_rev_key(user_id, todo_id), sync_tx)?
+        .map(|value| deserialize_in_transaction_with_span::<SyncSeq>(bincode_config, &value))
+        .transpose()
+}
+
+/// Gives the change of a todo the next sequence of its user and returns it as
+/// the new revision of the todo. The previous log entry of the todo is
+/// dropped, the log keeps only the latest change of each todo.
+pub(super) fn record_change_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    deleted: bool,
+    sync_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+) -> Result<SyncSeq, SledStorageError> {
+    // Reading the head makes concurrent changes of the user conflict, so
+    // sequences are committed in the order they were handed out
+    let head_key = sync_head_key(user_id);
+    let head = match get_value_in_transaction_with_span(&head_key, sync_tx)? {
+        Some(value) => deserialize_in_transaction_with_span::<SyncSeq>(bincode_config, &value)?,
+        None => SyncSeq::default(),
+    };
+    let seq = SyncSeq(head.0 + 1);
+
+    if let Some(rev) = get_rev_in_transaction(user_id, todo_id, sync_tx, bincode_config)? {
+        remove_value_in_transaction_with_span(&sync_log_key(user_id, rev), sync_tx)?;
+    }
+
+    let record = SyncRecord {
+        todo_id: *todo_id,
+        deleted,
+    };
+    let encoded = serialize_in_transaction_with_span(bincode_config, &record)?;
+    insert_value_in_transaction_with_span(&sync_log_key(user_id, seq), &encoded, sync_tx)?;
+
+    let encoded = serialize_in_transaction_with_span(bincode_config, &seq)?;
+    insert_value_in_transaction_with_span(&sync_rev_key(user_id, todo_id), &encoded, sync_tx)?;
+    insert_value_in_transaction_with_span(&head_key, &encoded, sync_tx)?;
+
+    Ok(seq)
+}
+
+/// Records todos written before the change log existed, so that the first
+/// sync of a user returns them too. Done once per user.
+fn backfill(
+    user_id: &UserId,
+    todo_tree: &Tree,
+    sync_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<(), SledStorageError> {
+    let marker = sync_backfill_key(user_id);
+    if sync_tree.contains_key(marker.as_bytes())? {
+        return Ok(());
+    }
+
+    let first_key = Key::new(KeyPrefix::from_kind(PrefixKind::Todo), user_id);
+    let key_prefix = KeyPrefix::new(PrefixKind::Todo, user_id);
+    let mut recorded = 0;
+
+    for_each_page(
+        todo_tree,
+        &first_key,
+        &key_prefix,
+        settings.delete_batch_size,
+        bincode_config,
+        |key, bytes, config| StoredTodo::from_bytes(key, bytes, config, vault),
+        |page, _| {
+            recorded += sync_tree.transaction(|sync_tx| {
+                let mut count = 0;
+                for item in page {
+                    let todo_id = &item.todo.id;
+                    if get_rev_in_transaction(user_id, todo_id, sync_tx, bincode_config)?.is_none()
+                    {
+                        record_change_in_transaction(
+                            user_id,
+                            todo_id,
+                            false,
+                            sync_tx,
+                            bincode_config,
+                        )?;
+                        count += 1;
+                    }
+                }
+                Ok(count)
+            })?;
+            Ok(())
+        },
+    )?;
+
+    sync_tree.insert(marker.as_bytes(), &[])?;
+    info!(count = recorded, "backfilled todo revisions");
+    Ok(())
+}
+
+#[instrument(name = "SledStorage::get_changes", skip_all)]
+pub(super) fn get_changes(
+    user_id: UserId,
+    pagination: Pagination<SyncSeq>,
+    (todo_tree, sync_tree): (&Tree, &Tree),
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<(Vec<SyncChange>, Option<SyncSeq>), StorageError> {
+    info!(user_id = %user_id, pagination = ?pagination, "get todo changes");
+
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::get_changes", || {
+            trace_err!(
+                backfill(
+                    &user_id,
+                    todo_tree,
+                    sync_tree,
+                    bincode_config,
+                    vault,
+                    settings
+                ),
+                "failed to backfill todo revisions"
+            )?;
+
+            let prefix = sync_log_prefix(&user_id);
+            let after_key = match pagination.after {
+                Some(seq) => sync_log_key(&user_id, seq),
+                None => Key::from_prefix(prefix.clone()),
+            };
+            // The entry at `after` is gone once its todo changed again, so
+            // the scan must not insist on it being there
+            let scan_pagination = Pagination {
+                after: None,
+                limit: pagination.limit,
+            };
+
+            let page = trace_err!(
+                TreeScan::scan_from(sync_tree, &after_key)
+                    .within(prefix)
+                    .with_pagination(scan_pagination)
+                    .collect(
+                        bincode_config,
+                        |key, bytes, config| {
+                            let record = SyncRecord::from_bytes(bytes, config)?;
+                            let rev = key
+                                .u64_segment(2)
+                                .map(SyncSeq)
+                                .ok_or_else(|| SledStorageError::InvalidKey(key.to_string()))?;
+                            let todo = if record.deleted {
+                                None
+                            } else {
+                                // a todo deleted after the log was read shows up as a
+                                // tombstone here and in the next sync again
+                                match get_value_with_span(
+                                    &todo_key(&user_id, &record.todo_id),
+                                    todo_tree,
+                                ) {
+                                    Ok(value) => Some(vault.open_todo(
+                                        &user_id,
+                                        &record.todo_id,
+                                        TodoVersion::from_bytes(&value, config)?,
+                                    )?),
+                                    Err(SledStorageError::NotFound) => None,
+                                    Err(e) => return Err(e),
+                                }
+                            };
+                            Ok(SyncChange {
+                                id: record.todo_id,
+                                rev,
+                                todo,
+                            })
+                        },
+                        None,
+                    ),
+                "failed to do tree scan to get page of todo changes"
+            )?;
+            Ok((page.items, page.next_cursor))
+        });
+
+    Ok(result?)
+}
+
+fn get_todo_in_transaction(
+    user_id: &UserId,
+    todo_id: &TodoId,
+    todo_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Option<Todo>, SledStorageError> {
+    get_value_in_transaction_with_span(&todo_key(user_id, todo_id), todo_tx)?
+        .map(|value| {
+            let todo = deserialize_in_transaction_with_span::<TodoVersion>(bincode_config, &value)?;
+            vault.open_todo(user_id, todo_id, todo)
+        })
+        .transpose()
+}
+
+#[instrument(name = "SledStorage::apply_changes", skip_all)]
+pub(super) fn apply_changes(
+    user_id: UserId,
+    changes: Vec<ClientChange>,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<Vec<SyncResult>, StorageError> {
+    info!(user_id = %user_id, count = changes.len(), "apply client changes");
+
+    let at = chrono::Utc::now().timestamp();
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::apply_changes", || {
+            // todos without a revision would be taken for new ones
+            trace_err!(
+                backfill(
+                    &user_id,
+                    &trees.todo,
+                    &trees.sync,
+                    bincode_config,
+                    vault,
+                    settings
+                ),
+                "failed to backfill todo revisions"
+            )?;
+
+            let results = trees.transaction(&user_id, bincode_config, vault, |tx| {
+                let mut results = Vec::with_capacity(changes.len());
+                let mut steps = Vec::new();
+
+                for change in &changes {
+                    let (id, base_rev) = match change {
+                        ClientChange::Upsert { todo, base_rev } => (todo.id, *base_rev),
+                        ClientChange::Delete { id, base_rev } => (*id, Some(*base_rev)),
+                    };
+                    let rev = get_rev_in_transaction(&user_id, &id, tx.sync, bincode_config)?;
+                    let current =
+                        get_todo_in_transaction(&user_id, &id, tx.todo, bincode_config, vault)?;
+
+                    let conflict = match change {
+                        ClientChange::Upsert { base_rev: None, .. } => {
+                            rev.is_some() || current.is_some()
+                        }
+                        // a deleted todo can't be edited, it has to be created again
+                        ClientChange::Upsert { .. } => rev != base_rev || current.is_none(),
+                        ClientChange::Delete { .. } => rev != base_rev,
+                    };
+                    if conflict {
+                        info!(todo_id = %id, "client change conflicts with stored revision");
+                        results.push(SyncResult::Conflict {
+                            id,
+                            rev,
+                            todo: current,
+                        });
+                        continue;
+                    }
+
+                    match change {
+                        ClientChange::Upsert {
+                            todo,
+                            base_rev: None,
+                        } => {
+                            steps.push(create_in_transaction(
+                                &user_id,
+                                todo,
+                                at,
+                                tx,
+                                bincode_config,
+                                vault,
+                            )?);
+                        }
+                        ClientChange::Upsert { todo, .. } => {
+                            // the client decides on completion of blocked todos itself
+                            let patch = UpdateTodo {
+                                text: Some(todo.text.clone()),
+                                completed: Some(todo.completed),
+                                group: Some(todo.group.clone()),
+                                due: Some(todo.due),
+                                ignore_blockers: true,
+                            };
+                            let before = update_in_transaction(
+                                &user_id,
+                                &id,
+                                &patch,
+                                at,
+                                tx,
+                                bincode_config,
+                                vault,
+                            )?;
+                            steps.extend(before.map(|before| UndoStep::Updated {
+                                before: before.into(),
+                            }));
+                        }
+                        // deleting an already deleted todo is a no-op
+                        ClientChange::Delete { .. } if current.is_none() => {}
+                        ClientChange::Delete { .. } => {
+                            steps.push(delete_in_transaction(
+                                &user_id,
+                                &id,
+                                at,
+                                tx,
+                                bincode_config,
+                                vault,
+                            )?);
+                        }
+                    }
+
+                    let rev = get_rev_in_transaction(&user_id, &id, tx.sync, bincode_config)?
+                        .unwrap_or_default();
+                    results.push(SyncResult::Applied { id, rev });
+                }
+
+                if !steps.is_empty() {
+                    trace_err!(
+                        push_undo_in_transaction(
+                            &user_id,
+                            None,
+                            UndoOperation::Sync,
+                            UndoStep::Batch { steps },
+                            tx.undo,
+                            bincode_config,
+                            vault
+                        ),
+                        "failed to write undo record"
+                    )?;
+                }
+
+                Ok(results)
+            })?;
+
+            trace_err!(
+                trim_undo_log(
+                    &user_id,
+                    &trees.undo,
+                    bincode_config,
+                    settings.undo_log_size
+                ),
+                "failed to trim undo log"
+            )?;
+
+            Ok(results)
+        });
+
+    Ok(result?)
+}
+
+/// Drops the change log and the usage of a user, used when the user is deleted.
+pub(in crate::storage::sled) fn remove_user_sync_log(
+    user_id: &UserId,
+    sync_tree: &Tree,
+) -> Result<usize, SledStorageError> {
+    let mut keys = sync_tree
+        .scan_prefix(sync_prefix(user_id).as_bytes())
+        .keys()
+        .map(|key| Key::from_bytes(&key?))
+        .collect::<Result<Vec<_>, _>>()?;
+    keys.push(usage_key(user_id));
+
+    sync_tree.transaction(|sync_tx| {
+        trace_err!(
+            remove_batch_in_transaction_with_span(&keys, sync_tx),
+            "failed to remove sync log entries"
+        )?;
+        Ok(())
+    })?;
+    Ok(keys.len())
+}
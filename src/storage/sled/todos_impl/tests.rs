@@ -1,7 +1,10 @@
 use super::*;
 
 use crate::{
-    storage::sled::test_util::{TestStorageBuilder, ADMIN_UUID},
+    storage::sled::{
+        test_util::{TestStorageBuilder, ADMIN_UUID},
+        ToBytesWithConfig,
+    },
     Settings,
 };
 
@@ -71,6 +74,8 @@ async fn test_update() {
                 text: Some(new_text.clone()),
                 completed: Some(true),
                 group: Some(group.clone()),
+                due: None,
+                ignore_blockers: false,
             },
         )
         .await
@@ -89,12 +94,46 @@ async fn test_update() {
                 text: None,
                 completed: None,
                 group: None,
+                due: None,
+                ignore_blockers: false,
             },
         )
         .await;
     assert!(matches!(result, Err(StorageError::NotFound)));
 }
 
+#[tokio::test]
+async fn test_update_due() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+
+    let todo = Todo::new(TodoId::new(), "aaa");
+    let id = todo.id;
+    storage.put(ADMIN_UUID.into(), id, todo).await.unwrap();
+
+    let due_patch = |due| UpdateTodo {
+        text: None,
+        completed: None,
+        group: None,
+        due: Some(due),
+        ignore_blockers: false,
+    };
+
+    storage
+        .update(ADMIN_UUID.into(), id, due_patch(Some(1_700_000_000)))
+        .await
+        .unwrap();
+    let todo = storage.get(ADMIN_UUID.into(), id).await.unwrap();
+    assert_eq!(todo.due, Some(1_700_000_000));
+
+    storage
+        .update(ADMIN_UUID.into(), id, due_patch(None))
+        .await
+        .unwrap();
+    let todo = storage.get(ADMIN_UUID.into(), id).await.unwrap();
+    assert_eq!(todo.due, None);
+}
+
 #[tokio::test]
 async fn test_get_all_first_page() {
     let todos_count = 15;
@@ -283,3 +322,1125 @@ async fn test_delete_all_more_than_2_pages() {
     assert_eq!(next, None);
     assert_eq!(items.len(), 0);
 }
+
+fn complete_patch(ignore_blockers: bool) -> UpdateTodo {
+    UpdateTodo {
+        text: None,
+        completed: Some(true),
+        group: None,
+        due: None,
+        ignore_blockers,
+    }
+}
+
+#[tokio::test]
+async fn test_add_and_remove_link() {
+    let builder = TestStorageBuilder::new().with_todos(2);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let (todo, blocker) = (todos[0].id, todos[1].id);
+
+    storage
+        .add_link(ADMIN_UUID.into(), todo, blocker)
+        .await
+        .unwrap();
+
+    let links = storage.get_links(ADMIN_UUID.into(), todo).await.unwrap();
+    assert_eq!(links.blocked_by, vec![blocker]);
+    assert!(links.blocking.is_empty());
+    let links = storage.get_links(ADMIN_UUID.into(), blocker).await.unwrap();
+    assert_eq!(links.blocking, vec![todo]);
+
+    storage
+        .remove_link(ADMIN_UUID.into(), todo, blocker)
+        .await
+        .unwrap();
+
+    assert!(storage
+        .get_links(ADMIN_UUID.into(), todo)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(storage
+        .get_links(ADMIN_UUID.into(), blocker)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let result = storage.remove_link(ADMIN_UUID.into(), todo, blocker).await;
+    assert!(matches!(result, Err(StorageError::NoContent)));
+}
+
+#[tokio::test]
+async fn test_add_link_to_missing_todo() {
+    let builder = TestStorageBuilder::new().with_todos(1);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+
+    let result = storage
+        .add_link(ADMIN_UUID.into(), todos[0].id, TodoId::new())
+        .await;
+
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+#[tokio::test]
+async fn test_add_link_rejects_cycle() {
+    let builder = TestStorageBuilder::new().with_todos(3);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let (a, b, c) = (todos[0].id, todos[1].id, todos[2].id);
+
+    storage.add_link(ADMIN_UUID.into(), a, b).await.unwrap();
+    storage.add_link(ADMIN_UUID.into(), b, c).await.unwrap();
+
+    let result = storage.add_link(ADMIN_UUID.into(), c, a).await;
+    assert!(matches!(result, Err(StorageError::DependencyCycle)));
+
+    let result = storage.add_link(ADMIN_UUID.into(), a, a).await;
+    assert!(matches!(result, Err(StorageError::DependencyCycle)));
+
+    assert!(storage
+        .get_links(ADMIN_UUID.into(), a)
+        .await
+        .unwrap()
+        .blocking
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_complete_blocked_todo() {
+    let builder = TestStorageBuilder::new().with_todos(2);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let (todo, blocker) = (todos[0].id, todos[1].id);
+
+    storage
+        .add_link(ADMIN_UUID.into(), todo, blocker)
+        .await
+        .unwrap();
+
+    let result = storage
+        .update(ADMIN_UUID.into(), todo, complete_patch(false))
+        .await;
+    assert!(matches!(result, Err(StorageError::TodoBlocked)));
+    assert!(
+        !storage
+            .get(ADMIN_UUID.into(), todo)
+            .await
+            .unwrap()
+            .completed
+    );
+
+    storage
+        .update(ADMIN_UUID.into(), blocker, complete_patch(false))
+        .await
+        .unwrap();
+    storage
+        .update(ADMIN_UUID.into(), todo, complete_patch(false))
+        .await
+        .unwrap();
+
+    assert!(
+        storage
+            .get(ADMIN_UUID.into(), todo)
+            .await
+            .unwrap()
+            .completed
+    );
+}
+
+#[tokio::test]
+async fn test_complete_blocked_todo_ignoring_blockers() {
+    let builder = TestStorageBuilder::new().with_todos(2);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let (todo, blocker) = (todos[0].id, todos[1].id);
+
+    storage
+        .add_link(ADMIN_UUID.into(), todo, blocker)
+        .await
+        .unwrap();
+
+    storage
+        .update(ADMIN_UUID.into(), todo, complete_patch(true))
+        .await
+        .unwrap();
+
+    assert!(
+        storage
+            .get(ADMIN_UUID.into(), todo)
+            .await
+            .unwrap()
+            .completed
+    );
+}
+
+#[tokio::test]
+async fn test_delete_removes_links() {
+    let builder = TestStorageBuilder::new().with_todos(3);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let (a, b, c) = (todos[0].id, todos[1].id, todos[2].id);
+
+    storage.add_link(ADMIN_UUID.into(), a, b).await.unwrap();
+    storage.add_link(ADMIN_UUID.into(), b, c).await.unwrap();
+
+    storage.delete(ADMIN_UUID.into(), b).await.unwrap();
+
+    assert!(storage
+        .get_links(ADMIN_UUID.into(), a)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(storage
+        .get_links(ADMIN_UUID.into(), b)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(storage
+        .get_links(ADMIN_UUID.into(), c)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_delete_all_removes_links() {
+    let settings = Settings::from_file("test").unwrap();
+    let todos_count = settings.storage.sled.unwrap().delete_batch_size * 2 + 1;
+    let builder = TestStorageBuilder::new().with_todos(todos_count);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+
+    for pair in todos.windows(2) {
+        storage
+            .add_link(ADMIN_UUID.into(), pair[0].id, pair[1].id)
+            .await
+            .unwrap();
+    }
+
+    storage.delete_all(ADMIN_UUID.into()).await.unwrap();
+
+    for todo in &todos {
+        assert!(storage
+            .get_links(ADMIN_UUID.into(), todo.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_delete_moves_todo_to_trash() {
+    let builder = TestStorageBuilder::new().with_todos(2);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let limit = 10;
+
+    storage
+        .delete(ADMIN_UUID.into(), todos[0].id)
+        .await
+        .unwrap();
+
+    let (trash, cursor) = storage
+        .get_trash(ADMIN_UUID.into(), Pagination { after: None, limit })
+        .await
+        .unwrap();
+
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].todo, todos[0]);
+    assert!(cursor.is_none());
+
+    let result = storage.get(ADMIN_UUID.into(), todos[0].id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+#[tokio::test]
+async fn test_restore() {
+    let builder = TestStorageBuilder::new().with_todos(1);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let limit = 10;
+
+    storage
+        .delete(ADMIN_UUID.into(), todos[0].id)
+        .await
+        .unwrap();
+    storage
+        .restore(ADMIN_UUID.into(), todos[0].id)
+        .await
+        .unwrap();
+
+    let todo = storage.get(ADMIN_UUID.into(), todos[0].id).await.unwrap();
+    assert_eq!(todo, todos[0]);
+
+    let (trash, _) = storage
+        .get_trash(ADMIN_UUID.into(), Pagination { after: None, limit })
+        .await
+        .unwrap();
+    assert!(trash.is_empty());
+
+    let result = storage.restore(ADMIN_UUID.into(), todos[0].id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+#[tokio::test]
+async fn test_delete_all_moves_todos_to_trash() {
+    let settings = Settings::from_file("test").unwrap();
+    let todos_count = settings.storage.sled.unwrap().delete_batch_size * 2 + 1;
+    let builder = TestStorageBuilder::new().with_todos(todos_count);
+    let mut todos = builder.todos();
+    todos.sort_by_key(|t| t.id);
+    let storage = builder.build_todo().await;
+
+    storage.delete_all(ADMIN_UUID.into()).await.unwrap();
+
+    let (trash, cursor) = storage
+        .get_trash(
+            ADMIN_UUID.into(),
+            Pagination {
+                after: None,
+                limit: todos_count,
+            },
+        )
+        .await
+        .unwrap();
+
+    assert!(cursor.is_none());
+    let trashed: Vec<Todo> = trash.into_iter().map(|item| item.todo).collect();
+    assert_eq!(trashed, todos);
+}
+
+#[tokio::test]
+async fn test_purge_trash() {
+    let settings = Settings::from_file("test").unwrap();
+    let todos_count = settings.storage.sled.unwrap().delete_batch_size * 2 + 1;
+    let builder = TestStorageBuilder::new().with_todos(todos_count);
+    let storage = builder.build_todo().await;
+    let limit = todos_count;
+
+    storage.delete_all(ADMIN_UUID.into()).await.unwrap();
+
+    let purged = storage
+        .purge_trash(chrono::Utc::now().timestamp() - 3600)
+        .await
+        .unwrap();
+    assert_eq!(purged, 0);
+
+    let purged = storage
+        .purge_trash(chrono::Utc::now().timestamp() + 1)
+        .await
+        .unwrap();
+    assert_eq!(purged, todos_count);
+
+    let (trash, _) = storage
+        .get_trash(ADMIN_UUID.into(), Pagination { after: None, limit })
+        .await
+        .unwrap();
+    assert!(trash.is_empty());
+}
+
+async fn history(storage: &dyn TodoStorage, todo_id: TodoId) -> Vec<HistoryAction> {
+    let (entries, cursor) = storage
+        .get_history(
+            ADMIN_UUID.into(),
+            todo_id,
+            Pagination {
+                after: None,
+                limit: 100,
+            },
+        )
+        .await
+        .unwrap();
+    assert!(cursor.is_none());
+    entries.into_iter().map(|entry| entry.action).collect()
+}
+
+#[tokio::test]
+async fn test_history_records_every_change() {
+    let builder = TestStorageBuilder::new().with_todos(1);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let id = todos[0].id;
+
+    let patch = UpdateTodo {
+        text: None,
+        completed: Some(true),
+        group: None,
+        due: None,
+        ignore_blockers: false,
+    };
+    storage.update(ADMIN_UUID.into(), id, patch).await.unwrap();
+
+    // Same values again, nothing changes so nothing is recorded
+    let patch = UpdateTodo {
+        text: Some(todos[0].text.clone()),
+        completed: Some(true),
+        group: None,
+        due: None,
+        ignore_blockers: false,
+    };
+    storage.update(ADMIN_UUID.into(), id, patch).await.unwrap();
+
+    storage.delete(ADMIN_UUID.into(), id).await.unwrap();
+    storage.restore(ADMIN_UUID.into(), id).await.unwrap();
+
+    assert_eq!(
+        history(storage.as_ref(), id).await,
+        vec![
+            HistoryAction::Created,
+            HistoryAction::Updated,
+            HistoryAction::Deleted,
+            HistoryAction::Restored,
+        ]
+    );
+
+    let (entries, _) = storage
+        .get_history(
+            ADMIN_UUID.into(),
+            id,
+            Pagination {
+                after: None,
+                limit: 2,
+            },
+        )
+        .await
+        .unwrap();
+    let update = &entries[1];
+    assert_eq!(update.actor, ADMIN_UUID.into());
+    assert_eq!(update.changes.len(), 1);
+    assert!(update.changes[0].old.is_some() && update.changes[0].new.is_some());
+}
+
+#[tokio::test]
+async fn test_history_pagination() {
+    let builder = TestStorageBuilder::new().with_todos(1);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let id = todos[0].id;
+
+    for i in 0..4 {
+        let patch = UpdateTodo {
+            text: Some(format!("text {i}")),
+            completed: None,
+            group: None,
+            due: None,
+            ignore_blockers: false,
+        };
+        storage.update(ADMIN_UUID.into(), id, patch).await.unwrap();
+    }
+
+    let mut seen = Vec::new();
+    let mut after = None;
+    loop {
+        let (entries, cursor) = storage
+            .get_history(ADMIN_UUID.into(), id, Pagination { after, limit: 2 })
+            .await
+            .unwrap();
+        seen.extend(entries.into_iter().map(|entry| entry.seq));
+        match cursor {
+            Some(cursor) => after = Some(cursor),
+            None => break,
+        }
+    }
+
+    assert_eq!(seen.len(), 5);
+    assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[tokio::test]
+async fn test_purge_trash_removes_history() {
+    let builder = TestStorageBuilder::new().with_todos(2);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+
+    storage
+        .delete(ADMIN_UUID.into(), todos[0].id)
+        .await
+        .unwrap();
+    storage
+        .purge_trash(chrono::Utc::now().timestamp() + 1)
+        .await
+        .unwrap();
+
+    assert!(history(storage.as_ref(), todos[0].id).await.is_empty());
+    assert_eq!(
+        history(storage.as_ref(), todos[1].id).await,
+        vec![HistoryAction::Created]
+    );
+}
+
+#[tokio::test]
+async fn test_undo_create_update_and_delete() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let todo = Todo::new(TodoId::new(), "aaa");
+    let id = todo.id;
+    storage.put(user_id, id, todo.clone()).await.unwrap();
+
+    let patch = UpdateTodo {
+        text: Some("bbb".to_string()),
+        completed: None,
+        group: None,
+        due: None,
+        ignore_blockers: false,
+    };
+    storage.update(user_id, id, patch).await.unwrap();
+    storage.delete(user_id, id).await.unwrap();
+
+    let (result, touched) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::Delete);
+    assert_eq!(result.count, 1);
+    assert_eq!(touched, vec![id]);
+    assert_eq!(storage.get(user_id, id).await.unwrap().text, "bbb");
+
+    let (result, _) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::Update);
+    assert_eq!(storage.get(user_id, id).await.unwrap(), todo);
+
+    let (result, _) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::Create);
+    let result = storage.get(user_id, id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+    assert!(history(storage.as_ref(), id).await.is_empty());
+
+    let result = storage.undo(user_id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+#[tokio::test]
+async fn test_undo_delete_all() {
+    let settings = Settings::from_file("test").unwrap();
+    let todos_count = settings.storage.sled.unwrap().delete_batch_size * 2 + 1;
+    let builder = TestStorageBuilder::new().with_todos(todos_count);
+    let mut todos = builder.todos();
+    todos.sort_by_key(|t| t.id);
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let deleted = storage.delete_all(user_id).await.unwrap();
+    assert_eq!(deleted.len(), todos_count);
+
+    let (result, _) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::DeleteAll);
+    assert_eq!(result.count, todos_count);
+
+    let (restored, _) = storage
+        .get_all(
+            user_id,
+            Pagination {
+                after: None,
+                limit: todos_count,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(restored, todos);
+
+    let (trash, _) = storage
+        .get_trash(
+            user_id,
+            Pagination {
+                after: None,
+                limit: 10,
+            },
+        )
+        .await
+        .unwrap();
+    assert!(trash.is_empty());
+}
+
+#[tokio::test]
+async fn test_undo_log_is_bounded() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    // TestStorageBuilder keeps 5 operations
+    for _ in 0..7 {
+        let todo = Todo::new(TodoId::new(), "aaa");
+        storage.put(user_id, todo.id, todo).await.unwrap();
+    }
+
+    for _ in 0..5 {
+        storage.undo(user_id).await.unwrap();
+    }
+    let result = storage.undo(user_id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+fn patch_completed() -> UpdateTodo {
+    UpdateTodo {
+        text: None,
+        completed: Some(true),
+        group: None,
+        due: None,
+        ignore_blockers: false,
+    }
+}
+
+#[tokio::test]
+async fn test_batch() {
+    let builder = TestStorageBuilder::new().with_todos(2);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let new_todo = Todo::new(TodoId::new(), "new");
+    let results = storage
+        .batch(
+            user_id,
+            vec![
+                TodoOp::Create(new_todo.clone()),
+                TodoOp::Update {
+                    id: todos[0].id,
+                    patch: patch_completed(),
+                },
+                TodoOp::Delete { id: todos[1].id },
+            ],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            TodoOpResult::Created { id: new_todo.id },
+            TodoOpResult::Updated { id: todos[0].id },
+            TodoOpResult::Deleted { id: todos[1].id },
+        ]
+    );
+    assert_eq!(storage.get(user_id, new_todo.id).await.unwrap(), new_todo);
+    assert!(storage.get(user_id, todos[0].id).await.unwrap().completed);
+    let result = storage.get(user_id, todos[1].id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+
+    // the whole batch is a single undo operation
+    let (result, _) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::Batch);
+    assert_eq!(result.count, 3);
+    let result = storage.get(user_id, new_todo.id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+    assert_eq!(storage.get(user_id, todos[0].id).await.unwrap(), todos[0]);
+    assert_eq!(storage.get(user_id, todos[1].id).await.unwrap(), todos[1]);
+}
+
+#[tokio::test]
+async fn test_batch_is_all_or_nothing() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let new_todo = Todo::new(TodoId::new(), "new");
+    let result = storage
+        .batch(
+            user_id,
+            vec![
+                TodoOp::Create(new_todo.clone()),
+                TodoOp::Delete { id: TodoId::new() },
+            ],
+        )
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(StorageError::BatchOperation { index: 1, source }) if matches!(*source, StorageError::NotFound)
+    ));
+    let result = storage.get(user_id, new_todo.id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+#[tokio::test]
+async fn test_update_group() {
+    let builder = TestStorageBuilder::new().with_todos(4);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    for todo in &todos[..3] {
+        let patch = UpdateTodo {
+            text: None,
+            completed: None,
+            group: Some("red".to_string()),
+            due: None,
+            ignore_blockers: false,
+        };
+        storage.update(user_id, todo.id, patch).await.unwrap();
+    }
+    // blockers inside the group get completed together with the todo
+    storage
+        .add_link(user_id, todos[0].id, todos[1].id)
+        .await
+        .unwrap();
+
+    let updated = storage
+        .update_group(user_id, "red".to_string(), patch_completed())
+        .await
+        .unwrap();
+    assert_eq!(updated.len(), 3);
+    for todo in &todos[..3] {
+        assert!(storage.get(user_id, todo.id).await.unwrap().completed);
+    }
+    assert!(!storage.get(user_id, todos[3].id).await.unwrap().completed);
+
+    let (result, _) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::UpdateGroup);
+    assert_eq!(result.count, 3);
+    assert!(!storage.get(user_id, todos[0].id).await.unwrap().completed);
+}
+
+#[tokio::test]
+async fn test_update_group_with_blocker_outside_group() {
+    let builder = TestStorageBuilder::new().with_todos(2);
+    let todos = builder.todos();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    storage
+        .add_link(user_id, todos[0].id, todos[1].id)
+        .await
+        .unwrap();
+    let patch = UpdateTodo {
+        text: None,
+        completed: None,
+        group: Some("red".to_string()),
+        due: None,
+        ignore_blockers: false,
+    };
+    storage.update(user_id, todos[0].id, patch).await.unwrap();
+
+    let result = storage
+        .update_group(user_id, "red".to_string(), patch_completed())
+        .await;
+    assert!(matches!(result, Err(StorageError::TodoBlocked)));
+    assert!(!storage.get(user_id, todos[0].id).await.unwrap().completed);
+}
+
+#[tokio::test]
+async fn test_update_group_spanning_pages() {
+    let settings = Settings::from_file("test").unwrap();
+    let todos_count = settings.storage.sled.unwrap().delete_batch_size * 2 + 1;
+    let builder = TestStorageBuilder::new().with_todos(todos_count);
+    let mut todos = builder.todos();
+    todos.sort_by_key(|t| t.id);
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    for todo in &todos {
+        let patch = UpdateTodo {
+            text: None,
+            completed: None,
+            group: Some("red".to_string()),
+            due: None,
+            ignore_blockers: false,
+        };
+        storage.update(user_id, todo.id, patch).await.unwrap();
+    }
+    // the blocker is read in a later page than the todo it blocks
+    storage
+        .add_link(user_id, todos[0].id, todos[todos_count - 1].id)
+        .await
+        .unwrap();
+
+    let updated = storage
+        .update_group(user_id, "red".to_string(), patch_completed())
+        .await
+        .unwrap();
+    assert_eq!(updated.len(), todos_count);
+    for todo in &todos {
+        assert!(storage.get(user_id, todo.id).await.unwrap().completed);
+    }
+
+    // undone as one operation
+    let (result, _) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::UpdateGroup);
+    assert_eq!(result.count, todos_count);
+    for todo in &todos {
+        assert!(!storage.get(user_id, todo.id).await.unwrap().completed);
+    }
+}
+
+#[tokio::test]
+async fn test_update_group_blocked_on_a_later_page() {
+    let settings = Settings::from_file("test").unwrap();
+    let todos_count = settings.storage.sled.unwrap().delete_batch_size * 2 + 1;
+    let builder = TestStorageBuilder::new().with_todos(todos_count + 1);
+    let mut todos = builder.todos();
+    todos.sort_by_key(|t| t.id);
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let (blocker, group) = todos.split_first().unwrap();
+    for todo in group {
+        let patch = UpdateTodo {
+            text: None,
+            completed: None,
+            group: Some("red".to_string()),
+            due: None,
+            ignore_blockers: false,
+        };
+        storage.update(user_id, todo.id, patch).await.unwrap();
+    }
+    storage
+        .add_link(user_id, group[todos_count - 1].id, blocker.id)
+        .await
+        .unwrap();
+
+    let result = storage
+        .update_group(user_id, "red".to_string(), patch_completed())
+        .await;
+    assert!(matches!(result, Err(StorageError::TodoBlocked)));
+    // nothing of the group was written
+    for todo in group {
+        assert!(!storage.get(user_id, todo.id).await.unwrap().completed);
+    }
+}
+
+async fn changes(storage: &dyn TodoStorage, after: Option<SyncSeq>) -> Vec<SyncChange> {
+    storage
+        .get_changes(ADMIN_UUID.into(), Pagination { after, limit: 100 })
+        .await
+        .unwrap()
+        .0
+}
+
+#[tokio::test]
+async fn test_changes_keep_latest_change_of_todo() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let first = Todo::new(TodoId::new(), "aaa");
+    let second = Todo::new(TodoId::new(), "bbb");
+    storage.put(user_id, first.id, first.clone()).await.unwrap();
+    storage
+        .put(user_id, second.id, second.clone())
+        .await
+        .unwrap();
+    storage
+        .update(user_id, first.id, patch_completed())
+        .await
+        .unwrap();
+    storage.delete(user_id, second.id).await.unwrap();
+
+    let all = changes(storage.as_ref(), None).await;
+    assert_eq!(all.len(), 2);
+    assert_eq!(all[0].id, first.id);
+    assert_eq!(all[0].rev, SyncSeq(3));
+    assert!(all[0].todo.as_ref().unwrap().completed);
+    assert_eq!(all[1].id, second.id);
+    assert_eq!(all[1].rev, SyncSeq(4));
+    assert_eq!(all[1].todo, None);
+
+    // the entry at the token is gone, the todo changed once more
+    let since = changes(storage.as_ref(), Some(SyncSeq(1))).await;
+    assert_eq!(since, all);
+    let since = changes(storage.as_ref(), Some(SyncSeq(3))).await;
+    assert_eq!(since, all[1..]);
+    assert!(changes(storage.as_ref(), Some(SyncSeq(4))).await.is_empty());
+
+    storage.undo(user_id).await.unwrap();
+    let since = changes(storage.as_ref(), Some(SyncSeq(4))).await;
+    assert_eq!(since.len(), 1);
+    assert_eq!(since[0].rev, SyncSeq(5));
+    assert_eq!(since[0].todo, Some(second));
+
+    let (page, cursor) = storage
+        .get_changes(
+            user_id,
+            Pagination {
+                after: None,
+                limit: 1,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(cursor, Some(page[0].rev));
+}
+
+#[tokio::test]
+async fn test_apply_changes_detects_conflicts() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let todo = Todo::new(TodoId::new(), "aaa");
+    storage.put(user_id, todo.id, todo.clone()).await.unwrap();
+
+    let edited = Todo {
+        text: "bbb".to_string(),
+        ..todo.clone()
+    };
+    let created = Todo::new(TodoId::new(), "ccc");
+    let missing = TodoId::new();
+    let results = storage
+        .apply_changes(
+            user_id,
+            vec![
+                ClientChange::Upsert {
+                    todo: edited.clone(),
+                    base_rev: Some(SyncSeq(1)),
+                },
+                // based on the revision replaced by the change above
+                ClientChange::Upsert {
+                    todo: Todo {
+                        text: "zzz".to_string(),
+                        ..todo.clone()
+                    },
+                    base_rev: Some(SyncSeq(1)),
+                },
+                ClientChange::Upsert {
+                    todo: created.clone(),
+                    base_rev: None,
+                },
+                ClientChange::Delete {
+                    id: missing,
+                    base_rev: SyncSeq(1),
+                },
+            ],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        results,
+        vec![
+            SyncResult::Applied {
+                id: todo.id,
+                rev: SyncSeq(2),
+            },
+            SyncResult::Conflict {
+                id: todo.id,
+                rev: Some(SyncSeq(2)),
+                todo: Some(edited.clone()),
+            },
+            SyncResult::Applied {
+                id: created.id,
+                rev: SyncSeq(3),
+            },
+            SyncResult::Conflict {
+                id: missing,
+                rev: None,
+                todo: None,
+            },
+        ]
+    );
+    assert_eq!(storage.get(user_id, todo.id).await.unwrap(), edited);
+    assert_eq!(storage.get(user_id, created.id).await.unwrap(), created);
+
+    let results = storage
+        .apply_changes(
+            user_id,
+            vec![ClientChange::Delete {
+                id: todo.id,
+                base_rev: SyncSeq(2),
+            }],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![SyncResult::Applied {
+            id: todo.id,
+            rev: SyncSeq(4),
+        }]
+    );
+    assert!(matches!(
+        storage.get(user_id, todo.id).await,
+        Err(StorageError::NotFound)
+    ));
+
+    let (result, _) = storage.undo(user_id).await.unwrap();
+    assert_eq!(result.operation, UndoOperation::Sync);
+    assert_eq!(storage.get(user_id, todo.id).await.unwrap(), edited);
+}
+
+#[tokio::test]
+async fn test_changes_include_todos_written_before_change_log() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let settings = SledConfig {
+        path: std::path::PathBuf::new(),
+        delete_batch_size: 10,
+        undo_log_size: 5,
+        encryption: None,
+        compression: None,
+    };
+    let storage = SledStorage::from_db(&db, &settings).unwrap();
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let legacy = Todo::new(TodoId::new(), "aaa");
+    let encoded = TodoVersion::from(legacy.clone())
+        .to_bytes(&storage.bincode_config)
+        .unwrap();
+    storage
+        .todo_tree
+        .insert(todo_key(&user_id, &legacy.id).as_bytes(), encoded)
+        .unwrap();
+    let todo = Todo::new(TodoId::new(), "bbb");
+    storage.put(user_id, todo.id, todo.clone()).await.unwrap();
+
+    let all = changes(&storage, None).await;
+    assert_eq!(all.len(), 2);
+    assert_eq!(all[0].todo, Some(todo));
+    assert_eq!(all[1].todo, Some(legacy));
+    assert_eq!(all[1].rev, SyncSeq(2));
+
+    // done once, later syncs don't hand out new revisions
+    assert_eq!(changes(&storage, None).await, all);
+}
+
+fn limits(max_todos: usize, max_groups: usize) -> LimitsConfig {
+    LimitsConfig {
+        max_todos,
+        max_groups,
+        ..LimitsConfig::default()
+    }
+}
+
+fn group_patch(group: &str) -> UpdateTodo {
+    UpdateTodo {
+        text: None,
+        completed: None,
+        group: Some(group.to_owned()),
+        due: None,
+        ignore_blockers: false,
+    }
+}
+
+#[tokio::test]
+async fn test_limits_hold_for_every_write() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+    storage.set_limits(limits(2, 1));
+
+    let [first, second, third] = ["aaa", "bbb", "ccc"].map(|text| Todo::new(TodoId::new(), text));
+    storage.put(user_id, first.id, first.clone()).await.unwrap();
+    storage
+        .put(user_id, second.id, second.clone())
+        .await
+        .unwrap();
+    let result = storage.put(user_id, third.id, third.clone()).await;
+    assert!(matches!(
+        result,
+        Err(StorageError::QuotaExceeded {
+            quota: "todos",
+            max: 2
+        })
+    ));
+    let result = storage
+        .batch(
+            user_id,
+            vec![
+                TodoOp::Delete { id: second.id },
+                TodoOp::Create(third.clone()),
+            ],
+        )
+        .await;
+    assert!(result.is_ok());
+
+    // the trash and the undo log don't bring back more todos than allowed
+    let result = storage.restore(user_id, second.id).await;
+    assert!(matches!(
+        result,
+        Err(StorageError::QuotaExceeded { quota: "todos", .. })
+    ));
+    storage.delete(user_id, third.id).await.unwrap();
+    storage.set_limits(limits(1, 1));
+    let result = storage.undo(user_id).await;
+    assert!(matches!(
+        result,
+        Err(StorageError::QuotaExceeded { quota: "todos", .. })
+    ));
+    storage.set_limits(limits(2, 1));
+    storage
+        .put(user_id, second.id, second.clone())
+        .await
+        .unwrap();
+
+    storage
+        .update(user_id, first.id, group_patch("home"))
+        .await
+        .unwrap();
+    let result = storage
+        .update(user_id, second.id, group_patch("work"))
+        .await;
+    assert!(matches!(
+        result,
+        Err(StorageError::QuotaExceeded {
+            quota: "groups",
+            max: 1
+        })
+    ));
+    // a group goes with its last todo
+    storage
+        .update(user_id, first.id, group_patch("work"))
+        .await
+        .unwrap();
+    storage
+        .update(user_id, second.id, group_patch("work"))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_lowered_limits_let_usage_shrink() {
+    let builder = TestStorageBuilder::new();
+    let storage = builder.build_todo().await;
+    let user_id: UserId = ADMIN_UUID.into();
+
+    let todos = ["aaa", "bbb", "ccc"].map(|text| Todo::new(TodoId::new(), text));
+    for todo in &todos {
+        storage.put(user_id, todo.id, todo.clone()).await.unwrap();
+    }
+    storage.set_limits(limits(1, 0));
+
+    storage.delete(user_id, todos[0].id).await.unwrap();
+    let result = storage.restore(user_id, todos[0].id).await;
+    assert!(matches!(
+        result,
+        Err(StorageError::QuotaExceeded { quota: "todos", .. })
+    ));
+    storage
+        .update(user_id, todos[1].id, complete_patch(false))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_limits_count_todos_written_before_the_usage() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let settings = SledConfig {
+        path: std::path::PathBuf::new(),
+        delete_batch_size: 10,
+        undo_log_size: 5,
+        encryption: None,
+        compression: None,
+    };
+    let storage = SledStorage::from_db(&db, &settings).unwrap();
+    let user_id: UserId = ADMIN_UUID.into();
+    storage.set_limits(limits(1, 1));
+
+    let legacy = Todo::new(TodoId::new(), "aaa");
+    let encoded = TodoVersion::from(legacy.clone())
+        .to_bytes(&storage.bincode_config)
+        .unwrap();
+    storage
+        .todo_tree
+        .insert(todo_key(&user_id, &legacy.id).as_bytes(), encoded)
+        .unwrap();
+
+    let todo = Todo::new(TodoId::new(), "bbb");
+    let result = storage.put(user_id, todo.id, todo).await;
+    assert!(matches!(
+        result,
+        Err(StorageError::QuotaExceeded { quota: "todos", .. })
+    ));
+    storage.delete(user_id, legacy.id).await.unwrap();
+    let todo = Todo::new(TodoId::new(), "bbb");
+    storage.put(user_id, todo.id, todo).await.unwrap();
+}
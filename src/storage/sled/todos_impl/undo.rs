@@ -0,0 +1,306 @@
+use sled::transaction::TransactionalTree;
+use sled::Tree;
+use tracing::{info, instrument};
+
+use super::{
+    append_history_in_transaction, record_change_in_transaction, remove_todo_history,
+    restore_in_transaction, unlink_todo_in_transaction, TodoTrees, TodoTx,
+};
+use crate::config::types::SledConfig;
+use crate::storage::sled::error::SledStorageError;
+use crate::storage::sled::internal::{
+    span_wrappers::{
+        deserialize_in_transaction_with_span, get_value_in_transaction_with_span,
+        insert_value_in_transaction_with_span, remove_batch_in_transaction_with_span,
+        serialize_in_transaction_with_span,
+    },
+    Key,
+};
+use crate::storage::sled::webhooks_impl::enqueue_todo_change_in_transaction;
+use crate::storage::sled::{
+    todo_key, undo_key, undo_prefix, BincodeConfig, FromBytesWithConfig, Vault,
+};
+use crate::storage::{
+    diff, HistoryAction, StorageError, TodoId, TodoVersion, UndoOperation, UndoRecord, UndoResult,
+    UndoStep, UserId,
+};
+use crate::trace_err;
+use crate::utils::measure_metrics::measure_and_record_storage;
+
+/// Appends a step to the undo log. Pass `op` of the first step to group
+/// further steps of a multi transaction operation; returns the op of the step.
+pub(super) fn push_undo_in_transaction(
+    user_id: &UserId,
+    op: Option<u64>,
+    operation: UndoOperation,
+    step: UndoStep,
+    undo_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<u64, SledStorageError> {
+    let seq = undo_tx.generate_id()?;
+    let op = op.unwrap_or(seq);
+    let record = UndoRecord {
+        op,
+        operation,
+        step: vault.seal_step(user_id, step)?,
+    };
+
+    let encoded = serialize_in_transaction_with_span(bincode_config, &record)?;
+    insert_value_in_transaction_with_span(&undo_key(user_id, seq), &encoded, undo_tx)?;
+    Ok(op)
+}
+
+/// Drops the oldest operations so that at most `max_ops` of them stay in the log.
+#[instrument(name = "SledStorage::trim_undo_log", skip_all)]
+pub(super) fn trim_undo_log(
+    user_id: &UserId,
+    undo_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    max_ops: usize,
+) -> Result<(), SledStorageError> {
+    let prefix = undo_prefix(user_id);
+
+    let mut ops = 0;
+    let mut last_op = None;
+    let mut expired = Vec::new();
+    for item in undo_tree.scan_prefix(prefix.as_bytes()).rev() {
+        let (key, value) = item?;
+        let record = UndoRecord::from_bytes(&value, bincode_config)?;
+        if last_op != Some(record.op) {
+            last_op = Some(record.op);
+            ops += 1;
+        }
+        if ops > max_ops {
+            expired.push(Key::from_bytes(&key)?);
+        }
+    }
+
+    if !expired.is_empty() {
+        info!(count = expired.len(), "trim undo log");
+        undo_tree.transaction(|undo_tx| {
+            trace_err!(
+                remove_batch_in_transaction_with_span(&expired, undo_tx),
+                "failed to remove expired undo records"
+            )?;
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn latest_record(
+    user_id: &UserId,
+    undo_tree: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<Option<(Key, UndoRecord)>, SledStorageError> {
+    let prefix = undo_prefix(user_id);
+    let Some(item) = undo_tree.scan_prefix(prefix.as_bytes()).next_back() else {
+        return Ok(None);
+    };
+    let (key, value) = item?;
+
+    Ok(Some((
+        Key::from_bytes(&key)?,
+        UndoRecord::from_bytes(&value, bincode_config)?,
+    )))
+}
+
+/// Drops the whole undo log of a user, used when the user is deleted.
+pub(in crate::storage::sled) fn remove_user_undo_log(
+    user_id: &UserId,
+    undo_tree: &Tree,
+) -> Result<usize, SledStorageError> {
+    let keys = undo_tree
+        .scan_prefix(undo_prefix(user_id).as_bytes())
+        .keys()
+        .map(|key| Key::from_bytes(&key?))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    undo_tree.transaction(|undo_tx| {
+        trace_err!(
+            remove_batch_in_transaction_with_span(&keys, undo_tx),
+            "failed to remove undo records"
+        )?;
+        Ok(())
+    })?;
+    Ok(keys.len())
+}
+
+/// Reverts a single step and returns the todos it touched. Todos changed in
+/// the meantime (e.g. purged from the trash) are skipped.
+fn revert_step_in_transaction(
+    user_id: &UserId,
+    step: &UndoStep,
+    at: i64,
+    tx: &TodoTx<'_>,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Vec<TodoId>, SledStorageError> {
+    match step {
+        UndoStep::Created { todo_id } => {
+            let Some(value) = tx.todo.remove(todo_key(user_id, todo_id).as_bytes())? else {
+                return Ok(Vec::new());
+            };
+            let removed =
+                deserialize_in_transaction_with_span::<TodoVersion>(bincode_config, &value)?;
+            let removed = vault.open_todo(user_id, todo_id, removed)?;
+            tx.count(Some(&removed), None);
+            unlink_todo_in_transaction(user_id, todo_id, tx.link, bincode_config)?;
+            record_change_in_transaction(user_id, todo_id, true, tx.sync, bincode_config)?;
+            enqueue_todo_change_in_transaction(
+                user_id,
+                Some(&removed),
+                None,
+                at,
+                tx.outbox,
+                bincode_config,
+                vault,
+            )?;
+            Ok(vec![*todo_id])
+        }
+        UndoStep::Updated { before } => {
+            let before = vault.open_todo(user_id, &before.id(), before.clone())?;
+            let todo_id = before.id;
+            let key = todo_key(user_id, &todo_id);
+            let Some(value) = get_value_in_transaction_with_span(&key, tx.todo)? else {
+                return Ok(Vec::new());
+            };
+            let current =
+                deserialize_in_transaction_with_span::<TodoVersion>(bincode_config, &value)?;
+            let current = vault.open_todo(user_id, &todo_id, current)?;
+            tx.count(Some(&current), Some(&before));
+
+            append_history_in_transaction(
+                user_id,
+                &todo_id,
+                HistoryAction::Updated,
+                diff(Some(&current), Some(&before)),
+                at,
+                tx.history,
+                bincode_config,
+                vault,
+            )?;
+            record_change_in_transaction(user_id, &todo_id, false, tx.sync, bincode_config)?;
+            enqueue_todo_change_in_transaction(
+                user_id,
+                Some(&current),
+                Some(&before),
+                at,
+                tx.outbox,
+                bincode_config,
+                vault,
+            )?;
+            let encoded = serialize_in_transaction_with_span(
+                bincode_config,
+                &vault.seal_todo(user_id, before)?,
+            )?;
+            insert_value_in_transaction_with_span(&key, &encoded, tx.todo)?;
+            Ok(vec![todo_id])
+        }
+        UndoStep::Deleted { todo_ids } => {
+            let mut restored = Vec::new();
+            for todo_id in todo_ids {
+                if restore_in_transaction(user_id, todo_id, at, tx, bincode_config, vault)? {
+                    restored.push(*todo_id);
+                }
+            }
+            Ok(restored)
+        }
+        UndoStep::Batch { steps } => {
+            let mut reverted = Vec::new();
+            for step in steps.iter().rev() {
+                reverted.extend(revert_step_in_transaction(
+                    user_id,
+                    step,
+                    at,
+                    tx,
+                    bincode_config,
+                    vault,
+                )?);
+            }
+            Ok(reverted)
+        }
+    }
+}
+
+/// Todos created by the step, their history goes away together with them.
+fn created_todos(step: &UndoStep) -> Vec<TodoId> {
+    match step {
+        UndoStep::Created { todo_id } => vec![*todo_id],
+        UndoStep::Batch { steps } => steps.iter().flat_map(created_todos).collect(),
+        UndoStep::Updated { .. } | UndoStep::Deleted { .. } => Vec::new(),
+    }
+}
+
+/// Reverts the latest operation in the undo log. Each step is reverted in its
+/// own transaction, so a `delete_all` comes back batch by batch.
+#[instrument(name = "SledStorage::undo", skip_all)]
+pub(super) fn undo_last_operation(
+    user_id: UserId,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+    settings: &SledConfig,
+) -> Result<(UndoResult, Vec<TodoId>), StorageError> {
+    info!(user_id = %user_id, "undo last todo operation");
+
+    let undo_tree = &trees.undo;
+    let at = chrono::Utc::now().timestamp();
+
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::undo", || {
+            let Some((_, latest)) = latest_record(&user_id, undo_tree, bincode_config)? else {
+                info!("undo log is empty");
+                return Err(SledStorageError::NotFound);
+            };
+            let op = latest.op;
+            let mut todo_ids = Vec::new();
+
+            while let Some((key, record)) = latest_record(&user_id, undo_tree, bincode_config)? {
+                if record.op != op {
+                    break;
+                }
+
+                todo_ids.extend(trees.transaction(&user_id, bincode_config, vault, |tx| {
+                    // Someone else reverted this step in the meantime
+                    if tx.undo.remove(key.as_bytes())?.is_none() {
+                        return Ok(Vec::new());
+                    }
+                    let reverted = trace_err!(
+                        revert_step_in_transaction(
+                            &user_id,
+                            &record.step,
+                            at,
+                            tx,
+                            bincode_config,
+                            vault
+                        ),
+                        "failed to revert undo step"
+                    )?;
+                    Ok(reverted)
+                })?);
+
+                for todo_id in created_todos(&record.step) {
+                    trace_err!(
+                        remove_todo_history(
+                            &user_id,
+                            &todo_id,
+                            &trees.history,
+                            bincode_config,
+                            settings
+                        ),
+                        "failed to remove history of undone todo"
+                    )?;
+                }
+            }
+
+            let result = UndoResult {
+                operation: latest.operation,
+                count: todo_ids.len(),
+            };
+            Ok((result, todo_ids))
+        });
+
+    Ok(result?)
+}
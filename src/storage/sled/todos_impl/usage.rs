@@ -0,0 +1,107 @@
+use sled::{transaction::TransactionalTree, Tree};
+use tracing::info;
+
+use super::TodoTrees;
+use crate::config::types::LimitsConfig;
+use crate::storage::sled::error::SledStorageError;
+use crate::storage::sled::internal::{
+    span_wrappers::{
+        get_value_in_transaction_with_span, insert_value_in_transaction_with_span,
+        serialize_in_transaction_with_span,
+    },
+    Key, KeyPrefix, PrefixKind,
+};
+use crate::storage::sled::{
+    key_todo_id, usage_key, BincodeConfig, FromBytesWithConfig, ToBytesWithConfig, Vault,
+};
+use crate::storage::{TodoVersion, Usage, UsageVersion, UserId};
+
+/// Counts the todos of a user who has no usage record yet, because the todos
+/// were written before the counts existed or restored from an older backup.
+/// Transactions don't run without the record, so none of them can commit a
+/// change the count misses.
+pub(super) fn count_usage(
+    user_id: &UserId,
+    trees: &TodoTrees,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    let key = usage_key(user_id);
+    if trees.sync.contains_key(key.as_bytes())? {
+        return Ok(());
+    }
+
+    let usage = counted_usage(user_id, &trees.todo, bincode_config, vault)?;
+    let encoded = vault.seal_usage(user_id, usage)?.to_bytes(bincode_config)?;
+    // losing the race is fine, the other count saw the same todos
+    let counted =
+        trees
+            .sync
+            .compare_and_swap(key.as_bytes(), None as Option<&[u8]>, Some(encoded))?;
+    if counted.is_ok() {
+        info!(user_id = %user_id, "counted todo usage");
+    }
+    Ok(())
+}
+
+/// Usage of the todos a user has now, read outside of a transaction.
+pub(in crate::storage::sled) fn counted_usage(
+    user_id: &UserId,
+    todo_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Usage, SledStorageError> {
+    let mut usage = Usage::default();
+    let prefix = KeyPrefix::new(PrefixKind::Todo, user_id);
+    for entry in todo_tree.scan_prefix(prefix.as_bytes()) {
+        let (key, value) = entry?;
+        let todo_id = key_todo_id(&Key::from_ivec(key)?)?;
+        let todo = TodoVersion::from_bytes(&value, bincode_config)?;
+        usage.add(&vault.open_todo(user_id, &todo_id, todo)?);
+    }
+    Ok(usage)
+}
+
+pub(super) fn get_usage_in_transaction(
+    user_id: &UserId,
+    sync_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<Usage, SledStorageError> {
+    let Some(value) = get_value_in_transaction_with_span(&usage_key(user_id), sync_tx)? else {
+        // the user was removed since the usage was counted
+        tracing::error!(user_id = %user_id, "failed to find todo usage");
+        return Err(SledStorageError::NotFound);
+    };
+    vault.open_usage(user_id, UsageVersion::from_bytes(&value, bincode_config)?)
+}
+
+/// Stores the usage a transaction leaves, unless it grew past a limit. Usage
+/// over a limit that was lowered can still shrink.
+pub(super) fn put_usage_in_transaction(
+    user_id: &UserId,
+    usage: Usage,
+    before: &Usage,
+    limits: &LimitsConfig,
+    sync_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    let exceeds = |used: usize, before: usize, max: usize| used > max && used > before;
+    if exceeds(usage.todos, before.todos, limits.max_todos) {
+        return Err(SledStorageError::QuotaExceeded {
+            quota: "todos",
+            max: limits.max_todos,
+        });
+    }
+    if exceeds(usage.groups.len(), before.groups.len(), limits.max_groups) {
+        return Err(SledStorageError::QuotaExceeded {
+            quota: "groups",
+            max: limits.max_groups,
+        });
+    }
+
+    let encoded =
+        serialize_in_transaction_with_span(bincode_config, &vault.seal_usage(user_id, usage)?)?;
+    insert_value_in_transaction_with_span(&usage_key(user_id), &encoded, sync_tx)
+}
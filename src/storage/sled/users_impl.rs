@@ -5,19 +5,24 @@ use crate::utils::measure_metrics::measure_and_record_storage;
 
 use super::error::SledStorageError;
 use super::internal::span_wrappers::remove_value_in_transaction_with_span;
-use super::internal::TreeScan;
+use super::internal::{for_each_page, TreeScan};
 use super::internal::{
     span_wrappers::{
         deserialize_in_span, deserialize_in_transaction_with_span,
         get_value_in_transaction_with_span, get_value_with_span,
-        insert_value_in_transaction_with_span, remove_batch_in_transaction_with_span,
-        serialize_in_transaction_with_span,
+        insert_value_in_transaction_with_span, serialize_in_transaction_with_span,
     },
     Key, KeyPrefix, PrefixKind,
 };
-use super::{email_key, BincodeConfig, SledStorage};
-use super::{user_key, FromBytesWithConfig};
-use super::{StorageError, User, UserStorage};
+use super::todos_impl::{
+    remove_todos_in_transaction, remove_user_history, remove_user_sync_log, remove_user_trash,
+    remove_user_undo_log, StoredTodo,
+};
+use super::webhooks_impl::{enqueue_in_transaction, remove_user_webhooks};
+use super::{calendar_token_key, calendar_user_key, BincodeConfig, SledStorage, Vault};
+use super::{user_key, FromBytesWithConfig, ToBytesWithConfig};
+use super::{CalendarToken, StorageError, User, UserStorage, UserVersion};
+use crate::storage::{WebhookData, WebhookEventKind};
 use crate::trace_err;
 use async_trait::async_trait;
 use sled::transaction::ConflictableTransactionError;
@@ -29,19 +34,29 @@ impl UserStorage for SledStorage {
     #[instrument(name = "SledStorage::create_user", skip_all)]
     async fn put(&self, user_id: UserId, user: User) -> Result<(), StorageError> {
         // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
-        let (user_tree, email_tree, bincode_config) = info_span!("Cloning trees and config")
-            .in_scope(|| {
+        let (user_tree, email_tree, outbox_tree, bincode_config, vault) =
+            info_span!("Cloning trees and config").in_scope(|| {
                 (
                     self.user_tree.clone(),
                     self.email_tree.clone(),
+                    self.outbox_tree.clone(),
                     self.bincode_config,
+                    self.vault.clone(),
                 )
             });
 
         let span = Span::current();
         tokio::task::spawn_blocking(move || {
             let _guard = BlockingTaskGuard::new("add_new_user");
-            span.in_scope(|| add_user(user_id, user, &user_tree, &email_tree, &bincode_config))
+            span.in_scope(|| {
+                add_user(
+                    user_id,
+                    user,
+                    (&user_tree, &email_tree, &outbox_tree),
+                    &bincode_config,
+                    &vault,
+                )
+            })
         })
         .await?
     }
@@ -52,16 +67,35 @@ impl UserStorage for SledStorage {
 
         let result: Result<User, SledStorageError> =
             measure_and_record_storage("SledStorage::get_user_by_email", || {
-                let key = email_key(email);
-                let value = trace_err!(
-                    get_value_with_span(&key, &self.email_tree),
-                    "failed to read user from emails tree"
+                let keys = trace_err!(
+                    self.vault.email_keys(email),
+                    "failed to derive email index keys"
+                )?;
+                let mut entry = None;
+                for key in keys {
+                    entry = trace_err!(
+                        self.email_tree.get(key.as_bytes()),
+                        "failed to read user id from emails tree"
+                    )?;
+                    if entry.is_some() {
+                        break;
+                    }
+                }
+                let value = entry.ok_or(SledStorageError::NotFound)?;
+                let user_id = trace_err!(
+                    UserId::from_bytes(&value, &self.bincode_config),
+                    "failed to bin decode user id"
                 )?;
 
-                trace_err!(
-                    deserialize_in_span(&self.bincode_config, &value),
+                let value = trace_err!(
+                    get_value_with_span(&user_key(&user_id), &self.user_tree),
+                    "failed to read user from users tree"
+                )?;
+                let user = trace_err!(
+                    deserialize_in_span::<UserVersion>(&self.bincode_config, &value),
                     "failed to bin decode user"
-                )
+                )?;
+                trace_err!(self.vault.open_user(user), "failed to open sealed user")
             });
 
         Ok(result?)
@@ -79,10 +113,11 @@ impl UserStorage for SledStorage {
                     "failed to read user from users tree"
                 )?;
 
-                trace_err!(
-                    deserialize_in_span(&self.bincode_config, &value),
+                let user = trace_err!(
+                    deserialize_in_span::<UserVersion>(&self.bincode_config, &value),
                     "failed to bin decode user"
-                )
+                )?;
+                trace_err!(self.vault.open_user(user), "failed to open sealed user")
             });
 
         Ok(result?)
@@ -92,8 +127,9 @@ impl UserStorage for SledStorage {
     async fn delete(&self, user_id: UserId) -> Result<(), StorageError> {
         info!(user_id = %user_id, "delete user");
 
-        let result: Result<_, SledStorageError> =
-            measure_and_record_storage("SledStorage::delete_user", || {
+        let result: Result<_, SledStorageError> = measure_and_record_storage(
+            "SledStorage::delete_user",
+            || {
                 {
                     let _ = info_span!("remove user and todos in transaction", user_id = ?user_id)
                         .entered();
@@ -108,56 +144,96 @@ impl UserStorage for SledStorage {
                         return Err(SledStorageError::NoContent);
                     }
 
-                    let mut after: Option<Key> = None;
-                    loop {
-                        let after_key = after.as_ref().map_or(&first_key, |v| v);
-
-                        let mut page = trace_err!(
-                            TreeScan::scan_from(&self.todo_tree, after_key)
-                                .within(todos_key_prefix.clone())
-                                .with_pagination(Pagination {
-                                    after: after.clone(),
-                                    limit: self.storage_settings.delete_batch_size,
-                                })
-                                .collect(&self.bincode_config, |key, _, _| Ok(key.clone()), None),
-                            "failed to do tree scan to get page of users todo to delete"
-                        )?;
-
-                        // Delete cursor from prev iteration
-                        if let Some(prev_cursor) = after {
-                            page.items.insert(0, prev_cursor);
-                        }
-                        // Keep cursor for next iteration
-                        if page.next_cursor.is_some() {
-                            page.items.pop();
-                        }
-                        (&self.user_tree, &self.email_tree, &self.todo_tree).transaction(
-                            |(user_tree, email_tree, todo_tree)| {
-                                trace_err!(
-                                    remove_batch_in_transaction_with_span(&page.items, todo_tree),
-                                    "failed to remove page of user todo-s"
-                                )?;
-
-                                if page.next_cursor.is_none() {
-                                    trace_err!(
-                                        self.remove_user_and_email(
-                                            user_tree, email_tree, &user_key
-                                        ),
-                                        "failed to remove user records in users and emails trees"
+                    trace_err!(
+                        remove_user_trash(
+                            &user_id,
+                            &self.trash_tree,
+                            &self.bincode_config,
+                            &self.storage_settings
+                        ),
+                        "failed to remove users trashed todo-s"
+                    )?;
+
+                    trace_err!(
+                        remove_user_history(
+                            &user_id,
+                            &self.history_tree,
+                            &self.bincode_config,
+                            &self.storage_settings
+                        ),
+                        "failed to remove users todo history"
+                    )?;
+
+                    trace_err!(
+                        remove_user_undo_log(&user_id, &self.undo_tree),
+                        "failed to remove users undo log"
+                    )?;
+
+                    trace_err!(
+                        remove_user_sync_log(&user_id, &self.sync_tree),
+                        "failed to remove users sync log"
+                    )?;
+
+                    trace_err!(
+                        remove_calendar_token(&user_id, &self.calendar_tree, &self.bincode_config),
+                        "failed to remove users calendar token"
+                    )?;
+
+                    trace_err!(
+                        remove_user_webhooks(&user_id, &self.webhook_tree),
+                        "failed to remove users webhooks"
+                    )?;
+
+                    trace_err!(
+                        for_each_page(
+                            &self.todo_tree,
+                            &first_key,
+                            &todos_key_prefix,
+                            self.storage_settings.delete_batch_size,
+                            &self.bincode_config,
+                            |key, bytes, config| StoredTodo::from_bytes(
+                                key,
+                                bytes,
+                                config,
+                                &self.vault
+                            ),
+                            |page, is_last| {
+                                (
+                                    &self.user_tree,
+                                    &self.email_tree,
+                                    &self.todo_tree,
+                                    &self.link_tree,
+                                    &self.outbox_tree,
+                                )
+                                    .transaction(
+                                        |(user_tree, email_tree, todo_tree, link_tree, outbox_tree)| {
+                                            trace_err!(
+                                                remove_todos_in_transaction(
+                                                    &user_id, page, todo_tree, link_tree
+                                                ),
+                                                "failed to remove page of user todo-s"
+                                            )?;
+
+                                            if is_last {
+                                                trace_err!(
+                                                    self.remove_user_and_email(
+                                                        user_tree, email_tree, outbox_tree, &user_key
+                                                    ),
+                                                    "failed to remove user records in users and emails trees"
+                                                )?;
+                                            }
+                                            Ok(())
+                                        },
                                     )?;
-                                }
                                 Ok(())
-                            },
-                        )?;
-
-                        match page.next_cursor {
-                            Some(cursor) => after = Some(cursor),
-                            None => break,
-                        }
-                    }
+                            }
+                        ),
+                        "failed to do tree scan to get page of users todo to delete"
+                    )?;
                 }
                 Ok(())
-            });
+            },
+        );
 
         Ok(result?)
     }
@@ -165,7 +241,7 @@ impl UserStorage for SledStorage {
     #[instrument(name = "SledStorage::get_users", skip_all)]
     async fn get_all(
         &self,
-        user_id: UserId,
+        exclude: Option<UserId>,
         pagination: Pagination<UserId>,
     ) -> Result<(Vec<User>, Option<UserId>), StorageError> {
         info!(pagination = ?pagination, "get all users");
@@ -179,14 +255,17 @@ impl UserStorage for SledStorage {
 
                 let page = info_span!("TreeScan::scan_from::within::until_pagination::collect")
                     .in_scope(|| {
-                        let user_filter = |user: &User| user.id != user_id;
+                        let user_filter = |user: &User| Some(user.id) != exclude;
                         trace_err!(
                             TreeScan::scan_from(&self.user_tree, &after_key)
                                 .within(KeyPrefix::from_kind(PrefixKind::User))
                                 .with_pagination(pagination)
                                 .collect(
                                     &self.bincode_config,
-                                    |_, bytes, config| User::from_bytes(bytes, config),
+                                    |_, bytes, config| {
+                                        self.vault
+                                            .open_user(UserVersion::from_bytes(bytes, config)?)
+                                    },
                                     Some(&user_filter),
                                 ),
                             "failed to do tree scan to get page of users"
@@ -201,12 +280,13 @@ impl UserStorage for SledStorage {
     #[instrument(name = "SledStorage::change_user_role", skip_all)]
     async fn update_role(&self, user_id: UserId, role: Role) -> Result<(), StorageError> {
         // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
-        let (user_tree, email_tree, bincode_config) = info_span!("Cloning trees and config")
-            .in_scope(|| {
+        let (user_tree, outbox_tree, bincode_config, vault) =
+            info_span!("Cloning trees and config").in_scope(|| {
                 (
                     self.user_tree.clone(),
-                    self.email_tree.clone(),
+                    self.outbox_tree.clone(),
                     self.bincode_config,
+                    self.vault.clone(),
                 )
             });
 
@@ -214,7 +294,67 @@ impl UserStorage for SledStorage {
         tokio::task::spawn_blocking(move || {
             let _guard = BlockingTaskGuard::new("update_user_role");
             span.in_scope(|| {
-                update_user_role(user_id, role, &user_tree, &email_tree, &bincode_config)
+                update_user_role(
+                    user_id,
+                    role,
+                    (&user_tree, &outbox_tree),
+                    &bincode_config,
+                    &vault,
+                )
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::put_calendar_token", skip_all)]
+    async fn put_calendar_token(&self, token: CalendarToken) -> Result<(), StorageError> {
+        let (calendar_tree, bincode_config) = info_span!("Cloning trees and config")
+            .in_scope(|| (self.calendar_tree.clone(), self.bincode_config));
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("put_calendar_token");
+            span.in_scope(|| put_calendar_token(token, &calendar_tree, &bincode_config))
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::get_calendar_token", skip_all)]
+    async fn get_calendar_token(&self, token_hash: &str) -> Result<CalendarToken, StorageError> {
+        let result: Result<CalendarToken, SledStorageError> =
+            measure_and_record_storage("SledStorage::get_calendar_token", || {
+                let value = trace_err!(
+                    get_value_with_span(&calendar_token_key(token_hash)?, &self.calendar_tree),
+                    "failed to read calendar token"
+                )?;
+
+                trace_err!(
+                    deserialize_in_span(&self.bincode_config, &value),
+                    "failed to bin decode calendar token"
+                )
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::delete_calendar_token", skip_all)]
+    async fn delete_calendar_token(&self, user_id: UserId) -> Result<(), StorageError> {
+        info!(user_id = %user_id, "delete calendar token");
+
+        let (calendar_tree, bincode_config) = info_span!("Cloning trees and config")
+            .in_scope(|| (self.calendar_tree.clone(), self.bincode_config));
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("delete_calendar_token");
+            span.in_scope(|| {
+                measure_and_record_storage("SledStorage::delete_calendar_token", || {
+                    if remove_calendar_token(&user_id, &calendar_tree, &bincode_config)? {
+                        Ok(())
+                    } else {
+                        Err(SledStorageError::NoContent.into())
+                    }
+                })
             })
         })
         .await?
@@ -227,18 +367,35 @@ impl SledStorage {
         &self,
         user_tree: &sled::transaction::TransactionalTree,
         email_tree: &sled::transaction::TransactionalTree,
+        outbox_tree: &sled::transaction::TransactionalTree,
         user_key: &Key,
     ) -> Result<(), SledStorageError> {
         info!(key = %user_key, "remove user record with user_id and email keys");
 
         let value = get_value_in_transaction_with_span(user_key, user_tree)?;
         if let Some(value) = value {
-            let user: User = deserialize_in_transaction_with_span(&self.bincode_config, &value)?;
-
-            let email_key = email_key(&user.email);
+            let user: UserVersion =
+                deserialize_in_transaction_with_span(&self.bincode_config, &value)?;
+            let user = self.vault.open_user(user)?;
 
             remove_value_in_transaction_with_span(user_key, user_tree)?;
-            remove_value_in_transaction_with_span(&email_key, email_tree)?;
+            // only the index entries that point at this user
+            for email_key in self.vault.email_keys(&user.email)? {
+                let Some(entry) = get_value_in_transaction_with_span(&email_key, email_tree)?
+                else {
+                    continue;
+                };
+                if UserId::from_bytes(&entry, &self.bincode_config)? == user.id {
+                    remove_value_in_transaction_with_span(&email_key, email_tree)?;
+                }
+            }
+            enqueue_user_event_in_transaction(
+                WebhookEventKind::UserDeleted,
+                &user,
+                outbox_tree,
+                &self.bincode_config,
+                &self.vault,
+            )?;
         }
 
         Ok(())
@@ -249,20 +406,26 @@ impl SledStorage {
 fn add_user(
     user_id: UserId,
     user: User,
-    user_tree: &Tree,
-    email_tree: &Tree,
+    trees: (&Tree, &Tree, &Tree),
     bincode_config: &BincodeConfig,
+    vault: &Vault,
 ) -> Result<(), StorageError> {
     info!(user_id = %user_id, user = ?user, "create user");
 
     measure_and_record_storage("SledStorage::add_new_user", || {
         let key_user_id = user_key(&user_id);
-        let key_email = email_key(&user.email);
 
         info_span!("sled::add_new_user_in_transaction", user = ?user).in_scope(|| {
-            (user_tree, email_tree).transaction(|(users_tx, emails_tx)| {
+            trees.transaction(|(users_tx, emails_tx, outbox_tx)| {
+                let key_email = trace_err!(
+                    vault.email_key(&user.email),
+                    "failed to derive email index key"
+                )?;
                 let encoded: Vec<u8> = trace_err!(
-                    serialize_in_transaction_with_span(bincode_config, &user),
+                    serialize_in_transaction_with_span(
+                        bincode_config,
+                        &vault.seal_user(user.clone())?
+                    ),
                     "failed to bin encode user"
                 )?;
 
@@ -270,9 +433,23 @@ fn add_user(
                     insert_value_in_transaction_with_span(&key_user_id, &encoded, users_tx),
                     "failed to insert user record into users tree"
                 )?;
+                let encoded_id = trace_err!(
+                    user_id.to_bytes(bincode_config),
+                    "failed to bin encode user id"
+                )?;
+                trace_err!(
+                    insert_value_in_transaction_with_span(&key_email, &encoded_id, emails_tx),
+                    "failed to insert user id into emails tree"
+                )?;
                 trace_err!(
-                    insert_value_in_transaction_with_span(&key_email, &encoded, emails_tx),
-                    "failed to insert user record into emails tree"
+                    enqueue_user_event_in_transaction(
+                        WebhookEventKind::UserCreated,
+                        &user,
+                        outbox_tx,
+                        bincode_config,
+                        vault
+                    ),
+                    "failed to write webhook event"
                 )?;
 
                 Ok(())
@@ -283,46 +460,124 @@ fn add_user(
     Ok(())
 }
 
+#[instrument(name = "SledStorage::put_calendar_token", skip_all)]
+fn put_calendar_token(
+    token: CalendarToken,
+    calendar_tree: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<(), StorageError> {
+    info!(user_id = %token.user_id, "put calendar token");
+    let token_key = calendar_token_key(&token.token_hash)?;
+
+    measure_and_record_storage("SledStorage::put_calendar_token", || {
+        let user_key = calendar_user_key(&token.user_id);
+
+        calendar_tree.transaction(|calendar_tx| {
+            if let Some(value) = get_value_in_transaction_with_span(&user_key, calendar_tx)? {
+                let previous: CalendarToken = trace_err!(
+                    deserialize_in_transaction_with_span(bincode_config, &value),
+                    "failed to bin decode calendar token"
+                )?;
+                remove_value_in_transaction_with_span(
+                    &calendar_token_key(&previous.token_hash)?,
+                    calendar_tx,
+                )?;
+            }
+
+            let encoded = trace_err!(
+                serialize_in_transaction_with_span(bincode_config, &token),
+                "failed to bin encode calendar token"
+            )?;
+            insert_value_in_transaction_with_span(&user_key, &encoded, calendar_tx)?;
+            insert_value_in_transaction_with_span(&token_key, &encoded, calendar_tx)?;
+
+            Ok(())
+        })
+    })
+    .map_err(SledStorageError::from)?;
+    Ok(())
+}
+
+/// Returns whether the user had a calendar token.
+#[instrument(name = "SledStorage::remove_calendar_token", skip_all)]
+fn remove_calendar_token(
+    user_id: &UserId,
+    calendar_tree: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<bool, SledStorageError> {
+    let user_key = calendar_user_key(user_id);
+
+    let removed = calendar_tree
+        .transaction(|calendar_tx| {
+            let Some(value) = get_value_in_transaction_with_span(&user_key, calendar_tx)? else {
+                return Ok(false);
+            };
+            let token: CalendarToken = trace_err!(
+                deserialize_in_transaction_with_span(bincode_config, &value),
+                "failed to bin decode calendar token"
+            )?;
+
+            remove_value_in_transaction_with_span(&user_key, calendar_tx)?;
+            remove_value_in_transaction_with_span(
+                &calendar_token_key(&token.token_hash)?,
+                calendar_tx,
+            )?;
+            Ok(true)
+        })
+        .map_err(SledStorageError::from)?;
+
+    Ok(removed)
+}
+
 #[instrument(name = "SledStorage::update_user_role", skip_all)]
 fn update_user_role(
     user_id: UserId,
     role: Role,
-    user_tree: &Tree,
-    email_tree: &Tree,
+    trees: (&Tree, &Tree),
     bincode_config: &BincodeConfig,
+    vault: &Vault,
 ) -> Result<(), StorageError> {
     info!(user_id = %user_id, role = ?role, "update user role");
 
     measure_and_record_storage("SledStorage::change_user_role", || {
         let key = user_key(&user_id);
         info_span!("change role in transaction").in_scope(|| {
-            (user_tree, email_tree).transaction(|(user_tx, email_tx)| {
+            trees.transaction(|(user_tx, outbox_tx)| {
                 let value = trace_err!(
                     get_value_in_transaction_with_span(&key, user_tx),
                     "failed to read user from users tree"
                 )?;
 
                 if let Some(value) = value {
-                    let mut user: User = trace_err!(
+                    let user: UserVersion = trace_err!(
                         deserialize_in_transaction_with_span(bincode_config, &value),
                         "failed to bin decode user"
                     )?;
+                    let mut user = trace_err!(vault.open_user(user), "failed to open sealed user")?;
 
                     user.role = role;
 
                     let encode = trace_err!(
-                        serialize_in_transaction_with_span(bincode_config, &user),
+                        serialize_in_transaction_with_span(
+                            bincode_config,
+                            &vault.seal_user(user.clone())?
+                        ),
                         "failed to bin encode user"
                     )?;
-                    let email_key = email_key(&user.email);
 
                     trace_err!(
                         insert_value_in_transaction_with_span(&key, &encode, user_tx),
                         "failed to insert user in users tree"
                     )?;
                     trace_err!(
-                        insert_value_in_transaction_with_span(&email_key, &encode, email_tx),
-                        "failed to insert user in emails tree"
+                        enqueue_user_event_in_transaction(
+                            WebhookEventKind::UserRoleChanged,
+                            &user,
+                            outbox_tx,
+                            bincode_config,
+                            vault
+                        ),
+                        "failed to write webhook event"
                     )?;
 
                     Ok(())
@@ -340,5 +595,21 @@ fn update_user_role(
     Ok(())
 }
 
+fn enqueue_user_event_in_transaction(
+    kind: WebhookEventKind,
+    user: &User,
+    outbox_tx: &sled::transaction::TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    let data = WebhookData::User {
+        id: user.id,
+        email: user.email.clone(),
+        role: user.role,
+    };
+    let at = chrono::Utc::now().timestamp();
+    enqueue_in_transaction(&user.id, kind, data, at, outbox_tx, bincode_config, vault)
+}
+
 #[cfg(test)]
 mod tests;
@@ -17,7 +17,7 @@ async fn test_get_users_first_page() {
     let limit = 10;
 
     let (users, cursor) = storage
-        .get_all(ADMIN_UUID.into(), Pagination { after: None, limit })
+        .get_all(Some(ADMIN_UUID.into()), Pagination { after: None, limit })
         .await
         .unwrap();
 
@@ -33,13 +33,13 @@ async fn test_get_users_last_page() {
     let limit = 10;
 
     let (_, cursor) = storage
-        .get_all(ADMIN_UUID.into(), Pagination { after: None, limit })
+        .get_all(Some(ADMIN_UUID.into()), Pagination { after: None, limit })
         .await
         .unwrap();
 
     let (users, cursor) = storage
         .get_all(
-            ADMIN_UUID.into(),
+            Some(ADMIN_UUID.into()),
             Pagination {
                 after: cursor,
                 limit,
@@ -169,3 +169,101 @@ async fn test_delete_user() {
     assert_eq!(next, None);
     assert_eq!(items.len(), 0);
 }
+
+#[tokio::test]
+async fn test_calendar_token() {
+    let builder = TestStorageBuilder::new();
+    let user_storage = builder.build_user().await;
+    let user_id = UserId::from(ADMIN_UUID);
+
+    let first = CalendarToken::new(user_id, "first");
+    user_storage
+        .put_calendar_token(first.clone())
+        .await
+        .unwrap();
+    let token = user_storage
+        .get_calendar_token(&CalendarToken::hash("first"))
+        .await
+        .unwrap();
+    assert_eq!(token, first);
+
+    // a new token revokes the previous one
+    let second = CalendarToken::new(user_id, "second");
+    user_storage.put_calendar_token(second).await.unwrap();
+    let result = user_storage.get_calendar_token(&first.token_hash).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+    let token = user_storage
+        .get_calendar_token(&CalendarToken::hash("second"))
+        .await
+        .unwrap();
+    assert_eq!(token.user_id, user_id);
+
+    user_storage.delete_calendar_token(user_id).await.unwrap();
+    let result = user_storage
+        .get_calendar_token(&CalendarToken::hash("second"))
+        .await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+
+    let result = user_storage.delete_calendar_token(user_id).await;
+    assert!(matches!(result, Err(StorageError::NoContent)));
+}
+
+#[tokio::test]
+async fn test_delete_user_purges_trash() {
+    let limit = 20;
+    let builder = TestStorageBuilder::new().with_todos(15);
+    let todos = builder.todos();
+    let todo_storage = builder.build_todo().await;
+    let user_storage = builder.build_user().await;
+
+    let new_user = User {
+        id: ADMIN_UUID.into(),
+        email: "aaa@gmail.com".to_string(),
+        hashed_password: create_password_hash("password", &test_settings().auth)
+            .await
+            .unwrap(),
+        role: Role::User,
+    };
+    user_storage.put(ADMIN_UUID.into(), new_user).await.unwrap();
+
+    for todo in &todos[..12] {
+        todo_storage
+            .delete(ADMIN_UUID.into(), todo.id)
+            .await
+            .unwrap();
+    }
+
+    user_storage.delete(ADMIN_UUID.into()).await.unwrap();
+
+    let (items, next) = todo_storage
+        .get_trash(ADMIN_UUID.into(), Pagination { after: None, limit })
+        .await
+        .unwrap();
+
+    assert_eq!(next, None);
+    assert_eq!(items.len(), 0);
+}
+
+#[tokio::test]
+async fn test_email_index_points_at_user_id() {
+    let storage = TestStorageBuilder::new().build_user().await;
+
+    let user_id = UserId::new();
+    let new_user = User {
+        id: user_id,
+        email: "Mixed.Case@Gmail.com".to_string(),
+        hashed_password: create_password_hash("password", &test_settings().auth)
+            .await
+            .unwrap(),
+        role: Role::User,
+    };
+    storage.put(user_id, new_user.clone()).await.unwrap();
+
+    let user = storage.get_by_email(" mixed.case@gmail.com").await.unwrap();
+    assert_eq!(user, new_user);
+
+    storage.update_role(user_id, Role::Admin).await.unwrap();
+    let user = storage.get_by_email("MIXED.CASE@GMAIL.COM").await.unwrap();
+    assert_eq!(user.role, Role::Admin);
+    assert_eq!(user.email, new_user.email);
+}
@@ -0,0 +1,788 @@
+//! Envelope encryption of stored content. Every user gets data keys that
+//! seal the text of their todos and, optionally, their email. The data keys
+//! are stored in their own tree, each wrapped with the master key from the
+//! config. Rotating adds a new generation of data keys, records name the
+//! generation they were sealed with so older ones stay readable until every
+//! record moved on. Sealed values are bound to the record they belong to, one
+//! copied to another record fails to open.
+
+use std::sync::{Arc, RwLock};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bincode::{Decode, Encode};
+use dashmap::DashMap;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM},
+    digest, hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use sled::Tree;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use super::{
+    email_key,
+    error::{SledStartupError, SledStorageError},
+    internal::{display_key, Key, KeyPrefix, PrefixKind},
+    normalized_email, BincodeConfig, BINCODE_CONFIG,
+};
+use crate::{
+    config::types::EncryptionConfig,
+    storage::{
+        FieldChange, HistoryEntry, HistorySeq, HistoryVersion, Sealed, SealedRecord, Todo, TodoId,
+        TodoVersion, UndoStep, Usage, UsageVersion, User, UserId, UserVersion, WebhookData,
+        WebhookEvent, NONCE_LEN,
+    },
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Env variable holding the base64 encoded master key, takes precedence over
+/// `master_key_file`.
+pub(crate) const MASTER_KEY_ENV: &str = "STORAGE_MASTER_KEY";
+
+const KEY_LEN: usize = 32;
+const FINGERPRINT_LEN: usize = 8;
+
+/// Seals and opens stored content, does nothing when encryption is not
+/// configured. Cheap to clone.
+#[derive(Clone, Default)]
+pub(crate) struct Vault {
+    keys: Option<Arc<Keys>>,
+}
+
+struct MasterKey {
+    /// Start of the SHA-256 of the key, tells which master wrapped a data key.
+    fingerprint: [u8; FINGERPRINT_LEN],
+    key: LessSafeKey,
+}
+
+struct Keys {
+    master: MasterKey,
+    previous: Vec<MasterKey>,
+    seal_emails: bool,
+    tree: Tree,
+    opened: DashMap<(UserId, u64), LessSafeKey>,
+    newest: DashMap<UserId, u64>,
+    email_index: RwLock<Option<hmac::Key>>,
+    rng: SystemRandom,
+    /// Compresses content before it is sealed, ciphertext doesn't compress.
+    codec: BincodeConfig,
+}
+
+/// Stored data key, sealed with a master key.
+#[derive(Encode, Decode)]
+struct WrappedKey {
+    master: [u8; FINGERPRINT_LEN],
+    nonce: [u8; NONCE_LEN],
+    key: Vec<u8>,
+}
+
+/// The part of a todo that gets sealed.
+#[derive(Encode, Decode)]
+struct TodoContent {
+    text: String,
+    group: String,
+}
+
+/// The record a sealed value belongs to, authenticated along with its owner.
+#[derive(Clone, Copy)]
+enum Record<'a> {
+    /// A todo wherever it is kept: its tree, the trash, undo steps and events.
+    Todo(&'a TodoId),
+    History(&'a TodoId, HistorySeq),
+    Email,
+    Usage,
+}
+
+impl Record<'_> {
+    fn aad(self, owner: &UserId) -> Vec<u8> {
+        let mut aad = Uuid::from(*owner).as_bytes().to_vec();
+        match self {
+            Record::Todo(id) => {
+                aad.extend_from_slice(b"todo");
+                aad.extend_from_slice(Uuid::from(*id).as_bytes());
+            }
+            Record::History(id, seq) => {
+                aad.extend_from_slice(b"history");
+                aad.extend_from_slice(Uuid::from(*id).as_bytes());
+                aad.extend_from_slice(&seq.0.to_be_bytes());
+            }
+            Record::Email => aad.extend_from_slice(b"email"),
+            Record::Usage => aad.extend_from_slice(b"usage"),
+        }
+        aad
+    }
+}
+
+fn data_key_prefix(owner: &UserId) -> KeyPrefix {
+    KeyPrefix::new(PrefixKind::DataKey, owner)
+}
+
+fn data_key_key(owner: &UserId, generation: u64) -> Key {
+    Key::new(data_key_prefix(owner), generation)
+}
+
+// Key of the email index is not bound to a user, it hashes every address.
+fn email_index_key() -> Key {
+    Key::new(KeyPrefix::from_kind(PrefixKind::DataKey), "email_index")
+}
+
+fn blind_email_key(index: &hmac::Key, email: &str) -> Result<Key, SledStorageError> {
+    let tag = hmac::sign(index, normalized_email(email).as_bytes());
+    Key::try_new(KeyPrefix::new(PrefixKind::Email, "blind"), tag)
+}
+
+fn master_key(source: &str, encoded: &str) -> Result<MasterKey, SledStartupError> {
+    let raw = STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| SledStartupError::MasterKey(format!("{source} is not base64: {e}")))?;
+    let key = UnboundKey::new(&AES_256_GCM, &raw).map_err(|_| {
+        SledStartupError::MasterKey(format!("{source} does not hold a {KEY_LEN} byte key"))
+    })?;
+    let mut fingerprint = [0; FINGERPRINT_LEN];
+    fingerprint.copy_from_slice(&digest::digest(&digest::SHA256, &raw).as_ref()[..FINGERPRINT_LEN]);
+
+    Ok(MasterKey {
+        fingerprint,
+        key: LessSafeKey::new(key),
+    })
+}
+
+fn master_key_file(path: &std::path::Path) -> Result<MasterKey, SledStartupError> {
+    let source = path.display().to_string();
+    let encoded = std::fs::read_to_string(path)
+        .map_err(|e| SledStartupError::MasterKey(format!("failed to read {source}: {e}")))?;
+    master_key(&source, &encoded)
+}
+
+impl Vault {
+    /// Loads the master keys and checks that every stored data key is wrapped
+    /// with one of them.
+    #[instrument(name = "Vault::open", skip_all)]
+    pub(crate) fn open(
+        config: Option<&EncryptionConfig>,
+        tree: &Tree,
+        codec: BincodeConfig,
+    ) -> Result<Self, SledStartupError> {
+        let Some(config) = config else {
+            if !tree.is_empty() {
+                return Err(SledStartupError::MasterKey(
+                    "storage holds encrypted data but encryption is not configured".to_owned(),
+                ));
+            }
+            return Ok(Self::default());
+        };
+
+        let master = match (std::env::var(MASTER_KEY_ENV), &config.master_key_file) {
+            (Ok(encoded), _) => master_key(MASTER_KEY_ENV, &encoded)?,
+            (Err(_), Some(path)) => master_key_file(path)?,
+            (Err(_), None) => {
+                return Err(SledStartupError::MasterKey(format!(
+                    "neither {MASTER_KEY_ENV} nor master_key_file is set"
+                )))
+            }
+        };
+        let previous = config
+            .previous_master_key_files
+            .iter()
+            .map(|path| master_key_file(path))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let keys = Keys {
+            master,
+            previous,
+            seal_emails: config.emails,
+            tree: tree.clone(),
+            opened: DashMap::new(),
+            newest: DashMap::new(),
+            email_index: RwLock::new(None),
+            rng: SystemRandom::new(),
+            codec,
+        };
+
+        for entry in tree.iter() {
+            let (key, value) = entry.map_err(SledStartupError::OpenSledStorageError)?;
+            let unknown = || {
+                SledStartupError::MasterKey(format!(
+                    "data key {} is wrapped with an unknown master key",
+                    display_key(&key)
+                ))
+            };
+            let (wrapped, _) = bincode::decode_from_slice::<WrappedKey, _>(&value, BINCODE_CONFIG)
+                .map_err(|_| unknown())?;
+            keys.master_of(&wrapped).ok_or_else(unknown)?;
+        }
+
+        info!(emails = config.emails, "storage encryption enabled");
+        Ok(Self {
+            keys: Some(Arc::new(keys)),
+        })
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    pub(crate) fn seals_emails(&self) -> bool {
+        self.keys.as_ref().is_some_and(|keys| keys.seal_emails)
+    }
+
+    /// Drops cached keys, the data keys tree was replaced.
+    pub(crate) fn forget(&self) {
+        if let Some(keys) = &self.keys {
+            keys.opened.clear();
+            keys.newest.clear();
+            *keys.email_index.write().expect("email index lock poisoned") = None;
+        }
+    }
+
+    pub(crate) fn seal_todo(
+        &self,
+        owner: &UserId,
+        todo: Todo,
+    ) -> Result<TodoVersion, SledStorageError> {
+        let Some(keys) = &self.keys else {
+            return Ok(TodoVersion::from(todo));
+        };
+        let content = TodoContent {
+            text: todo.text,
+            group: todo.group,
+        };
+        Ok(TodoVersion::Sealed {
+            id: todo.id,
+            completed: todo.completed,
+            due: todo.due,
+            content: keys.seal(owner, Record::Todo(&todo.id), &content)?,
+        })
+    }
+
+    /// Opens the todo stored as `todo_id`, a sealed one stored as another
+    /// todo fails with `Unseal`.
+    pub(crate) fn open_todo(
+        &self,
+        owner: &UserId,
+        todo_id: &TodoId,
+        version: TodoVersion,
+    ) -> Result<Todo, SledStorageError> {
+        match (version, &self.keys) {
+            (TodoVersion::Sealed { id, .. }, Some(_)) if id != *todo_id => {
+                Err(SledStorageError::Unseal)
+            }
+            (
+                TodoVersion::Sealed {
+                    id,
+                    completed,
+                    due,
+                    content,
+                },
+                Some(keys),
+            ) => {
+                let content: TodoContent = keys.open(owner, Record::Todo(&id), &content)?;
+                Ok(Todo {
+                    id,
+                    text: content.text,
+                    completed,
+                    group: content.group,
+                    due,
+                })
+            }
+            (version, _) => Ok(Todo::try_from(version)?),
+        }
+    }
+
+    /// Seals a stored todo that is still plain.
+    pub(crate) fn seal_version(
+        &self,
+        owner: &UserId,
+        version: TodoVersion,
+    ) -> Result<TodoVersion, SledStorageError> {
+        match version {
+            TodoVersion::Sealed { .. } => Ok(version),
+            _ if !self.is_enabled() => Ok(version),
+            version => self.seal_todo(owner, Todo::try_from(version)?),
+        }
+    }
+
+    pub(crate) fn seal_usage(
+        &self,
+        owner: &UserId,
+        usage: Usage,
+    ) -> Result<UsageVersion, SledStorageError> {
+        match &self.keys {
+            Some(keys) => Ok(UsageVersion::Sealed(keys.seal(
+                owner,
+                Record::Usage,
+                &usage,
+            )?)),
+            None => Ok(UsageVersion::Plain(usage)),
+        }
+    }
+
+    pub(crate) fn open_usage(
+        &self,
+        owner: &UserId,
+        version: UsageVersion,
+    ) -> Result<Usage, SledStorageError> {
+        match (version, &self.keys) {
+            (UsageVersion::Sealed(sealed), Some(keys)) => keys.open(owner, Record::Usage, &sealed),
+            (UsageVersion::Sealed(_), None) => Err(SealedRecord.into()),
+            (UsageVersion::Plain(usage), _) => Ok(usage),
+        }
+    }
+
+    pub(crate) fn seal_history(
+        &self,
+        owner: &UserId,
+        todo_id: &TodoId,
+        entry: HistoryEntry,
+    ) -> Result<HistoryVersion, SledStorageError> {
+        let Some(keys) = &self.keys else {
+            return Ok(HistoryVersion::from(entry));
+        };
+        Ok(HistoryVersion::Sealed {
+            seq: entry.seq,
+            action: entry.action,
+            actor: entry.actor,
+            at: entry.at,
+            changes: keys.seal(owner, Record::History(todo_id, entry.seq), &entry.changes)?,
+        })
+    }
+
+    pub(crate) fn open_history(
+        &self,
+        owner: &UserId,
+        todo_id: &TodoId,
+        version: HistoryVersion,
+    ) -> Result<HistoryEntry, SledStorageError> {
+        match (version, &self.keys) {
+            (
+                HistoryVersion::Sealed {
+                    seq,
+                    action,
+                    actor,
+                    at,
+                    changes,
+                },
+                Some(keys),
+            ) => Ok(HistoryEntry {
+                seq,
+                action,
+                actor,
+                at,
+                changes: keys.open::<Vec<FieldChange>>(
+                    owner,
+                    Record::History(todo_id, seq),
+                    &changes,
+                )?,
+            }),
+            (version, _) => Ok(HistoryEntry::try_from(version)?),
+        }
+    }
+
+    /// Seals the todos an undo step keeps.
+    pub(crate) fn seal_step(
+        &self,
+        owner: &UserId,
+        step: UndoStep,
+    ) -> Result<UndoStep, SledStorageError> {
+        Ok(match step {
+            UndoStep::Updated { before } => UndoStep::Updated {
+                before: self.seal_version(owner, before)?,
+            },
+            UndoStep::Batch { steps } => UndoStep::Batch {
+                steps: steps
+                    .into_iter()
+                    .map(|step| self.seal_step(owner, step))
+                    .collect::<Result<_, _>>()?,
+            },
+            step => step,
+        })
+    }
+
+    /// Users are sealed with their own data key, only when emails are sealed.
+    pub(crate) fn seal_user(&self, user: User) -> Result<UserVersion, SledStorageError> {
+        match &self.keys {
+            Some(keys) if keys.seal_emails => Ok(UserVersion::Sealed {
+                id: user.id,
+                email: keys.seal(&user.id, Record::Email, &user.email)?,
+                hashed_password: user.hashed_password,
+                role: user.role,
+            }),
+            _ => Ok(UserVersion::from(user)),
+        }
+    }
+
+    pub(crate) fn open_user(&self, version: UserVersion) -> Result<User, SledStorageError> {
+        match (version, &self.keys) {
+            (
+                UserVersion::Sealed {
+                    id,
+                    email,
+                    hashed_password,
+                    role,
+                },
+                Some(keys),
+            ) => Ok(User {
+                id,
+                email: keys.open(&id, Record::Email, &email)?,
+                hashed_password,
+                role,
+            }),
+            (version, _) => Ok(User::try_from(version)?),
+        }
+    }
+
+    /// Seals the data of an outbox event with the key of its user.
+    pub(crate) fn seal_event(&self, event: WebhookEvent) -> Result<WebhookEvent, SledStorageError> {
+        let data = match event.data {
+            WebhookData::Todo(version) => {
+                WebhookData::Todo(self.seal_version(&event.user_id, version)?)
+            }
+            WebhookData::User { id, email, role } => match &self.keys {
+                Some(keys) if keys.seal_emails => WebhookData::SealedUser {
+                    id,
+                    email: keys.seal(&id, Record::Email, &email)?,
+                    role,
+                },
+                _ => WebhookData::User { id, email, role },
+            },
+            data => data,
+        };
+        Ok(WebhookEvent { data, ..event })
+    }
+
+    pub(crate) fn open_event(&self, event: WebhookEvent) -> Result<WebhookEvent, SledStorageError> {
+        let data = match event.data {
+            WebhookData::Todo(version) => {
+                let id = version.id();
+                WebhookData::Todo(self.open_todo(&event.user_id, &id, version)?.into())
+            }
+            WebhookData::SealedUser { id, email, role } => {
+                let keys = self.keys.as_ref().ok_or(SealedRecord)?;
+                WebhookData::User {
+                    id,
+                    email: keys.open(&id, Record::Email, &email)?,
+                    role,
+                }
+            }
+            data => data,
+        };
+        Ok(WebhookEvent { data, ..event })
+    }
+
+    /// Key of the email index entry written for a new user. Sealed emails are
+    /// indexed by a keyed hash, the address itself is never stored in plain.
+    pub(crate) fn email_key(&self, email: &str) -> Result<Key, SledStorageError> {
+        match &self.keys {
+            Some(keys) if keys.seal_emails => {
+                let index = keys.email_index(true)?.ok_or(SledStorageError::NotFound)?;
+                blind_email_key(&index, email)
+            }
+            _ => email_key(email),
+        }
+    }
+
+    /// Keys an email may be indexed under, the one `email_key` writes first.
+    /// Entries written before emails were sealed (or after they stopped being
+    /// sealed) stay reachable until `rotate-keys` rewrites the index.
+    pub(crate) fn email_keys(&self, email: &str) -> Result<Vec<Key>, SledStorageError> {
+        let blind = match &self.keys {
+            Some(keys) => keys
+                .email_index(false)?
+                .map(|index| blind_email_key(&index, email))
+                .transpose()?,
+            None => None,
+        };
+        let plain = email_key(email)?;
+        Ok(match blind {
+            Some(blind) if self.seals_emails() => vec![blind, plain],
+            Some(blind) => vec![plain, blind],
+            None => vec![plain],
+        })
+    }
+
+    /// Users that have data keys.
+    pub(super) fn owners(&self) -> Result<Vec<UserId>, SledStorageError> {
+        let Some(keys) = &self.keys else {
+            return Ok(Vec::new());
+        };
+        let mut owners: Vec<UserId> = Vec::new();
+        for key in keys.tree.iter().keys() {
+            let owner = Key::from_ivec(key?)?.id_segment(0);
+            if let Some(owner) = owner.filter(|owner| owners.last() != Some(owner)) {
+                owners.push(owner);
+            }
+        }
+        Ok(owners)
+    }
+
+    /// Adds a data key generation, new records of the owner are sealed with it.
+    pub(super) fn add_generation(&self, owner: &UserId) -> Result<u64, SledStorageError> {
+        let keys = self.keys.as_ref().ok_or(SealedRecord)?;
+        let generation = keys.stored_newest(owner)?.unwrap_or(0) + 1;
+        keys.create(owner, generation)?;
+        keys.set_newest(owner, generation);
+        Ok(generation)
+    }
+
+    /// Whether the value is sealed with the newest data key of the owner.
+    pub(super) fn is_current(
+        &self,
+        owner: &UserId,
+        sealed: &Sealed,
+    ) -> Result<bool, SledStorageError> {
+        match &self.keys {
+            Some(keys) => Ok(keys.newest(owner)?.0 == sealed.generation),
+            None => Ok(false),
+        }
+    }
+
+    /// Removes the data keys of the owner older than the newest one, returns
+    /// how many were removed.
+    pub(super) fn retire(&self, owner: &UserId) -> Result<usize, SledStorageError> {
+        let Some(keys) = &self.keys else {
+            return Ok(0);
+        };
+        let (newest, _) = keys.newest(owner)?;
+        let mut removed = 0;
+        for key in keys
+            .tree
+            .scan_prefix(data_key_prefix(owner).as_bytes())
+            .keys()
+        {
+            let key = Key::from_ivec(key?)?;
+            match key.u64_segment(1) {
+                Some(generation) if generation < newest => {
+                    keys.tree.remove(key.as_bytes())?;
+                    keys.opened.remove(&(*owner, generation));
+                    removed += 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Wraps the data keys wrapped with a previous master key with the
+    /// current one, returns how many were rewrapped.
+    pub(super) fn rewrap(&self) -> Result<usize, SledStorageError> {
+        let Some(keys) = &self.keys else {
+            return Ok(0);
+        };
+        let mut rewrapped = 0;
+        for entry in keys.tree.iter() {
+            let (key, value) = entry?;
+            let key = Key::from_ivec(key)?;
+            let (wrapped, _) = bincode::decode_from_slice::<WrappedKey, _>(&value, BINCODE_CONFIG)?;
+            if wrapped.master == keys.master.fingerprint {
+                continue;
+            }
+            let raw = keys.unwrap(&key, wrapped)?;
+            let bytes = keys.wrap(&key, &raw)?;
+            // a concurrent writer only ever adds keys, never changes them
+            if keys
+                .tree
+                .compare_and_swap(key.as_bytes(), Some(value), Some(bytes))?
+                .is_ok()
+            {
+                rewrapped += 1;
+            }
+        }
+        Ok(rewrapped)
+    }
+}
+
+impl Keys {
+    fn master_of(&self, wrapped: &WrappedKey) -> Option<&MasterKey> {
+        std::iter::once(&self.master)
+            .chain(&self.previous)
+            .find(|master| master.fingerprint == wrapped.master)
+    }
+
+    fn random<const N: usize>(&self) -> Result<[u8; N], SledStorageError> {
+        let mut bytes = [0; N];
+        self.rng
+            .fill(&mut bytes)
+            .map_err(|_| SledStorageError::Seal)?;
+        Ok(bytes)
+    }
+
+    fn wrap(&self, key: &Key, raw: &[u8]) -> Result<Vec<u8>, SledStorageError> {
+        let nonce = self.random::<NONCE_LEN>()?;
+        let mut sealed = raw.to_vec();
+        self.master
+            .key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(key.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| SledStorageError::Seal)?;
+        let wrapped = WrappedKey {
+            master: self.master.fingerprint,
+            nonce,
+            key: sealed,
+        };
+        Ok(bincode::encode_to_vec(wrapped, BINCODE_CONFIG)?)
+    }
+
+    fn unwrap(&self, key: &Key, wrapped: WrappedKey) -> Result<Vec<u8>, SledStorageError> {
+        let master = self
+            .master_of(&wrapped)
+            .ok_or_else(|| SledStorageError::DataKey(key.to_string()))?;
+        let mut raw = wrapped.key;
+        let len = master
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(wrapped.nonce),
+                Aad::from(key.as_bytes()),
+                &mut raw,
+            )
+            .map_err(|_| SledStorageError::DataKey(key.to_string()))?
+            .len();
+        raw.truncate(len);
+        Ok(raw)
+    }
+
+    fn read(&self, key: &Key) -> Result<Option<Vec<u8>>, SledStorageError> {
+        let Some(value) = self.tree.get(key.as_bytes())? else {
+            return Ok(None);
+        };
+        let (wrapped, _) = bincode::decode_from_slice::<WrappedKey, _>(&value, BINCODE_CONFIG)?;
+        self.unwrap(key, wrapped).map(Some)
+    }
+
+    /// Stores a new random key unless one is stored under `key` already.
+    fn insert_new(&self, key: &Key) -> Result<(), SledStorageError> {
+        let raw = self.random::<KEY_LEN>()?;
+        let bytes = self.wrap(key, &raw)?;
+        // losing the race is fine, the key stored first is used
+        let _ = self
+            .tree
+            .compare_and_swap(key.as_bytes(), None as Option<&[u8]>, Some(bytes))?;
+        Ok(())
+    }
+
+    fn create(&self, owner: &UserId, generation: u64) -> Result<(), SledStorageError> {
+        info!(owner = %owner, generation, "creating data key");
+        self.insert_new(&data_key_key(owner, generation))
+    }
+
+    fn data_key(&self, owner: &UserId, generation: u64) -> Result<LessSafeKey, SledStorageError> {
+        if let Some(key) = self.opened.get(&(*owner, generation)) {
+            return Ok(key.clone());
+        }
+        let storage_key = data_key_key(owner, generation);
+        let raw = self
+            .read(&storage_key)?
+            .ok_or_else(|| SledStorageError::DataKey(storage_key.to_string()))?;
+        let key = UnboundKey::new(&AES_256_GCM, &raw)
+            .map(LessSafeKey::new)
+            .map_err(|_| SledStorageError::DataKey(storage_key.to_string()))?;
+        self.opened.insert((*owner, generation), key.clone());
+        Ok(key)
+    }
+
+    fn stored_newest(&self, owner: &UserId) -> Result<Option<u64>, SledStorageError> {
+        match self
+            .tree
+            .scan_prefix(data_key_prefix(owner).as_bytes())
+            .keys()
+            .next_back()
+        {
+            Some(key) => Ok(Key::from_ivec(key?)?.u64_segment(1)),
+            None => Ok(None),
+        }
+    }
+
+    // The cached generation only ever moves forward, so that a lookup racing
+    // with `add_generation` can't bring back a key about to be retired.
+    fn set_newest(&self, owner: &UserId, generation: u64) {
+        self.newest
+            .entry(*owner)
+            .and_modify(|newest| *newest = (*newest).max(generation))
+            .or_insert(generation);
+    }
+
+    /// Newest data key of the owner, the first one is created on first use.
+    fn newest(&self, owner: &UserId) -> Result<(u64, LessSafeKey), SledStorageError> {
+        let cached = self.newest.get(owner).map(|generation| *generation);
+        let generation = match cached {
+            Some(generation) => generation,
+            None => {
+                let generation = match self.stored_newest(owner)? {
+                    Some(generation) => generation,
+                    None => {
+                        self.create(owner, 1)?;
+                        1
+                    }
+                };
+                self.set_newest(owner, generation);
+                generation
+            }
+        };
+        Ok((generation, self.data_key(owner, generation)?))
+    }
+
+    fn email_index(&self, create: bool) -> Result<Option<hmac::Key>, SledStorageError> {
+        if let Some(index) = &*self.email_index.read().expect("email index lock poisoned") {
+            return Ok(Some(index.clone()));
+        }
+        let key = email_index_key();
+        let raw = match self.read(&key)? {
+            Some(raw) => raw,
+            None if create => {
+                self.insert_new(&key)?;
+                self.read(&key)?.ok_or(SledStorageError::NotFound)?
+            }
+            None => return Ok(None),
+        };
+        let index = hmac::Key::new(hmac::HMAC_SHA256, &raw);
+        *self.email_index.write().expect("email index lock poisoned") = Some(index.clone());
+        Ok(Some(index))
+    }
+
+    fn seal<T: Encode>(
+        &self,
+        owner: &UserId,
+        record: Record,
+        value: &T,
+    ) -> Result<Sealed, SledStorageError> {
+        let (generation, key) = self.newest(owner)?;
+        let nonce = self.random::<NONCE_LEN>()?;
+        let mut ciphertext = self.codec.encode(value)?;
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(record.aad(owner)),
+            &mut ciphertext,
+        )
+        .map_err(|_| SledStorageError::Seal)?;
+        Ok(Sealed {
+            generation,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    fn open<T: Decode<()>>(
+        &self,
+        owner: &UserId,
+        record: Record,
+        sealed: &Sealed,
+    ) -> Result<T, SledStorageError> {
+        let key = self.data_key(owner, sealed.generation)?;
+        let mut plain = sealed.ciphertext.clone();
+        let len = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(sealed.nonce),
+                Aad::from(record.aad(owner)),
+                &mut plain,
+            )
+            .map_err(|_| SledStorageError::Unseal)?
+            .len();
+        plain.truncate(len);
+        self.codec.decode(&plain)
+    }
+}
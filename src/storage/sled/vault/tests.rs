@@ -0,0 +1,221 @@
+use std::path::{Path, PathBuf};
+
+use super::*;
+
+use crate::storage::{HashedPassword, HistoryAction, Role, TodoId};
+
+fn key_file(seed: u8) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("todo-master-key-{}", Uuid::new_v4()));
+    std::fs::write(&path, STANDARD.encode([seed; KEY_LEN])).unwrap();
+    path
+}
+
+fn config(master: &Path, previous: &[&Path], emails: bool) -> EncryptionConfig {
+    EncryptionConfig {
+        master_key_file: Some(master.to_path_buf()),
+        previous_master_key_files: previous.iter().map(|path| path.to_path_buf()).collect(),
+        emails,
+    }
+}
+
+fn tree() -> Tree {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    db.open_tree("data_keys").unwrap()
+}
+
+fn codec() -> BincodeConfig {
+    BincodeConfig::default()
+}
+
+fn todo() -> Todo {
+    Todo {
+        id: TodoId::new(),
+        text: "secret plans".to_string(),
+        completed: false,
+        group: "private".to_string(),
+        due: Some(1_700_000_000),
+    }
+}
+
+fn content(version: &TodoVersion) -> &Sealed {
+    match version {
+        TodoVersion::Sealed { content, .. } => content,
+        _ => panic!("todo is not sealed"),
+    }
+}
+
+#[test]
+fn test_seal_and_open_todo() {
+    let master = key_file(1);
+    let vault = Vault::open(Some(&config(&master, &[], false)), &tree(), codec()).unwrap();
+    let owner = UserId::new();
+    let todo = todo();
+
+    let sealed = vault.seal_todo(&owner, todo.clone()).unwrap();
+    let bytes = bincode::encode_to_vec(&sealed, BINCODE_CONFIG).unwrap();
+    assert!(!bytes.windows(6).any(|window| window == b"secret"));
+    assert_eq!(content(&sealed).generation, 1);
+    assert_eq!(
+        vault.open_todo(&owner, &todo.id, sealed.clone()).unwrap(),
+        todo
+    );
+
+    // bound to its owner
+    assert!(matches!(
+        vault.open_todo(&UserId::new(), &todo.id, sealed),
+        Err(SledStorageError::DataKey(_))
+    ));
+    // plain records stay readable
+    let plain = TodoVersion::from(todo.clone());
+    assert_eq!(vault.open_todo(&owner, &todo.id, plain).unwrap(), todo);
+    std::fs::remove_file(master).unwrap();
+}
+
+#[test]
+fn test_sealed_values_are_bound_to_their_record() {
+    let master = key_file(1);
+    let vault = Vault::open(Some(&config(&master, &[], false)), &tree(), codec()).unwrap();
+    let owner = UserId::new();
+    let (todo, other) = (todo(), todo());
+    let sealed = vault.seal_todo(&owner, todo.clone()).unwrap();
+
+    // a todo stored under the key of another
+    assert!(matches!(
+        vault.open_todo(&owner, &other.id, sealed.clone()),
+        Err(SledStorageError::Unseal)
+    ));
+    // the content of a todo moved into another
+    let moved = TodoVersion::Sealed {
+        id: other.id,
+        completed: false,
+        due: None,
+        content: content(&sealed).clone(),
+    };
+    assert!(matches!(
+        vault.open_todo(&owner, &other.id, moved),
+        Err(SledStorageError::Unseal)
+    ));
+
+    let entry = HistoryEntry {
+        seq: HistorySeq(1),
+        action: HistoryAction::Created,
+        actor: owner,
+        at: 0,
+        changes: Vec::new(),
+    };
+    let history = vault.seal_history(&owner, &todo.id, entry).unwrap();
+    assert!(vault
+        .open_history(&owner, &todo.id, history.clone())
+        .is_ok());
+    assert!(matches!(
+        vault.open_history(&owner, &other.id, history),
+        Err(SledStorageError::Unseal)
+    ));
+    std::fs::remove_file(master).unwrap();
+}
+
+#[test]
+fn test_disabled_vault_keeps_records_plain() {
+    let vault = Vault::default();
+    let owner = UserId::new();
+    let todo = todo();
+
+    let version = vault.seal_todo(&owner, todo.clone()).unwrap();
+    assert!(!matches!(version, TodoVersion::Sealed { .. }));
+    assert_eq!(vault.open_todo(&owner, &todo.id, version).unwrap(), todo);
+}
+
+#[test]
+fn test_open_checks_master_key() {
+    let master = key_file(1);
+    let other = key_file(2);
+    let tree = tree();
+    let vault = Vault::open(Some(&config(&master, &[], false)), &tree, codec()).unwrap();
+    let owner = UserId::new();
+    let todo = todo();
+    let sealed = vault.seal_todo(&owner, todo.clone()).unwrap();
+
+    assert!(matches!(
+        Vault::open(None, &tree, codec()),
+        Err(SledStartupError::MasterKey(_))
+    ));
+    assert!(matches!(
+        Vault::open(Some(&config(&other, &[], false)), &tree, codec()),
+        Err(SledStartupError::MasterKey(_))
+    ));
+    // the old master key as a previous one still opens the data keys
+    let vault = Vault::open(Some(&config(&other, &[&master], false)), &tree, codec()).unwrap();
+    assert_eq!(vault.rewrap().unwrap(), 1);
+    assert_eq!(vault.rewrap().unwrap(), 0);
+    let vault = Vault::open(Some(&config(&other, &[], false)), &tree, codec()).unwrap();
+    assert_eq!(vault.open_todo(&owner, &todo.id, sealed).unwrap(), todo);
+
+    std::fs::remove_file(master).unwrap();
+    std::fs::remove_file(other).unwrap();
+}
+
+#[test]
+fn test_generations() {
+    let master = key_file(1);
+    let vault = Vault::open(Some(&config(&master, &[], false)), &tree(), codec()).unwrap();
+    let owner = UserId::new();
+    let todo = todo();
+    let old = vault.seal_todo(&owner, todo.clone()).unwrap();
+
+    assert_eq!(vault.owners().unwrap(), vec![owner]);
+    assert_eq!(vault.add_generation(&owner).unwrap(), 2);
+    assert!(!vault.is_current(&owner, content(&old)).unwrap());
+    let new = vault.seal_todo(&owner, todo.clone()).unwrap();
+    assert_eq!(content(&new).generation, 2);
+    assert!(vault.is_current(&owner, content(&new)).unwrap());
+    // older generations open until they are retired
+    assert_eq!(
+        vault.open_todo(&owner, &todo.id, old.clone()).unwrap(),
+        todo
+    );
+
+    assert_eq!(vault.retire(&owner).unwrap(), 1);
+    assert!(vault.open_todo(&owner, &todo.id, old).is_err());
+    assert_eq!(vault.open_todo(&owner, &todo.id, new).unwrap(), todo);
+    std::fs::remove_file(master).unwrap();
+}
+
+#[test]
+fn test_sealed_emails() {
+    let master = key_file(1);
+    let tree = tree();
+    let vault = Vault::open(Some(&config(&master, &[], true)), &tree, codec()).unwrap();
+    let user = User {
+        id: UserId::new(),
+        email: "Someone@Gmail.com".to_string(),
+        hashed_password: HashedPassword {
+            salt: vec![1; 4],
+            hash: vec![2; 4],
+        },
+        role: Role::User,
+    };
+
+    let sealed = vault.seal_user(user.clone()).unwrap();
+    assert!(matches!(sealed, UserVersion::Sealed { .. }));
+    assert_eq!(vault.open_user(sealed).unwrap(), user);
+
+    let blind = vault.email_key("someone@gmail.com ").unwrap();
+    assert_eq!(blind.segment(0), Some(b"blind".as_slice()));
+    assert_eq!(
+        vault.email_keys(&user.email).unwrap(),
+        vec![blind.clone(), email_key(&user.email).unwrap()]
+    );
+
+    // without sealed emails the plain entry comes first, the blind one is
+    // still found until the index is rewritten
+    let vault = Vault::open(Some(&config(&master, &[], false)), &tree, codec()).unwrap();
+    assert_eq!(
+        vault.email_keys(&user.email).unwrap(),
+        vec![email_key(&user.email).unwrap(), blind]
+    );
+    assert!(matches!(
+        vault.seal_user(user.clone()).unwrap(),
+        UserVersion::V1 { .. }
+    ));
+    std::fs::remove_file(master).unwrap();
+}
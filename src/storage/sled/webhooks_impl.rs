@@ -0,0 +1,514 @@
+use async_trait::async_trait;
+use sled::transaction::{ConflictableTransactionError, TransactionalTree};
+use sled::{Transactional, Tree};
+use tracing::{info, info_span, instrument, Span};
+
+use super::error::SledStorageError;
+use super::internal::{
+    span_wrappers::{
+        deserialize_in_span, deserialize_in_transaction_with_span,
+        get_value_in_transaction_with_span, get_value_with_span,
+        insert_value_in_transaction_with_span, remove_batch_in_transaction_with_span,
+        remove_value_in_transaction_with_span, serialize_in_transaction_with_span,
+    },
+    Key, KeyPrefix, PrefixKind, TreeScan,
+};
+use super::{
+    dead_letter_key, dead_letter_prefix, delivery_key, outbox_key, webhook_key, webhook_prefix,
+    BincodeConfig, FromBytesWithConfig, SledStorage, ToBytesWithConfig, Vault,
+};
+use super::{Delivery, DeliveryId, Pagination, StorageError, Todo, UserId};
+use super::{Webhook, WebhookEvent, WebhookId};
+use crate::storage::{WebhookData, WebhookEventKind, WebhookStorage};
+use crate::trace_err;
+use crate::utils::blocking_task_guard::BlockingTaskGuard;
+use crate::utils::measure_metrics::measure_and_record_storage;
+
+#[async_trait]
+impl WebhookStorage for SledStorage {
+    #[instrument(name = "SledStorage::put_webhook", skip_all)]
+    async fn put_webhook(&self, webhook: Webhook) -> Result<(), StorageError> {
+        info!(owner = %webhook.owner, webhook_id = %webhook.id, "put webhook");
+
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::put_webhook", || {
+                let encoded = trace_err!(
+                    webhook.to_bytes(&self.bincode_config),
+                    "failed to bin encode webhook"
+                )?;
+                self.webhook_tree
+                    .insert(webhook_key(&webhook.owner, &webhook.id).as_bytes(), encoded)?;
+                Ok(())
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::get_webhook", skip_all)]
+    async fn get_webhook(&self, owner: UserId, id: WebhookId) -> Result<Webhook, StorageError> {
+        info!(owner = %owner, webhook_id = %id, "get webhook");
+
+        let result: Result<Webhook, SledStorageError> =
+            measure_and_record_storage("SledStorage::get_webhook", || {
+                let value = trace_err!(
+                    get_value_with_span(&webhook_key(&owner, &id), &self.webhook_tree),
+                    "failed to read webhook"
+                )?;
+
+                trace_err!(
+                    deserialize_in_span(&self.bincode_config, &value),
+                    "failed to bin decode webhook"
+                )
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::get_webhooks", skip_all)]
+    async fn get_webhooks(&self, owner: UserId) -> Result<Vec<Webhook>, StorageError> {
+        info!(owner = %owner, "get webhooks");
+
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::get_webhooks", || {
+                trace_err!(
+                    scan_webhooks(
+                        &self.webhook_tree,
+                        &webhook_prefix(&owner),
+                        &self.bincode_config
+                    ),
+                    "failed to read webhooks of user"
+                )
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::delete_webhook", skip_all)]
+    async fn delete_webhook(&self, owner: UserId, id: WebhookId) -> Result<(), StorageError> {
+        info!(owner = %owner, webhook_id = %id, "delete webhook");
+
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::delete_webhook", || {
+                match self
+                    .webhook_tree
+                    .remove(webhook_key(&owner, &id).as_bytes())?
+                {
+                    Some(_) => Ok(()),
+                    None => Err(SledStorageError::NotFound),
+                }
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::fan_out_events", skip_all)]
+    async fn fan_out_events(&self, limit: usize) -> Result<usize, StorageError> {
+        // cloning tree should be cheap: struct Tree{inner: Arc<TreeInner>}
+        let (outbox_tree, webhook_tree, bincode_config) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.outbox_tree.clone(),
+                    self.webhook_tree.clone(),
+                    self.bincode_config,
+                )
+            });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("fan_out_events");
+            span.in_scope(|| fan_out_events(limit, &outbox_tree, &webhook_tree, &bincode_config))
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::get_due_deliveries", skip_all)]
+    async fn get_due_deliveries(
+        &self,
+        now_ms: i64,
+        limit: usize,
+    ) -> Result<Vec<Delivery>, StorageError> {
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::get_due_deliveries", || {
+                let prefix = KeyPrefix::from_kind(PrefixKind::Delivery);
+                let mut due = Vec::new();
+                for item in self.webhook_tree.scan_prefix(prefix.as_bytes()).values() {
+                    let mut delivery: Delivery = trace_err!(
+                        deserialize_in_span(&self.bincode_config, &item?),
+                        "failed to bin decode delivery"
+                    )?;
+                    if delivery.next_attempt_at > now_ms || due.len() == limit {
+                        break;
+                    }
+                    delivery.event = trace_err!(
+                        self.vault.open_event(delivery.event),
+                        "failed to open sealed event"
+                    )?;
+                    due.push(delivery);
+                }
+                Ok(due)
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::remove_delivery", skip_all)]
+    async fn remove_delivery(&self, delivery: Delivery) -> Result<(), StorageError> {
+        info!(delivery_id = %delivery.id, "remove delivery");
+
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::remove_delivery", || {
+                self.webhook_tree
+                    .remove(delivery_key(delivery.next_attempt_at, delivery.id).as_bytes())?;
+                Ok(())
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::fail_delivery", skip_all)]
+    async fn fail_delivery(
+        &self,
+        delivery: Delivery,
+        next_attempt_at: Option<i64>,
+    ) -> Result<(), StorageError> {
+        info!(
+            delivery_id = %delivery.id,
+            attempts = delivery.attempts,
+            next_attempt_at = ?next_attempt_at,
+            "failed delivery"
+        );
+
+        let (webhook_tree, bincode_config, vault) = info_span!("Cloning trees and config")
+            .in_scope(|| {
+                (
+                    self.webhook_tree.clone(),
+                    self.bincode_config,
+                    self.vault.clone(),
+                )
+            });
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("fail_delivery");
+            span.in_scope(|| {
+                fail_delivery(
+                    delivery,
+                    next_attempt_at,
+                    &webhook_tree,
+                    &bincode_config,
+                    &vault,
+                )
+            })
+        })
+        .await?
+    }
+
+    #[instrument(name = "SledStorage::get_dead_letters", skip_all)]
+    async fn get_dead_letters(
+        &self,
+        owner: UserId,
+        pagination: Pagination<DeliveryId>,
+    ) -> Result<(Vec<Delivery>, Option<DeliveryId>), StorageError> {
+        info!(owner = %owner, pagination = ?pagination, "get dead letters");
+
+        let result: Result<_, SledStorageError> =
+            measure_and_record_storage("SledStorage::get_dead_letters", || {
+                let after_key = match pagination.after {
+                    Some(delivery_id) => dead_letter_key(&owner, delivery_id),
+                    None => Key::new(KeyPrefix::from_kind(PrefixKind::DeadLetter), owner),
+                };
+
+                let page = info_span!("TreeScan::scan_from::within::until_pagination::collect")
+                    .in_scope(|| {
+                        trace_err!(
+                            TreeScan::scan_from(&self.webhook_tree, &after_key)
+                                .within(dead_letter_prefix(&owner))
+                                .with_pagination(pagination)
+                                .collect(
+                                    &self.bincode_config,
+                                    |_, bytes, config| {
+                                        let mut delivery = Delivery::from_bytes(bytes, config)?;
+                                        delivery.event = self.vault.open_event(delivery.event)?;
+                                        Ok(delivery)
+                                    },
+                                    None,
+                                ),
+                            "failed to do tree scan to get page of dead letters"
+                        )
+                    })?;
+                Ok((page.items, page.next_cursor))
+            });
+
+        Ok(result?)
+    }
+
+    #[instrument(name = "SledStorage::retry_dead_letter", skip_all)]
+    async fn retry_dead_letter(
+        &self,
+        owner: UserId,
+        id: DeliveryId,
+        now_ms: i64,
+    ) -> Result<(), StorageError> {
+        info!(owner = %owner, delivery_id = %id, "retry dead letter");
+
+        let (webhook_tree, bincode_config) = info_span!("Cloning trees and config")
+            .in_scope(|| (self.webhook_tree.clone(), self.bincode_config));
+
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _guard = BlockingTaskGuard::new("retry_dead_letter");
+            span.in_scope(|| retry_dead_letter(owner, id, now_ms, &webhook_tree, &bincode_config))
+        })
+        .await?
+    }
+}
+
+/// Writes an event to the outbox. Called in the transaction of the change it
+/// describes, so the event exists exactly when the change was committed.
+pub(super) fn enqueue_in_transaction(
+    user_id: &UserId,
+    kind: WebhookEventKind,
+    data: WebhookData,
+    at: i64,
+    outbox_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    let event = vault.seal_event(WebhookEvent {
+        id: outbox_tx.generate_id()?,
+        kind,
+        user_id: *user_id,
+        occurred_at: at,
+        data,
+    })?;
+    let encoded = serialize_in_transaction_with_span(bincode_config, &event)?;
+    insert_value_in_transaction_with_span(&outbox_key(event.id), &encoded, outbox_tx)
+}
+
+/// Enqueues the event of a todo change, `before` and `after` being the todo
+/// around the change and `None` where it doesn't exist.
+pub(super) fn enqueue_todo_change_in_transaction(
+    user_id: &UserId,
+    before: Option<&Todo>,
+    after: Option<&Todo>,
+    at: i64,
+    outbox_tx: &TransactionalTree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), SledStorageError> {
+    let (kind, todo) = match (before, after) {
+        (None, None) => return Ok(()),
+        (Some(before), None) => (WebhookEventKind::TodoDeleted, before),
+        (None, Some(after)) => (WebhookEventKind::TodoCreated, after),
+        (Some(before), Some(after)) if !before.completed && after.completed => {
+            (WebhookEventKind::TodoCompleted, after)
+        }
+        (Some(_), Some(after)) => (WebhookEventKind::TodoUpdated, after),
+    };
+
+    enqueue_in_transaction(
+        user_id,
+        kind,
+        WebhookData::Todo(todo.clone().into()),
+        at,
+        outbox_tx,
+        bincode_config,
+        vault,
+    )
+}
+
+/// Removes the webhooks and dead letters of a user, used when the user is
+/// deleted. Pending deliveries are dropped by the dispatcher once it misses
+/// their webhook.
+pub(super) fn remove_user_webhooks(
+    user_id: &UserId,
+    webhook_tree: &Tree,
+) -> Result<usize, SledStorageError> {
+    let mut keys = Vec::new();
+    for prefix in [webhook_prefix(user_id), dead_letter_prefix(user_id)] {
+        for key in webhook_tree.scan_prefix(prefix.as_bytes()).keys() {
+            keys.push(Key::from_bytes(&key?)?);
+        }
+    }
+
+    webhook_tree.transaction(|webhook_tx| {
+        trace_err!(
+            remove_batch_in_transaction_with_span(&keys, webhook_tx),
+            "failed to remove webhooks"
+        )?;
+        Ok(())
+    })?;
+    Ok(keys.len())
+}
+
+fn scan_webhooks(
+    webhook_tree: &Tree,
+    prefix: &KeyPrefix,
+    bincode_config: &BincodeConfig,
+) -> Result<Vec<Webhook>, SledStorageError> {
+    webhook_tree
+        .scan_prefix(prefix.as_bytes())
+        .values()
+        .map(|value| deserialize_in_span(bincode_config, &value?))
+        .collect()
+}
+
+fn subscribed(webhook: &Webhook, event: &WebhookEvent) -> bool {
+    webhook.events.contains(&event.kind)
+        && (event.kind.is_user_event() || webhook.owner == event.user_id)
+}
+
+#[instrument(name = "fan_out_events", skip_all)]
+fn fan_out_events(
+    limit: usize,
+    outbox_tree: &Tree,
+    webhook_tree: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<usize, StorageError> {
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::fan_out_events", || {
+            let prefix = KeyPrefix::from_kind(PrefixKind::Outbox);
+            let events = outbox_tree
+                .scan_prefix(prefix.as_bytes())
+                .values()
+                .take(limit)
+                .map(|value| deserialize_in_span::<WebhookEvent>(bincode_config, &value?))
+                .collect::<Result<Vec<_>, _>>()?;
+            if events.is_empty() {
+                return Ok(0);
+            }
+
+            let webhooks = trace_err!(
+                scan_webhooks(
+                    webhook_tree,
+                    &KeyPrefix::from_kind(PrefixKind::Webhook),
+                    bincode_config
+                ),
+                "failed to read webhooks"
+            )?;
+
+            for event in &events {
+                let key = outbox_key(event.id);
+                (outbox_tree, webhook_tree).transaction(|(outbox_tx, webhook_tx)| {
+                    // Taken by someone else in the meantime
+                    if outbox_tx.remove(key.as_bytes())?.is_none() {
+                        return Ok(());
+                    }
+
+                    for webhook in webhooks.iter().filter(|webhook| subscribed(webhook, event)) {
+                        let delivery = Delivery {
+                            id: DeliveryId(webhook_tx.generate_id()?),
+                            webhook_id: webhook.id,
+                            owner: webhook.owner,
+                            event: event.clone(),
+                            attempts: 0,
+                            next_attempt_at: event.occurred_at * 1000,
+                            last_error: None,
+                        };
+                        let encoded = trace_err!(
+                            serialize_in_transaction_with_span(bincode_config, &delivery),
+                            "failed to bin encode delivery"
+                        )?;
+                        insert_value_in_transaction_with_span(
+                            &delivery_key(delivery.next_attempt_at, delivery.id),
+                            &encoded,
+                            webhook_tx,
+                        )?;
+                    }
+                    Ok(())
+                })?;
+            }
+
+            info!(count = events.len(), "fanned out webhook events");
+            Ok(events.len())
+        });
+
+    Ok(result?)
+}
+
+#[instrument(name = "fail_delivery", skip_all)]
+fn fail_delivery(
+    delivery: Delivery,
+    next_attempt_at: Option<i64>,
+    webhook_tree: &Tree,
+    bincode_config: &BincodeConfig,
+    vault: &Vault,
+) -> Result<(), StorageError> {
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::fail_delivery", || {
+            let key = delivery_key(delivery.next_attempt_at, delivery.id);
+            // handed out opened by `get_due_deliveries`
+            let delivery = Delivery {
+                event: vault.seal_event(delivery.event)?,
+                ..delivery
+            };
+
+            webhook_tree.transaction(|webhook_tx| {
+                remove_value_in_transaction_with_span(&key, webhook_tx)?;
+
+                let mut delivery = delivery.clone();
+                let key = match next_attempt_at {
+                    Some(next_attempt_at) => {
+                        delivery.next_attempt_at = next_attempt_at;
+                        delivery_key(next_attempt_at, delivery.id)
+                    }
+                    None => dead_letter_key(&delivery.owner, delivery.id),
+                };
+                let encoded = trace_err!(
+                    serialize_in_transaction_with_span(bincode_config, &delivery),
+                    "failed to bin encode delivery"
+                )?;
+                insert_value_in_transaction_with_span(&key, &encoded, webhook_tx)?;
+                Ok(())
+            })?;
+            Ok(())
+        });
+
+    Ok(result?)
+}
+
+#[instrument(name = "retry_dead_letter", skip_all)]
+fn retry_dead_letter(
+    owner: UserId,
+    id: DeliveryId,
+    now_ms: i64,
+    webhook_tree: &Tree,
+    bincode_config: &BincodeConfig,
+) -> Result<(), StorageError> {
+    let result: Result<_, SledStorageError> =
+        measure_and_record_storage("SledStorage::retry_dead_letter", || {
+            let key = dead_letter_key(&owner, id);
+
+            webhook_tree.transaction(|webhook_tx| {
+                let Some(value) = get_value_in_transaction_with_span(&key, webhook_tx)? else {
+                    return Err(ConflictableTransactionError::Abort(
+                        SledStorageError::NotFound,
+                    ));
+                };
+                let mut delivery: Delivery = trace_err!(
+                    deserialize_in_transaction_with_span(bincode_config, &value),
+                    "failed to bin decode dead letter"
+                )?;
+                remove_value_in_transaction_with_span(&key, webhook_tx)?;
+
+                delivery.attempts = 0;
+                delivery.next_attempt_at = now_ms;
+                let encoded = trace_err!(
+                    serialize_in_transaction_with_span(bincode_config, &delivery),
+                    "failed to bin encode delivery"
+                )?;
+                insert_value_in_transaction_with_span(
+                    &delivery_key(now_ms, delivery.id),
+                    &encoded,
+                    webhook_tx,
+                )?;
+                Ok(())
+            })?;
+            Ok(())
+        });
+
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,225 @@
+use super::*;
+
+use crate::storage::{
+    sled::test_util::{TestStorageBuilder, ADMIN_UUID},
+    TodoId, UpdateTodo,
+};
+
+const FAR_FUTURE: i64 = i64::MAX / 2;
+
+fn webhook(owner: UserId, events: Vec<WebhookEventKind>) -> Webhook {
+    Webhook {
+        id: WebhookId::new(),
+        owner,
+        url: "http://localhost/hook".to_string(),
+        secret: "secret".to_string(),
+        events,
+        created_at: 0,
+    }
+}
+
+fn patch(text: Option<&str>, completed: Option<bool>) -> UpdateTodo {
+    UpdateTodo {
+        text: text.map(str::to_string),
+        completed,
+        group: None,
+        due: None,
+        ignore_blockers: false,
+    }
+}
+
+async fn dead_letters(storage: &dyn WebhookStorage, owner: UserId) -> Vec<Delivery> {
+    let (items, _) = storage
+        .get_dead_letters(
+            owner,
+            Pagination {
+                after: None,
+                limit: 10,
+            },
+        )
+        .await
+        .unwrap();
+    items
+}
+
+#[tokio::test]
+async fn test_todo_changes_fan_out_to_subscribed_webhooks() {
+    let builder = TestStorageBuilder::new();
+    let todos = builder.build_todo().await;
+    let storage = builder.build_webhook().await;
+    let owner: UserId = ADMIN_UUID.into();
+
+    let subscribed = webhook(
+        owner,
+        vec![
+            WebhookEventKind::TodoCreated,
+            WebhookEventKind::TodoCompleted,
+            WebhookEventKind::TodoDeleted,
+        ],
+    );
+    storage.put_webhook(subscribed.clone()).await.unwrap();
+    let other = webhook(UserId::new(), vec![WebhookEventKind::TodoCreated]);
+    storage.put_webhook(other).await.unwrap();
+
+    let todo = Todo::new(TodoId::new(), "aaa");
+    let id = todo.id;
+    todos.put(owner, id, todo).await.unwrap();
+    todos
+        .update(owner, id, patch(Some("bbb"), None))
+        .await
+        .unwrap();
+    todos
+        .update(owner, id, patch(None, Some(true)))
+        .await
+        .unwrap();
+    todos.delete(owner, id).await.unwrap();
+
+    assert_eq!(storage.fan_out_events(10).await.unwrap(), 4);
+    assert_eq!(storage.fan_out_events(10).await.unwrap(), 0);
+
+    let due = storage.get_due_deliveries(FAR_FUTURE, 10).await.unwrap();
+    let kinds: Vec<_> = due.iter().map(|delivery| delivery.event.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            WebhookEventKind::TodoCreated,
+            WebhookEventKind::TodoCompleted,
+            WebhookEventKind::TodoDeleted,
+        ]
+    );
+    assert!(due
+        .iter()
+        .all(|delivery| delivery.webhook_id == subscribed.id && delivery.owner == owner));
+
+    let limited = storage.get_due_deliveries(FAR_FUTURE, 2).await.unwrap();
+    assert_eq!(limited.len(), 2);
+    assert!(storage.get_due_deliveries(0, 10).await.unwrap().is_empty());
+
+    for delivery in due {
+        storage.remove_delivery(delivery).await.unwrap();
+    }
+    assert!(storage
+        .get_due_deliveries(FAR_FUTURE, 10)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_user_events_go_to_subscribed_webhooks() {
+    let builder = TestStorageBuilder::new().with_users(2).await;
+    let storage = builder.build_webhook().await;
+    let admin_hook = webhook(ADMIN_UUID.into(), vec![WebhookEventKind::UserCreated]);
+    storage.put_webhook(admin_hook.clone()).await.unwrap();
+
+    let users = builder.build_user().await;
+    assert_eq!(storage.fan_out_events(10).await.unwrap(), 2);
+
+    let due = storage.get_due_deliveries(FAR_FUTURE, 10).await.unwrap();
+    assert_eq!(due.len(), 2);
+    assert!(due.iter().all(|delivery| {
+        delivery.webhook_id == admin_hook.id && delivery.event.kind == WebhookEventKind::UserCreated
+    }));
+
+    // not subscribed to role changes
+    let user_id = due[0].event.user_id;
+    users
+        .update_role(user_id, crate::storage::Role::Admin)
+        .await
+        .unwrap();
+    assert_eq!(storage.fan_out_events(10).await.unwrap(), 1);
+    assert_eq!(
+        storage
+            .get_due_deliveries(FAR_FUTURE, 10)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_failed_delivery_is_retried_then_dead_lettered() {
+    let builder = TestStorageBuilder::new();
+    let todos = builder.build_todo().await;
+    let storage = builder.build_webhook().await;
+    let owner: UserId = ADMIN_UUID.into();
+    storage
+        .put_webhook(webhook(owner, vec![WebhookEventKind::TodoCreated]))
+        .await
+        .unwrap();
+
+    let todo = Todo::new(TodoId::new(), "aaa");
+    todos.put(owner, todo.id, todo).await.unwrap();
+    storage.fan_out_events(10).await.unwrap();
+
+    let mut delivery = storage.get_due_deliveries(FAR_FUTURE, 10).await.unwrap()[0].clone();
+    let retry_at = delivery.next_attempt_at + 60_000;
+    delivery.attempts = 1;
+    delivery.last_error = Some("500 Internal Server Error".to_string());
+    storage
+        .fail_delivery(delivery.clone(), Some(retry_at))
+        .await
+        .unwrap();
+
+    assert!(storage
+        .get_due_deliveries(retry_at - 1, 10)
+        .await
+        .unwrap()
+        .is_empty());
+    let mut delivery = storage.get_due_deliveries(retry_at, 10).await.unwrap()[0].clone();
+    assert_eq!(delivery.attempts, 1);
+
+    delivery.attempts = 2;
+    storage.fail_delivery(delivery.clone(), None).await.unwrap();
+    assert!(storage
+        .get_due_deliveries(FAR_FUTURE, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let dead = dead_letters(storage.as_ref(), owner).await;
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].id, delivery.id);
+    assert_eq!(dead[0].attempts, 2);
+    assert!(dead_letters(storage.as_ref(), UserId::new())
+        .await
+        .is_empty());
+
+    storage
+        .retry_dead_letter(owner, delivery.id, 1_000)
+        .await
+        .unwrap();
+    assert!(dead_letters(storage.as_ref(), owner).await.is_empty());
+    let due = storage.get_due_deliveries(1_000, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].attempts, 0);
+
+    let result = storage.retry_dead_letter(owner, delivery.id, 1_000).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
+
+#[tokio::test]
+async fn test_delete_webhook() {
+    let storage = TestStorageBuilder::new().build_webhook().await;
+    let owner = UserId::new();
+    let hook = webhook(owner, vec![WebhookEventKind::TodoUpdated]);
+    storage.put_webhook(hook.clone()).await.unwrap();
+
+    assert_eq!(storage.get_webhook(owner, hook.id).await.unwrap(), hook);
+    assert_eq!(
+        storage.get_webhooks(owner).await.unwrap(),
+        vec![hook.clone()]
+    );
+    assert!(storage
+        .get_webhooks(UserId::new())
+        .await
+        .unwrap()
+        .is_empty());
+
+    storage.delete_webhook(owner, hook.id).await.unwrap();
+    let result = storage.get_webhook(owner, hook.id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+    let result = storage.delete_webhook(owner, hook.id).await;
+    assert!(matches!(result, Err(StorageError::NotFound)));
+}
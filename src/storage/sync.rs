@@ -0,0 +1,97 @@
+use std::{fmt::Display, str::FromStr};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{page::HasId, StorageError, Todo, TodoId};
+
+/// Position in the change sequence of a user. Every change of a todo takes
+/// the next one, which also becomes the revision of that todo.
+#[derive(
+    Encode,
+    Decode,
+    Serialize,
+    Deserialize,
+    Debug,
+    Copy,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(transparent)]
+pub struct SyncSeq(pub(crate) u64);
+
+impl Display for SyncSeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for SyncSeq {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.parse().map_err(StorageError::ParseSeqFromString)?))
+    }
+}
+
+/// Entry of the change log, only the latest change of each todo is kept.
+#[derive(Encode, Decode, Debug)]
+pub(crate) struct SyncRecord {
+    pub(crate) todo_id: TodoId,
+    pub(crate) deleted: bool,
+}
+
+/// Latest state of a changed todo, `todo` is `None` once it was deleted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct SyncChange {
+    #[schema(value_type = String)]
+    pub id: TodoId,
+    #[schema(value_type = u64)]
+    pub rev: SyncSeq,
+    pub todo: Option<Todo>,
+}
+
+impl HasId<SyncSeq> for SyncChange {
+    fn id(&self) -> SyncSeq {
+        self.rev
+    }
+}
+
+/// Change made by a client while offline. `base_rev` is the revision the
+/// client last saw, `None` for todos the client created itself.
+#[derive(Debug)]
+pub enum ClientChange {
+    Upsert {
+        todo: Todo,
+        base_rev: Option<SyncSeq>,
+    },
+    Delete {
+        id: TodoId,
+        base_rev: SyncSeq,
+    },
+}
+
+/// Outcome of a client change. On conflict nothing is written and the stored
+/// state is returned, `todo` being `None` when the todo is deleted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SyncResult {
+    Applied {
+        #[schema(value_type = String)]
+        id: TodoId,
+        #[schema(value_type = u64)]
+        rev: SyncSeq,
+    },
+    Conflict {
+        #[schema(value_type = String)]
+        id: TodoId,
+        #[schema(value_type = Option<u64>)]
+        rev: Option<SyncSeq>,
+        todo: Option<Todo>,
+    },
+}
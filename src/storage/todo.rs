@@ -1,5 +1,5 @@
 use super::page::HasId;
-use super::TodoId;
+use super::{Sealed, SealedRecord, TodoId};
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -12,6 +12,9 @@ pub struct Todo {
     pub completed: bool,
     #[serde(default)]
     pub group: String,
+    /// Unix timestamp (seconds) the todo is due at.
+    #[serde(default)]
+    pub due: Option<i64>,
 }
 
 impl HasId<TodoId> for Todo {
@@ -35,19 +38,24 @@ impl Todo {
             text: text.to_owned(),
             completed: false,
             group: String::new(),
+            due: None,
         }
     }
     pub(crate) fn apply(&mut self, update: &UpdateTodo) {
         apply_if_changed(&mut self.text, &update.text);
         apply_if_changed(&mut self.completed, &update.completed);
         apply_if_changed(&mut self.group, &update.group);
+        apply_if_changed(&mut self.due, &update.due);
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UpdateTodo {
     pub text: Option<String>,
     pub completed: Option<bool>,
     pub group: Option<String>,
+    /// `Some(None)` removes the due date.
+    pub due: Option<Option<i64>>,
+    pub ignore_blockers: bool,
 }
 
 impl From<&crate::handlers::UpdateTodo> for UpdateTodo {
@@ -56,11 +64,79 @@ impl From<&crate::handlers::UpdateTodo> for UpdateTodo {
             text: value.text.clone(),
             completed: value.completed,
             group: value.group.clone(),
+            due: value.due,
+            ignore_blockers: value.ignore_blockers,
         }
     }
 }
 
-#[derive(Encode, Decode, Serialize, Deserialize, Debug)]
+/// Single operation of a batch, all operations of a batch are applied in one transaction.
+#[derive(Debug)]
+pub enum TodoOp {
+    Create(Todo),
+    Update { id: TodoId, patch: UpdateTodo },
+    Delete { id: TodoId },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TodoOpResult {
+    Created {
+        #[schema(value_type = String)]
+        id: TodoId,
+    },
+    Updated {
+        #[schema(value_type = String)]
+        id: TodoId,
+    },
+    Deleted {
+        #[schema(value_type = String)]
+        id: TodoId,
+    },
+}
+
+/// Todo moved to the trash, kept until restored or purged after retention.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct TrashedTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    /// Unix timestamp (seconds) of the deletion.
+    pub deleted_at: i64,
+}
+
+impl HasId<TodoId> for TrashedTodo {
+    fn id(&self) -> TodoId {
+        self.todo.id
+    }
+}
+
+/// Stored form of [`TrashedTodo`]. Keeps the versioned todo so that records
+/// written before a todo format change can still be restored.
+#[derive(Encode, Decode, Debug)]
+pub(crate) struct TrashRecord {
+    pub(crate) todo: TodoVersion,
+    pub(crate) deleted_at: i64,
+}
+
+/// "Blocked by" links of a single todo, stored in both directions so that
+/// either side can be read and cleaned up without a scan.
+#[derive(
+    Encode, Decode, Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, ToSchema,
+)]
+pub struct TodoLinks {
+    #[schema(value_type = Vec<String>)]
+    pub blocked_by: Vec<TodoId>,
+    #[schema(value_type = Vec<String>)]
+    pub blocking: Vec<TodoId>,
+}
+
+impl TodoLinks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.blocked_by.is_empty() && self.blocking.is_empty()
+    }
+}
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "version", content = "data")]
 pub(crate) enum TodoVersion {
     V1 {
@@ -74,11 +150,38 @@ pub(crate) enum TodoVersion {
         completed: bool,
         group: String,
     },
+    V3 {
+        id: TodoId,
+        text: String,
+        completed: bool,
+        group: String,
+        due: Option<i64>,
+    },
+    /// Text and group encrypted at rest, the storage opens them.
+    Sealed {
+        id: TodoId,
+        completed: bool,
+        due: Option<i64>,
+        content: Sealed,
+    },
+}
+
+impl TodoVersion {
+    pub(crate) fn id(&self) -> TodoId {
+        match self {
+            Self::V1 { id, .. }
+            | Self::V2 { id, .. }
+            | Self::V3 { id, .. }
+            | Self::Sealed { id, .. } => *id,
+        }
+    }
 }
 
-impl From<TodoVersion> for Todo {
-    fn from(value: TodoVersion) -> Self {
-        match value {
+impl TryFrom<TodoVersion> for Todo {
+    type Error = SealedRecord;
+
+    fn try_from(value: TodoVersion) -> Result<Self, Self::Error> {
+        Ok(match value {
             TodoVersion::V1 {
                 id,
                 text,
@@ -88,6 +191,7 @@ impl From<TodoVersion> for Todo {
                 text,
                 completed,
                 group: String::default(),
+                due: None,
             },
             TodoVersion::V2 {
                 id,
@@ -99,18 +203,34 @@ impl From<TodoVersion> for Todo {
                 text,
                 completed,
                 group,
+                due: None,
             },
-        }
+            TodoVersion::V3 {
+                id,
+                text,
+                completed,
+                group,
+                due,
+            } => Self {
+                id,
+                text,
+                completed,
+                group,
+                due,
+            },
+            TodoVersion::Sealed { .. } => return Err(SealedRecord),
+        })
     }
 }
 
 impl From<Todo> for TodoVersion {
     fn from(value: Todo) -> Self {
-        Self::V2 {
+        Self::V3 {
             id: value.id,
             text: value.text,
             completed: value.completed,
             group: value.group,
+            due: value.due,
         }
     }
 }
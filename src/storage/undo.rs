@@ -0,0 +1,52 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{TodoId, TodoVersion};
+
+/// Kind of the todo operation kept in the undo log.
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UndoOperation {
+    Create,
+    Update,
+    Delete,
+    DeleteAll,
+    Batch,
+    UpdateGroup,
+    Sync,
+}
+
+/// Outcome of an undo: which operation was reverted and how many todos it touched.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct UndoResult {
+    pub operation: UndoOperation,
+    pub count: usize,
+}
+
+/// State needed to revert a single step of an operation.
+#[derive(Encode, Decode, Debug)]
+pub(crate) enum UndoStep {
+    Created {
+        todo_id: TodoId,
+    },
+    Updated {
+        before: TodoVersion,
+    },
+    Deleted {
+        todo_ids: Vec<TodoId>,
+    },
+    /// Steps reverted together, in reverse order.
+    Batch {
+        steps: Vec<UndoStep>,
+    },
+}
+
+/// Entry of the undo log. Operations done in several transactions (`delete_all`)
+/// write one record per batch, all sharing the same `op`.
+#[derive(Encode, Decode, Debug)]
+pub(crate) struct UndoRecord {
+    pub(crate) op: u64,
+    pub(crate) operation: UndoOperation,
+    pub(crate) step: UndoStep,
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+use bincode::{Decode, Encode};
+
+use super::{Sealed, Todo};
+
+/// What a user stores, counted by the transactions that write their todos.
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Usage {
+    pub(crate) todos: usize,
+    /// Todos of every non-empty group, a group goes with its last todo.
+    pub(crate) groups: BTreeMap<String, usize>,
+}
+
+impl Usage {
+    pub(crate) fn add(&mut self, todo: &Todo) {
+        self.todos += 1;
+        if !todo.group.is_empty() {
+            *self.groups.entry(todo.group.clone()).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, todo: &Todo) {
+        self.todos = self.todos.saturating_sub(1);
+        if let Some(count) = self.groups.get_mut(&todo.group) {
+            *count -= 1;
+            if *count == 0 {
+                self.groups.remove(&todo.group);
+            }
+        }
+    }
+}
+
+/// Stored form of [`Usage`]. Group names are sealed like the todos they
+/// come from.
+#[derive(Encode, Decode, Debug)]
+pub(crate) enum UsageVersion {
+    Plain(Usage),
+    Sealed(Sealed),
+}
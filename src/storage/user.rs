@@ -1,14 +1,16 @@
 use std::fmt::Debug;
 
-use super::{page::HasId, UserId};
+use super::{page::HasId, Sealed, SealedRecord, UserId};
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use strum::EnumString;
+use strum::{AsRefStr, EnumString};
 
 pub const SALT_LEN: usize = 20;
 pub const HASH_LEN: usize = ring::digest::SHA256_OUTPUT_LEN;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq, Eq, EnumString)]
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, Encode, Decode, PartialEq, Eq, EnumString, AsRefStr,
+)]
 #[strum(serialize_all = "lowercase")]
 pub enum Role {
     User,
@@ -29,6 +31,57 @@ pub struct User {
     pub role: Role,
 }
 
+/// How a `User` is stored. A change of the user model gets a new variant, so
+/// records written before it still decode.
+#[derive(Encode, Decode, Debug, Clone)]
+pub(crate) enum UserVersion {
+    V1 {
+        id: UserId,
+        email: String,
+        hashed_password: HashedPassword,
+        role: Role,
+    },
+    /// Email encrypted at rest, the storage opens it.
+    Sealed {
+        id: UserId,
+        email: Sealed,
+        hashed_password: HashedPassword,
+        role: Role,
+    },
+}
+
+impl TryFrom<UserVersion> for User {
+    type Error = SealedRecord;
+
+    fn try_from(value: UserVersion) -> Result<Self, Self::Error> {
+        Ok(match value {
+            UserVersion::V1 {
+                id,
+                email,
+                hashed_password,
+                role,
+            } => Self {
+                id,
+                email,
+                hashed_password,
+                role,
+            },
+            UserVersion::Sealed { .. } => return Err(SealedRecord),
+        })
+    }
+}
+
+impl From<User> for UserVersion {
+    fn from(value: User) -> Self {
+        Self::V1 {
+            id: value.id,
+            email: value.email,
+            hashed_password: value.hashed_password,
+            role: value.role,
+        }
+    }
+}
+
 impl HasId<UserId> for User {
     fn id(&self) -> UserId {
         self.id
@@ -0,0 +1,144 @@
+use std::{fmt::Display, str::FromStr};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{
+    page::HasId, Role, Sealed, SealedRecord, StorageError, Todo, TodoVersion, UserId, WebhookId,
+};
+
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, ToSchema)]
+pub enum WebhookEventKind {
+    #[serde(rename = "todo.created")]
+    TodoCreated,
+    #[serde(rename = "todo.updated")]
+    TodoUpdated,
+    /// Sent instead of `todo.updated` when the change completes the todo.
+    #[serde(rename = "todo.completed")]
+    TodoCompleted,
+    #[serde(rename = "todo.deleted")]
+    TodoDeleted,
+    #[serde(rename = "user.created")]
+    UserCreated,
+    #[serde(rename = "user.role_changed")]
+    UserRoleChanged,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+}
+
+impl WebhookEventKind {
+    /// User lifecycle events go to admin webhooks, todo events to the owner's ones.
+    pub(crate) fn is_user_event(&self) -> bool {
+        matches!(
+            self,
+            Self::UserCreated | Self::UserRoleChanged | Self::UserDeleted
+        )
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::TodoCreated => "todo.created",
+            Self::TodoUpdated => "todo.updated",
+            Self::TodoCompleted => "todo.completed",
+            Self::TodoDeleted => "todo.deleted",
+            Self::UserCreated => "user.created",
+            Self::UserRoleChanged => "user.role_changed",
+            Self::UserDeleted => "user.deleted",
+        }
+    }
+}
+
+/// Endpoint registered by a user. The secret signs every delivery, so unlike
+/// calendar tokens it has to be stored as is.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub(crate) id: WebhookId,
+    pub(crate) owner: UserId,
+    pub(crate) url: String,
+    pub(crate) secret: String,
+    pub(crate) events: Vec<WebhookEventKind>,
+    pub(crate) created_at: i64,
+}
+
+/// State of the todo or user an event is about, taken in the transaction of the change.
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum WebhookData {
+    Todo(TodoVersion),
+    User {
+        id: UserId,
+        email: String,
+        role: Role,
+    },
+    /// `User` with the email encrypted at rest.
+    SealedUser {
+        id: UserId,
+        email: Sealed,
+        role: Role,
+    },
+}
+
+impl WebhookData {
+    /// Fails for data the storage didn't open.
+    pub(crate) fn to_json(&self) -> Result<serde_json::Value, SealedRecord> {
+        Ok(match self {
+            Self::Todo(todo) => serde_json::json!({ "todo": Todo::try_from(todo.clone())? }),
+            Self::User { id, email, role } => serde_json::json!({
+                "user": { "id": id, "email": email, "role": role }
+            }),
+            Self::SealedUser { .. } => return Err(SealedRecord),
+        })
+    }
+}
+
+/// Entry of the outbox, written in the same transaction as the change it describes.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct WebhookEvent {
+    /// Stays the same across retries, receivers can use it to drop duplicates.
+    pub(crate) id: u64,
+    pub(crate) kind: WebhookEventKind,
+    pub(crate) user_id: UserId,
+    /// Unix timestamp in seconds.
+    pub(crate) occurred_at: i64,
+    pub(crate) data: WebhookData,
+}
+
+/// Identifies a delivery of an event to one webhook.
+#[derive(
+    Encode, Decode, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(transparent)]
+pub struct DeliveryId(pub(crate) u64);
+
+impl Display for DeliveryId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for DeliveryId {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.parse().map_err(StorageError::ParseSeqFromString)?))
+    }
+}
+
+/// Pending or dead delivery of an event to one webhook.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Delivery {
+    pub(crate) id: DeliveryId,
+    pub(crate) webhook_id: WebhookId,
+    pub(crate) owner: UserId,
+    pub(crate) event: WebhookEvent,
+    pub(crate) attempts: u32,
+    /// Unix timestamp in milliseconds.
+    pub(crate) next_attempt_at: i64,
+    pub(crate) last_error: Option<String>,
+}
+
+impl HasId<DeliveryId> for Delivery {
+    fn id(&self) -> DeliveryId {
+        self.id
+    }
+}
@@ -2,6 +2,7 @@ pub(crate) mod blocking_task_guard;
 pub(crate) mod measure_metrics;
 pub(crate) mod metrics;
 pub(crate) mod root_span;
+pub(crate) mod storage_metrics;
 
 pub(crate) static JWT_SECRET_KEY: &str = "JWT_SECRET";
 pub(crate) static APP_NAME: &str = "todo_app";
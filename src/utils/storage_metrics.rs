@@ -0,0 +1,89 @@
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use opentelemetry::KeyValue;
+
+use super::APP_NAME;
+use crate::storage::StorageStats;
+
+/// Last statistics collected by the stats job, the gauges report them until
+/// the next run.
+static STORAGE_STATS: Lazy<RwLock<Option<StorageStats>>> = Lazy::new(|| RwLock::new(None));
+
+pub(crate) fn record_storage_stats(stats: &StorageStats) {
+    *STORAGE_STATS.write().expect("storage stats lock poisoned") = Some(stats.clone());
+}
+
+fn observe(f: impl FnOnce(&StorageStats)) {
+    if let Some(stats) = &*STORAGE_STATS.read().expect("storage stats lock poisoned") {
+        f(stats);
+    }
+}
+
+pub(crate) fn init_storage_metrics() {
+    let meter = opentelemetry::global::meter(APP_NAME);
+
+    let _gauge_size = meter
+        .u64_observable_gauge("storage_size_on_disk_bytes")
+        .with_description("Bytes the database takes on disk")
+        .with_unit("bytes")
+        .with_callback(|observer| observe(|stats| observer.observe(stats.size_on_disk, &[])))
+        .build();
+
+    let _gauge_records = meter
+        .u64_observable_gauge("storage_tree_records")
+        .with_description("Records in each storage tree")
+        .with_callback(|observer| {
+            observe(|stats| {
+                for (tree, records) in &stats.records {
+                    observer.observe(*records as u64, &[KeyValue::new("tree", *tree)]);
+                }
+            })
+        })
+        .build();
+
+    let _gauge_users_by_todos = meter
+        .u64_observable_gauge("storage_users_by_todos")
+        .with_description("Users by how many todos they have, at most `up_to`")
+        .with_callback(|observer| {
+            observe(|stats| {
+                for bucket in &stats.todos_per_user {
+                    let up_to = bucket
+                        .up_to
+                        .map_or_else(|| "+Inf".to_string(), |up_to| up_to.to_string());
+                    observer.observe(bucket.users as u64, &[KeyValue::new("up_to", up_to)]);
+                }
+            })
+        })
+        .build();
+
+    // ranks rather than user ids, so that the series don't change with the users
+    let _gauge_top_users = meter
+        .u64_observable_gauge("storage_top_user_todos")
+        .with_description("Todos of the users with the most todos, by rank")
+        .with_callback(|observer| {
+            observe(|stats| {
+                for (rank, (_, todos)) in stats.top_users.iter().enumerate() {
+                    observer.observe(*todos as u64, &[KeyValue::new("rank", rank as i64 + 1)]);
+                }
+            })
+        })
+        .build();
+
+    let _gauge_sessions = meter
+        .u64_observable_gauge("storage_sessions")
+        .with_description("Stored sessions, active or expired")
+        .with_callback(|observer| {
+            observe(|stats| {
+                observer.observe(
+                    stats.active_sessions as u64,
+                    &[KeyValue::new("state", "active")],
+                );
+                observer.observe(
+                    stats.expired_sessions as u64,
+                    &[KeyValue::new("state", "expired")],
+                );
+            })
+        })
+        .build();
+}
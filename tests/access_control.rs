@@ -1,14 +1,18 @@
 mod common;
-use common::{create_test_app, spawn_test_app, CreateTodoResponse, TestAppClient};
-use reqwest::StatusCode;
+use common::{assert_api_error, create_test_app, logged_in, spawn_test_app};
+use todo_client::{ErrorCode, StatusCode};
 
 #[tokio::test]
 async fn access_todo_without_token() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let res = client.create_todo(None, None).await;
+    // the client doesn't send requests without a session
+    let res = reqwest::Client::new()
+        .post(handle.address.join("todos").unwrap())
+        .json(&serde_json::json!({ "text": "pay rent" }))
+        .send()
+        .await
+        .unwrap();
 
     assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
 }
@@ -17,17 +21,13 @@ async fn access_todo_without_token() {
 async fn access_other_user_todo() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    // user A
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
-
-    let res = client.create_todo(Some(&tokens.access_token), None).await;
-    let todo_id = res.json::<CreateTodoResponse>().await.unwrap().0;
-
-    // user B
-    let tokens = client.register_and_login("userB@gmail.com", "123").await;
+    let user_a = logged_in(&handle, "userA@gmail.com", "123").await;
+    let todo_id = user_a.create_todo("pay rent", None).await.unwrap();
 
-    let res = client.get_todo(&tokens.access_token, &todo_id).await;
-    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let user_b = logged_in(&handle, "userB@gmail.com", "123").await;
+    assert_api_error(
+        user_b.get_todo(todo_id).await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
 }
@@ -1,105 +1,116 @@
 mod common;
-use common::{create_test_app, spawn_test_app, LoginResponse, TestAppClient};
-use reqwest::StatusCode;
+use common::{assert_api_error, assert_status, create_test_app, spawn_test_app};
+use todo_client::{Client, ErrorCode, StatusCode};
 
 #[tokio::test]
 async fn register_success() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = Client::new(handle.address.clone());
 
-    let res = client.register_user("aaa@gmail.com", "123").await;
-
-    assert_eq!(res.status(), StatusCode::CREATED);
+    client.register("aaa@gmail.com", "123").await.unwrap();
 }
 
 #[tokio::test]
 async fn register_duplicate_email() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let res = client.register_user("aaa@gmail.com", "123").await;
+    let client = Client::new(handle.address.clone());
 
-    assert_eq!(res.status(), StatusCode::CREATED);
+    client.register("aaa@gmail.com", "123").await.unwrap();
 
-    let res = client.register_user("aaa@gmail.com", "123").await;
-
-    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert_api_error(
+        client.register("aaa@gmail.com", "123").await,
+        StatusCode::CONFLICT,
+        ErrorCode::UserAlreadyExists,
+    );
 }
 
 #[tokio::test]
 async fn login_success() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let res = client.register_user("aaa@gmail.com", "123").await;
+    let client = Client::new(handle.address.clone());
 
-    assert_eq!(res.status(), StatusCode::CREATED);
+    client.register("aaa@gmail.com", "123").await.unwrap();
 
-    let res = client.login_user("aaa@gmail.com", "123").await;
-
-    assert_eq!(res.status(), StatusCode::OK);
-
-    let token = res.json::<LoginResponse>().await.unwrap().access_token;
-    assert_ne!(token.len(), 0);
+    let tokens = client.login("aaa@gmail.com", "123").await.unwrap();
+    assert_ne!(tokens.access_token.len(), 0);
 }
 
 #[tokio::test]
 async fn login_wrong_password() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let res = client.register_user("aaa@gmail.com", "123").await;
+    let client = Client::new(handle.address.clone());
 
-    assert_eq!(res.status(), StatusCode::CREATED);
+    client.register("aaa@gmail.com", "123").await.unwrap();
 
-    let res = client.login_user("aaa@gmail.com", "xxx").await;
-
-    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_status(
+        client.login("aaa@gmail.com", "xxx").await,
+        StatusCode::UNAUTHORIZED,
+    );
 }
 
 #[tokio::test]
 async fn login_nonexistent_user() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let res = client.login_user("aaa@gmail.com", "xxx").await;
+    let client = Client::new(handle.address.clone());
 
-    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_status(
+        client.login("aaa@gmail.com", "xxx").await,
+        StatusCode::UNAUTHORIZED,
+    );
 }
 
 #[tokio::test]
 async fn register_with_missing_fields() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = Client::new(handle.address.clone());
 
-    let res = client.register_user("", "123").await;
-    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_status(client.register("", "123").await, StatusCode::BAD_REQUEST);
+    assert_status(client.register("aaa", "").await, StatusCode::BAD_REQUEST);
+    assert_status(client.register("", "").await, StatusCode::BAD_REQUEST);
+}
 
-    let res = client.register_user("aaa", "").await;
-    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+#[tokio::test]
+async fn email_too_long() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let res = client.register_user("", "").await;
-    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let client = Client::new(handle.address.clone());
+
+    // longer than a storage key segment can hold
+    let long = format!("{}@gmail.com", "a".repeat(70_000));
+    assert_status(
+        client.register(&long, "123").await,
+        StatusCode::UNPROCESSABLE_ENTITY,
+    );
+    assert_status(
+        client.login(&long, "123").await,
+        StatusCode::UNPROCESSABLE_ENTITY,
+    );
+
+    assert_status(
+        client
+            .register(&format!("{}@gmail.com", "a".repeat(245)), "123")
+            .await,
+        StatusCode::UNPROCESSABLE_ENTITY,
+    );
+    client
+        .register(&format!("{}@gmail.com", "a".repeat(244)), "123")
+        .await
+        .unwrap();
 }
 
 #[tokio::test]
 async fn login_with_missing_fields() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let res = client.login_user("", "123").await;
-    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
-
-    let res = client.login_user("aaa", "").await;
-    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let client = Client::new(handle.address.clone());
 
-    let res = client.login_user("", "").await;
-    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_status(client.login("", "123").await, StatusCode::BAD_REQUEST);
+    assert_status(client.login("aaa", "").await, StatusCode::BAD_REQUEST);
+    assert_status(client.login("", "").await, StatusCode::BAD_REQUEST);
 }
@@ -0,0 +1,40 @@
+mod common;
+use common::{assert_status, create_test_app, logged_in, spawn_test_app};
+use todo_client::StatusCode;
+
+// matches `[storage.backup]` of config/test.toml, which keeps 2 archives
+const BACKUP_DIR: &str = "target/test_backups";
+
+#[tokio::test]
+async fn create_and_keep_backups() {
+    let _ = std::fs::remove_dir_all(BACKUP_DIR);
+    let handle = spawn_test_app(create_test_app(Some("test")).await).await;
+
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+    user.create_todo("aaa", None).await.unwrap();
+
+    assert_status(user.create_backup().await, StatusCode::FORBIDDEN);
+
+    let backup = admin.create_backup().await.unwrap();
+    assert!(backup.name.ends_with(".todo-backup"), "{}", backup.name);
+    assert_eq!(
+        backup.records,
+        backup.trees.iter().map(|tree| tree.records).sum::<u64>()
+    );
+    let todos = backup.trees.iter().find(|tree| tree.name == "todos");
+    assert_eq!(todos.map(|tree| tree.records), Some(1));
+
+    // only the 2 newest archives are kept
+    let mut names = vec![backup.name];
+    for _ in 0..2 {
+        names.push(admin.create_backup().await.unwrap().name);
+    }
+    let backups = admin.backups().await.unwrap();
+    names.reverse();
+    assert_eq!(
+        backups.iter().map(|b| b.name.clone()).collect::<Vec<_>>(),
+        names[..2]
+    );
+    assert!(backups.iter().all(|b| b.size > 0));
+}
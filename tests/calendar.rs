@@ -0,0 +1,59 @@
+mod common;
+use common::{assert_status, create_test_app, logged_in, spawn_test_app};
+use futures_util::TryStreamExt;
+use todo_client::{BatchOperation, StatusCode, TransferFormat};
+
+#[tokio::test]
+async fn calendar_feed() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    client
+        .batch(&[
+            BatchOperation::Create {
+                text: "pay rent".to_owned(),
+                due: Some(1_700_000_000),
+            },
+            BatchOperation::Create {
+                text: "read book".to_owned(),
+                due: None,
+            },
+        ])
+        .await
+        .unwrap();
+
+    let first = client.create_calendar_token().await.unwrap();
+
+    let calendar = client.calendar_feed(&first.feed).await.unwrap();
+    assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+    assert_eq!(calendar.matches("BEGIN:VTODO").count(), 2);
+    assert!(calendar.contains("DUE:20231114T221320Z\r\n"));
+
+    // a new token replaces the old one
+    let second = client.create_calendar_token().await.unwrap();
+    assert_status(
+        client.calendar_feed(&first.feed).await,
+        StatusCode::NOT_FOUND,
+    );
+
+    client.revoke_calendar_token().await.unwrap();
+    assert_status(
+        client.calendar_feed(&second.feed).await,
+        StatusCode::NOT_FOUND,
+    );
+
+    // the feed can be imported back
+    let other = logged_in(&handle, "other@gmail.com", "123").await;
+    assert_eq!(
+        other
+            .import_todos(TransferFormat::Ical, calendar)
+            .await
+            .unwrap(),
+        2
+    );
+
+    let todos: Vec<_> = other.todos(10).try_collect().await.unwrap();
+    assert!(todos.iter().any(|todo| todo.due == Some(1_700_000_000)));
+}
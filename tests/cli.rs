@@ -0,0 +1,259 @@
+mod common;
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use common::{create_test_app, spawn_test_app, TestAppHandle};
+use serial_test::{parallel, serial};
+use todo_cli::{Cli, CliError, Config};
+use todo_client::Client;
+use uuid::Uuid;
+
+/// Runs the CLI against the test app with its own config file.
+struct Terminal {
+    server: String,
+    config: PathBuf,
+}
+
+impl Terminal {
+    fn new(handle: &TestAppHandle) -> Self {
+        let config = std::env::temp_dir().join(format!("todo-cli-{}.toml", Uuid::new_v4()));
+        Self {
+            server: handle.address.to_string(),
+            config,
+        }
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String, CliError> {
+        let config = self.config.to_str().unwrap();
+        let mut argv = vec!["todo", "--config", config, "--server", &self.server];
+        argv.extend_from_slice(args);
+        let mut out = Vec::new();
+        todo_cli::run(Cli::try_parse_from(argv).unwrap(), &mut out).await?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    async fn logged_in(handle: &TestAppHandle, email: &str, password: &str) -> Self {
+        let _ = Client::new(handle.address.clone())
+            .register(email, password)
+            .await;
+        let terminal = Self::new(handle);
+        terminal
+            .run(&["login", email, "--password", password])
+            .await
+            .unwrap();
+        terminal
+    }
+
+    fn saved(&self) -> Config {
+        Config::load(&self.config).unwrap()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.config);
+    }
+}
+
+#[tokio::test]
+#[parallel]
+async fn cli_session() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let terminal = Terminal::new(&handle);
+
+    assert!(matches!(
+        terminal.run(&["ls"]).await,
+        Err(CliError::NotLoggedIn)
+    ));
+    Client::new(handle.address.clone())
+        .register("user@gmail.com", "123")
+        .await
+        .unwrap();
+    assert!(matches!(
+        terminal
+            .run(&["login", "user@gmail.com", "--password", "wrong"])
+            .await,
+        Err(CliError::Client(_))
+    ));
+    assert_eq!(terminal.saved().tokens, None);
+
+    terminal
+        .run(&["login", "user@gmail.com", "--password", "123"])
+        .await
+        .unwrap();
+    let saved = terminal.saved();
+    assert_eq!(saved.server.as_deref(), Some(terminal.server.as_str()));
+    assert!(saved.tokens.is_some());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&terminal.config)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // the server url is remembered
+    let mut out = Vec::new();
+    let cli = Cli::try_parse_from(["todo", "--config", terminal.config.to_str().unwrap(), "ls"]);
+    todo_cli::run(cli.unwrap(), &mut out).await.unwrap();
+    assert!(out.is_empty());
+
+    terminal.run(&["logout"]).await.unwrap();
+    assert_eq!(terminal.saved().tokens, None);
+    assert!(matches!(
+        terminal.run(&["add", "milk"]).await,
+        Err(CliError::NotLoggedIn)
+    ));
+}
+
+#[tokio::test]
+#[parallel]
+async fn cli_todos() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let terminal = Terminal::logged_in(&handle, "user@gmail.com", "123").await;
+
+    let milk = terminal
+        .run(&[
+            "add",
+            "buy",
+            "milk",
+            "--group",
+            "shop",
+            "--due",
+            "2030-01-02",
+        ])
+        .await
+        .unwrap();
+    let milk = milk.trim().to_string();
+    assert!(milk.parse::<Uuid>().is_ok());
+    let rent = terminal.run(&["add", "pay rent"]).await.unwrap();
+    let rent = rent.trim().to_string();
+    let bread = terminal
+        .run(&[
+            "add",
+            "Bread",
+            "-g",
+            "shop",
+            "--due",
+            "2030-01-01T10:30:00Z",
+        ])
+        .await
+        .unwrap();
+    let bread = bread.trim().to_string();
+
+    let listed = terminal.run(&["ls"]).await.unwrap();
+    assert_eq!(listed.lines().count(), 3);
+    assert!(listed.contains(&format!(
+        "{}  [ ] buy milk  #shop  due 2030-01-02",
+        &milk[..8]
+    )));
+    assert!(listed.contains(&format!(
+        "{}  [ ] Bread  #shop  due 2030-01-01 10:30",
+        &bread[..8]
+    )));
+    assert!(listed.contains(&format!("{}  [ ] pay rent\n", &rent[..8])));
+
+    // the short ids of `ls` are accepted
+    terminal.run(&["done", &milk[..8]]).await.unwrap();
+    let done = terminal.run(&["ls", "--done"]).await.unwrap();
+    assert_eq!(done.lines().count(), 1);
+    assert!(done.contains("[x] buy milk"));
+    let open = terminal
+        .run(&["ls", "--open", "--group", "shop"])
+        .await
+        .unwrap();
+    assert_eq!(open.lines().count(), 1);
+    assert!(open.contains("Bread"));
+    let found = terminal.run(&["ls", "-s", "bread"]).await.unwrap();
+    assert_eq!(found.lines().count(), 1);
+    let due = terminal
+        .run(&["ls", "--due-before", "2030-01-02"])
+        .await
+        .unwrap();
+    assert_eq!(due.lines().count(), 1);
+    assert!(due.contains("Bread"));
+
+    terminal
+        .run(&[
+            "edit",
+            &rent,
+            "--text",
+            "pay the rent",
+            "--due",
+            "2030-02-01",
+        ])
+        .await
+        .unwrap();
+    terminal.run(&["edit", &bread, "--no-due"]).await.unwrap();
+    terminal.run(&["done", &milk, "--reopen"]).await.unwrap();
+    let json = terminal.run(&["ls", "--json"]).await.unwrap();
+    let todos: Vec<todo_client::Todo> = serde_json::from_str(&json).unwrap();
+    assert_eq!(todos.len(), 3);
+    assert!(todos.iter().all(|todo| !todo.completed));
+    let rent_todo = todos.iter().find(|todo| todo.id.to_string() == rent);
+    assert_eq!(rent_todo.unwrap().text, "pay the rent");
+    let bread_todo = todos.iter().find(|todo| todo.id.to_string() == bread);
+    assert_eq!(bread_todo.unwrap().due, None);
+
+    assert!(matches!(
+        terminal.run(&["done", "zzz"]).await,
+        Err(CliError::UnknownId(_))
+    ));
+    assert!(matches!(
+        terminal.run(&["rm", ""]).await,
+        Err(CliError::AmbiguousId(_))
+    ));
+
+    terminal.run(&["rm", &milk, &rent[..8]]).await.unwrap();
+    let left = terminal.run(&["ls"]).await.unwrap();
+    assert_eq!(left.lines().count(), 1);
+    assert!(left.contains("Bread"));
+}
+
+#[tokio::test]
+#[parallel]
+async fn cli_export_and_import() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let terminal = Terminal::logged_in(&handle, "user@gmail.com", "123").await;
+    terminal.run(&["add", "milk"]).await.unwrap();
+    terminal.run(&["add", "eggs"]).await.unwrap();
+
+    let csv = terminal.run(&["export"]).await.unwrap();
+    assert!(csv.contains("milk") && csv.contains("eggs"));
+    let file = terminal.config.with_extension("ndjson");
+    terminal
+        .run(&["export", "-f", "ndjson", "-o", file.to_str().unwrap()])
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&file).unwrap().lines().count(), 2);
+
+    let other = Terminal::logged_in(&handle, "other@gmail.com", "123").await;
+    let imported = other
+        .run(&["import", "--format", "ndjson", file.to_str().unwrap()])
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(imported, "Imported 2 todos\n");
+    let listed = other.run(&["ls"]).await.unwrap();
+    assert!(listed.contains("milk") && listed.contains("eggs"));
+}
+
+#[tokio::test]
+#[serial]
+async fn cli_saves_refreshed_tokens() {
+    std::env::set_var("APP__JWT__ACCESS_TOKEN_TTL_SEC", "1");
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    std::env::remove_var("APP__JWT__ACCESS_TOKEN_TTL_SEC");
+
+    let terminal = Terminal::logged_in(&handle, "user@gmail.com", "123").await;
+    let tokens = terminal.saved().tokens.unwrap();
+    tokio::time::sleep(Duration::from_millis(2_100)).await;
+
+    terminal.run(&["add", "milk"]).await.unwrap();
+    let refreshed = terminal.saved().tokens.unwrap();
+    assert_ne!(refreshed.refresh_token, tokens.refresh_token);
+    // the saved pair is the one the server expects next
+    assert!(terminal.run(&["ls"]).await.unwrap().contains("milk"));
+}
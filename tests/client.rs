@@ -0,0 +1,293 @@
+mod common;
+use std::time::Duration;
+
+use common::{assert_api_error, create_test_app, logged_in, spawn_test_app};
+use futures_util::{StreamExt, TryStreamExt};
+use serial_test::{parallel, serial};
+use todo_client::{
+    BatchOperation, Client, ClientError, ErrorCode, Role, StatusCode, TodoEventKind, TodoOpResult,
+    TransferFormat, UpdateTodo,
+};
+use tokio::time::timeout;
+
+#[tokio::test]
+#[parallel]
+async fn client_session() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let client = Client::new(handle.address.clone());
+
+    assert!(matches!(
+        client.create_todo("milk", None).await,
+        Err(ClientError::NotLoggedIn)
+    ));
+    client.register("user@gmail.com", "123").await.unwrap();
+    assert_api_error(
+        client.register("user@gmail.com", "123").await,
+        StatusCode::CONFLICT,
+        ErrorCode::UserAlreadyExists,
+    );
+    assert_api_error(
+        client.login("user@gmail.com", "wrong").await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::PasswordMismatch,
+    );
+
+    let tokens = client.login("user@gmail.com", "123").await.unwrap();
+    let refreshed = client.refresh().await.unwrap();
+    assert_ne!(refreshed, tokens);
+    assert_eq!(client.tokens().await, Some(refreshed.clone()));
+
+    // a saved session can be resumed
+    let resumed = Client::new(handle.address.clone()).with_tokens(refreshed);
+    resumed.create_todo("milk", None).await.unwrap();
+
+    client.logout().await.unwrap();
+    assert_eq!(client.tokens().await, None);
+    assert_api_error(
+        resumed.create_todo("bread", None).await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::InvalidSession,
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn client_refreshes_expired_access_token() {
+    std::env::set_var("APP__JWT__ACCESS_TOKEN_TTL_SEC", "1");
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    std::env::remove_var("APP__JWT__ACCESS_TOKEN_TTL_SEC");
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    let tokens = client.tokens().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(2_100)).await;
+
+    let id = client.create_todo("milk", None).await.unwrap();
+    assert_eq!(client.get_todo(id).await.unwrap().todo.text, "milk");
+    let refreshed = client.tokens().await.unwrap();
+    assert_ne!(refreshed.access_token, tokens.access_token);
+    assert_ne!(refreshed.refresh_token, tokens.refresh_token);
+}
+
+#[tokio::test]
+#[parallel]
+async fn client_todos() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let mut ids = Vec::new();
+    for text in ["first", "second", "third", "fourth", "fifth"] {
+        ids.push(client.create_todo(text, None).await.unwrap());
+    }
+    let todos: Vec<_> = client.todos(2).try_collect().await.unwrap();
+    assert_eq!(todos.len(), 5);
+    let page = client.todos_page(2, None).await.unwrap();
+    assert_eq!(page.items.len(), 2);
+    assert!(page.cursor.is_some());
+
+    client
+        .update_todo(
+            ids[0],
+            &UpdateTodo::default()
+                .text("first, edited")
+                .group("home")
+                .due(Some(1_700_000_000)),
+        )
+        .await
+        .unwrap();
+    client.add_blocker(ids[0], ids[1]).await.unwrap();
+    let details = client.get_todo(ids[0]).await.unwrap();
+    assert_eq!(details.todo.text, "first, edited");
+    assert_eq!(details.todo.due, Some(1_700_000_000));
+    assert_eq!(details.links.blocked_by, vec![ids[1]]);
+
+    assert_api_error(
+        client
+            .update_todo(ids[0], &UpdateTodo::default().completed(true))
+            .await,
+        StatusCode::CONFLICT,
+        ErrorCode::TodoBlocked,
+    );
+    assert_api_error(
+        client.update_todo(ids[0], &UpdateTodo::default()).await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::EmptyPatch,
+    );
+    client
+        .update_todo(ids[0], &UpdateTodo::default().due(None))
+        .await
+        .unwrap();
+    assert_eq!(client.get_todo(ids[0]).await.unwrap().todo.due, None);
+
+    let updated = client
+        .update_group(
+            "home",
+            &UpdateTodo::default().completed(true).ignore_blockers(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(updated, 1);
+
+    let e = assert_api_error(
+        client
+            .batch(&[
+                BatchOperation::Create {
+                    text: "sixth".into(),
+                    due: None,
+                },
+                BatchOperation::Delete { id: ids[4] },
+                BatchOperation::Update {
+                    id: ids[4],
+                    patch: UpdateTodo::default().text("gone"),
+                },
+            ])
+            .await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::BatchOperation,
+    );
+    let ClientError::Api { body, .. } = e else {
+        unreachable!()
+    };
+    assert_eq!(body.index, Some(2));
+    assert_eq!(body.source, Some(ErrorCode::NotFound));
+
+    let results = client
+        .batch(&[BatchOperation::Delete { id: ids[4] }])
+        .await
+        .unwrap();
+    assert_eq!(results, vec![TodoOpResult::Deleted { id: ids[4] }]);
+    let trash: Vec<_> = client.trash(10).try_collect().await.unwrap();
+    assert_eq!(trash.len(), 1);
+    client.restore_todo(ids[4]).await.unwrap();
+
+    client.delete_todo(ids[3]).await.unwrap();
+    let undone = client.undo().await.unwrap();
+    assert_eq!(undone.count, 1);
+    let history: Vec<_> = client.history(ids[3], 1).try_collect().await.unwrap();
+    assert!(history.len() >= 2);
+
+    assert_api_error(
+        client
+            .get_todo(todo_client::TodoId(uuid::Uuid::new_v4()))
+            .await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
+}
+
+#[tokio::test]
+#[parallel]
+async fn client_export_and_import() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    client.create_todo("milk", None).await.unwrap();
+    let exported = client.export_todos(TransferFormat::Ndjson).await.unwrap();
+    assert_eq!(exported.lines().count(), 1);
+
+    let other = logged_in(&handle, "other@gmail.com", "123").await;
+    let imported = other
+        .import_todos(TransferFormat::Ndjson, exported)
+        .await
+        .unwrap();
+    assert_eq!(imported, 1);
+
+    let e = assert_api_error(
+        other
+            .import_todos(
+                TransferFormat::Csv,
+                "id,text,completed,group,due\nnot,a,row\n",
+            )
+            .await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidImport,
+    );
+    let ClientError::Api { body, .. } = e else {
+        unreachable!()
+    };
+    assert_eq!(body.errors[0].line, 2);
+
+    let calendar = client.create_calendar_token().await.unwrap();
+    let feed = Client::new(handle.address.clone())
+        .calendar_feed(&calendar.feed)
+        .await
+        .unwrap();
+    assert!(feed.contains("SUMMARY:milk"));
+}
+
+#[tokio::test]
+#[parallel]
+async fn client_events() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let mut events = Box::pin(client.events(None).await.unwrap());
+    let id = client.create_todo("milk", None).await.unwrap();
+    client.delete_todo(id).await.unwrap();
+
+    let created = timeout(Duration::from_secs(5), events.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(created.kind, TodoEventKind::Created);
+    assert_eq!(created.todo.unwrap().text, "milk");
+    let deleted = timeout(Duration::from_secs(5), events.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(deleted.kind, TodoEventKind::Deleted);
+    assert_eq!(deleted.todo_id, Some(id));
+
+    // resuming replays what came after the given event
+    let mut resumed = Box::pin(client.events(Some(created.id)).await.unwrap());
+    let replayed = timeout(Duration::from_secs(5), resumed.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(replayed, deleted);
+}
+
+#[tokio::test]
+#[serial]
+async fn client_admin() {
+    // the admin routes allow only small bursts by default
+    const BURSTS: [&str; 2] = [
+        "APP__RATE_LIMITER__ADMIN__GLOBAL__BURST_PER_SECOND",
+        "APP__RATE_LIMITER__ADMIN__PER_IP__BURST_PER_SECOND",
+    ];
+    for name in BURSTS {
+        std::env::set_var(name, "20");
+    }
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    for name in BURSTS {
+        std::env::remove_var(name);
+    }
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+
+    let e = user.users_page(10, None).await.unwrap_err();
+    assert!(matches!(
+        e,
+        ClientError::Status {
+            status: StatusCode::FORBIDDEN,
+            ..
+        }
+    ));
+
+    let found = admin.user_by_email("user@gmail.com").await.unwrap();
+    assert_eq!(found.role, Role::User);
+    let users: Vec<_> = admin.users(1).try_collect().await.unwrap();
+    assert!(users.contains(&found));
+
+    admin.set_role(found.id, Role::Admin).await.unwrap();
+    assert_eq!(user.user(found.id).await.unwrap().role, Role::Admin);
+
+    admin.delete_user(found.id).await.unwrap();
+    assert_api_error(
+        admin.user(found.id).await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
+}
@@ -1,46 +1,113 @@
 #![allow(dead_code, unused_imports)]
 
-mod client;
 mod server;
 
+use std::fmt::Debug;
 use std::sync::Arc;
 
 use axum::Router;
-pub use client::TestAppClient;
 use todo_app::Service;
-use todo_app::{build_app, Settings};
+use todo_app::{
+    build_app, build_grpc, spawn_webhook_dispatcher, Faults, RateLimiters, Settings, WebhookConfig,
+};
+use tonic::service::Routes;
 
-pub use server::{spawn_test_app, TestAppHandle};
+pub use server::{spawn_test_app, spawn_test_grpc, TestAppHandle};
 use todo_app::TestStorageBuilder;
+use todo_client::{Client, ClientError, ErrorCode, StatusCode};
 
-#[derive(Debug, serde::Deserialize)]
-pub struct LoginResponse {
-    pub access_token: String,
-    pub refresh_token: String,
+/// Session of the user, who is registered first unless they already exist.
+pub async fn logged_in(handle: &TestAppHandle, email: &str, password: &str) -> Client {
+    let client = Client::new(handle.address.clone());
+    let _ = client.register(email, password).await;
+    client.login(email, password).await.unwrap();
+    client
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct UnauthorizedBody {
-    pub error: String,
-    pub message: String,
+/// Asserts the request failed with `status`, e.g. a rejection without an error body.
+pub fn assert_status<T: Debug>(result: Result<T, ClientError>, status: StatusCode) -> ClientError {
+    let e = result.unwrap_err();
+    assert_eq!(e.status(), Some(status), "{e:?}");
+    e
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct CreateTodoResponse(pub String);
+pub fn assert_api_error<T: Debug>(
+    result: Result<T, ClientError>,
+    status: StatusCode,
+    code: ErrorCode,
+) -> ClientError {
+    let e = assert_status(result, status);
+    assert_eq!(e.code(), Some(code), "{e:?}");
+    e
+}
 
 pub async fn create_test_app(settings_file: Option<&str>) -> Router {
-    let todo_storage = TestStorageBuilder::new().build_todo().await;
-    let user_storage = TestStorageBuilder::new().build_user().await;
-    let session_storage = TestStorageBuilder::new().build_session().await;
-    let flush_storage = TestStorageBuilder::new().build_flush().await;
+    let (service, settings) = create_test_service(settings_file).await;
+    let limiters = RateLimiters::new(&settings);
+    build_app(service, settings, &limiters)
+}
+
+/// Test app with the webhook dispatcher running next to it.
+pub async fn create_test_app_with_webhooks(config: &WebhookConfig) -> Router {
+    let (service, settings) = create_test_service(None).await;
+    spawn_webhook_dispatcher(service.clone(), config);
+    let limiters = RateLimiters::new(&settings);
+    build_app(service, settings, &limiters)
+}
+
+/// Test app whose storage calls take the given faults.
+pub async fn create_test_app_with_faults(faults: &Faults) -> Router {
+    let storage = TestStorageBuilder::new().with_faults(faults);
+    let (service, settings) = create_test_service_with(storage, Some("test")).await;
+    let limiters = RateLimiters::new(&settings);
+    build_app(service, settings, &limiters)
+}
+
+pub async fn create_test_grpc() -> Routes {
+    let (service, settings) = create_test_service(None).await;
+    let limiters = RateLimiters::new(&settings);
+    build_grpc(service, settings, &limiters)
+}
+
+/// REST app and gRPC routes of one service, taking from the same rate limits.
+pub async fn create_test_servers() -> (Router, Routes) {
+    let (service, settings) = create_test_service(None).await;
+    let limiters = RateLimiters::new(&settings);
+    let grpc = build_grpc(service.clone(), settings.clone(), &limiters);
+    (build_app(service, settings, &limiters), grpc)
+}
+
+pub async fn create_test_service(settings_file: Option<&str>) -> (Service, Settings) {
+    // one database, so that todo and user changes reach the webhook outbox
+    create_test_service_with(TestStorageBuilder::new(), settings_file).await
+}
+
+async fn create_test_service_with(
+    storage: TestStorageBuilder,
+    settings_file: Option<&str>,
+) -> (Service, Settings) {
+    let todo_storage = storage.build_todo().await;
+    let user_storage = storage.build_user().await;
+    let session_storage = storage.build_session().await;
+    let flush_storage = storage.build_flush().await;
+    let webhook_storage = storage.build_webhook().await;
+    let maintenance_storage = storage.build_maintenance().await;
 
     let settings = match settings_file {
         Some(file_name) => Settings::from_file(file_name).unwrap(),
         None => Settings::new().unwrap(),
     };
 
-    let service = Service::new(todo_storage, user_storage, session_storage, flush_storage).await;
+    let service = Service::new(
+        todo_storage,
+        user_storage,
+        session_storage,
+        flush_storage,
+        webhook_storage,
+        maintenance_storage,
+    )
+    .await;
     service.user().create_admins(&settings).await.unwrap();
 
-    build_app(service, settings)
+    (service, settings)
 }
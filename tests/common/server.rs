@@ -4,6 +4,8 @@ use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::service::Routes;
 
 pub struct TestAppHandle {
     pub address: Url,
@@ -33,3 +35,28 @@ pub async fn spawn_test_app(app: Router) -> TestAppHandle {
         _server_task: server_task,
     }
 }
+
+pub async fn spawn_test_grpc(routes: Routes) -> TestAppHandle {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+    let server = tonic::transport::Server::builder()
+        .add_routes(routes)
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+            shutdown_rx.await.ok();
+        });
+
+    let server_task = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            println!("grpc server error : {e:?}");
+        }
+    });
+
+    TestAppHandle {
+        address: Url::parse(&format!("http://{addr}")).unwrap(),
+        _shutdown: shutdown_tx,
+        _server_task: server_task,
+    }
+}
@@ -0,0 +1,213 @@
+mod common;
+use std::time::Duration;
+
+use common::{assert_status, create_test_app, logged_in, spawn_test_app};
+use futures_util::{SinkExt, Stream, StreamExt};
+use reqwest::Url;
+use todo_client::{
+    Client, ClientError, LoginToken, StatusCode, TodoEvent, TodoEventKind, UpdateTodo,
+};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+async fn next_event(
+    events: &mut (impl Stream<Item = Result<TodoEvent, ClientError>> + Unpin),
+) -> TodoEvent {
+    timeout(WAIT, events.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+}
+
+/// Reads the response until the next event with data, skipping keep-alive comments.
+async fn next_sse_event(res: &mut reqwest::Response, buf: &mut String) -> (String, TodoEvent) {
+    loop {
+        if let Some(end) = buf.find("\n\n") {
+            let block = buf[..end].to_owned();
+            buf.drain(..end + 2);
+
+            let mut kind = None;
+            let mut data = None;
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("event:") {
+                    kind = Some(value.trim().to_owned());
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data = Some(value.trim().to_owned());
+                }
+            }
+            if let (Some(kind), Some(data)) = (kind, data) {
+                return (kind, serde_json::from_str(&data).unwrap());
+            }
+            continue;
+        }
+
+        let chunk = timeout(WAIT, res.chunk()).await.unwrap().unwrap().unwrap();
+        buf.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+}
+
+#[tokio::test]
+async fn sse_events() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    // second session of the same user makes the changes
+    let other_session = logged_in(&handle, "user@gmail.com", "123").await;
+    let stranger = logged_in(&handle, "other@gmail.com", "123").await;
+
+    let invalid = Client::new(handle.address.clone()).with_tokens(LoginToken {
+        access_token: "invalid".to_owned(),
+        refresh_token: "invalid".to_owned(),
+    });
+    assert_status(
+        invalid.events(None).await.map(|_| ()),
+        StatusCode::UNAUTHORIZED,
+    );
+
+    let mut events = Box::pin(client.events(None).await.unwrap());
+
+    stranger.create_todo("not mine", None).await.unwrap();
+
+    let id = other_session.create_todo("pay rent", None).await.unwrap();
+
+    let created = next_event(&mut events).await;
+    assert_eq!(created.kind, TodoEventKind::Created);
+    assert_eq!(created.todo_id, Some(id));
+    assert_eq!(created.todo.unwrap().text, "pay rent");
+
+    other_session
+        .update_todo(id, &UpdateTodo::default().text("pay bills"))
+        .await
+        .unwrap();
+    let updated = next_event(&mut events).await;
+    assert_eq!(updated.kind, TodoEventKind::Updated);
+    assert_eq!(updated.todo.unwrap().text, "pay bills");
+
+    other_session.delete_todo(id).await.unwrap();
+    let deleted = next_event(&mut events).await;
+    assert_eq!(deleted.kind, TodoEventKind::Deleted);
+    assert_eq!(deleted.todo, None);
+    drop(events);
+
+    // reconnecting after the created event replays the rest
+    let mut events = Box::pin(client.events(Some(created.id)).await.unwrap());
+    assert_eq!(next_event(&mut events).await.id, updated.id);
+    assert_eq!(next_event(&mut events).await.id, deleted.id);
+
+    // events from before the start of the server are gone
+    let mut events = Box::pin(client.events(Some(0)).await.unwrap());
+    let reset = next_event(&mut events).await;
+    assert_eq!(reset.kind, TodoEventKind::Reset);
+    assert_eq!(reset.todo_id, None);
+    assert_eq!(next_event(&mut events).await.id, created.id);
+}
+
+/// `EventSource`s resume through the `Last-Event-ID` header, which the client doesn't send.
+#[tokio::test]
+async fn sse_last_event_id_header() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    let access_token = client.tokens().await.unwrap().access_token;
+
+    let mut events = Box::pin(client.events(None).await.unwrap());
+    let id = client.create_todo("pay rent", None).await.unwrap();
+    client.delete_todo(id).await.unwrap();
+    let created = next_event(&mut events).await;
+
+    let subscribe = |last_event_id: String| {
+        let mut url = handle.address.join("todos/events").unwrap();
+        url.query_pairs_mut().append_pair("last_event_id", "0");
+        reqwest::Client::new()
+            .get(url)
+            .bearer_auth(&access_token)
+            .header("Last-Event-ID", last_event_id)
+            .send()
+    };
+
+    // the header wins over the query, which would replay both events
+    let mut res = subscribe(created.id.to_string()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()["content-type"].to_str().unwrap(),
+        "text/event-stream"
+    );
+    let mut buf = String::new();
+    let (kind, deleted) = next_sse_event(&mut res, &mut buf).await;
+    assert_eq!(kind, "deleted");
+    assert_eq!(deleted.todo_id, Some(id));
+
+    let res = subscribe("latest".to_owned()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn websocket_events() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    let access_token = client.tokens().await.unwrap().access_token;
+
+    let request = events_ws_url(&handle.address, None)
+        .as_str()
+        .into_client_request()
+        .unwrap();
+    assert!(tokio_tungstenite::connect_async(request).await.is_err());
+
+    let mut request = events_ws_url(&handle.address, None)
+        .as_str()
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "Authorization",
+        format!("Bearer {access_token}").parse().unwrap(),
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+    let id = client.create_todo("read book", None).await.unwrap();
+
+    let message = timeout(WAIT, socket.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let created = serde_json::from_str::<TodoEvent>(message.to_text().unwrap()).unwrap();
+    assert_eq!(created.kind, TodoEventKind::Created);
+    assert_eq!(created.todo_id, Some(id));
+
+    client.delete_todo(id).await.unwrap();
+    socket.close(None).await.unwrap();
+
+    // resumed through the query, browsers can't set headers on a WebSocket
+    let mut request = events_ws_url(&handle.address, Some(created.id))
+        .as_str()
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "Authorization",
+        format!("Bearer {access_token}").parse().unwrap(),
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    let message = timeout(WAIT, socket.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let deleted = serde_json::from_str::<TodoEvent>(message.to_text().unwrap()).unwrap();
+    assert_eq!(deleted.kind, TodoEventKind::Deleted);
+    assert_eq!(deleted.todo_id, Some(id));
+    socket.send(Message::Close(None)).await.unwrap();
+}
+
+fn events_ws_url(address: &Url, last_event_id: Option<u64>) -> Url {
+    let mut url = address.join("todos/ws").unwrap();
+    url.set_scheme("ws").unwrap();
+    if let Some(last_event_id) = last_event_id {
+        url.query_pairs_mut()
+            .append_pair("last_event_id", &last_event_id.to_string());
+    }
+    url
+}
@@ -0,0 +1,252 @@
+mod common;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use common::{
+    assert_api_error, create_test_app_with_faults, logged_in, spawn_test_app, TestAppHandle,
+};
+use futures_util::TryStreamExt;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_sdk::metrics::{
+    data::{AggregatedMetrics, MetricData},
+    InMemoryMetricExporter, PeriodicReader, SdkMeterProvider,
+};
+use todo_app::{Fault, Faults};
+use todo_client::{Client, ClientError, ErrorCode, StatusCode};
+
+struct Metrics {
+    provider: SdkMeterProvider,
+    exporter: InMemoryMetricExporter,
+    /// Collections of concurrent tests would reset each other's exports.
+    collecting: Mutex<()>,
+}
+
+/// Meter provider of the app, installed before anything records a metric.
+/// Tests share it, so each checks points only its own requests make.
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let exporter = InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone()).build())
+            .build();
+        global::set_meter_provider(provider.clone());
+        Metrics {
+            provider,
+            exporter,
+            collecting: Mutex::new(()),
+        }
+    })
+}
+
+/// Points recorded so far, summed up over all the collected metrics.
+#[derive(Default)]
+struct Recorded {
+    count: f64,
+    histogram_count: u64,
+    histogram_sum: f64,
+}
+
+fn has_attributes<'a>(
+    attributes: impl Iterator<Item = &'a KeyValue>,
+    wanted: &[(&str, &str)],
+) -> bool {
+    let attributes: Vec<_> = attributes.collect();
+    wanted.iter().all(|(key, value)| {
+        attributes
+            .iter()
+            .any(|attribute| attribute.key.as_str() == *key && attribute.value.as_str() == *value)
+    })
+}
+
+fn recorded(name: &str, wanted: &[(&str, &str)]) -> Recorded {
+    let metrics = metrics();
+    let _collecting = metrics.collecting.lock().unwrap();
+    metrics.exporter.reset();
+    metrics.provider.force_flush().unwrap();
+
+    let mut recorded = Recorded::default();
+    let finished = metrics.exporter.get_finished_metrics().unwrap();
+    let points = finished
+        .iter()
+        .flat_map(|resource| resource.scope_metrics())
+        .flat_map(|scope| scope.metrics())
+        .filter(|metric| metric.name() == name);
+    for metric in points {
+        match metric.data() {
+            AggregatedMetrics::F64(MetricData::Sum(sum)) => {
+                recorded.count += sum
+                    .data_points()
+                    .filter(|point| has_attributes(point.attributes(), wanted))
+                    .map(|point| point.value())
+                    .sum::<f64>();
+            }
+            AggregatedMetrics::F64(MetricData::Histogram(histogram)) => {
+                for point in histogram
+                    .data_points()
+                    .filter(|point| has_attributes(point.attributes(), wanted))
+                {
+                    recorded.histogram_count += point.count();
+                    recorded.histogram_sum += point.sum();
+                }
+            }
+            _ => {}
+        }
+    }
+    recorded
+}
+
+async fn faulty_app(faults: &Faults) -> (TestAppHandle, Client) {
+    metrics();
+    let handle = spawn_test_app(create_test_app_with_faults(faults).await).await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    (handle, client)
+}
+
+fn assert_internal<T: std::fmt::Debug>(result: Result<T, ClientError>) {
+    assert_api_error(
+        result,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::InternalStorage,
+    );
+}
+
+#[tokio::test]
+async fn storage_errors() {
+    let faults = Faults::seeded(1);
+    let (_handle, client) = faulty_app(&faults).await;
+
+    let id = client.create_todo("first", None).await.unwrap();
+
+    faults.inject("todo::get", 1.0, Fault::Internal);
+    assert_internal(client.get_todo(id).await);
+    faults.clear();
+    faults.inject("todo::*", 1.0, Fault::Join);
+    assert_internal(client.get_todo(id).await);
+    assert_eq!(faults.injected("todo::get"), 2);
+
+    // the storage works again once the faults are gone
+    faults.clear();
+    client.get_todo(id).await.unwrap();
+}
+
+#[tokio::test]
+async fn latency_and_partial_failures() {
+    let faults = Faults::seeded(1);
+    let (_handle, client) = faulty_app(&faults).await;
+
+    let delay = Duration::from_millis(200);
+    faults.script("todo::put", [Some(Fault::Latency(delay))]);
+    let started = Instant::now();
+    client.create_todo("slow", None).await.unwrap();
+    assert!(started.elapsed() >= delay);
+
+    // the write lands even though the request fails
+    faults.script("todo::put", [Some(Fault::Partial)]);
+    assert_internal(client.create_todo("partial", None).await);
+    let todos: Vec<_> = client.todos(10).try_collect().await.unwrap();
+    assert!(todos.iter().any(|todo| todo.text == "partial"), "{todos:?}");
+}
+
+#[tokio::test]
+async fn scripted_and_random_faults() {
+    let faults = Faults::seeded(7);
+    let (_handle, client) = faulty_app(&faults).await;
+
+    // the first write fails, the retry goes through
+    faults.script("todo::put", [Some(Fault::Internal), None]);
+    assert_internal(client.create_todo("retried", None).await);
+    let id = client.create_todo("retried", None).await.unwrap();
+
+    // few enough requests to stay under the rate limiter, the seed makes the
+    // picks the same on every run
+    faults.inject("todo::get", 0.5, Fault::Internal);
+    let mut failed = 0;
+    for _ in 0..4 {
+        let result = client.get_todo(id).await;
+        if result.is_err() {
+            assert_internal(result);
+            failed += 1;
+        }
+    }
+    assert_eq!(faults.injected("todo::get"), failed);
+    assert!((1..4).contains(&failed), "{failed}");
+}
+
+#[tokio::test]
+async fn rate_limits_hold_while_storage_fails() {
+    let faults = Faults::seeded(1);
+    let (_handle, client) = faulty_app(&faults).await;
+    let id = client.create_todo("first", None).await.unwrap();
+
+    // more than the bursts of `rate_limiter.crud_light` at once
+    faults.inject("todo::get", 1.0, Fault::Internal);
+    let mut gets = tokio::task::JoinSet::new();
+    for _ in 0..40 {
+        let client = client.clone();
+        gets.spawn(async move { client.get_todo(id).await.unwrap_err().status() });
+    }
+    let statuses = gets.join_all().await;
+    let failed = statuses
+        .iter()
+        .filter(|status| **status == Some(StatusCode::INTERNAL_SERVER_ERROR))
+        .count();
+    let limited = statuses
+        .iter()
+        .filter(|status| **status == Some(StatusCode::TOO_MANY_REQUESTS))
+        .count();
+    assert_eq!(failed + limited, statuses.len(), "{statuses:?}");
+    assert!(limited > 0, "{statuses:?}");
+    // failed requests use up the limiter, limited ones never reach the storage
+    assert_eq!(faults.injected("todo::get"), failed);
+    assert!(recorded("http_status_code_429_total", &[]).count >= limited as f64);
+
+    // the limiter lets requests through again once the storage is back
+    faults.clear();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    client.get_todo(id).await.unwrap();
+}
+
+#[tokio::test]
+async fn metrics_record_storage_errors_and_latency() {
+    let faults = Faults::seeded(1);
+    let (_handle, client) = faulty_app(&faults).await;
+    let id = client.create_todo("first", None).await.unwrap();
+
+    // the history is only read here, so the points below are this test's
+    let delay = Duration::from_millis(200);
+    faults.script(
+        "todo::get_history",
+        [Some(Fault::Latency(delay)), Some(Fault::Internal)],
+    );
+    client.history_page(id, 10, None).await.unwrap();
+    assert_internal(client.history_page(id, 10, None).await);
+
+    let uri = ("uri", "/todos/{id}/history?limit=10");
+    let ok = recorded("http_requests_total", &[uri, ("http_status_code", "200")]);
+    assert_eq!(ok.count, 1.0);
+    let failed = recorded("http_requests_total", &[uri, ("http_status_code", "500")]);
+    assert_eq!(failed.count, 1.0);
+
+    let slow = recorded(
+        "http_request_duration_milliseconds",
+        &[uri, ("status_group", "2xx")],
+    );
+    assert_eq!(slow.histogram_count, 1);
+    assert!(slow.histogram_sum >= delay.as_millis() as f64);
+
+    let operation = ("operation", "get_todo_history");
+    let ok = recorded(
+        "service_operation_duration_milliseconds",
+        &[operation, ("status", "ok")],
+    );
+    assert_eq!(ok.histogram_count, 1);
+    assert!(ok.histogram_sum >= delay.as_millis() as f64);
+    let failed = recorded(
+        "service_operation_duration_milliseconds",
+        &[operation, ("status", "error")],
+    );
+    assert_eq!(failed.histogram_count, 1);
+}
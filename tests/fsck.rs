@@ -0,0 +1,35 @@
+mod common;
+use common::{assert_status, create_test_app, logged_in, spawn_test_app};
+use todo_client::StatusCode;
+
+#[tokio::test]
+async fn fsck_and_repair() {
+    let handle = spawn_test_app(create_test_app(Some("test")).await).await;
+
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+
+    assert_status(user.fsck(false).await, StatusCode::FORBIDDEN);
+    assert_status(user.fsck(true).await, StatusCode::FORBIDDEN);
+
+    let report = admin.fsck(false).await.unwrap();
+    assert!(report.problems.is_empty(), "{report:?}");
+    let users = report.trees.iter().find(|tree| tree.name == "users");
+    assert_eq!(users.map(|tree| tree.records), Some(2));
+
+    // sessions of deleted users are left behind
+    let user_id = admin.user_by_email("user@gmail.com").await.unwrap().id;
+    admin.delete_user(user_id).await.unwrap();
+
+    let report = admin.fsck(false).await.unwrap();
+    assert_eq!(report.problems.len(), 1, "{report:?}");
+    assert_eq!(report.problems[0].kind, "orphan_session");
+    assert!(!report.problems[0].repaired);
+
+    let report = admin.fsck(true).await.unwrap();
+    assert_eq!(report.problems.len(), 1, "{report:?}");
+    assert!(report.problems[0].repaired);
+
+    let report = admin.fsck(false).await.unwrap();
+    assert!(report.problems.is_empty(), "{report:?}");
+}
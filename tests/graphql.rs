@@ -0,0 +1,199 @@
+mod common;
+use common::{assert_status, create_test_app, logged_in, spawn_test_app};
+use serde_json::{json, Value};
+use todo_client::{Client, LoginToken, StatusCode};
+
+async fn query(client: &Client, query: &str, variables: Value) -> Value {
+    client.graphql(query, variables).await.unwrap()
+}
+
+fn error_code(response: &Value) -> &str {
+    response["errors"][0]["extensions"]["code"]
+        .as_str()
+        .unwrap()
+}
+
+const CREATE: &str = r#"
+    mutation Create($text: String!, $due: Int) {
+        createTodo(text: $text, due: $due) { id text completed due }
+    }
+"#;
+
+const UPDATE: &str = r#"
+    mutation Update($id: ID!, $patch: UpdateTodoInput!) {
+        updateTodo(id: $id, patch: $patch) { id text completed group due }
+    }
+"#;
+
+#[tokio::test]
+async fn graphql_requires_auth() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let client = Client::new(handle.address.clone()).with_tokens(LoginToken {
+        access_token: "bad token".to_owned(),
+        refresh_token: "bad token".to_owned(),
+    });
+
+    assert_status(
+        client.graphql("{ me { email } }", json!({})).await,
+        StatusCode::UNAUTHORIZED,
+    );
+
+    let res = reqwest::get(handle.address.join("graphql").unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.text().await.unwrap().contains("graphiql"));
+}
+
+#[tokio::test]
+async fn graphql_user_groups_and_todos_in_one_request() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let mut ids = Vec::new();
+    for (text, group) in [("milk", "shop"), ("bread", "shop"), ("taxes", "home")] {
+        let res = query(&client, CREATE, json!({ "text": text })).await;
+        let id = res["data"]["createTodo"]["id"].as_str().unwrap().to_owned();
+        query(
+            &client,
+            UPDATE,
+            json!({ "id": id, "patch": { "group": group } }),
+        )
+        .await;
+        ids.push(id);
+    }
+    query(&client, CREATE, json!({ "text": "no group" })).await;
+
+    let me = r#"
+        query Me($after: ID) {
+            me {
+                email
+                role
+                groups
+                todos(first: 3, after: $after) {
+                    pageInfo { hasNextPage hasPreviousPage endCursor }
+                    edges { cursor node { id text group blockedBy } }
+                }
+            }
+        }
+    "#;
+    let res = query(&client, me, json!({})).await;
+    assert!(res.get("errors").is_none(), "{res}");
+    let user = &res["data"]["me"];
+    assert_eq!(user["email"], "user@gmail.com");
+    assert_eq!(user["role"], "USER");
+    assert_eq!(user["groups"], json!(["home", "shop"]));
+    let todos = &user["todos"];
+    assert_eq!(todos["edges"].as_array().unwrap().len(), 3);
+    assert_eq!(todos["pageInfo"]["hasNextPage"], true);
+    assert_eq!(todos["pageInfo"]["hasPreviousPage"], false);
+    assert_eq!(todos["edges"][0]["node"]["blockedBy"], json!([]));
+
+    let after = todos["pageInfo"]["endCursor"].clone();
+    let res = query(&client, me, json!({ "after": after })).await;
+    let todos = &res["data"]["me"]["todos"];
+    assert_eq!(todos["edges"].as_array().unwrap().len(), 1);
+    assert_eq!(todos["pageInfo"]["hasNextPage"], false);
+    assert_eq!(todos["pageInfo"]["hasPreviousPage"], true);
+
+    let res = query(
+        &client,
+        "{ todos(first: 0) { edges { cursor } } }",
+        json!({}),
+    )
+    .await;
+    assert!(res["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("first"));
+}
+
+#[tokio::test]
+async fn graphql_todo_mutations() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let res = query(
+        &client,
+        CREATE,
+        json!({ "text": "pay rent", "due": 1_700_000_000 }),
+    )
+    .await;
+    let created = &res["data"]["createTodo"];
+    assert_eq!(created["text"], "pay rent");
+    assert_eq!(created["completed"], false);
+    assert_eq!(created["due"], 1_700_000_000);
+    let id = created["id"].as_str().unwrap().to_owned();
+
+    let res = query(
+        &client,
+        UPDATE,
+        json!({ "id": id, "patch": { "completed": true, "due": null } }),
+    )
+    .await;
+    let updated = &res["data"]["updateTodo"];
+    assert_eq!(updated["completed"], true);
+    assert_eq!(updated["due"], Value::Null);
+    assert_eq!(updated["text"], "pay rent");
+
+    let res = query(&client, UPDATE, json!({ "id": id, "patch": {} })).await;
+    assert_eq!(error_code(&res), "empty_patch");
+
+    // todos of other users are invisible
+    let other = logged_in(&handle, "other@gmail.com", "123").await;
+    let res = query(
+        &other,
+        "query Get($id: ID!) { todo(id: $id) { text } }",
+        json!({ "id": id }),
+    )
+    .await;
+    assert_eq!(error_code(&res), "not_found");
+
+    let res = query(
+        &client,
+        "mutation Delete($id: ID!) { deleteTodo(id: $id) }",
+        json!({ "id": id }),
+    )
+    .await;
+    assert_eq!(res["data"]["deleteTodo"], id.as_str());
+    let res = query(
+        &client,
+        "query Get($id: ID!) { todo(id: $id) { text } }",
+        json!({ "id": id }),
+    )
+    .await;
+    assert_eq!(error_code(&res), "not_found");
+}
+
+#[tokio::test]
+async fn graphql_admin_queries_are_gated_on_role() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+
+    let users = "{ users(first: 10) { edges { node { id email role } } } }";
+    let res = query(&user, users, json!({})).await;
+    assert_eq!(error_code(&res), "forbidden");
+    assert_eq!(res["data"], Value::Null);
+
+    let res = query(&admin, users, json!({})).await;
+    let edges = res["data"]["users"]["edges"].as_array().unwrap();
+    let listed = edges
+        .iter()
+        .find(|edge| edge["node"]["email"] == "user@gmail.com")
+        .unwrap();
+    assert_eq!(listed["node"]["role"], "USER");
+
+    let by_email = r#"
+        query ByEmail($email: String!) { userByEmail(email: $email) { id email } }
+    "#;
+    let res = query(&admin, by_email, json!({ "email": "user@gmail.com" })).await;
+    assert_eq!(res["data"]["userByEmail"]["id"], listed["node"]["id"]);
+
+    // admins don't get to read the todos of others
+    let todos = r#"
+        query User($id: ID!) { user(id: $id) { email todos { edges { cursor } } } }
+    "#;
+    let res = query(&admin, todos, json!({ "id": listed["node"]["id"] })).await;
+    assert_eq!(error_code(&res), "forbidden");
+}
@@ -0,0 +1,491 @@
+mod common;
+use common::{
+    create_test_grpc, create_test_servers, spawn_test_app, spawn_test_grpc, TestAppHandle,
+};
+use todo_app::proto::{
+    admin_service_client::AdminServiceClient, auth_service_client::AuthServiceClient,
+    todo_service_client::TodoServiceClient, update_todo_request::DueChange, CreateTodoRequest,
+    DeleteTodoRequest, DeleteUserRequest, GetTodoRequest, GetUserByEmailRequest, GetUserRequest,
+    ListTodosRequest, ListUsersRequest, LoginRequest, LogoutRequest, RefreshRequest,
+    RegisterRequest, Role, TokenPair, UpdateRoleRequest, UpdateTodoRequest,
+};
+use todo_client::{Client, StatusCode};
+use tonic::{transport::Channel, Code, Request};
+
+async fn connect(handle: &TestAppHandle) -> Channel {
+    Channel::from_shared(handle.address.to_string())
+        .unwrap()
+        .connect()
+        .await
+        .unwrap()
+}
+
+fn authorized<T>(message: T, token: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {token}").parse().unwrap());
+    request
+}
+
+async fn register_and_login(channel: &Channel, email: &str, password: &str) -> TokenPair {
+    let mut auth = AuthServiceClient::new(channel.clone());
+    let _ = auth
+        .register(RegisterRequest {
+            email: email.into(),
+            password: password.into(),
+        })
+        .await;
+    auth.login(LoginRequest {
+        email: email.into(),
+        password: password.into(),
+    })
+    .await
+    .unwrap()
+    .into_inner()
+}
+
+#[tokio::test]
+async fn grpc_auth() {
+    let handle = spawn_test_grpc(create_test_grpc().await).await;
+    let channel = connect(&handle).await;
+    let mut auth = AuthServiceClient::new(channel.clone());
+    let mut todos = TodoServiceClient::new(channel.clone());
+
+    let err = auth
+        .register(RegisterRequest {
+            email: "".into(),
+            password: "123".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let tokens = register_and_login(&channel, "user@gmail.com", "123").await;
+    let err = auth
+        .register(RegisterRequest {
+            email: "user@gmail.com".into(),
+            password: "123".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::AlreadyExists);
+    let err = auth
+        .login(LoginRequest {
+            email: "user@gmail.com".into(),
+            password: "wrong".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    let err = auth
+        .login(LoginRequest {
+            email: format!("{}@gmail.com", "a".repeat(70_000)),
+            password: "123".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let err = todos
+        .list_todos(ListTodosRequest {
+            after: None,
+            limit: 10,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    assert_eq!(err.message(), "Malformed auth header");
+
+    let err = todos
+        .list_todos(authorized(
+            ListTodosRequest {
+                after: None,
+                limit: 10,
+            },
+            &tokens.refresh_token,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    assert_eq!(
+        err.message(),
+        "Expected access token, refresh token provided"
+    );
+
+    let err = auth
+        .refresh(authorized(RefreshRequest {}, &tokens.access_token))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    let refreshed = auth
+        .refresh(authorized(RefreshRequest {}, &tokens.refresh_token))
+        .await
+        .unwrap()
+        .into_inner();
+    // the old refresh token was rotated out
+    let err = auth
+        .refresh(authorized(RefreshRequest {}, &tokens.refresh_token))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+
+    auth.logout(authorized(LogoutRequest {}, &refreshed.access_token))
+        .await
+        .unwrap();
+    let err = todos
+        .list_todos(authorized(
+            ListTodosRequest {
+                after: None,
+                limit: 10,
+            },
+            &refreshed.access_token,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn grpc_auth_rate_limits() {
+    let handle = spawn_test_grpc(create_test_grpc().await).await;
+    let channel = connect(&handle).await;
+
+    // more than the bursts of `rate_limiter.login` and `.registration` at once
+    let mut calls = tokio::task::JoinSet::new();
+    for i in 0..30 {
+        let mut auth = AuthServiceClient::new(channel.clone());
+        calls.spawn(async move {
+            let login = auth
+                .login(LoginRequest {
+                    email: "nobody@gmail.com".into(),
+                    password: "123".into(),
+                })
+                .await;
+            let register = auth
+                .register(RegisterRequest {
+                    email: format!("user{i}@gmail.com"),
+                    password: "123".into(),
+                })
+                .await;
+            (
+                login.unwrap_err().code(),
+                register.err().map(|err| err.code()),
+            )
+        });
+    }
+    let results = calls.join_all().await;
+    assert!(results
+        .iter()
+        .any(|(login, _)| *login == Code::ResourceExhausted));
+    assert!(results
+        .iter()
+        .any(|(_, register)| *register == Some(Code::ResourceExhausted)));
+    // the rest went through to the service
+    assert!(results
+        .iter()
+        .any(|(login, _)| *login == Code::Unauthenticated));
+}
+
+#[tokio::test]
+async fn grpc_shares_rate_limits_with_rest() {
+    let (app, grpc) = create_test_servers().await;
+    let rest_handle = spawn_test_app(app).await;
+    let rest = Client::new(rest_handle.address.clone());
+    let handle = spawn_test_grpc(grpc).await;
+    let channel = connect(&handle).await;
+    let tokens = register_and_login(&channel, "user@gmail.com", "123").await;
+
+    // more than the burst of `rate_limiter.crud_light` at once
+    let mut calls = tokio::task::JoinSet::new();
+    for _ in 0..60 {
+        let mut todos = TodoServiceClient::new(channel.clone());
+        let token = tokens.access_token.clone();
+        calls.spawn(async move {
+            let request = ListTodosRequest {
+                after: None,
+                limit: 10,
+            };
+            todos.list_todos(authorized(request, &token)).await.err()
+        });
+    }
+    let results = calls.join_all().await;
+    assert!(results
+        .iter()
+        .any(|err| err.as_ref().map(|err| err.code()) == Some(Code::ResourceExhausted)));
+
+    // the login cells spent over REST are gone for gRPC too
+    let mut limited = false;
+    for _ in 0..100 {
+        let e = rest.login("nobody@gmail.com", "123").await.unwrap_err();
+        if e.status() == Some(StatusCode::TOO_MANY_REQUESTS) {
+            limited = true;
+            break;
+        }
+    }
+    assert!(limited);
+    let mut calls = tokio::task::JoinSet::new();
+    for _ in 0..5 {
+        let mut auth = AuthServiceClient::new(channel.clone());
+        calls.spawn(async move {
+            let request = LoginRequest {
+                email: "nobody@gmail.com".into(),
+                password: "123".into(),
+            };
+            auth.login(request).await.unwrap_err().code()
+        });
+    }
+    let codes = calls.join_all().await;
+    assert!(codes.contains(&Code::ResourceExhausted), "{codes:?}");
+}
+
+#[tokio::test]
+async fn grpc_todos() {
+    let handle = spawn_test_grpc(create_test_grpc().await).await;
+    let channel = connect(&handle).await;
+    let mut todos = TodoServiceClient::new(channel.clone());
+    let tokens = register_and_login(&channel, "user@gmail.com", "123").await;
+    let token = tokens.access_token.as_str();
+
+    let mut ids = Vec::new();
+    for text in ["first", "second", "third"] {
+        let id = todos
+            .create_todo(authorized(
+                CreateTodoRequest {
+                    text: text.into(),
+                    due: None,
+                },
+                token,
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .id;
+        ids.push(id);
+    }
+
+    let page = todos
+        .list_todos(authorized(
+            ListTodosRequest {
+                after: None,
+                limit: 2,
+            },
+            token,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(page.items.len(), 2);
+    let rest = todos
+        .list_todos(authorized(
+            ListTodosRequest {
+                after: page.cursor,
+                limit: 2,
+            },
+            token,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(rest.items.len(), 1);
+    assert!(rest.cursor.is_none());
+
+    for limit in [0, 101] {
+        let err = todos
+            .list_todos(authorized(ListTodosRequest { after: None, limit }, token))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+
+    todos
+        .update_todo(authorized(
+            UpdateTodoRequest {
+                id: ids[0].clone(),
+                text: Some("first, edited".into()),
+                completed: Some(true),
+                due_change: Some(DueChange::Due(1_700_000_000)),
+                ..Default::default()
+            },
+            token,
+        ))
+        .await
+        .unwrap();
+    let details = todos
+        .get_todo(authorized(GetTodoRequest { id: ids[0].clone() }, token))
+        .await
+        .unwrap()
+        .into_inner();
+    let todo = details.todo.unwrap();
+    assert_eq!(todo.text, "first, edited");
+    assert!(todo.completed);
+    assert_eq!(todo.due, Some(1_700_000_000));
+    assert!(details.blocked_by.is_empty());
+
+    todos
+        .update_todo(authorized(
+            UpdateTodoRequest {
+                id: ids[0].clone(),
+                due_change: Some(DueChange::ClearDue(true)),
+                ..Default::default()
+            },
+            token,
+        ))
+        .await
+        .unwrap();
+    let todo = todos
+        .get_todo(authorized(GetTodoRequest { id: ids[0].clone() }, token))
+        .await
+        .unwrap()
+        .into_inner()
+        .todo
+        .unwrap();
+    assert_eq!(todo.due, None);
+
+    let err = todos
+        .update_todo(authorized(
+            UpdateTodoRequest {
+                id: ids[0].clone(),
+                ..Default::default()
+            },
+            token,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = todos
+        .get_todo(authorized(GetTodoRequest { id: "nope".into() }, token))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    // todos of other users are invisible
+    let other = register_and_login(&channel, "other@gmail.com", "123").await;
+    let err = todos
+        .get_todo(authorized(
+            GetTodoRequest { id: ids[1].clone() },
+            &other.access_token,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    todos
+        .delete_todo(authorized(DeleteTodoRequest { id: ids[1].clone() }, token))
+        .await
+        .unwrap();
+    let err = todos
+        .get_todo(authorized(GetTodoRequest { id: ids[1].clone() }, token))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn grpc_admin() {
+    let handle = spawn_test_grpc(create_test_grpc().await).await;
+    let channel = connect(&handle).await;
+    let mut admin = AdminServiceClient::new(channel.clone());
+    let user = register_and_login(&channel, "user@gmail.com", "123").await;
+    let admin_tokens = register_and_login(&channel, "admin@gmail.com", "admin").await;
+    let token = admin_tokens.access_token.as_str();
+
+    let err = admin
+        .list_users(authorized(
+            ListUsersRequest {
+                after: None,
+                limit: 10,
+            },
+            &user.access_token,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    let users = admin
+        .list_users(authorized(
+            ListUsersRequest {
+                after: None,
+                limit: 10,
+            },
+            token,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(users
+        .items
+        .iter()
+        .any(|listed| listed.email == "user@gmail.com"));
+
+    let found = admin
+        .get_user_by_email(authorized(
+            GetUserByEmailRequest {
+                email: "user@gmail.com".into(),
+            },
+            token,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(found.role(), Role::User);
+
+    let err = admin
+        .update_role(authorized(
+            UpdateRoleRequest {
+                id: found.id.clone(),
+                role: Role::Unspecified.into(),
+            },
+            token,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    admin
+        .update_role(authorized(
+            UpdateRoleRequest {
+                id: found.id.clone(),
+                role: Role::Admin.into(),
+            },
+            token,
+        ))
+        .await
+        .unwrap();
+    let promoted = admin
+        .get_user(authorized(
+            GetUserRequest {
+                id: found.id.clone(),
+            },
+            token,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(promoted.role(), Role::Admin);
+
+    // the promoted user passes the role check now
+    admin
+        .get_user(authorized(
+            GetUserRequest {
+                id: found.id.clone(),
+            },
+            &user.access_token,
+        ))
+        .await
+        .unwrap();
+
+    admin
+        .delete_user(authorized(
+            DeleteUserRequest {
+                id: found.id.clone(),
+            },
+            token,
+        ))
+        .await
+        .unwrap();
+    let err = admin
+        .get_user(authorized(GetUserRequest { id: found.id }, token))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
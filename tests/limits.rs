@@ -0,0 +1,136 @@
+mod common;
+use std::fmt::Debug;
+
+use common::{assert_status, create_test_service, logged_in, spawn_test_app, TestAppHandle};
+use todo_app::{build_app, LimitsConfig, RateLimiters};
+use todo_client::{BatchOperation, ClientError, StatusCode, UpdateTodo};
+
+async fn limited_app() -> TestAppHandle {
+    let (service, settings) = create_test_service(Some("test")).await;
+    let service = service.with_limits(LimitsConfig {
+        max_todos: 3,
+        max_text_len: 10,
+        max_group_len: 5,
+        max_groups: 2,
+    });
+    let limiters = RateLimiters::new(&settings);
+    spawn_test_app(build_app(service, settings, &limiters)).await
+}
+
+fn assert_limit<T: Debug>(result: Result<T, ClientError>, status: StatusCode, limit: &str) {
+    match assert_status(result, status) {
+        ClientError::Api { body, .. } => assert_eq!(body.limit.as_deref(), Some(limit), "{body:?}"),
+        e => panic!("{e:?}"),
+    }
+}
+
+fn in_group(group: &str) -> UpdateTodo {
+    UpdateTodo::default().text("done").group(group)
+}
+
+#[tokio::test]
+async fn todo_limits() {
+    let handle = limited_app().await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    assert_limit(
+        client.create_todo("much too long", None).await,
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "text",
+    );
+
+    let mut ids = Vec::new();
+    for text in ["one", "two", "three"] {
+        ids.push(client.create_todo(text, None).await.unwrap());
+    }
+    assert_limit(
+        client.create_todo("four", None).await,
+        StatusCode::CONFLICT,
+        "todos",
+    );
+    assert_limit(
+        client
+            .batch(&[BatchOperation::Create {
+                text: "four".to_owned(),
+                due: None,
+            }])
+            .await,
+        StatusCode::CONFLICT,
+        "todos",
+    );
+
+    // a deleted todo frees its place
+    client.delete_todo(ids[2]).await.unwrap();
+    ids[2] = client.create_todo("four", None).await.unwrap();
+
+    assert_limit(
+        client.update_todo(ids[0], &in_group("too long")).await,
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "group",
+    );
+    for (id, group) in ids.iter().zip(["home", "work"]) {
+        client.update_todo(*id, &in_group(group)).await.unwrap();
+    }
+    assert_limit(
+        client.update_todo(ids[2], &in_group("gym")).await,
+        StatusCode::CONFLICT,
+        "groups",
+    );
+    // groups in use stay available
+    client.update_todo(ids[2], &in_group("home")).await.unwrap();
+
+    // a group goes with its last todo
+    client.update_todo(ids[1], &in_group("home")).await.unwrap();
+    client.update_todo(ids[2], &in_group("gym")).await.unwrap();
+}
+
+#[tokio::test]
+async fn limits_are_per_user() {
+    let handle = limited_app().await;
+    let first = logged_in(&handle, "first@gmail.com", "123").await;
+    let second = logged_in(&handle, "second@gmail.com", "123").await;
+
+    for _ in 0..3 {
+        first.create_todo("todo", None).await.unwrap();
+    }
+    assert_status(first.create_todo("todo", None).await, StatusCode::CONFLICT);
+    second.create_todo("todo", None).await.unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_creates_stay_within_limits() {
+    let handle = limited_app().await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let mut creates = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let client = client.clone();
+        creates.spawn(async move { client.create_todo("todo", None).await });
+    }
+    let results = creates.join_all().await;
+    let created = results.iter().filter(|result| result.is_ok()).count();
+    assert_eq!(created, 3, "{results:?}");
+    assert!(results.iter().all(|result| match result {
+        Ok(_) => true,
+        Err(e) => e.status() == Some(StatusCode::CONFLICT),
+    }));
+}
+
+#[tokio::test]
+async fn restore_stays_within_limits() {
+    let handle = limited_app().await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let mut ids = Vec::new();
+    for text in ["one", "two", "three"] {
+        ids.push(client.create_todo(text, None).await.unwrap());
+    }
+    client.delete_todo(ids[0]).await.unwrap();
+    client.create_todo("four", None).await.unwrap();
+
+    assert_limit(
+        client.restore_todo(ids[0]).await,
+        StatusCode::CONFLICT,
+        "todos",
+    );
+}
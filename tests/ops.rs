@@ -0,0 +1,296 @@
+mod common;
+use common::{assert_status, create_test_service, logged_in, spawn_test_app};
+use futures_util::TryStreamExt;
+use serial_test::{parallel, serial};
+use todo_app::{
+    build_app,
+    ops::{execute, OpsCommand, OpsError},
+    AppError, RateLimiters, Service, Settings,
+};
+use todo_client::{Client, StatusCode};
+
+struct EnvSetter {
+    name: &'static str,
+}
+
+impl EnvSetter {
+    fn new(name: &'static str, value: &str) -> Self {
+        std::env::set_var(name, value);
+        Self { name }
+    }
+}
+
+impl Drop for EnvSetter {
+    fn drop(&mut self) {
+        std::env::remove_var(self.name);
+    }
+}
+
+async fn run(service: &Service, settings: &Settings, command: OpsCommand) -> String {
+    try_run(service, settings, command).await.unwrap()
+}
+
+async fn try_run(
+    service: &Service,
+    settings: &Settings,
+    command: OpsCommand,
+) -> Result<String, OpsError> {
+    let mut out = Vec::new();
+    execute(command, service, settings, &mut out).await?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+#[tokio::test]
+#[parallel]
+async fn create_admin_and_set_role() {
+    let (service, settings) = create_test_service(None).await;
+
+    let out = run(
+        &service,
+        &settings,
+        OpsCommand::CreateAdmin {
+            email: "root@gmail.com".into(),
+            password: "secret".into(),
+        },
+    )
+    .await;
+    assert_eq!(out, "created admin root@gmail.com\n");
+
+    let handle = spawn_test_app(build_app(
+        service.clone(),
+        settings.clone(),
+        &RateLimiters::new(&settings),
+    ))
+    .await;
+    let root = Client::new(handle.address.clone());
+    root.login("root@gmail.com", "secret").await.unwrap();
+    root.users_page(10, None).await.unwrap();
+
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+    let out = run(
+        &service,
+        &settings,
+        OpsCommand::SetRole {
+            user: "user@gmail.com".into(),
+            role: "admin".into(),
+        },
+    )
+    .await;
+    assert_eq!(out, "user@gmail.com is now admin\n");
+    user.users_page(10, None).await.unwrap();
+
+    let out = run(&service, &settings, OpsCommand::ListUsers).await;
+    let mut lines: Vec<_> = out
+        .lines()
+        .map(|line| {
+            line.split_whitespace()
+                .skip(1)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec![
+            "admin admin@gmail.com",
+            "admin root@gmail.com",
+            "admin user@gmail.com",
+        ]
+    );
+
+    let id = out
+        .lines()
+        .find(|line| line.ends_with("user@gmail.com"))
+        .and_then(|line| line.split_whitespace().next())
+        .unwrap()
+        .to_string();
+    let out = run(
+        &service,
+        &settings,
+        OpsCommand::SetRole {
+            user: id,
+            role: "user".into(),
+        },
+    )
+    .await;
+    assert_eq!(out, "user@gmail.com is now user\n");
+
+    let result = try_run(
+        &service,
+        &settings,
+        OpsCommand::SetRole {
+            user: "user@gmail.com".into(),
+            role: "owner".into(),
+        },
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(OpsError::App(AppError::InvalidRole(_)))
+    ));
+
+    let result = try_run(
+        &service,
+        &settings,
+        OpsCommand::CreateAdmin {
+            email: "root@gmail.com".into(),
+            password: "other".into(),
+        },
+    )
+    .await;
+    assert!(matches!(result, Err(OpsError::App(_))));
+}
+
+#[tokio::test]
+#[parallel]
+async fn gc_sessions_of_deleted_users() {
+    let (service, settings) = create_test_service(None).await;
+    let handle = spawn_test_app(build_app(
+        service.clone(),
+        settings.clone(),
+        &RateLimiters::new(&settings),
+    ))
+    .await;
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+    let user_id = admin.user_by_email("user@gmail.com").await.unwrap().id;
+
+    let out = run(&service, &settings, OpsCommand::GcSessions).await;
+    assert_eq!(out, "removed 0 sessions\n");
+
+    admin.delete_user(user_id).await.unwrap();
+    let out = run(&service, &settings, OpsCommand::GcSessions).await;
+    assert_eq!(out, "removed 1 sessions\n");
+
+    assert_status(user.refresh().await, StatusCode::UNAUTHORIZED);
+    admin.refresh().await.unwrap();
+}
+
+#[tokio::test]
+#[parallel]
+async fn backup_restore_and_verify() {
+    let (service, settings) = create_test_service(None).await;
+    let handle = spawn_test_app(build_app(
+        service.clone(),
+        settings.clone(),
+        &RateLimiters::new(&settings),
+    ))
+    .await;
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+    user.create_todo("keep me", None).await.unwrap();
+
+    let path = std::env::temp_dir().join(format!("todo-ops-{}.bin", todo_app::TodoId::new()));
+    let out = run(
+        &service,
+        &settings,
+        OpsCommand::Backup { path: path.clone() },
+    )
+    .await;
+    assert!(out.starts_with("backed up "), "{out}");
+
+    user.delete_all_todos().await.unwrap();
+    // logging in caches the user, a restore must forget it
+    logged_in(&handle, "late@gmail.com", "456").await;
+
+    let out = run(
+        &service,
+        &settings,
+        OpsCommand::Restore {
+            path: path.clone(),
+            dry_run: true,
+        },
+    )
+    .await;
+    assert!(out.starts_with("would restore "), "{out}");
+    let todos: Vec<_> = user.todos(10).try_collect().await.unwrap();
+    assert!(todos.is_empty(), "{todos:?}");
+
+    let out = run(
+        &service,
+        &settings,
+        OpsCommand::Restore {
+            path: path.clone(),
+            dry_run: false,
+        },
+    )
+    .await;
+    std::fs::remove_file(&path).unwrap();
+    assert!(out.starts_with("restored "), "{out}");
+
+    let todos: Vec<_> = user.todos(10).try_collect().await.unwrap();
+    assert_eq!(todos[0].text, "keep me");
+    let e = Client::new(handle.address.clone())
+        .login("late@gmail.com", "456")
+        .await
+        .unwrap_err();
+    assert!(e.status().unwrap().is_client_error(), "{e:?}");
+
+    let out = run(&service, &settings, OpsCommand::Fsck { repair: false }).await;
+    assert!(out.contains("users: 2 records"), "{out}");
+}
+
+#[tokio::test]
+#[parallel]
+async fn fsck_and_repair() {
+    let (service, settings) = create_test_service(None).await;
+    let handle = spawn_test_app(build_app(
+        service.clone(),
+        settings.clone(),
+        &RateLimiters::new(&settings),
+    ))
+    .await;
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+    logged_in(&handle, "user@gmail.com", "123").await;
+    let user_id = admin.user_by_email("user@gmail.com").await.unwrap().id;
+    // the session of a deleted user stays until it is collected
+    admin.delete_user(user_id).await.unwrap();
+
+    let result = try_run(&service, &settings, OpsCommand::Fsck { repair: false }).await;
+    assert!(matches!(result, Err(OpsError::StorageProblems(1))));
+
+    let out = run(&service, &settings, OpsCommand::Fsck { repair: true }).await;
+    let line = format!("orphan_session: user {user_id} doesn't exist [repaired]");
+    assert!(out.contains(&line), "{out}");
+    run(&service, &settings, OpsCommand::Fsck { repair: false }).await;
+}
+
+#[tokio::test]
+#[parallel]
+async fn migrate_up_to_date_storage() {
+    let (service, settings) = create_test_service(None).await;
+
+    for dry_run in [true, false] {
+        let out = run(&service, &settings, OpsCommand::Migrate { dry_run }).await;
+        assert!(out.starts_with("storage is at schema version "), "{out}");
+    }
+}
+
+#[tokio::test]
+#[parallel]
+async fn rotate_keys_needs_encryption() {
+    let (service, settings) = create_test_service(None).await;
+
+    let result = try_run(
+        &service,
+        &settings,
+        OpsCommand::RotateKeys { batch_size: 100 },
+    )
+    .await;
+    assert!(matches!(result, Err(OpsError::App(_))), "{result:?}");
+}
+
+#[tokio::test]
+#[serial]
+async fn check_config() {
+    let (service, settings) = create_test_service(None).await;
+
+    let out = run(&service, &settings, OpsCommand::CheckConfig).await;
+    assert!(out.contains("password hashing: "), "{out}");
+    assert!(out.ends_with("config ok\n"), "{out}");
+
+    let _env_setter = EnvSetter::new("APP__JWT__SESSION_TTL_SEC", "0");
+    let broken = Settings::new().unwrap();
+    let result = try_run(&service, &broken, OpsCommand::CheckConfig).await;
+    assert!(matches!(result, Err(OpsError::InvalidConfig(_))));
+}
@@ -0,0 +1,34 @@
+mod common;
+use common::{assert_status, create_test_app, logged_in, spawn_test_app};
+use todo_client::StatusCode;
+
+#[tokio::test]
+async fn storage_stats() {
+    let handle = spawn_test_app(create_test_app(Some("test")).await).await;
+
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+    let user = logged_in(&handle, "user@gmail.com", "123").await;
+    for text in ["first", "second"] {
+        user.create_todo(text, None).await.unwrap();
+    }
+
+    assert_status(user.storage_stats().await, StatusCode::FORBIDDEN);
+
+    let stats = admin.storage_stats().await.unwrap();
+    assert!(stats.size_on_disk > 0, "{stats:?}");
+    let todos = stats.trees.iter().find(|tree| tree.name == "todos");
+    assert_eq!(todos.map(|tree| tree.records), Some(2));
+    assert_eq!(stats.top_users.len(), 1, "{stats:?}");
+    assert_eq!(stats.top_users[0].todos, 2);
+    // the admin has none
+    assert_eq!(stats.todos_per_user[0].users, 1);
+    assert_eq!(stats.todos_per_user[1].users, 1);
+    assert_eq!(stats.active_sessions, 2);
+    assert_eq!(stats.expired_sessions, 0);
+
+    // later requests get the same collection until the next run
+    user.create_todo("third", None).await.unwrap();
+    let again = admin.storage_stats().await.unwrap();
+    assert_eq!(again.collected_at, stats.collected_at);
+    assert_eq!(again.top_users[0].todos, 2);
+}
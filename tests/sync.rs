@@ -0,0 +1,75 @@
+mod common;
+use common::{assert_api_error, create_test_app, logged_in, spawn_test_app};
+use todo_client::{ErrorCode, StatusCode, SyncOperation, SyncResult, Todo, TodoId, UpdateTodo};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn sync_changes() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let id = client.create_todo("pay rent", None).await.unwrap();
+
+    let pulled = client.pull_changes(None).await.unwrap();
+    assert_eq!(pulled.changes.len(), 1);
+    assert!(!pulled.has_more);
+    let change = &pulled.changes[0];
+    assert_eq!(change.id, id);
+    assert_eq!(change.todo.as_ref().unwrap().text, "pay rent");
+    let rev = change.rev;
+
+    // changed on the server meanwhile
+    client
+        .update_todo(id, &UpdateTodo::default().text("pay bills"))
+        .await
+        .unwrap();
+
+    let created = TodoId(Uuid::new_v4());
+    let upsert = |id, text: &str, base_rev| SyncOperation::Upsert {
+        todo: Todo {
+            id,
+            text: text.to_owned(),
+            completed: false,
+            group: String::new(),
+            due: None,
+        },
+        base_rev,
+    };
+    let results = client
+        .push_changes(&[
+            upsert(id, "pay rent today", Some(rev)),
+            upsert(created, "read book", None),
+        ])
+        .await
+        .unwrap();
+    assert!(matches!(
+        &results[0],
+        SyncResult::Conflict { id: conflict, todo: Some(todo), .. }
+            if *conflict == id && todo.text == "pay bills"
+    ));
+    assert!(matches!(results[1], SyncResult::Applied { id, .. } if id == created));
+
+    let delta = client.pull_changes(Some(pulled.token)).await.unwrap();
+    assert_eq!(delta.changes.len(), 2);
+    assert_eq!(delta.changes[0].id, id);
+    assert_eq!(delta.changes[1].id, created);
+
+    client.delete_todo(id).await.unwrap();
+
+    let delta = client.pull_changes(Some(delta.token)).await.unwrap();
+    assert_eq!(delta.changes.len(), 1);
+    assert_eq!(delta.changes[0].id, id);
+    assert_eq!(delta.changes[0].todo, None);
+
+    // other users see only their own changes
+    let other = logged_in(&handle, "other@gmail.com", "123").await;
+    let pulled = other.pull_changes(None).await.unwrap();
+    assert!(pulled.changes.is_empty());
+
+    assert_api_error(
+        other.push_changes(&[]).await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidBatchSize,
+    );
+}
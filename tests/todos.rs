@@ -1,26 +1,31 @@
 mod common;
-use common::{create_test_app, spawn_test_app, CreateTodoResponse, TestAppClient};
-use reqwest::StatusCode;
-use todo_app::TodosPageResponse;
-use todo_app::{Todo, TodoId};
+use common::{assert_api_error, assert_status, create_test_app, logged_in, spawn_test_app};
+use futures_util::TryStreamExt;
+use todo_client::{BatchOperation, ClientError, ErrorCode, StatusCode, TodoId, TransferFormat};
+use todo_client::{HistoryAction, TodoOpResult, UndoOperation, UpdateTodo};
+use uuid::Uuid;
+
+fn update(text: &str, group: &str) -> UpdateTodo {
+    UpdateTodo::default()
+        .text(text)
+        .completed(true)
+        .group(group)
+}
+
+fn completed() -> UpdateTodo {
+    UpdateTodo::default().completed(true)
+}
 
 #[tokio::test]
 async fn create_and_get_todo() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
 
-    let res = client.create_todo(Some(&tokens.access_token), None).await;
-    assert_eq!(res.status(), StatusCode::CREATED);
-    let todo_id = res.json::<CreateTodoResponse>().await.unwrap().0;
-
-    let res = client.get_todo(&tokens.access_token, &todo_id).await;
-    assert_eq!(res.status(), StatusCode::OK);
-
-    let todo = res.json::<Todo>().await.unwrap();
-    assert_eq!(todo.id.to_string(), todo_id);
+    let todo = client.get_todo(todo_id).await.unwrap();
+    assert_eq!(todo.todo.id, todo_id);
 }
 
 #[tokio::test]
@@ -30,29 +35,18 @@ async fn get_todos_paginated() {
     let mut todo_items = Vec::with_capacity(todo_count);
 
     let handle = spawn_test_app(create_test_app(None).await).await;
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
     for i in 0..todo_count {
-        let res = client
-            .create_todo(Some(&tokens.access_token), Some(&format!("todo{i}")))
-            .await;
-        assert_eq!(res.status(), StatusCode::CREATED);
+        client.create_todo(&format!("todo{i}"), None).await.unwrap();
     }
 
-    let res = client
-        .get_all_todos(&tokens.access_token, limit, None)
-        .await;
-    let todos = res.json::<TodosPageResponse>().await.unwrap();
+    let todos = client.todos_page(limit, None).await.unwrap();
     assert_eq!(todos.items.len(), limit);
     assert!(todos.cursor.is_some());
     todo_items.extend(todos.items);
 
-    let res = client
-        .get_all_todos(&tokens.access_token, limit, todos.cursor)
-        .await;
-    let todos = res.json::<TodosPageResponse>().await.unwrap();
+    let todos = client.todos_page(limit, todos.cursor).await.unwrap();
     assert_eq!(todos.items.len(), todo_count - limit);
     assert!(todos.cursor.is_none());
     todo_items.extend(todos.items);
@@ -67,22 +61,16 @@ async fn get_todos_paginated() {
 async fn update_todo() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
 
-    let res = client.create_todo(Some(&tokens.access_token), None).await;
-    let todo_id = res.json::<CreateTodoResponse>().await.unwrap().0;
+    client
+        .update_todo(todo_id, &update("qwerty", "red"))
+        .await
+        .unwrap();
 
-    let res = client
-        .update_todo(&tokens.access_token, &todo_id, "qwerty", "red")
-        .await;
-    assert_eq!(res.status(), StatusCode::OK);
-
-    let res = client.get_todo(&tokens.access_token, &todo_id).await;
-    assert_eq!(res.status(), StatusCode::OK);
-
-    let todo = res.json::<Todo>().await.unwrap();
+    let todo = client.get_todo(todo_id).await.unwrap().todo;
     assert_eq!(todo.text, "qwerty");
     assert_eq!(todo.group, "red");
     assert!(todo.completed);
@@ -92,69 +80,374 @@ async fn update_todo() {
 async fn update_nonexistent_todo() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let res = client
-        .update_todo(
-            &tokens.access_token,
-            &TodoId::new().to_string(),
-            "qwerty",
-            "red",
-        )
-        .await;
-    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_api_error(
+        client
+            .update_todo(TodoId(Uuid::new_v4()), &update("qwerty", "red"))
+            .await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
 }
 
 #[tokio::test]
 async fn update_todo_with_empty_patch() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    assert_api_error(
+        client
+            .update_todo(TodoId(Uuid::new_v4()), &UpdateTodo::default())
+            .await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::EmptyPatch,
+    );
+}
 
-    let res = client
-        .update_todo_with_empty_patch(&tokens.access_token, &TodoId::new().to_string())
-        .await;
-    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+#[tokio::test]
+async fn page_size_is_capped() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    client.todos_page(100, None).await.unwrap();
+    assert_status(client.todos_page(101, None).await, StatusCode::BAD_REQUEST);
 }
 
 #[tokio::test]
 async fn delete_todo() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
 
-    let res = client.create_todo(Some(&tokens.access_token), None).await;
-    let todo_id = res.json::<CreateTodoResponse>().await.unwrap().0;
+    client.get_todo(todo_id).await.unwrap();
 
-    let res = client.get_todo(&tokens.access_token, &todo_id).await;
-    assert_eq!(res.status(), StatusCode::OK);
+    assert!(client.delete_todo(todo_id).await.unwrap());
 
-    let res = client.delete_todo(&tokens.access_token, &todo_id).await;
-    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!client.delete_todo(todo_id).await.unwrap());
 
-    let res = client.delete_todo(&tokens.access_token, &todo_id).await;
-    assert_eq!(res.status(), StatusCode::NO_CONTENT);
-
-    let res = client.get_todo(&tokens.access_token, &todo_id).await;
-    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_api_error(
+        client.get_todo(todo_id).await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
 }
 
 #[tokio::test]
 async fn delete_nonexistent_todo() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    assert!(!client.delete_todo(TodoId(Uuid::new_v4())).await.unwrap());
+}
+
+#[tokio::test]
+async fn todo_dependencies() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
+    let blocker_id = client.create_todo("aaa", None).await.unwrap();
+
+    client.add_blocker(todo_id, blocker_id).await.unwrap();
+
+    let details = client.get_todo(todo_id).await.unwrap();
+    assert_eq!(details.links.blocked_by, vec![blocker_id]);
+
+    let details = client.get_todo(blocker_id).await.unwrap();
+    assert_eq!(details.links.blocking[0], todo_id);
+
+    assert_api_error(
+        client.add_blocker(blocker_id, todo_id).await,
+        StatusCode::CONFLICT,
+        ErrorCode::DependencyCycle,
+    );
+
+    assert_api_error(
+        client.update_todo(todo_id, &completed()).await,
+        StatusCode::CONFLICT,
+        ErrorCode::TodoBlocked,
+    );
+
+    client.remove_blocker(todo_id, blocker_id).await.unwrap();
+
+    client.update_todo(todo_id, &completed()).await.unwrap();
+}
+
+#[tokio::test]
+async fn complete_blocked_todo_ignoring_blockers() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
+    let blocker_id = client.create_todo("aaa", None).await.unwrap();
+
+    client.add_blocker(todo_id, blocker_id).await.unwrap();
+
+    client
+        .update_todo(todo_id, &completed().ignore_blockers())
+        .await
+        .unwrap();
+
+    assert!(client.delete_todo(blocker_id).await.unwrap());
+
+    let details = client.get_todo(todo_id).await.unwrap();
+    assert!(details.todo.completed);
+    assert!(details.links.blocked_by.is_empty());
+}
+
+#[tokio::test]
+async fn delete_and_restore_todo() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
+
+    assert!(client.delete_todo(todo_id).await.unwrap());
+
+    let trash = client.trash_page(10, None).await.unwrap();
+    assert_eq!(trash.items.len(), 1);
+    assert_eq!(trash.items[0].todo.id, todo_id);
+
+    client.restore_todo(todo_id).await.unwrap();
+
+    client.get_todo(todo_id).await.unwrap();
+
+    assert_api_error(
+        client.restore_todo(todo_id).await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
+}
+
+#[tokio::test]
+async fn undo_delete_all() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    for _ in 0..3 {
+        client.create_todo("aaa", None).await.unwrap();
+    }
+
+    client.delete_all_todos().await.unwrap();
+
+    let result = client.undo().await.unwrap();
+    assert_eq!(result.operation, UndoOperation::DeleteAll);
+    assert_eq!(result.count, 3);
+
+    let page = client.todos_page(10, None).await.unwrap();
+    assert_eq!(page.items.len(), 3);
+
+    for _ in 0..3 {
+        client.undo().await.unwrap();
+    }
+
+    assert_api_error(
+        client.undo().await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
+}
+
+#[tokio::test]
+async fn batch_operations() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
+
+    let results = client
+        .batch(&[
+            BatchOperation::Create {
+                text: "first".to_owned(),
+                due: None,
+            },
+            BatchOperation::Update {
+                id: todo_id,
+                patch: UpdateTodo::default().group("home"),
+            },
+            BatchOperation::Create {
+                text: "second".to_owned(),
+                due: None,
+            },
+        ])
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(matches!(results[0], TodoOpResult::Created { .. }));
+    assert_eq!(results[1], TodoOpResult::Updated { id: todo_id });
+
+    // the failing delete rolls back the create before it
+    let e = assert_api_error(
+        client
+            .batch(&[
+                BatchOperation::Create {
+                    text: "third".to_owned(),
+                    due: None,
+                },
+                BatchOperation::Delete {
+                    id: TodoId(Uuid::new_v4()),
+                },
+            ])
+            .await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::BatchOperation,
+    );
+    let ClientError::Api { body, .. } = e else {
+        panic!("unexpected error {e:?}");
+    };
+    assert_eq!(body.index, Some(1));
+
+    let page = client.todos_page(10, None).await.unwrap();
+    assert_eq!(page.items.len(), 3);
+
+    assert_api_error(
+        client.batch(&[]).await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidBatchSize,
+    );
+}
+
+#[tokio::test]
+async fn update_group() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let results = client
+        .batch(&[
+            BatchOperation::Create {
+                text: "first".to_owned(),
+                due: None,
+            },
+            BatchOperation::Create {
+                text: "second".to_owned(),
+                due: None,
+            },
+        ])
+        .await
+        .unwrap();
+    for result in &results {
+        let TodoOpResult::Created { id } = result else {
+            panic!("unexpected result {result:?}");
+        };
+        client
+            .batch(&[BatchOperation::Update {
+                id: *id,
+                patch: UpdateTodo::default().group("home"),
+            }])
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(client.update_group("home", &completed()).await.unwrap(), 2);
+
+    let page = client.todos_page(10, None).await.unwrap();
+    assert!(page.items.iter().all(|todo| todo.completed));
+
+    assert_api_error(
+        client.update_group("home", &UpdateTodo::default()).await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::EmptyPatch,
+    );
+}
+
+#[tokio::test]
+async fn export_and_import_todos() {
+    let todo_count = 150;
+
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    let other = logged_in(&handle, "other@gmail.com", "123").await;
+
+    let csv = (0..todo_count).fold("text,completed,group\n".to_owned(), |csv, i| {
+        csv + &format!("\"todo, {i}\",{},home\n", i % 2 == 0)
+    });
+    assert_eq!(
+        client.import_todos(TransferFormat::Csv, csv).await.unwrap(),
+        todo_count
+    );
+
+    for format in [
+        TransferFormat::Csv,
+        TransferFormat::Ndjson,
+        TransferFormat::Markdown,
+        TransferFormat::TodoTxt,
+    ] {
+        let file = client.export_todos(format).await.unwrap();
+
+        let imported = other.import_todos(format, file).await;
+        assert_eq!(imported.unwrap(), todo_count, "{format:?}");
+    }
+
+    let todos: Vec<_> = other.todos(100).try_collect().await.unwrap();
+    assert!(todos.iter().all(|todo| todo.group == "home"));
+
+    let e = assert_api_error(
+        client
+            .import_todos(
+                TransferFormat::Ndjson,
+                "{\"text\":\"ok\"}\n{\"text\":\"\"}\n",
+            )
+            .await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidImport,
+    );
+    let ClientError::Api { body, .. } = e else {
+        panic!("unexpected error {e:?}");
+    };
+    assert_eq!(body.errors.len(), 1);
+    assert_eq!(body.errors[0].line, 2);
+
+    // formats the client doesn't know of
+    let mut url = handle.address.join("todos/export").unwrap();
+    url.query_pairs_mut().append_pair("format", "xml");
+    let res = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(client.tokens().await.unwrap().access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn todo_history() {
+    let handle = spawn_test_app(create_test_app(None).await).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+
+    let todo_id = client.create_todo("aaa", None).await.unwrap();
+
+    client.update_todo(todo_id, &completed()).await.unwrap();
+
+    assert!(client.delete_todo(todo_id).await.unwrap());
+
+    let history = client.history_page(todo_id, 10, None).await.unwrap();
 
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let actions: Vec<HistoryAction> = history.items.iter().map(|entry| entry.action).collect();
+    assert_eq!(
+        actions,
+        vec![
+            HistoryAction::Created,
+            HistoryAction::Updated,
+            HistoryAction::Deleted
+        ]
+    );
+    assert!(history.cursor.is_none());
 
-    let res = client
-        .delete_todo(&tokens.access_token, &TodoId::new().to_string())
-        .await;
-    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let other = logged_in(&handle, "other@gmail.com", "123").await;
+    let history = other.history_page(todo_id, 10, None).await.unwrap();
+    assert!(history.items.is_empty());
 }
@@ -1,11 +1,8 @@
-#![allow(unused_imports)]
 mod common;
 use serial_test::{parallel, serial};
-use std::time::Duration;
 
-use common::{create_test_app, spawn_test_app, LoginResponse, TestAppClient, UnauthorizedBody};
-use reqwest::StatusCode;
-use todo_app::AuthError;
+use common::{assert_api_error, create_test_app, logged_in, spawn_test_app};
+use todo_client::{Client, ErrorCode, LoginToken, StatusCode};
 
 struct EnvSetter {
     name: &'static str,
@@ -28,15 +25,14 @@ async fn expired_access_token_test() {
     let _env_setter = EnvSetter::new("APP__JWT__ACCESS_TOKEN_TTL_SEC", "-1");
 
     let handle = spawn_test_app(create_test_app(None).await).await;
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
-
-    let response = client.create_todo(Some(&tokens.access_token), None).await;
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-
-    let body = response.json::<UnauthorizedBody>().await.unwrap();
-    assert_eq!(body.error, AuthError::TokenExpired.as_ref());
+    let client = logged_in(&handle, "userA@gmail.com", "123").await;
+
+    // the refreshed access token has expired as well
+    assert_api_error(
+        client.create_todo("pay rent", None).await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::TokenExpired,
+    );
 }
 
 #[tokio::test]
@@ -47,15 +43,13 @@ async fn expired_refresh_token_test() {
 
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
-
-    let response = client.refresh_token(&tokens.refresh_token).await;
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let client = logged_in(&handle, "userA@gmail.com", "123").await;
 
-    let body = response.json::<UnauthorizedBody>().await.unwrap();
-    assert_eq!(body.error, AuthError::TokenExpired.as_ref());
+    assert_api_error(
+        client.refresh().await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::TokenExpired,
+    );
 }
 
 #[tokio::test]
@@ -64,15 +58,15 @@ async fn valid_refresh_token_test() {
     let _env_setter = EnvSetter::new("APP__JWT__ACCESS_TOKEN_TTL_SEC", "-1");
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = logged_in(&handle, "userA@gmail.com", "123").await;
 
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
+    assert_api_error(
+        client.create_todo("pay rent", None).await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::TokenExpired,
+    );
 
-    let response = client.create_todo(Some(&tokens.access_token), None).await;
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-
-    let response = client.refresh_token(&tokens.refresh_token).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    client.refresh().await.unwrap();
 }
 
 #[tokio::test]
@@ -80,18 +74,17 @@ async fn valid_refresh_token_test() {
 async fn old_refresh_token_rejected_test() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
+    let client = logged_in(&handle, "userA@gmail.com", "123").await;
+    let tokens = client.tokens().await.unwrap();
 
-    let response = client.refresh_token(&tokens.refresh_token).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    client.refresh().await.unwrap();
 
-    let response = client.refresh_token(&tokens.refresh_token).await;
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-
-    let body = response.json::<UnauthorizedBody>().await.unwrap();
-    assert_eq!(body.error, AuthError::InavlidRefreshJti.as_ref());
+    let stale = Client::new(handle.address.clone()).with_tokens(tokens);
+    assert_api_error(
+        stale.refresh().await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::InvalidRefreshJti,
+    );
 }
 
 #[tokio::test]
@@ -99,17 +92,11 @@ async fn old_refresh_token_rejected_test() {
 async fn valid_refresh_and_access_token_test() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
+    let client = logged_in(&handle, "userA@gmail.com", "123").await;
 
-    let response = client.refresh_token(&tokens.refresh_token).await;
-    assert_eq!(response.status(), StatusCode::OK);
+    client.refresh().await.unwrap();
 
-    let tokens = response.json::<LoginResponse>().await.unwrap();
-
-    let response = client.create_todo(Some(&tokens.access_token), None).await;
-    assert_eq!(response.status(), StatusCode::CREATED);
+    client.create_todo("pay rent", None).await.unwrap();
 }
 
 #[tokio::test]
@@ -117,21 +104,19 @@ async fn valid_refresh_and_access_token_test() {
 async fn logout_invalidates_tokens_test() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
+    let client = logged_in(&handle, "userA@gmail.com", "123").await;
+    let tokens = client.tokens().await.unwrap();
 
-    let response = client.create_todo(Some(&tokens.access_token), None).await;
-    assert_eq!(response.status(), StatusCode::CREATED);
+    client.create_todo("pay rent", None).await.unwrap();
 
-    let response = client.logout(&tokens.access_token).await;
-    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    client.logout().await.unwrap();
 
-    let response = client.create_todo(Some(&tokens.access_token), None).await;
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-
-    let body = response.json::<UnauthorizedBody>().await.unwrap();
-    assert_eq!(body.error, AuthError::InvalidSession.as_ref());
+    let resumed = Client::new(handle.address.clone()).with_tokens(tokens);
+    assert_api_error(
+        resumed.create_todo("pay rent", None).await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::InvalidSession,
+    );
 }
 
 #[tokio::test]
@@ -139,14 +124,21 @@ async fn logout_invalidates_tokens_test() {
 async fn refresh_token_required_test() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
-    let response = client.refresh_token(&tokens.access_token).await;
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-
-    let body = response.json::<UnauthorizedBody>().await.unwrap();
-    assert_eq!(body.error, AuthError::ExpectedRefreshToken.as_ref());
+    let tokens = logged_in(&handle, "userA@gmail.com", "123")
+        .await
+        .tokens()
+        .await
+        .unwrap();
+
+    let client = Client::new(handle.address.clone()).with_tokens(LoginToken {
+        refresh_token: tokens.access_token.clone(),
+        ..tokens
+    });
+    assert_api_error(
+        client.refresh().await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::ExpectedRefreshToken,
+    );
 }
 
 #[tokio::test]
@@ -154,13 +146,19 @@ async fn refresh_token_required_test() {
 async fn access_token_required_test() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("userA@gmail.com", "123").await;
-
-    let response = client.create_todo(Some(&tokens.refresh_token), None).await;
-    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
-
-    let body = response.json::<UnauthorizedBody>().await.unwrap();
-    assert_eq!(body.error, AuthError::ExpectedAccessToken.as_ref());
+    let tokens = logged_in(&handle, "userA@gmail.com", "123")
+        .await
+        .tokens()
+        .await
+        .unwrap();
+
+    let client = Client::new(handle.address.clone()).with_tokens(LoginToken {
+        access_token: tokens.refresh_token.clone(),
+        ..tokens
+    });
+    assert_api_error(
+        client.create_todo("pay rent", None).await,
+        StatusCode::UNAUTHORIZED,
+        ErrorCode::ExpectedAccessToken,
+    );
 }
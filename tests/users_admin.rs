@@ -1,7 +1,7 @@
 mod common;
-use common::{create_test_app, spawn_test_app, LoginResponse, TestAppClient};
-use reqwest::StatusCode;
-use todo_app::{User, UserId, UsersPageResponse};
+use common::{assert_status, create_test_app, logged_in, spawn_test_app};
+use todo_client::{Client, Role, StatusCode, UserId};
+use uuid::Uuid;
 
 #[tokio::test]
 async fn get_all_users_as_admin() {
@@ -10,27 +10,23 @@ async fn get_all_users_as_admin() {
     let mut users = Vec::with_capacity(users_count);
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = Client::new(handle.address.clone());
 
     for i in 0..users_count {
-        let res = client
-            .register_user(&format!("user{i}@gmail.com"), "123")
-            .await;
-        assert_eq!(res.status(), StatusCode::CREATED);
+        client
+            .register(&format!("user{i}@gmail.com"), "123")
+            .await
+            .unwrap();
     }
 
-    let res = client.login_user("admin@gmail.com", "admin").await;
-    let token = res.json::<LoginResponse>().await.unwrap().access_token;
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
 
-    let res = client.get_all_users(&token, limit, None).await;
-
-    let users_page = res.json::<UsersPageResponse>().await.unwrap();
+    let users_page = admin.users_page(limit, None).await.unwrap();
     assert_eq!(users_page.items.len(), limit);
     assert!(users_page.cursor.is_some());
     users.extend(users_page.items);
 
-    let res = client.get_all_users(&token, limit, users_page.cursor).await;
-    let users_page = res.json::<UsersPageResponse>().await.unwrap();
+    let users_page = admin.users_page(limit, users_page.cursor).await.unwrap();
     assert_eq!(users_page.items.len(), users_count - limit);
     assert!(users_page.cursor.is_none());
     users.extend(users_page.items);
@@ -47,69 +43,58 @@ async fn get_all_users_as_admin() {
 async fn get_all_users_as_user() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let res = client.get_all_users(&tokens.access_token, 10, None).await;
-    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_status(client.users_page(10, None).await, StatusCode::FORBIDDEN);
 }
 
 #[tokio::test]
 async fn promote_user_as_admin() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    client.register_user("user@gmail.com", "123").await;
+    Client::new(handle.address.clone())
+        .register("user@gmail.com", "123")
+        .await
+        .unwrap();
 
-    let res = client.login_user("admin@gmail.com", "admin").await;
-    let token = res.json::<LoginResponse>().await.unwrap().access_token;
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
 
-    let res = client.get_user_by_email(&token, "user@gmail.com").await;
-    let user = res.json::<User>().await.unwrap();
+    let user = admin.user_by_email("user@gmail.com").await.unwrap();
 
-    let res = client.promote_user(&token, &user.id).await;
-    assert_eq!(res.status(), StatusCode::OK);
+    admin.set_role(user.id, Role::Admin).await.unwrap();
 
-    let res = client.login_user("user@gmail.com", "123").await;
-    let token = res.json::<LoginResponse>().await.unwrap().access_token;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let res = client.get_all_users(&token, 10, None).await;
-    assert_eq!(res.status(), StatusCode::OK);
+    client.users_page(10, None).await.unwrap();
 }
 
 #[tokio::test]
 async fn promote_user_as_user() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
-
-    let res = client
-        .promote_user(&tokens.access_token, &UserId::new())
-        .await;
-    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_status(
+        client.set_role(UserId(Uuid::new_v4()), Role::Admin).await,
+        StatusCode::FORBIDDEN,
+    );
 }
 
 #[tokio::test]
 async fn get_user_as_admin() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    client.register_user("user@gmail.com", "123").await;
+    Client::new(handle.address.clone())
+        .register("user@gmail.com", "123")
+        .await
+        .unwrap();
 
-    let res = client.login_user("admin@gmail.com", "admin").await;
-    let token = res.json::<LoginResponse>().await.unwrap().access_token;
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
 
-    let res = client.get_user_by_email(&token, "user@gmail.com").await;
-    let user_by_email = res.json::<User>().await.unwrap();
+    let user_by_email = admin.user_by_email("user@gmail.com").await.unwrap();
     assert_eq!(user_by_email.email, "user@gmail.com");
 
-    let res = client.get_user(&token, &user_by_email.id).await;
-    let user_by_id = res.json::<User>().await.unwrap();
+    let user_by_id = admin.user(user_by_email.id).await.unwrap();
     assert_eq!(user_by_email, user_by_id);
 }
 
@@ -117,15 +102,15 @@ async fn get_user_as_admin() {
 async fn get_user_as_user() {
     let handle = spawn_test_app(create_test_app(None).await).await;
 
-    let client = TestAppClient::new(handle.address);
-
-    let tokens = client.register_and_login("user@gmail.com", "123").await;
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
 
-    let res = client
-        .get_user_by_email(&tokens.access_token, "user@gmail.com")
-        .await;
-    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_status(
+        client.user_by_email("user@gmail.com").await,
+        StatusCode::FORBIDDEN,
+    );
 
-    let res = client.get_user(&tokens.access_token, &UserId::new()).await;
-    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_status(
+        client.user(UserId(Uuid::new_v4())).await,
+        StatusCode::FORBIDDEN,
+    );
 }
@@ -0,0 +1,410 @@
+mod common;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header::LOCATION, HeaderMap},
+    routing::post,
+    Router,
+};
+use common::{
+    assert_api_error, assert_status, create_test_app, create_test_app_with_webhooks, logged_in,
+    spawn_test_app,
+};
+use ring::hmac;
+use todo_app::WebhookConfig;
+use todo_client::{
+    Client, DeadLettersPageResponse, ErrorCode, StatusCode, UpdateTodo, WebhookEventKind,
+};
+use tokio::{net::TcpListener, sync::mpsc, time::timeout};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+fn config(max_attempts: u32) -> WebhookConfig {
+    WebhookConfig {
+        poll_interval_ms: 20,
+        timeout_ms: 2_000,
+        max_attempts,
+        backoff_base_ms: 50,
+        backoff_max_ms: 200,
+        batch_size: 100,
+        block_private_targets: false,
+    }
+}
+
+#[derive(Debug)]
+struct Received {
+    headers: HeaderMap,
+    body: String,
+    /// Whether the receiver answered with a success.
+    accepted: bool,
+}
+
+#[derive(Clone)]
+struct ReceiverState {
+    failures_left: Arc<AtomicUsize>,
+    tx: mpsc::UnboundedSender<Received>,
+}
+
+async fn receive(
+    State(state): State<ReceiverState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let accepted = state
+        .failures_left
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+            left.checked_sub(1)
+        })
+        .is_err();
+    let _ = state.tx.send(Received {
+        headers,
+        body: String::from_utf8(body.to_vec()).unwrap(),
+        accepted,
+    });
+    if accepted {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// Local endpoint answering the first `failures` requests with a 500.
+struct Receiver {
+    url: String,
+    failures_left: Arc<AtomicUsize>,
+    rx: mpsc::UnboundedReceiver<Received>,
+}
+
+impl Receiver {
+    async fn spawn(failures: usize) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let failures_left = Arc::new(AtomicUsize::new(failures));
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(ReceiverState {
+                failures_left: failures_left.clone(),
+                tx,
+            });
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self {
+            url: format!("http://{addr}/hook"),
+            failures_left,
+            rx,
+        }
+    }
+
+    async fn next(&mut self) -> Received {
+        timeout(WAIT, self.rx.recv()).await.unwrap().unwrap()
+    }
+}
+
+fn verify_signature(secret: &str, received: &Received) {
+    let header = received.headers["x-webhook-signature"].to_str().unwrap();
+    let (timestamp, signature) = header
+        .strip_prefix("t=")
+        .and_then(|rest| rest.split_once(",v1="))
+        .unwrap();
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{timestamp}.{}", received.body).as_bytes());
+    let expected: String = tag
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    assert_eq!(signature, expected);
+}
+
+fn payload(received: &Received) -> serde_json::Value {
+    serde_json::from_str(&received.body).unwrap()
+}
+
+async fn wait_for_dead_letters(client: &Client) -> DeadLettersPageResponse {
+    timeout(WAIT, async {
+        loop {
+            let page = client.dead_letters_page(10, None).await.unwrap();
+            if !page.items.is_empty() {
+                break page;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn webhook_deliveries_are_signed_and_retried() {
+    let handle = spawn_test_app(create_test_app_with_webhooks(&config(3)).await).await;
+    let mut receiver = Receiver::spawn(1).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    let webhook = client
+        .create_webhook(
+            &receiver.url,
+            &[
+                WebhookEventKind::TodoCreated,
+                WebhookEventKind::TodoCompleted,
+                WebhookEventKind::TodoDeleted,
+            ],
+        )
+        .await
+        .unwrap();
+
+    let id = client.create_todo("pay rent", None).await.unwrap();
+    client
+        .update_todo(
+            id,
+            &UpdateTodo::default()
+                .text("pay rent today")
+                .completed(true)
+                .group("home"),
+        )
+        .await
+        .unwrap();
+    // already completed, so a plain update nobody subscribed to
+    client
+        .update_todo(id, &UpdateTodo::default().completed(true))
+        .await
+        .unwrap();
+    client.delete_todo(id).await.unwrap();
+
+    let failed = receiver.next().await;
+    assert!(!failed.accepted);
+
+    let mut delivered = Vec::new();
+    while delivered.len() < 3 {
+        let received = receiver.next().await;
+        assert!(received.accepted);
+        verify_signature(&webhook.secret, &received);
+        delivered.push(received);
+    }
+
+    // the retry carries the same event
+    let retried = delivered
+        .iter()
+        .find(|received| received.headers["x-webhook-id"] == failed.headers["x-webhook-id"])
+        .unwrap();
+    assert_eq!(retried.body, failed.body);
+
+    let created = delivered
+        .iter()
+        .map(payload)
+        .find(|payload| payload["type"] == "todo.created")
+        .unwrap();
+    assert_eq!(created["type"], "todo.created");
+    assert_eq!(created["data"]["todo"]["id"], id.to_string());
+    assert_eq!(created["data"]["todo"]["text"], "pay rent");
+
+    let mut kinds: Vec<_> = delivered
+        .iter()
+        .map(|received| payload(received)["type"].clone())
+        .collect();
+    kinds.sort_by_key(|kind| kind.to_string());
+    assert_eq!(
+        kinds,
+        vec!["todo.completed", "todo.created", "todo.deleted"]
+    );
+    let completed = delivered
+        .iter()
+        .map(payload)
+        .find(|payload| payload["type"] == "todo.completed")
+        .unwrap();
+    assert_eq!(completed["data"]["todo"]["text"], "pay rent today");
+    assert_eq!(completed["data"]["todo"]["completed"], true);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(receiver.rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn failing_deliveries_end_up_in_dead_letters() {
+    let handle = spawn_test_app(create_test_app_with_webhooks(&config(2)).await).await;
+    let mut receiver = Receiver::spawn(usize::MAX).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    client
+        .create_webhook(&receiver.url, &[WebhookEventKind::TodoCreated])
+        .await
+        .unwrap();
+
+    client.create_todo("pay rent", None).await.unwrap();
+    assert!(!receiver.next().await.accepted);
+    assert!(!receiver.next().await.accepted);
+
+    let dead = wait_for_dead_letters(&client).await;
+    assert_eq!(dead.items.len(), 1);
+    let letter = &dead.items[0];
+    assert_eq!(letter.event, WebhookEventKind::TodoCreated);
+    assert_eq!(letter.attempts, 2);
+    assert!(letter.last_error.as_ref().unwrap().contains("500"));
+
+    let stranger = logged_in(&handle, "other@gmail.com", "123").await;
+    assert_api_error(
+        stranger.retry_dead_letter(letter.id).await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
+
+    receiver.failures_left.store(0, Ordering::SeqCst);
+    client.retry_dead_letter(letter.id).await.unwrap();
+    let received = receiver.next().await;
+    assert!(received.accepted);
+    assert_eq!(
+        received.headers["x-webhook-id"].to_str().unwrap(),
+        letter.event_id.to_string()
+    );
+
+    let page = client.dead_letters_page(10, None).await.unwrap();
+    assert!(page.items.is_empty());
+    assert_api_error(
+        client.retry_dead_letter(letter.id).await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
+}
+
+#[tokio::test]
+async fn manage_webhooks() {
+    let handle = spawn_test_app(create_test_app_with_webhooks(&config(3)).await).await;
+    let mut receiver = Receiver::spawn(0).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    for url in ["not a url", "ftp://localhost/hook"] {
+        assert_api_error(
+            client
+                .create_webhook(url, &[WebhookEventKind::TodoCreated])
+                .await,
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidWebhook,
+        );
+    }
+    assert_api_error(
+        client.create_webhook(&receiver.url, &[]).await,
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidWebhook,
+    );
+    assert_status(
+        client
+            .create_webhook(&receiver.url, &[WebhookEventKind::UserCreated])
+            .await,
+        StatusCode::FORBIDDEN,
+    );
+
+    let webhook = client
+        .create_webhook(&receiver.url, &[WebhookEventKind::TodoUpdated])
+        .await
+        .unwrap();
+    let webhooks = client.webhooks().await.unwrap();
+    assert_eq!(webhooks.len(), 1);
+    assert_eq!(webhooks[0].id, webhook.webhook.id);
+    assert_eq!(webhooks[0].events, vec![WebhookEventKind::TodoUpdated]);
+
+    client.delete_webhook(webhook.webhook.id).await.unwrap();
+    assert_api_error(
+        client.delete_webhook(webhook.webhook.id).await,
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+    );
+
+    // admins hear about new users
+    let admin = logged_in(&handle, "admin@gmail.com", "admin").await;
+    let webhook = admin
+        .create_webhook(&receiver.url, &[WebhookEventKind::UserCreated])
+        .await
+        .unwrap();
+
+    client.register("new@gmail.com", "123").await.unwrap();
+    // earlier registrations may still be waiting in the outbox
+    let event = loop {
+        let received = receiver.next().await;
+        verify_signature(&webhook.secret, &received);
+        let event = payload(&received);
+        assert_eq!(event["type"], "user.created");
+        if event["data"]["user"]["email"] == "new@gmail.com" {
+            break event;
+        }
+    };
+    assert_eq!(event["data"]["user"]["role"], "User");
+}
+
+#[tokio::test]
+async fn private_targets_are_refused() {
+    // the default config blocks them, the test config lets them through
+    let handle = spawn_test_app(create_test_app(Some("default")).await).await;
+    let receiver = Receiver::spawn(0).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    for url in [
+        receiver.url.as_str(),
+        "http://localhost/hook",
+        "http://[::1]/hook",
+        "http://169.254.169.254/latest/meta-data/",
+        "https://192.168.0.1/hook",
+    ] {
+        let e = client
+            .create_webhook(url, &[WebhookEventKind::TodoCreated])
+            .await
+            .unwrap_err();
+        assert_eq!(e.status(), Some(StatusCode::BAD_REQUEST), "{url}");
+    }
+
+    // checked again before every delivery
+    let config = WebhookConfig {
+        block_private_targets: true,
+        ..config(1)
+    };
+    let handle = spawn_test_app(create_test_app_with_webhooks(&config).await).await;
+    let mut receiver = Receiver::spawn(0).await;
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    client
+        .create_webhook(&receiver.url, &[WebhookEventKind::TodoCreated])
+        .await
+        .unwrap();
+    client.create_todo("pay rent", None).await.unwrap();
+
+    let dead = wait_for_dead_letters(&client).await;
+    let error = dead.items[0].last_error.as_ref().unwrap();
+    assert!(error.contains("private address"), "{error}");
+    assert!(receiver.rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn redirects_are_not_followed() {
+    let handle = spawn_test_app(create_test_app_with_webhooks(&config(1)).await).await;
+    let mut receiver = Receiver::spawn(0).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let redirect_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let target = receiver.url.clone();
+    let redirect = Router::new().route(
+        "/hook",
+        post(move || async move { (StatusCode::TEMPORARY_REDIRECT, [(LOCATION, target)]) }),
+    );
+    tokio::spawn(async move { axum::serve(listener, redirect).await.unwrap() });
+
+    let client = logged_in(&handle, "user@gmail.com", "123").await;
+    client
+        .create_webhook(&redirect_url, &[WebhookEventKind::TodoCreated])
+        .await
+        .unwrap();
+    client.create_todo("pay rent", None).await.unwrap();
+
+    let dead = wait_for_dead_letters(&client).await;
+    let error = dead.items[0].last_error.as_ref().unwrap();
+    assert!(error.contains("307"), "{error}");
+    assert!(receiver.rx.try_recv().is_err());
+}
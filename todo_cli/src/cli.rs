@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use todo_client::{TransferFormat, Url};
+
+#[derive(Parser, Debug)]
+#[command(name = "todo", version, about = "Manage your todos from the terminal")]
+pub struct Cli {
+    /// File holding the server url and the session, defaults to `todo/config.toml`
+    /// in the user config directory.
+    #[arg(long, global = true, env = "TODO_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Server url, remembered by `login`.
+    #[arg(long, global = true, env = "TODO_SERVER")]
+    pub server: Option<Url>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Log in and save the session.
+    Login {
+        email: String,
+        /// Asked for on the terminal when missing.
+        #[arg(long, env = "TODO_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// End the session and forget its tokens.
+    Logout,
+    /// Create a todo and print its id.
+    Add {
+        #[arg(required = true, num_args = 1..)]
+        text: Vec<String>,
+        #[arg(long, short)]
+        group: Option<String>,
+        /// `YYYY-MM-DD`, RFC 3339 time or unix timestamp.
+        #[arg(long, value_parser = parse_due)]
+        due: Option<i64>,
+    },
+    /// List todos.
+    Ls(ListFilter),
+    /// Mark todos as completed.
+    Done {
+        /// Full ids or unique prefixes of them.
+        #[arg(required = true, num_args = 1..)]
+        ids: Vec<String>,
+        /// Mark them as open again instead.
+        #[arg(long)]
+        reopen: bool,
+        /// Complete todos that still have open blockers.
+        #[arg(long, conflicts_with = "reopen")]
+        force: bool,
+    },
+    /// Change a todo.
+    Edit {
+        /// Full id or a unique prefix of it.
+        id: String,
+        #[arg(long)]
+        text: Option<String>,
+        #[arg(long, short)]
+        group: Option<String>,
+        /// `YYYY-MM-DD`, RFC 3339 time or unix timestamp.
+        #[arg(long, value_parser = parse_due)]
+        due: Option<i64>,
+        /// Remove the due date.
+        #[arg(long, conflicts_with = "due")]
+        no_due: bool,
+    },
+    /// Move todos to the trash.
+    Rm {
+        /// Full ids or unique prefixes of them.
+        #[arg(required = true, num_args = 1..)]
+        ids: Vec<String>,
+    },
+    /// Write all todos to stdout or a file.
+    Export {
+        #[arg(long, short, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Create todos from a file, `-` or no file reads stdin.
+    Import {
+        #[arg(long, short, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug, Default)]
+pub struct ListFilter {
+    #[arg(long, short)]
+    pub group: Option<String>,
+    /// Only completed todos.
+    #[arg(long, conflicts_with = "open")]
+    pub done: bool,
+    /// Only todos that are not completed.
+    #[arg(long)]
+    pub open: bool,
+    /// Only todos due before this time.
+    #[arg(long, value_parser = parse_due)]
+    pub due_before: Option<i64>,
+    /// Only todos whose text contains this, ignoring case.
+    #[arg(long, short)]
+    pub search: Option<String>,
+    /// Print a JSON array instead of a table.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Ndjson,
+    Markdown,
+    TodoTxt,
+    Ical,
+}
+
+impl From<Format> for TransferFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Csv => Self::Csv,
+            Format::Ndjson => Self::Ndjson,
+            Format::Markdown => Self::Markdown,
+            Format::TodoTxt => Self::TodoTxt,
+            Format::Ical => Self::Ical,
+        }
+    }
+}
+
+/// Due dates given as a day are due at its start, in UTC.
+fn parse_due(value: &str) -> Result<i64, String> {
+    if let Ok(timestamp) = value.parse() {
+        return Ok(timestamp);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp())
+        .map_err(|_| "expected YYYY-MM-DD, an RFC 3339 time or a unix timestamp".to_string())
+}
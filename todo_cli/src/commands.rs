@@ -0,0 +1,190 @@
+use std::{fs, io::Write};
+
+use chrono::{DateTime, Timelike};
+use futures_util::TryStreamExt;
+use todo_client::{BatchOperation, Client, Todo, TodoId, TransferFormat, UpdateTodo};
+
+use crate::{
+    cli::{Command, ListFilter},
+    error::CliError,
+};
+
+/// Limit of `POST /todos/batch`.
+const MAX_BATCH_OPERATIONS: usize = 100;
+const PAGE_SIZE: usize = 100;
+/// Length of the ids printed by `ls`.
+const SHORT_ID_LEN: usize = 8;
+
+async fn login(client: &Client, email: &str, password: Option<String>) -> Result<(), CliError> {
+    let password = match password {
+        Some(password) => password,
+        None => rpassword::prompt_password("Password: ").map_err(CliError::ReadPassword)?,
+    };
+    client.login(email, &password).await?;
+    Ok(())
+}
+
+async fn logout(client: &Client) -> Result<(), CliError> {
+    match client.logout().await.map_err(CliError::from) {
+        Err(CliError::SessionExpired) => Ok(()),
+        result => result,
+    }
+}
+
+pub(crate) async fn run(
+    client: &Client,
+    command: Command,
+    out: &mut dyn Write,
+) -> Result<(), CliError> {
+    match command {
+        Command::Login { email, password } => login(client, &email, password).await?,
+        Command::Logout => logout(client).await?,
+        Command::Add { text, group, due } => {
+            let id = client.create_todo(&text.join(" "), due).await?;
+            if let Some(group) = group {
+                client
+                    .update_todo(id, &UpdateTodo::default().group(group))
+                    .await?;
+            }
+            writeln!(out, "{id}")?;
+        }
+        Command::Ls(filter) => list(client, &filter, out).await?,
+        Command::Done { ids, reopen, force } => {
+            let mut patch = UpdateTodo::default().completed(!reopen);
+            if force {
+                patch = patch.ignore_blockers();
+            }
+            let operations = resolve_ids(client, &ids)
+                .await?
+                .into_iter()
+                .map(|id| BatchOperation::Update {
+                    id,
+                    patch: patch.clone(),
+                })
+                .collect();
+            batch(client, operations).await?;
+        }
+        Command::Edit {
+            id,
+            text,
+            group,
+            due,
+            no_due,
+        } => {
+            let id = resolve_ids(client, &[id]).await?[0];
+            let patch = UpdateTodo {
+                text,
+                group,
+                due: if no_due { Some(None) } else { due.map(Some) },
+                ..Default::default()
+            };
+            client.update_todo(id, &patch).await?;
+        }
+        Command::Rm { ids } => {
+            let operations = resolve_ids(client, &ids)
+                .await?
+                .into_iter()
+                .map(|id| BatchOperation::Delete { id })
+                .collect();
+            batch(client, operations).await?;
+        }
+        Command::Export { format, output } => {
+            let exported = client.export_todos(format.into()).await?;
+            match output {
+                Some(path) => fs::write(&path, exported).map_err(|e| CliError::Io(path, e))?,
+                None => out.write_all(exported.as_bytes())?,
+            }
+        }
+        Command::Import { format, file } => {
+            let body = match file.filter(|path| path.as_os_str() != "-") {
+                Some(path) => fs::read_to_string(&path).map_err(|e| CliError::Io(path, e))?,
+                None => std::io::read_to_string(std::io::stdin())
+                    .map_err(|e| CliError::Io("stdin".into(), e))?,
+            };
+            let imported = client
+                .import_todos(TransferFormat::from(format), body)
+                .await?;
+            writeln!(out, "Imported {imported} todos")?;
+        }
+    }
+    Ok(())
+}
+
+async fn list(client: &Client, filter: &ListFilter, out: &mut dyn Write) -> Result<(), CliError> {
+    let search = filter.search.as_deref().map(str::to_lowercase);
+    let todos: Vec<Todo> = client
+        .todos(PAGE_SIZE)
+        .try_filter(|todo| {
+            let keep = filter
+                .group
+                .as_ref()
+                .is_none_or(|group| &todo.group == group)
+                && (!filter.done || todo.completed)
+                && (!filter.open || !todo.completed)
+                && filter
+                    .due_before
+                    .is_none_or(|before| todo.due.is_some_and(|due| due < before))
+                && search
+                    .as_ref()
+                    .is_none_or(|search| todo.text.to_lowercase().contains(search));
+            std::future::ready(keep)
+        })
+        .try_collect()
+        .await?;
+
+    if filter.json {
+        serde_json::to_writer_pretty(&mut *out, &todos)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+    for todo in todos {
+        let id = todo.id.to_string();
+        let mark = if todo.completed { 'x' } else { ' ' };
+        write!(out, "{}  [{mark}] {}", &id[..SHORT_ID_LEN], todo.text)?;
+        if !todo.group.is_empty() {
+            write!(out, "  #{}", todo.group)?;
+        }
+        if let Some(due) = todo.due.and_then(|due| DateTime::from_timestamp(due, 0)) {
+            let format = if due.num_seconds_from_midnight() == 0 {
+                "%Y-%m-%d"
+            } else {
+                "%Y-%m-%d %H:%M"
+            };
+            write!(out, "  due {}", due.format(format))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Accepts full ids and the unique prefixes printed by `ls`. The todos are
+/// only listed when a prefix has to be matched.
+async fn resolve_ids(client: &Client, ids: &[String]) -> Result<Vec<TodoId>, CliError> {
+    if let Ok(ids) = ids.iter().map(|id| id.parse()).collect() {
+        return Ok(ids);
+    }
+
+    let known: Vec<String> = client
+        .todos(PAGE_SIZE)
+        .map_ok(|todo| todo.id.to_string())
+        .try_collect()
+        .await?;
+    ids.iter()
+        .map(|prefix| {
+            let prefix = prefix.to_lowercase();
+            let mut matches = known.iter().filter(|id| id.starts_with(&prefix));
+            match (matches.next(), matches.next()) {
+                (Some(id), None) => Ok(id.parse().expect("listed ids are valid")),
+                (Some(_), Some(_)) => Err(CliError::AmbiguousId(prefix)),
+                (None, _) => Err(CliError::UnknownId(prefix)),
+            }
+        })
+        .collect()
+}
+
+async fn batch(client: &Client, operations: Vec<BatchOperation>) -> Result<(), CliError> {
+    for chunk in operations.chunks(MAX_BATCH_OPERATIONS) {
+        client.batch(chunk).await?;
+    }
+    Ok(())
+}
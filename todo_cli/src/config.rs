@@ -0,0 +1,50 @@
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use todo_client::LoginToken;
+
+use crate::error::CliError;
+
+/// User config file, rewritten whenever the session tokens change.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub server: Option<String>,
+    pub tokens: Option<LoginToken>,
+}
+
+impl Config {
+    pub fn default_path() -> Result<PathBuf, CliError> {
+        dirs::config_dir()
+            .map(|dir| dir.join("todo").join("config.toml"))
+            .ok_or(CliError::NoConfigDir)
+    }
+
+    /// A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Self, CliError> {
+        match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| CliError::ParseConfig(path.into(), e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(CliError::Io(path.into(), e)),
+        }
+    }
+
+    /// Only the owner may read the file, it holds a refresh token.
+    pub fn save(&self, path: &Path) -> Result<(), CliError> {
+        let io_error = |e| CliError::Io(path.into(), e);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path).map_err(io_error)?;
+        let text = toml::to_string(self).expect("config is always representable in TOML");
+        file.write_all(text.as_bytes()).map_err(io_error)
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+use todo_client::{ClientError, ErrorCode};
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error(transparent)]
+    Client(ClientError),
+
+    #[error("Not logged in, run `todo login <email>` first")]
+    NotLoggedIn,
+
+    #[error("Session expired, run `todo login <email>` again")]
+    SessionExpired,
+
+    #[error("No server url, pass --server or set TODO_SERVER")]
+    MissingServer,
+
+    #[error("Invalid server url in config: {0}")]
+    InvalidServer(String),
+
+    #[error("Couldn't find the user config directory, pass --config")]
+    NoConfigDir,
+
+    #[error("Failed to parse {}: {}", .0.display(), .1)]
+    ParseConfig(PathBuf, toml::de::Error),
+
+    #[error("{}: {}", .0.display(), .1)]
+    Io(PathBuf, std::io::Error),
+
+    #[error("Failed to read the password: {0}")]
+    ReadPassword(std::io::Error),
+
+    #[error("Failed to write output: {0}")]
+    Output(#[from] std::io::Error),
+
+    #[error("Failed to encode todos: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("No todo with id {0}")]
+    UnknownId(String),
+
+    #[error("Id prefix {0} matches several todos")]
+    AmbiguousId(String),
+}
+
+impl From<ClientError> for CliError {
+    fn from(e: ClientError) -> Self {
+        match e.code() {
+            Some(ErrorCode::SessionExpired | ErrorCode::InvalidSession) => Self::SessionExpired,
+            _ if matches!(e, ClientError::NotLoggedIn) => Self::NotLoggedIn,
+            _ => Self::Client(e),
+        }
+    }
+}
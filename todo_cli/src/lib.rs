@@ -0,0 +1,60 @@
+//! `todo` command-line client, built on `todo_client`.
+//!
+//! The server url and the session tokens live in a TOML config file. Expired
+//! access tokens are refreshed by the client, and the new pair is written back
+//! after every command.
+
+mod cli;
+mod commands;
+mod config;
+mod error;
+
+use std::io::Write;
+
+pub use cli::{Cli, Command, Format, ListFilter};
+pub use config::Config;
+pub use error::CliError;
+use todo_client::{Client, Url};
+
+/// Runs one command, writing what it prints to `out`.
+pub async fn run(cli: Cli, out: &mut dyn Write) -> Result<(), CliError> {
+    let path = match cli.config {
+        Some(path) => path,
+        None => Config::default_path()?,
+    };
+    let saved = Config::load(&path)?;
+    let url = match cli.server {
+        Some(url) => url,
+        None => server_url(&saved)?,
+    };
+    let mut client = Client::new(url.clone());
+    if let Some(tokens) = saved.tokens.clone() {
+        client = client.with_tokens(tokens);
+    }
+
+    let mut config = saved.clone();
+    if matches!(cli.command, Command::Login { .. }) {
+        config.server = Some(url.to_string());
+    }
+    let logging_out = matches!(cli.command, Command::Logout);
+    let result = commands::run(&client, cli.command, out).await;
+
+    // Also picks up tokens refreshed by a command that failed afterwards. A
+    // failed logout still drops the local session.
+    config.tokens = if logging_out {
+        None
+    } else {
+        client.tokens().await
+    };
+    if config != saved {
+        config.save(&path)?;
+    }
+    result
+}
+
+fn server_url(config: &Config) -> Result<Url, CliError> {
+    let server = config.server.as_deref().ok_or(CliError::MissingServer)?;
+    server
+        .parse()
+        .map_err(|_| CliError::InvalidServer(server.to_string()))
+}
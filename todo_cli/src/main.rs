@@ -0,0 +1,22 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use todo_cli::{Cli, CliError};
+use todo_client::ClientError;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match todo_cli::run(cli, &mut std::io::stdout().lock()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("todo: {e}");
+            if let CliError::Client(ClientError::Api { body, .. }) = &e {
+                for line in &body.errors {
+                    eprintln!("  line {}: {}", line.line, line.message);
+                }
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,646 @@
+use std::{fmt::Display, sync::Arc};
+
+use futures_util::Stream;
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{
+    error::{ApiError, ClientError, ErrorCode},
+    events::todo_events,
+    ids::{DeliveryId, HistorySeq, SyncSeq, TodoId, UserId, WebhookId},
+    page::paginate,
+    types::{
+        BackupFileResponse, BackupResponse, BatchOperation, BatchResponse, CalendarTokenResponse,
+        CreatedWebhookResponse, DeadLetter, DeadLettersPageResponse, DisplayUser, FsckResponse,
+        GroupUpdateResponse, HistoryEntry, HistoryPageResponse, ImportResponse, LoginToken, Role,
+        StorageStatsResponse, SyncOperation, SyncPushResponse, SyncResponse, SyncResult, Todo,
+        TodoDetails, TodoEvent, TodoOpResult, TodosPageResponse, TransferFormat, TrashPageResponse,
+        TrashedTodo, UndoResult, UpdateTodo, UsersPageResponse, WebhookEventKind, WebhookResponse,
+    },
+};
+
+/// Client of a single user session. Requests carry the access token, which
+/// is refreshed through `/auth/refresh` once it expires. Clones share the
+/// session.
+#[derive(Clone)]
+pub struct Client {
+    url: Url,
+    http: reqwest::Client,
+    tokens: Arc<Mutex<Option<LoginToken>>>,
+}
+
+impl Client {
+    pub fn new(url: Url) -> Self {
+        Self::with_http_client(url, reqwest::Client::new())
+    }
+
+    /// Shares the connection pool of `http`, e.g. between the sessions of many users.
+    pub fn with_http_client(url: Url, http: reqwest::Client) -> Self {
+        Self {
+            url,
+            http,
+            tokens: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Resumes a session, e.g. with tokens saved by an earlier run.
+    pub fn with_tokens(mut self, tokens: LoginToken) -> Self {
+        self.tokens = Arc::new(Mutex::new(Some(tokens)));
+        self
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Current tokens, they change with every refresh.
+    pub async fn tokens(&self) -> Option<LoginToken> {
+        self.tokens.lock().await.clone()
+    }
+
+    pub async fn register(&self, email: &str, password: &str) -> Result<(), ClientError> {
+        let url = self.endpoint(&["auth", "register"])?;
+        self.send(
+            self.http
+                .post(url)
+                .json(&json!({ "email": email, "password": password })),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Starts a session, following requests are made on behalf of this user.
+    pub async fn login(&self, email: &str, password: &str) -> Result<LoginToken, ClientError> {
+        let url = self.endpoint(&["auth", "login"])?;
+        let tokens: LoginToken = self
+            .send(
+                self.http
+                    .post(url)
+                    .json(&json!({ "email": email, "password": password })),
+            )
+            .await?
+            .json()
+            .await?;
+        *self.tokens.lock().await = Some(tokens.clone());
+        Ok(tokens)
+    }
+
+    /// Replaces both tokens ahead of their expiry.
+    pub async fn refresh(&self) -> Result<LoginToken, ClientError> {
+        let mut tokens = self.tokens.lock().await;
+        let current = tokens.as_ref().ok_or(ClientError::NotLoggedIn)?;
+        let refreshed = self.request_refresh(&current.refresh_token).await?;
+        *tokens = Some(refreshed.clone());
+        Ok(refreshed)
+    }
+
+    /// Ends the session on the server and forgets its tokens.
+    pub async fn logout(&self) -> Result<(), ClientError> {
+        let url = self.endpoint(&["auth", "logout"])?;
+        self.send_authorized(self.http.post(url)).await?;
+        *self.tokens.lock().await = None;
+        Ok(())
+    }
+
+    pub async fn health(&self) -> Result<(), ClientError> {
+        let url = self.endpoint(&["health"])?;
+        self.send(self.http.get(url)).await?;
+        Ok(())
+    }
+
+    pub async fn create_todo(&self, text: &str, due: Option<i64>) -> Result<TodoId, ClientError> {
+        let url = self.endpoint(&["todos"])?;
+        let request = self
+            .http
+            .post(url)
+            .json(&json!({ "text": text, "due": due }));
+        Ok(self.send_authorized(request).await?.json().await?)
+    }
+
+    pub async fn get_todo(&self, id: TodoId) -> Result<TodoDetails, ClientError> {
+        let url = self.endpoint(&["todos", &id.to_string()])?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn todos_page(
+        &self,
+        limit: usize,
+        after: Option<TodoId>,
+    ) -> Result<TodosPageResponse, ClientError> {
+        let url = self.page_endpoint(&["todos"], limit, after)?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// All todos of the user, fetched `page_size` at a time.
+    pub fn todos(&self, page_size: usize) -> impl Stream<Item = Result<Todo, ClientError>> + '_ {
+        paginate(move |after| async move {
+            let page = self.todos_page(page_size, after).await?;
+            Ok((page.items, page.cursor))
+        })
+    }
+
+    pub async fn update_todo(&self, id: TodoId, patch: &UpdateTodo) -> Result<(), ClientError> {
+        let url = self.endpoint(&["todos", &id.to_string()])?;
+        self.send_authorized(self.http.patch(url).json(patch))
+            .await?;
+        Ok(())
+    }
+
+    /// Applies `patch` to every todo of `group`, returns how many were updated.
+    pub async fn update_group(
+        &self,
+        group: &str,
+        patch: &UpdateTodo,
+    ) -> Result<usize, ClientError> {
+        let mut url = self.endpoint(&["todos"])?;
+        url.query_pairs_mut().append_pair("group", group);
+        let response: GroupUpdateResponse = self
+            .send_authorized(self.http.patch(url).json(patch))
+            .await?
+            .json()
+            .await?;
+        Ok(response.updated)
+    }
+
+    /// Returns whether the todo existed, deleting a missing one succeeds as well.
+    pub async fn delete_todo(&self, id: TodoId) -> Result<bool, ClientError> {
+        let url = self.endpoint(&["todos", &id.to_string()])?;
+        let response = self.send_authorized(self.http.delete(url)).await?;
+        Ok(response.status() != StatusCode::NO_CONTENT)
+    }
+
+    pub async fn delete_all_todos(&self) -> Result<(), ClientError> {
+        let url = self.endpoint(&["todos"])?;
+        self.send_authorized(self.http.delete(url)).await?;
+        Ok(())
+    }
+
+    /// Runs all operations in one transaction, nothing is applied if one fails.
+    pub async fn batch(
+        &self,
+        operations: &[BatchOperation],
+    ) -> Result<Vec<TodoOpResult>, ClientError> {
+        let url = self.endpoint(&["todos", "batch"])?;
+        let response: BatchResponse = self
+            .send_authorized(
+                self.http
+                    .post(url)
+                    .json(&json!({ "operations": operations })),
+            )
+            .await?
+            .json()
+            .await?;
+        Ok(response.results)
+    }
+
+    pub async fn add_blocker(&self, id: TodoId, blocker_id: TodoId) -> Result<(), ClientError> {
+        let url = self.endpoint(&["todos", &id.to_string(), "blocked_by"])?;
+        self.send_authorized(
+            self.http
+                .post(url)
+                .json(&json!({ "blocker_id": blocker_id })),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_blocker(&self, id: TodoId, blocker_id: TodoId) -> Result<(), ClientError> {
+        let url = self.endpoint(&[
+            "todos",
+            &id.to_string(),
+            "blocked_by",
+            &blocker_id.to_string(),
+        ])?;
+        self.send_authorized(self.http.delete(url)).await?;
+        Ok(())
+    }
+
+    pub async fn export_todos(&self, format: TransferFormat) -> Result<String, ClientError> {
+        let mut url = self.endpoint(&["todos", "export"])?;
+        url.query_pairs_mut().append_pair("format", format.as_str());
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .text()
+            .await?)
+    }
+
+    /// Returns how many todos were imported. When any line is invalid nothing
+    /// is imported and the error lists every broken line.
+    pub async fn import_todos(
+        &self,
+        format: TransferFormat,
+        body: impl Into<String>,
+    ) -> Result<usize, ClientError> {
+        let mut url = self.endpoint(&["todos", "import"])?;
+        url.query_pairs_mut().append_pair("format", format.as_str());
+        let response: ImportResponse = self
+            .send_authorized(self.http.post(url).body(body.into()))
+            .await?
+            .json()
+            .await?;
+        Ok(response.imported)
+    }
+
+    pub async fn trash_page(
+        &self,
+        limit: usize,
+        after: Option<TodoId>,
+    ) -> Result<TrashPageResponse, ClientError> {
+        let url = self.page_endpoint(&["todos", "trash"], limit, after)?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub fn trash(
+        &self,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<TrashedTodo, ClientError>> + '_ {
+        paginate(move |after| async move {
+            let page = self.trash_page(page_size, after).await?;
+            Ok((page.items, page.cursor))
+        })
+    }
+
+    pub async fn restore_todo(&self, id: TodoId) -> Result<(), ClientError> {
+        let url = self.endpoint(&["todos", &id.to_string(), "restore"])?;
+        self.send_authorized(self.http.post(url)).await?;
+        Ok(())
+    }
+
+    /// Reverts the latest todo operation of the user.
+    pub async fn undo(&self) -> Result<UndoResult, ClientError> {
+        let url = self.endpoint(&["todos", "undo"])?;
+        Ok(self
+            .send_authorized(self.http.post(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn history_page(
+        &self,
+        id: TodoId,
+        limit: usize,
+        after: Option<HistorySeq>,
+    ) -> Result<HistoryPageResponse, ClientError> {
+        let url = self.page_endpoint(&["todos", &id.to_string(), "history"], limit, after)?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub fn history(
+        &self,
+        id: TodoId,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<HistoryEntry, ClientError>> + '_ {
+        paginate(move |after| async move {
+            let page = self.history_page(id, page_size, after).await?;
+            Ok((page.items, page.cursor))
+        })
+    }
+
+    /// Live changes of the user's todos, resumed after `last_event_id` when given.
+    pub async fn events(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<TodoEvent, ClientError>>, ClientError> {
+        let mut url = self.endpoint(&["todos", "events"])?;
+        if let Some(last_event_id) = last_event_id {
+            url.query_pairs_mut()
+                .append_pair("last_event_id", &last_event_id.to_string());
+        }
+        let response = self.send_authorized(self.http.get(url)).await?;
+        Ok(todo_events(response.bytes_stream()))
+    }
+
+    /// Issues a calendar feed token, revoking the previous one.
+    pub async fn create_calendar_token(&self) -> Result<CalendarTokenResponse, ClientError> {
+        let url = self.endpoint(&["calendar", "token"])?;
+        Ok(self
+            .send_authorized(self.http.post(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn revoke_calendar_token(&self) -> Result<(), ClientError> {
+        let url = self.endpoint(&["calendar", "token"])?;
+        self.send_authorized(self.http.delete(url)).await?;
+        Ok(())
+    }
+
+    /// iCalendar feed at the `feed` path of [`CalendarTokenResponse`], needs no session.
+    pub async fn calendar_feed(&self, feed: &str) -> Result<String, ClientError> {
+        let segments: Vec<&str> = feed.split('/').filter(|s| !s.is_empty()).collect();
+        let url = self.endpoint(&segments)?;
+        Ok(self.send(self.http.get(url)).await?.text().await?)
+    }
+
+    /// Changes since the token of the previous pull, all todos without one.
+    pub async fn pull_changes(&self, since: Option<SyncSeq>) -> Result<SyncResponse, ClientError> {
+        let mut url = self.endpoint(&["sync"])?;
+        if let Some(since) = since {
+            url.query_pairs_mut()
+                .append_pair("since", &since.to_string());
+        }
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn push_changes(
+        &self,
+        changes: &[SyncOperation],
+    ) -> Result<Vec<SyncResult>, ClientError> {
+        let url = self.endpoint(&["sync"])?;
+        let response: SyncPushResponse = self
+            .send_authorized(self.http.post(url).json(&json!({ "changes": changes })))
+            .await?
+            .json()
+            .await?;
+        Ok(response.results)
+    }
+
+    pub async fn create_webhook(
+        &self,
+        url: &str,
+        events: &[WebhookEventKind],
+    ) -> Result<CreatedWebhookResponse, ClientError> {
+        let endpoint = self.endpoint(&["webhooks"])?;
+        Ok(self
+            .send_authorized(
+                self.http
+                    .post(endpoint)
+                    .json(&json!({ "url": url, "events": events })),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn webhooks(&self) -> Result<Vec<WebhookResponse>, ClientError> {
+        let url = self.endpoint(&["webhooks"])?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn delete_webhook(&self, id: WebhookId) -> Result<(), ClientError> {
+        let url = self.endpoint(&["webhooks", &id.to_string()])?;
+        self.send_authorized(self.http.delete(url)).await?;
+        Ok(())
+    }
+
+    pub async fn dead_letters_page(
+        &self,
+        limit: usize,
+        after: Option<DeliveryId>,
+    ) -> Result<DeadLettersPageResponse, ClientError> {
+        let url = self.page_endpoint(&["webhooks", "dead_letters"], limit, after)?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub fn dead_letters(
+        &self,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<DeadLetter, ClientError>> + '_ {
+        paginate(move |after| async move {
+            let page = self.dead_letters_page(page_size, after).await?;
+            Ok((page.items, page.cursor))
+        })
+    }
+
+    pub async fn retry_dead_letter(&self, id: DeliveryId) -> Result<(), ClientError> {
+        let url = self.endpoint(&["webhooks", "dead_letters", &id.to_string(), "retry"])?;
+        self.send_authorized(self.http.post(url)).await?;
+        Ok(())
+    }
+
+    /// Raw GraphQL response, resolver failures come back in its `errors`.
+    pub async fn graphql(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<serde_json::Value, ClientError> {
+        let url = self.endpoint(&["graphql"])?;
+        Ok(self
+            .send_authorized(
+                self.http
+                    .post(url)
+                    .json(&json!({ "query": query, "variables": variables })),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn users_page(
+        &self,
+        limit: usize,
+        after: Option<UserId>,
+    ) -> Result<UsersPageResponse, ClientError> {
+        let url = self.page_endpoint(&["admin", "users"], limit, after)?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub fn users(
+        &self,
+        page_size: usize,
+    ) -> impl Stream<Item = Result<DisplayUser, ClientError>> + '_ {
+        paginate(move |after| async move {
+            let page = self.users_page(page_size, after).await?;
+            Ok((page.items, page.cursor))
+        })
+    }
+
+    pub async fn user(&self, id: UserId) -> Result<DisplayUser, ClientError> {
+        let url = self.endpoint(&["admin", "user", &id.to_string()])?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn user_by_email(&self, email: &str) -> Result<DisplayUser, ClientError> {
+        let url = self.endpoint(&["admin", "user", "email", email])?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn set_role(&self, id: UserId, role: Role) -> Result<(), ClientError> {
+        let url = self.endpoint(&["admin", "user", &id.to_string(), "role"])?;
+        self.send_authorized(self.http.patch(url).json(&json!({ "role": role.as_str() })))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_user(&self, id: UserId) -> Result<(), ClientError> {
+        let url = self.endpoint(&["admin", "user", &id.to_string()])?;
+        self.send_authorized(self.http.delete(url)).await?;
+        Ok(())
+    }
+
+    /// Archives the storage into the server's backup directory.
+    pub async fn create_backup(&self) -> Result<BackupResponse, ClientError> {
+        let url = self.endpoint(&["admin", "backups"])?;
+        Ok(self
+            .send_authorized(self.http.post(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn backups(&self) -> Result<Vec<BackupFileResponse>, ClientError> {
+        let url = self.endpoint(&["admin", "backups"])?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Checks the storage, repairing what it can when `repair` is set.
+    pub async fn fsck(&self, repair: bool) -> Result<FsckResponse, ClientError> {
+        let request = if repair {
+            self.http.post(self.endpoint(&["admin", "fsck", "repair"])?)
+        } else {
+            self.http.get(self.endpoint(&["admin", "fsck"])?)
+        };
+        Ok(self.send_authorized(request).await?.json().await?)
+    }
+
+    /// Stats of the latest background collection.
+    pub async fn storage_stats(&self) -> Result<StorageStatsResponse, ClientError> {
+        let url = self.endpoint(&["admin", "storage", "stats"])?;
+        Ok(self
+            .send_authorized(self.http.get(url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Appends escaped path segments to the server url.
+    fn endpoint(&self, segments: &[&str]) -> Result<Url, ClientError> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| ClientError::InvalidUrl)?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    fn page_endpoint(
+        &self,
+        segments: &[&str],
+        limit: usize,
+        after: Option<impl Display>,
+    ) -> Result<Url, ClientError> {
+        let mut url = self.endpoint(segments)?;
+        {
+            let mut query_pairs = url.query_pairs_mut();
+            query_pairs.append_pair("limit", &limit.to_string());
+            if let Some(after) = after {
+                query_pairs.append_pair("after", &after.to_string());
+            }
+        }
+        Ok(url)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        check(request.send().await?).await
+    }
+
+    /// Sends the request with the access token. If the token expired, the
+    /// tokens are refreshed and the request is sent once more.
+    async fn send_authorized(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let access_token = self
+            .tokens
+            .lock()
+            .await
+            .as_ref()
+            .ok_or(ClientError::NotLoggedIn)?
+            .access_token
+            .clone();
+        let retry = request.try_clone();
+
+        match (
+            check(request.bearer_auth(&access_token).send().await?).await,
+            retry,
+        ) {
+            (Err(e), Some(retry)) if e.code() == Some(ErrorCode::TokenExpired) => {
+                let access_token = self.refresh_expired(&access_token).await?;
+                check(retry.bearer_auth(access_token).send().await?).await
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Refreshes the tokens unless a concurrent request already replaced `expired`.
+    async fn refresh_expired(&self, expired: &str) -> Result<String, ClientError> {
+        let mut tokens = self.tokens.lock().await;
+        let current = tokens.as_ref().ok_or(ClientError::NotLoggedIn)?;
+        if current.access_token != expired {
+            return Ok(current.access_token.clone());
+        }
+
+        let refreshed = self.request_refresh(&current.refresh_token).await?;
+        let access_token = refreshed.access_token.clone();
+        *tokens = Some(refreshed);
+        Ok(access_token)
+    }
+
+    async fn request_refresh(&self, refresh_token: &str) -> Result<LoginToken, ClientError> {
+        let url = self.endpoint(&["auth", "refresh"])?;
+        Ok(self
+            .send(self.http.post(url).bearer_auth(refresh_token))
+            .await?
+            .json()
+            .await?)
+    }
+}
+
+/// Turns unsuccessful responses into errors, decoding the JSON error body when there is one.
+async fn check(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await?;
+    Err(match serde_json::from_str::<ApiError>(&body) {
+        Ok(body) => ClientError::Api { status, body },
+        Err(_) => ClientError::Status { status, body },
+    })
+}
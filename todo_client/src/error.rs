@@ -0,0 +1,130 @@
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::types::ImportLineError;
+
+/// `error` field of a failed request: the snake_case name of the server's
+/// `AppError` or `AuthError` variant.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    UserAlreadyExists,
+    PasswordMismatch,
+    UserByEmailNotFound,
+    Forbidden,
+    EncodingToken,
+    HashingPassword,
+    MissingPbkdf2Config,
+    MissingArgon2Config,
+    InvalidArgon2Config,
+    ParsePasswordHash,
+    InvalidRole,
+    InternalStorage,
+    FailedToLoadEnvVar,
+    InvalidTtl,
+    MissingPasswordEmail,
+    EmptyPatch,
+    DependencyCycle,
+    TodoBlocked,
+    BatchOperation,
+    TooLong,
+    QuotaExceeded,
+    InvalidBatchSize,
+    InvalidLastEventId,
+    InvalidWebhook,
+    InvalidImport,
+    InvalidBackup,
+    BackupDir,
+    SerializeExport,
+    JoinTask,
+
+    TokenExpired,
+    SessionExpired,
+    InvalidSession,
+    InvalidToken,
+    InvalidUser,
+    InvalidHeader,
+    ExpectedAccessToken,
+    ExpectedRefreshToken,
+    MissingRefreshJti,
+    #[serde(rename = "inavlid_refresh_jti")]
+    InvalidRefreshJti,
+    FailedToDecodeToken,
+
+    /// Code added to the server after this client was built.
+    #[serde(other)]
+    Unknown,
+}
+
+/// JSON body of a failed request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub error: ErrorCode,
+    pub message: String,
+    /// Failed operation of a batch.
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Error of the failed batch operation.
+    #[serde(default)]
+    pub source: Option<ErrorCode>,
+    /// Invalid lines of an import.
+    #[serde(default)]
+    pub errors: Vec<ImportLineError>,
+    /// Limit a request ran into, e.g. `text` or `todos`.
+    #[serde(default)]
+    pub limit: Option<String>,
+    /// Value of that limit.
+    #[serde(default)]
+    pub max: Option<usize>,
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("{status}: {}", body.message)]
+    Api { status: StatusCode, body: ApiError },
+
+    /// Failures answered without an error body, e.g. role checks and rate limits.
+    #[error("{status}: {body}")]
+    Status { status: StatusCode, body: String },
+
+    #[error("Not logged in")]
+    NotLoggedIn,
+
+    #[error("Server url can't be a base")]
+    InvalidUrl,
+
+    #[error("Failed to decode event")]
+    DecodeEvent(#[from] serde_json::Error),
+
+    #[error("Request failed")]
+    Request(#[from] reqwest::Error),
+}
+
+impl ClientError {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Api { status, .. } | Self::Status { status, .. } => Some(*status),
+            Self::Request(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::Api { body, .. } => Some(body.error),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the same request again later may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Request(e) => e.is_connect() || e.is_timeout(),
+            _ => self.status().is_some_and(|status| {
+                status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use futures_util::{stream, Stream, StreamExt};
+
+use crate::{error::ClientError, types::TodoEvent};
+
+/// Decodes the Server-Sent Events of `GET /todos/events`. Keep-alive comments
+/// carry no `data` and are skipped.
+pub(crate) fn todo_events<S, B>(bytes: S) -> impl Stream<Item = Result<TodoEvent, ClientError>>
+where
+    S: Stream<Item = reqwest::Result<B>>,
+    B: AsRef<[u8]>,
+{
+    stream::try_unfold(
+        (Box::pin(bytes), Vec::new()),
+        |(mut bytes, mut buffer)| async move {
+            loop {
+                if let Some(end) = event_end(&buffer) {
+                    let block: Vec<u8> = buffer.drain(..end).collect();
+                    if let Some(event) = decode(&block)? {
+                        return Ok(Some((event, (bytes, buffer))));
+                    }
+                    continue;
+                }
+                match bytes.next().await {
+                    Some(chunk) => buffer.extend_from_slice(chunk?.as_ref()),
+                    None => return Ok(None),
+                }
+            }
+        },
+    )
+}
+
+/// Length of the first event in the buffer, including the blank line ending it.
+fn event_end(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .map(|position| position + 2)
+}
+
+fn decode(block: &[u8]) -> Result<Option<TodoEvent>, ClientError> {
+    let block = String::from_utf8_lossy(block);
+    let data: Vec<&str> = block
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_str(&data.join("\n"))?))
+}
@@ -0,0 +1,27 @@
+//! Typed client of the To-Do service REST API.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), todo_client::ClientError> {
+//! use futures_util::TryStreamExt;
+//! use todo_client::Client;
+//!
+//! let client = Client::new("http://localhost:3400".parse().unwrap());
+//! client.login("user@gmail.com", "123").await?;
+//! client.create_todo("pay rent", None).await?;
+//! let todos: Vec<_> = client.todos(50).try_collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod client;
+mod error;
+mod events;
+mod ids;
+mod page;
+mod types;
+
+pub use client::Client;
+pub use error::{ApiError, ClientError, ErrorCode};
+pub use ids::{DeliveryId, HistorySeq, SyncSeq, TodoId, UserId, WebhookId};
+pub use reqwest::{StatusCode, Url};
+pub use types::*;
@@ -0,0 +1,28 @@
+use std::future::Future;
+
+use futures_util::{stream, Stream, TryStreamExt};
+
+use crate::error::ClientError;
+
+/// Turns a page fetcher into a stream of items. `fetch` gets the cursor of the
+/// previous page (`None` for the first one), the stream ends with the first
+/// page returned without a cursor.
+pub(crate) fn paginate<'a, T, C, F, Fut>(
+    fetch: F,
+) -> impl Stream<Item = Result<T, ClientError>> + 'a
+where
+    T: 'a,
+    C: 'a,
+    F: FnMut(Option<C>) -> Fut + 'a,
+    Fut: Future<Output = Result<(Vec<T>, Option<C>), ClientError>> + 'a,
+{
+    stream::try_unfold((fetch, Some(None)), |(mut fetch, after)| async move {
+        let Some(after) = after else {
+            return Ok::<_, ClientError>(None);
+        };
+        let (items, cursor) = fetch(after).await?;
+        let items = stream::iter(items.into_iter().map(Ok));
+        Ok(Some((items, (fetch, cursor.map(Some)))))
+    })
+    .try_flatten()
+}
@@ -0,0 +1,473 @@
+//! Request and response bodies of the REST API, mirroring the server's
+//! `handlers::types` and the storage types they embed.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ids::{DeliveryId, HistorySeq, SyncSeq, TodoId, UserId, WebhookId};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoginToken {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    /// Name accepted by `PATCH /admin/user/{id}/role`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DisplayUser {
+    pub id: UserId,
+    pub email: String,
+    pub role: Role,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Todo {
+    pub id: TodoId,
+    pub text: String,
+    pub completed: bool,
+    #[serde(default)]
+    pub group: String,
+    /// Unix timestamp (seconds) the todo is due at.
+    #[serde(default)]
+    pub due: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TodoLinks {
+    pub blocked_by: Vec<TodoId>,
+    pub blocking: Vec<TodoId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TodoDetails {
+    #[serde(flatten)]
+    pub todo: Todo,
+    #[serde(flatten)]
+    pub links: TodoLinks,
+}
+
+/// Patch of `PATCH /todos/{id}`, fields left `None` are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateTodo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// `Some(None)` removes the due date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<Option<i64>>,
+    /// Allows completing a todo that still has open blockers.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_blockers: bool,
+}
+
+impl UpdateTodo {
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn completed(mut self, completed: bool) -> Self {
+        self.completed = Some(completed);
+        self
+    }
+
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    pub fn due(mut self, due: Option<i64>) -> Self {
+        self.due = Some(due);
+        self
+    }
+
+    pub fn ignore_blockers(mut self) -> Self {
+        self.ignore_blockers = true;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Create {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        due: Option<i64>,
+    },
+    Update {
+        id: TodoId,
+        #[serde(flatten)]
+        patch: UpdateTodo,
+    },
+    Delete {
+        id: TodoId,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TodoOpResult {
+    Created { id: TodoId },
+    Updated { id: TodoId },
+    Deleted { id: TodoId },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BatchResponse {
+    pub results: Vec<TodoOpResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GroupUpdateResponse {
+    pub updated: usize,
+}
+
+/// Text format of `GET /todos/export` and `POST /todos/import`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferFormat {
+    Csv,
+    Ndjson,
+    Markdown,
+    TodoTxt,
+    Ical,
+}
+
+impl TransferFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Markdown => "markdown",
+            Self::TodoTxt => "todo_txt",
+            Self::Ical => "ical",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImportResponse {
+    pub imported: usize,
+}
+
+/// Problem found in a single line of an imported file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImportLineError {
+    /// 1-based line number in the imported file.
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrashedTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    /// Unix timestamp (seconds) of the deletion.
+    pub deleted_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UndoOperation {
+    Create,
+    Update,
+    Delete,
+    DeleteAll,
+    Batch,
+    UpdateGroup,
+    Sync,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UndoResult {
+    pub operation: UndoOperation,
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoField {
+    Text,
+    Completed,
+    Group,
+    Due,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Text(String),
+    Flag(bool),
+    /// Unix timestamp (seconds).
+    Time(i64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: TodoField,
+    pub old: Option<FieldValue>,
+    pub new: Option<FieldValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub seq: HistorySeq,
+    pub action: HistoryAction,
+    pub actor: UserId,
+    /// Unix timestamp (seconds) of the change.
+    pub at: i64,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TodoEventKind {
+    Created,
+    Updated,
+    Deleted,
+    /// Events after the requested last event id were lost, the todos have to
+    /// be fetched again.
+    Reset,
+}
+
+/// Change pushed by `GET /todos/events`, `todo` is `None` once it was deleted.
+/// A `reset` has neither, its id is the last lost event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TodoEvent {
+    /// Pass as `last_event_id` to resume after this event.
+    pub id: u64,
+    pub kind: TodoEventKind,
+    pub todo_id: Option<TodoId>,
+    pub todo: Option<Todo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CalendarTokenResponse {
+    pub token: String,
+    /// Path of the iCalendar feed, relative to the server url.
+    pub feed: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncChange {
+    pub id: TodoId,
+    pub rev: SyncSeq,
+    pub todo: Option<Todo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncResponse {
+    pub changes: Vec<SyncChange>,
+    /// Pass as `since` to the next pull.
+    pub token: SyncSeq,
+    pub has_more: bool,
+}
+
+/// Change made while offline, `base_rev` is the revision the client last saw.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SyncOperation {
+    Upsert {
+        #[serde(flatten)]
+        todo: Todo,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        base_rev: Option<SyncSeq>,
+    },
+    Delete {
+        id: TodoId,
+        base_rev: SyncSeq,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SyncResult {
+    Applied {
+        id: TodoId,
+        rev: SyncSeq,
+    },
+    Conflict {
+        id: TodoId,
+        rev: Option<SyncSeq>,
+        todo: Option<Todo>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncPushResponse {
+    pub results: Vec<SyncResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEventKind {
+    #[serde(rename = "todo.created")]
+    TodoCreated,
+    #[serde(rename = "todo.updated")]
+    TodoUpdated,
+    #[serde(rename = "todo.completed")]
+    TodoCompleted,
+    #[serde(rename = "todo.deleted")]
+    TodoDeleted,
+    #[serde(rename = "user.created")]
+    UserCreated,
+    #[serde(rename = "user.role_changed")]
+    UserRoleChanged,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebhookResponse {
+    pub id: WebhookId,
+    pub url: String,
+    pub events: Vec<WebhookEventKind>,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CreatedWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: WebhookResponse,
+    /// Key of the `X-Webhook-Signature` HMAC, shown only once.
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub id: DeliveryId,
+    pub webhook_id: WebhookId,
+    pub event_id: u64,
+    pub event: WebhookEventKind,
+    pub occurred_at: i64,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TodosPageResponse {
+    pub items: Vec<Todo>,
+    pub cursor: Option<TodoId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TrashPageResponse {
+    pub items: Vec<TrashedTodo>,
+    pub cursor: Option<TodoId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryPageResponse {
+    pub items: Vec<HistoryEntry>,
+    pub cursor: Option<HistorySeq>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UsersPageResponse {
+    pub items: Vec<DisplayUser>,
+    pub cursor: Option<UserId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeadLettersPageResponse {
+    pub items: Vec<DeadLetter>,
+    pub cursor: Option<DeliveryId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackupResponse {
+    /// File name in the backup directory.
+    pub name: String,
+    pub created_at: i64,
+    pub records: u64,
+    pub trees: Vec<BackupTreeResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackupTreeResponse {
+    pub name: String,
+    pub records: u64,
+    pub checksum: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BackupFileResponse {
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FsckResponse {
+    pub trees: Vec<TreeRecords>,
+    pub problems: Vec<StorageProblem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TreeRecords {
+    pub name: String,
+    pub records: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageProblem {
+    pub tree: String,
+    pub key: String,
+    /// Snake case name of the server's `ProblemKind`, e.g. `orphan_session`.
+    pub kind: String,
+    pub detail: String,
+    pub repaired: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageStatsResponse {
+    /// Unix timestamp the stats were collected at.
+    pub collected_at: i64,
+    pub size_on_disk: u64,
+    pub trees: Vec<TreeRecords>,
+    /// Users with the most todos, most first.
+    pub top_users: Vec<TopUser>,
+    /// Users by how many todos they have.
+    pub todos_per_user: Vec<TodoBucket>,
+    pub active_sessions: usize,
+    pub expired_sessions: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TopUser {
+    pub user_id: UserId,
+    pub todos: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TodoBucket {
+    /// Most todos a user of the bucket has, missing for the last bucket.
+    pub up_to: Option<usize>,
+    pub users: usize,
+}